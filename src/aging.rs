@@ -0,0 +1,261 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Where a freed block goes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// Onto the hot cache for its size, for the next malloc of that
+    /// size to take without asking the backing allocator
+    Cache,
+    /// Back to the backing allocator, which merges it or not as it
+    /// usually would
+    Free,
+    /// Back to the backing allocator, then merge every run of free
+    /// blocks right away
+    Coalesce,
+}
+
+/// What a policy gets to see about a block being freed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Freed {
+    pub ptr: usize,
+    pub size: usize,
+    /// Mallocs and frees since it was allocated
+    pub age: usize,
+    /// Bytes in the hot cache, before this block
+    pub cached: usize,
+}
+
+/// Decides where each freed block goes. Any `FnMut(&Freed) -> Route`
+/// closure is one
+pub trait AgingPolicy {
+    fn route(&mut self, block: &Freed) -> Route;
+}
+
+impl<F: FnMut(&Freed) -> Route> AgingPolicy for F {
+    fn route(&mut self, block: &Freed) -> Route {
+        self(block)
+    }
+}
+
+/// Hands every free to a policy, which sends the block to a hot
+/// cache, the backing allocator's free structure, or the backing
+/// allocator followed by a full coalesce. Mallocs take the most
+/// recently cached block of the same size first. Like `PerCpu`,
+/// cached blocks stay allocated as far as the backing allocator
+/// knows, so they do not count as free space
+#[derive(Clone)]
+pub struct Aging<T, P> {
+    allocator: T,
+    policy: P,
+    cache: BTreeMap<usize, Vec<usize>>,
+    // Size and the op it was made at of each live allocation
+    live: BTreeMap<usize, (usize, usize)>,
+    ops: usize,
+    pub hits: usize,
+    pub misses: usize,
+    /// How many frees the policy sent each way
+    pub cached: usize,
+    pub freed: usize,
+    pub coalesced: usize,
+}
+
+impl<T: Allocator, P: AgingPolicy> Aging<T, P> {
+    pub fn new(allocator: T, policy: P) -> Self {
+        Self {
+            allocator,
+            policy,
+            cache: BTreeMap::new(),
+            live: BTreeMap::new(),
+            ops: 0,
+            hits: 0,
+            misses: 0,
+            cached: 0,
+            freed: 0,
+            coalesced: 0,
+        }
+    }
+
+    /// Bytes sitting in the hot cache
+    pub fn cached_bytes(&self) -> usize {
+        self.cache
+            .iter()
+            .map(|(size, ptrs)| size * ptrs.len())
+            .sum()
+    }
+
+    /// Give every cached block back to the backing allocator
+    pub fn flush(&mut self) {
+        for (_, ptrs) in std::mem::take(&mut self.cache) {
+            for ptr in ptrs {
+                // Never freed to the backing allocator, so this only
+                // fails if it injects failures
+                let _ = self.allocator.free(ptr);
+            }
+        }
+    }
+
+    pub fn into_inner(mut self) -> T {
+        self.flush();
+        self.allocator
+    }
+}
+
+impl<T: Allocator, P: AgingPolicy> Allocator for Aging<T, P> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.ops += 1;
+        let ptr = match self.cache.get_mut(&size).and_then(|ptrs| ptrs.pop()) {
+            Some(ptr) => {
+                self.hits += 1;
+                ptr
+            }
+            None => {
+                self.misses += 1;
+                self.allocator.try_malloc(size)?
+            }
+        };
+
+        self.live.insert(ptr, (size, self.ops));
+        Ok(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let (size, born) = self
+            .live
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        self.ops += 1;
+        let block = Freed {
+            ptr,
+            size,
+            age: self.ops - born,
+            cached: self.cached_bytes(),
+        };
+
+        match self.policy.route(&block) {
+            Route::Cache => {
+                self.cached += 1;
+                self.cache.entry(size).or_default().push(ptr);
+                Ok(())
+            }
+            Route::Free => {
+                self.freed += 1;
+                self.allocator.free(ptr)
+            }
+            Route::Coalesce => {
+                self.coalesced += 1;
+                self.allocator.free(ptr)?;
+                self.allocator.coalesce();
+                Ok(())
+            }
+        }
+    }
+
+    /// Cached blocks are still allocated in the backing allocator,
+    /// but not to anyone
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.live
+            .get(&ptr)
+            .and_then(|_| self.allocator.alloc_size(ptr))
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.allocator.reset()?;
+        self.cache.clear();
+        self.live.clear();
+        self.ops = 0;
+        self.hits = 0;
+        self.misses = 0;
+        self.cached = 0;
+        self.freed = 0;
+        self.coalesced = 0;
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+    }
+
+    /// Mallocs served from the cache count too
+    fn churn(&self) -> Churn {
+        let mut churn = self.allocator.churn();
+        churn.mallocs += self.hits;
+        churn
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().map(|&(size, _)| size).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (size, ptrs) in self.cache.iter() {
+            writeln!(out, "cache {}: {:?}", size, ptrs).unwrap();
+        }
+        out.push_str(&self.allocator.render());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::Policy;
+
+    #[test]
+    fn routes() {
+        // Cache small blocks, coalesce old ones, and leave the rest
+        let policy = |block: &Freed| {
+            if block.size <= 16 && block.cached < 32 {
+                Route::Cache
+            } else if block.age > 5 {
+                Route::Coalesce
+            } else {
+                Route::Free
+            }
+        };
+        let list = FreeList::new(0, 100, false).policy(Policy::First);
+        let mut heap = Aging::new(list, policy);
+        let a = heap.malloc(16).unwrap();
+        let b = heap.malloc(20).unwrap();
+        let c = heap.malloc(20).unwrap();
+        heap.malloc(44).unwrap();
+
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        assert_eq!(heap.cached_bytes(), 16);
+        assert_eq!(heap.free_space(), 0);
+        assert_eq!(heap.malloc(16), Some(a));
+        assert_eq!(heap.hits, 1);
+
+        // Freed young, so it isn't merged
+        heap.free(c).unwrap();
+        assert_eq!(heap.largest_alloc(), 20);
+        // Freed old, so the two merge
+        heap.free(b).unwrap();
+        assert_eq!(heap.largest_alloc(), 40);
+        assert_eq!((heap.cached, heap.freed, heap.coalesced), (1, 1, 1));
+
+        heap.free(a).unwrap();
+        let list = heap.into_inner();
+        assert_eq!(list.free_space(), 56);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{AllocError, Allocator, Churn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// A coalescing free list that keeps free blocks in separate lists
+/// by how well their start address is aligned. Bucket 0 holds blocks
+/// not aligned to any class, and bucket i holds blocks aligned to
+/// `classes[i - 1]` but not the next class up. An aligned request
+/// only scans the buckets that already guarantee its alignment, and
+/// falls back to carving an aligned piece out of the others
+#[derive(Debug, Clone)]
+pub struct AlignedFreeList {
+    base_addr: usize,
+    max_size: usize,
+    classes: Vec<usize>,
+    free: BTreeMap<usize, usize>,
+    buckets: Vec<BTreeSet<usize>>,
+    sizemap: BTreeMap<usize, usize>,
+    last_scan: usize,
+    churn: Churn,
+}
+
+impl AlignedFreeList {
+    /// `classes` must be powers of two. With no classes every block
+    /// is in one list, which is a plain address ordered first fit
+    pub fn new(base_addr: usize, max_size: usize, classes: &[usize]) -> Self {
+        if max_size == 0 {
+            panic!("Don't make a heap with size 0");
+        }
+        let mut classes = classes.to_vec();
+        if !classes.iter().all(|class| class.is_power_of_two()) {
+            panic!("Alignment classes must be powers of two");
+        }
+        classes.sort_unstable();
+        classes.dedup();
+
+        let mut list = Self {
+            base_addr,
+            max_size,
+            buckets: vec![BTreeSet::new(); classes.len() + 1],
+            classes,
+            free: BTreeMap::new(),
+            sizemap: BTreeMap::new(),
+            last_scan: 0,
+            churn: Churn::default(),
+        };
+        list.insert(base_addr, max_size);
+        list
+    }
+
+    pub fn alignment_loss(&self, size: usize, align: usize) -> AlignmentLoss {
+        AlignmentLoss::of(
+            self.free.iter().map(|(&addr, &len)| (addr, len)),
+            size,
+            align,
+        )
+    }
+
+    /// How many free blocks the last malloc looked at
+    pub fn last_scan(&self) -> usize {
+        self.last_scan
+    }
+
+    fn bucket(&self, addr: usize) -> usize {
+        self.classes
+            .iter()
+            .take_while(|&&class| addr.is_multiple_of(class))
+            .count()
+    }
+
+    fn insert(&mut self, addr: usize, size: usize) {
+        let b = self.bucket(addr);
+        self.free.insert(addr, size);
+        self.buckets[b].insert(addr);
+    }
+
+    fn remove(&mut self, addr: usize) -> usize {
+        let b = self.bucket(addr);
+        self.buckets[b].remove(&addr);
+        self.free.remove(&addr).unwrap()
+    }
+
+    // Put a range back, merging it with free neighbors on either side
+    fn release(&mut self, mut addr: usize, mut size: usize) {
+        if let Some((&prev, &prev_size)) = self.free.range(..addr).next_back() {
+            if prev + prev_size == addr {
+                self.churn.merges += 1;
+                self.remove(prev);
+                addr = prev;
+                size += prev_size;
+            }
+        }
+        if let Some(&next_size) = self.free.get(&(addr + size)) {
+            self.churn.merges += 1;
+            self.remove(addr + size);
+            size += next_size;
+        }
+        self.insert(addr, size);
+    }
+}
+
+/// How the free space of a heap splits up for one request size and
+/// alignment. A misaligned hole is big enough for the request, but
+/// not once its start is rounded up to the alignment, so the space
+/// is lost purely to the alignment constraint
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct AlignmentLoss {
+    pub fits: usize,
+    pub misaligned: usize,
+    pub too_small: usize,
+}
+
+impl AlignmentLoss {
+    /// Sort the bytes of each `(addr, size)` hole by whether it can
+    /// hold `size` bytes starting at a multiple of `align`
+    pub fn of(holes: impl IntoIterator<Item = (usize, usize)>, size: usize, align: usize) -> Self {
+        let align = align.max(1);
+        let mut loss = Self::default();
+        for (addr, len) in holes {
+            if len < size {
+                loss.too_small += len;
+            } else if round_up(addr, align) + size > addr + len {
+                loss.misaligned += len;
+            } else {
+                loss.fits += len;
+            }
+        }
+        loss
+    }
+}
+
+fn round_up(addr: usize, align: usize) -> usize {
+    match addr % align {
+        0 => addr,
+        left => addr + align - left,
+    }
+}
+
+impl Allocator for AlignedFreeList {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.malloc_aligned(size, 1)
+    }
+
+    /// The bytes before the aligned address go back on the list
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
+        let align = align.max(1);
+        if size == 0 || size > self.max_size || !align.is_power_of_two() {
+            return None;
+        }
+
+        // Blocks in these buckets start aligned, so the first one big
+        // enough will do. Buckets are scanned from the least aligned
+        // up, to save the well aligned blocks for requests that need them
+        let guaranteed = match align {
+            1 => 0,
+            _ => self
+                .classes
+                .iter()
+                .position(|&class| class >= align)
+                .map_or(self.buckets.len(), |i| i + 1),
+        };
+        let order = (guaranteed..self.buckets.len()).chain(0..guaranteed);
+
+        let mut scanned = 0;
+        let mut found = None;
+        'search: for b in order {
+            for &addr in self.buckets[b].iter() {
+                scanned += 1;
+                let start = round_up(addr, align);
+                if start + size <= addr + self.free[&addr] {
+                    found = Some((addr, start));
+                    break 'search;
+                }
+            }
+        }
+        self.last_scan = scanned;
+        self.churn.scanned += scanned;
+
+        let (addr, start) = found?;
+        let block = self.remove(addr);
+        if size < block {
+            self.churn.splits += 1;
+        }
+        if addr < start {
+            self.insert(addr, start - addr);
+        }
+        if start + size < addr + block {
+            self.insert(start + size, addr + block - start - size);
+        }
+        self.sizemap.insert(start, size);
+        Some(start)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let size = self
+            .sizemap
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        self.release(ptr, size);
+        Ok(())
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.sizemap.get(&ptr).copied()
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.free.clear();
+        self.buckets.iter_mut().for_each(BTreeSet::clear);
+        self.sizemap.clear();
+        self.last_scan = 0;
+        self.churn = Churn::default();
+        self.insert(self.base_addr, self.max_size);
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.free.values().copied().max().unwrap_or(0)
+    }
+
+    fn free_space(&self) -> usize {
+        self.free.values().sum()
+    }
+
+    /// The space skipped to align a block goes back on the free
+    /// list, so nothing is wasted inside allocations
+    fn internal_frag(&self) -> usize {
+        0
+    }
+
+    fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (b, bucket) in self.buckets.iter().enumerate() {
+            let align = if b == 0 { 1 } else { self.classes[b - 1] };
+            write!(out, "Aligned to {:<5}:", align).unwrap();
+            for addr in bucket.iter() {
+                write!(out, " [addr: {}, size: {}]", addr, self.free[addr]).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let mut list = AlignedFreeList::new(0, 8192, &[4096, 16, 64]);
+        assert_eq!(list.classes, vec![16, 64, 4096]);
+
+        assert_eq!(list.malloc(24), Some(0));
+        assert!(list.buckets[0].contains(&24));
+
+        // Nothing starts aligned, so an aligned piece is carved out
+        assert_eq!(list.malloc_aligned(100, 64), Some(64));
+        assert_eq!(list.free[&24], 40);
+        assert!(list.buckets[0].contains(&164));
+        assert_eq!(list.malloc_aligned(16, 4096), Some(4096));
+        assert_eq!(list.last_scan(), 2);
+        assert!(list.buckets[1].contains(&4112));
+        assert_eq!(list.free_space(), 8192 - 140);
+
+        assert!(list.malloc_aligned(8, 3).is_none());
+        assert!(list.free(64).is_ok());
+        assert!(list.free(64).is_err());
+        assert!(list.free(4096).is_ok());
+        assert!(list.free(0).is_ok());
+        assert_eq!(list.free.len(), 1);
+        assert_eq!(list.largest_alloc(), 8192);
+    }
+
+    #[test]
+    fn aligned_search_skips_unaligned_blocks() {
+        let scan = |classes: &[usize]| {
+            let mut list = AlignedFreeList::new(0, 4096, classes);
+            let ptrs: Vec<usize> = (0..16).map(|_| list.malloc(8).unwrap()).collect();
+            // Seven unaligned holes, then the rest of the heap at 128
+            for &ptr in ptrs[1..15].iter().step_by(2) {
+                list.free(ptr).unwrap();
+            }
+            assert_eq!(list.malloc_aligned(8, 64), Some(128));
+            list.last_scan()
+        };
+
+        assert_eq!(scan(&[]), 8);
+        assert_eq!(scan(&[64]), 1);
+    }
+
+    #[test]
+    fn alignment_loss() {
+        let holes = [(8, 16), (32, 16), (60, 4), (72, 24)];
+        let loss = AlignmentLoss::of(holes.iter().copied(), 16, 16);
+        assert_eq!(loss.fits, 16 + 24);
+        assert_eq!(loss.misaligned, 16);
+        assert_eq!(loss.too_small, 4);
+
+        let list = AlignedFreeList::new(8, 100, &[]);
+        assert_eq!(list.alignment_loss(100, 1).fits, 100);
+        assert_eq!(list.alignment_loss(100, 16).misaligned, 100);
+    }
+}
@@ -0,0 +1,138 @@
+use rand::Rng;
+
+/// How mallocs arrive on the simulation clock. Rates are arrivals
+/// per unit of time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arrivals {
+    /// Exponential gaps at a constant rate
+    Poisson { rate: f64 },
+    /// A Markov modulated Poisson process. Arrivals come at the rate
+    /// of the current state, which lasts an exponential time of mean
+    /// `dwell` before switching to one of the other states at random
+    Mmpp { rates: Vec<f64>, dwell: f64 },
+}
+
+impl Arrivals {
+    pub fn poisson(rate: f64) -> Self {
+        Arrivals::Poisson { rate }
+    }
+
+    /// Quiet periods at `rate` alternating with bursts `burst` times
+    /// as fast, each lasting `dwell` on average
+    pub fn bursty(rate: f64, burst: f64, dwell: f64) -> Self {
+        Arrivals::Mmpp {
+            rates: vec![rate, rate * burst],
+            dwell,
+        }
+    }
+
+    /// The arrival rate of each load level
+    pub fn rates(&self) -> Vec<f64> {
+        match self {
+            Arrivals::Poisson { rate } => vec![*rate],
+            Arrivals::Mmpp { rates, .. } => rates.clone(),
+        }
+    }
+
+    /// A clock at time 0, in the first load level
+    pub fn clock<R: Rng>(&self, rng: &mut R) -> Clock<'_> {
+        let switch = match self {
+            Arrivals::Poisson { .. } => f64::INFINITY,
+            Arrivals::Mmpp { dwell, .. } => exponential(rng, 1.0 / dwell),
+        };
+        Clock {
+            arrivals: self,
+            now: 0.0,
+            level: 0,
+            switch,
+        }
+    }
+}
+
+/// Steps through the arrivals of a process one at a time
+#[derive(Debug, Clone)]
+pub struct Clock<'a> {
+    arrivals: &'a Arrivals,
+    now: f64,
+    level: usize,
+    switch: f64,
+}
+
+impl Clock<'_> {
+    /// The time of the next arrival and the load level it came in.
+    /// Gaps are memoryless, so one that would run past a switch is
+    /// drawn again from the switch at the new level's rate
+    pub fn next<R: Rng>(&mut self, rng: &mut R) -> (f64, usize) {
+        let rates = self.arrivals.rates();
+        loop {
+            let at = self.now + exponential(rng, rates[self.level]);
+            if at <= self.switch {
+                self.now = at;
+                return (at, self.level);
+            }
+
+            self.now = self.switch;
+            if let Arrivals::Mmpp { dwell, .. } = self.arrivals {
+                let others = rates.len() - 1;
+                if others > 0 {
+                    self.level = (self.level + 1 + rng.gen_range(0..others)) % rates.len();
+                }
+                self.switch = self.now + exponential(rng, 1.0 / dwell);
+            }
+        }
+    }
+}
+
+// An exponential time with the given rate
+fn exponential<R: Rng>(rng: &mut R, rate: f64) -> f64 {
+    -(1.0 - rng.gen::<f64>()).ln() / rate
+}
+
+/// What a workload saw while arrivals came at one rate
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Load {
+    pub rate: f64,
+    pub mallocs: usize,
+    pub malloc_fails: usize,
+    /// Mean over the mallocs at this level, taken right after each
+    pub external_frag: f32,
+    pub live_bytes: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn poisson() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let arrivals = Arrivals::poisson(4.0);
+        let mut clock = arrivals.clock(&mut rng);
+        let mut last = 0.0;
+        for _ in 0..10000 {
+            let (at, level) = clock.next(&mut rng);
+            assert!(at >= last);
+            assert_eq!(level, 0);
+            last = at;
+        }
+        // 10000 arrivals at 4 per unit take about 2500 units
+        assert!((last - 2500.0).abs() < 100.0);
+    }
+
+    #[test]
+    fn bursts() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let arrivals = Arrivals::bursty(1.0, 9.0, 50.0);
+        let mut clock = arrivals.clock(&mut rng);
+        let mut counts = [0; 2];
+        for _ in 0..20000 {
+            counts[clock.next(&mut rng).1] += 1;
+        }
+        // Both levels last as long on average, so the burst level
+        // gets about 9 times the arrivals
+        let ratio = counts[1] as f64 / counts[0] as f64;
+        assert!(ratio > 6.0 && ratio < 12.0, "{:?}", counts);
+    }
+}
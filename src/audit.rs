@@ -0,0 +1,143 @@
+use crate::trace::{Event, Trace};
+use crate::Allocator;
+use std::fmt;
+use std::str::FromStr;
+
+/// The address every malloc in a replay returned, in order. Two
+/// runs of the same trace on the same allocator should always
+/// produce the same log, so comparing logs catches any
+/// nondeterminism leaking into allocator behavior
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressLog {
+    pub addrs: Vec<Option<usize>>,
+}
+
+/// The first malloc whose address differs between two logs.
+/// None stands for a failed malloc, or a log that ended early
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub malloc: usize,
+    pub expected: Option<usize>,
+    pub actual: Option<usize>,
+}
+
+impl AddressLog {
+    pub fn from_events(events: &[Event]) -> Self {
+        let addrs = events
+            .iter()
+            .filter(|event| event.op.bytes().is_some())
+            .map(|event| event.block.map(|(addr, _)| addr))
+            .collect();
+        Self { addrs }
+    }
+
+    pub fn record<T: Allocator>(allocator: &mut T, trace: &Trace) -> Self {
+        Self::from_events(&trace.replay(allocator))
+    }
+
+    /// Check that `actual` reproduces this log exactly
+    pub fn verify(&self, actual: &AddressLog) -> Result<(), Divergence> {
+        let len = self.addrs.len().max(actual.addrs.len());
+        match (0..len).find(|&i| self.addrs.get(i) != actual.addrs.get(i)) {
+            Some(malloc) => Err(Divergence {
+                malloc,
+                expected: self.addrs.get(malloc).copied().flatten(),
+                actual: actual.addrs.get(malloc).copied().flatten(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// One address per line, or `fail` if the malloc failed
+impl fmt::Display for AddressLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for addr in self.addrs.iter() {
+            match addr {
+                Some(addr) => writeln!(f, "{}", addr)?,
+                None => writeln!(f, "fail")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for AddressLog {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let addrs = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| match line {
+                "fail" => Ok(None),
+                _ => line
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("Could not parse address \"{}\"", line)),
+            })
+            .collect::<Result<Vec<Option<usize>>, String>>()?;
+        Ok(Self { addrs })
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |addr: Option<usize>| match addr {
+            Some(addr) => addr.to_string(),
+            None => "fail".to_string(),
+        };
+        write!(
+            f,
+            "malloc {} returned {}, expected {}",
+            self.malloc,
+            show(self.actual),
+            show(self.expected)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+    use crate::freelist::FreeList;
+    use crate::trace;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn replays_are_deterministic() {
+        let trace = trace::random_memory(0.6, &mut StdRng::seed_from_u64(7));
+
+        let freelist = FreeList::new(0, 4096, true).align(8);
+        let log = AddressLog::record(&mut freelist.clone(), &trace);
+        assert_eq!(
+            log.verify(&AddressLog::record(&mut freelist.clone(), &trace)),
+            Ok(())
+        );
+
+        let buddy = BuddyAllocator::new(3, 12);
+        let log = AddressLog::record(&mut buddy.clone(), &trace);
+        assert_eq!(
+            log.verify(&AddressLog::record(&mut buddy.clone(), &trace)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn divergence() {
+        let expected: AddressLog = "0\n16\nfail\n".parse().unwrap();
+        let actual: AddressLog = "0\n32\n".parse().unwrap();
+        assert_eq!(expected.to_string().parse(), Ok(expected.clone()));
+
+        let divergence = expected.verify(&actual).unwrap_err();
+        assert_eq!(divergence.malloc, 1);
+        assert_eq!(divergence.to_string(), "malloc 1 returned 32, expected 16");
+
+        // Ending early is a divergence too, even where a malloc failed
+        let actual: AddressLog = "0\n16\n".parse().unwrap();
+        assert_eq!(expected.verify(&actual).unwrap_err().malloc, 2);
+    }
+}
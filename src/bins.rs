@@ -0,0 +1,356 @@
+use crate::freelist::FreeList;
+use crate::{AllocError, AllocFailure, Allocator, Churn, Policy, Recycling, Retention};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// Regions of one size class carved from a run of whole pages
+#[derive(Debug, Clone)]
+struct Run {
+    class: usize,
+    len: usize,
+    free: BTreeSet<usize>,
+}
+
+/// jemalloc's size classes: multiples of 16 up to 128, then four
+/// evenly spaced classes per doubling, up to `max`
+pub fn size_classes(max: usize) -> Vec<usize> {
+    let mut classes: Vec<usize> = (1..=8).map(|i| 16 * i).take_while(|&c| c <= max).collect();
+    let mut group = 128;
+    while group < max {
+        classes.extend(
+            (1..=4)
+                .map(|i| group + i * group / 4)
+                .take_while(|&c| c <= max),
+        );
+        group *= 2;
+    }
+    classes
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// A jemalloc style allocator. Small requests are rounded up to a
+/// size class and served from that class's bin, out of runs of
+/// pages cut into regions of the class's size. Each run is the
+/// fewest pages that divide evenly into regions, and goes back to
+/// the pages once all its regions are free. Bins take the lowest
+/// free region of the lowest run with any. Requests too big for
+/// the largest class, just under 4 pages, take whole pages. The
+/// rounding counts as internal fragmentation. An empty run can be
+/// kept in its bin instead, by the retention policy, but only
+/// serves its own class
+#[derive(Debug, Clone)]
+pub struct BinAllocator {
+    base: usize,
+    size: usize,
+    page: usize,
+    // Free pages, in pages from `base`
+    pages: FreeList,
+    classes: Vec<usize>,
+    // Runs with a free region, by address, for each class
+    bins: Vec<BTreeSet<usize>>,
+    runs: BTreeMap<usize, Run>,
+    // Requested and rounded size of each live allocation
+    live: BTreeMap<usize, (usize, usize)>,
+    mallocs: usize,
+    retention: Retention,
+    // Empty runs kept in their bins
+    empty: BTreeSet<usize>,
+    pub recycling: Recycling,
+}
+
+impl BinAllocator {
+    /// Manage [base, base + size), rounded down to whole pages
+    pub fn new(base: usize, size: usize, page: usize) -> Self {
+        if page == 0 || size < page {
+            panic!("Need at least one page");
+        }
+        let classes = size_classes(4 * page - 1);
+
+        Self {
+            base,
+            size: size - size % page,
+            page,
+            pages: FreeList::new(0, size / page, true).policy(Policy::First),
+            bins: vec![BTreeSet::new(); classes.len()],
+            classes,
+            runs: BTreeMap::new(),
+            live: BTreeMap::new(),
+            mallocs: 0,
+            retention: Retention::Release,
+            empty: BTreeSet::new(),
+            recycling: Recycling::default(),
+        }
+    }
+
+    /// What to do with runs once they are empty. Releases their
+    /// pages by default
+    pub fn retain(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Bytes held in empty runs
+    pub fn retained(&self) -> usize {
+        self.empty.iter().map(|addr| self.runs[addr].len).sum()
+    }
+
+    pub fn classes(&self) -> &[usize] {
+        &self.classes
+    }
+
+    /// Runs currently carved out of the pages, empty ones included
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Bytes in the runs of a class, and how many of them are free
+    pub fn bin_bytes(&self, class: usize) -> (usize, usize) {
+        self.runs
+            .values()
+            .filter(|run| run.class == class)
+            .fold((0, 0), |(len, free), run| {
+                (len + run.len, free + run.free.len() * self.classes[class])
+            })
+    }
+
+    // Bytes in a run of a class: the least common multiple of the
+    // class and the page
+    fn run_len(&self, class: usize) -> usize {
+        let size = self.classes[class];
+        size / gcd(size, self.page) * self.page
+    }
+
+    fn take_pages(&mut self, len: usize) -> Option<usize> {
+        self.pages
+            .malloc(len / self.page)
+            .map(|page| self.base + page * self.page)
+    }
+
+    fn malloc_small(&mut self, class: usize) -> Option<usize> {
+        let addr = match self.bins[class].first() {
+            Some(&addr) => addr,
+            None => {
+                let len = self.run_len(class);
+                let addr = self.take_pages(len)?;
+                let size = self.classes[class];
+                let free = (0..len / size).map(|i| addr + i * size).collect();
+                self.runs.insert(addr, Run { class, len, free });
+                self.bins[class].insert(addr);
+                self.recycling.taken += 1;
+                addr
+            }
+        };
+        if self.empty.remove(&addr) {
+            self.recycling.reused += 1;
+        }
+
+        let run = self.runs.get_mut(&addr).unwrap();
+        let ptr = run.free.pop_first().unwrap();
+        if run.free.is_empty() {
+            self.bins[class].remove(&addr);
+        }
+        Some(ptr)
+    }
+
+    // The run a live small allocation is in
+    fn run_of(&self, ptr: usize) -> Option<usize> {
+        self.runs
+            .range(..=ptr)
+            .next_back()
+            .filter(|(&addr, run)| ptr < addr + run.len)
+            .map(|(&addr, _)| addr)
+    }
+}
+
+impl Allocator for BinAllocator {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.mallocs += 1;
+        if size == 0 {
+            return Err(AllocFailure::NoFit);
+        }
+
+        let (ptr, rounded) = match self.classes.iter().position(|&class| class >= size) {
+            Some(class) => (self.malloc_small(class), self.classes[class]),
+            None => {
+                let rounded = size.next_multiple_of(self.page);
+                if rounded > self.size {
+                    return Err(AllocFailure::Oversized);
+                }
+                (self.take_pages(rounded), rounded)
+            }
+        };
+
+        match ptr {
+            Some(ptr) => {
+                self.live.insert(ptr, (size, rounded));
+                Ok(ptr)
+            }
+            None if self.free_space() < size => Err(AllocFailure::Exhausted),
+            None => Err(AllocFailure::NoFit),
+        }
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let (_, rounded) = self
+            .live
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        let addr = match self.run_of(ptr) {
+            Some(addr) => addr,
+            None => return self.pages.free((ptr - self.base) / self.page),
+        };
+
+        let run = self.runs.get_mut(&addr).unwrap();
+        run.free.insert(ptr);
+        let class = run.class;
+        if run.free.len() * rounded == run.len {
+            if self.recycling.empty(self.retention, self.empty.len()) {
+                self.empty.insert(addr);
+                self.bins[class].insert(addr);
+                self.recycling.peak_retained = self.recycling.peak_retained.max(self.retained());
+                return Ok(());
+            }
+            self.runs.remove(&addr);
+            self.bins[class].remove(&addr);
+            return self.pages.free((addr - self.base) / self.page);
+        }
+        self.bins[class].insert(addr);
+        Ok(())
+    }
+
+    /// The longest run of free pages, or the largest class with a
+    /// free region if that is bigger
+    fn largest_alloc(&self) -> usize {
+        let small = self
+            .bins
+            .iter()
+            .zip(self.classes.iter())
+            .filter(|(bin, _)| !bin.is_empty())
+            .map(|(_, &class)| class)
+            .max()
+            .unwrap_or(0);
+        small.max(self.pages.largest_alloc() * self.page)
+    }
+
+    /// Free pages, and the free regions of every run
+    fn free_space(&self) -> usize {
+        let regions: usize = self
+            .runs
+            .values()
+            .map(|run| run.free.len() * self.classes[run.class])
+            .sum();
+        self.pages.free_space() * self.page + regions
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.live
+            .values()
+            .map(|(requested, rounded)| rounded - requested)
+            .sum()
+    }
+
+    /// Splits and merges of the pages, as runs and large
+    /// allocations come and go
+    fn churn(&self) -> Churn {
+        let mut churn = self.pages.churn();
+        churn.mallocs = self.mallocs;
+        churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().map(|&(requested, _)| requested).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (&addr, run) in self.runs.iter() {
+            writeln!(
+                out,
+                "run {}: class {}, {} bytes, {} regions free",
+                addr,
+                self.classes[run.class],
+                run.len,
+                run.free.len()
+            )
+            .unwrap();
+        }
+        writeln!(out, "Free pages:").unwrap();
+        out.push_str(&self.pages.render());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes() {
+        assert_eq!(
+            size_classes(512),
+            vec![16, 32, 48, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512]
+        );
+        let heap = BinAllocator::new(0, 4096, 256);
+        assert_eq!(heap.classes().last(), Some(&896));
+        assert_eq!(heap.run_len(2), 768);
+    }
+
+    #[test]
+    fn bins() {
+        let mut heap = BinAllocator::new(0, 4096, 256);
+        // 40 bytes round up to 48, in a run of three pages
+        let a = heap.malloc(40).unwrap();
+        let b = heap.malloc(33).unwrap();
+        assert_eq!((a, b), (0, 48));
+        assert_eq!(heap.internal_frag(), 8 + 15);
+        assert_eq!(heap.bin_bytes(2), (768, 768 - 96));
+        assert_eq!(heap.free_space(), 4096 - 96);
+
+        // A class of its own gets a run of its own
+        assert_eq!(heap.malloc(16), Some(768));
+        // Large requests take whole pages
+        assert_eq!(heap.malloc(1000), Some(1024));
+        assert_eq!(heap.internal_frag(), 23 + 24);
+        assert_eq!(heap.try_malloc(5000), Err(AllocFailure::Oversized));
+
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        assert_eq!(heap.malloc(48), Some(a));
+        heap.free(a).unwrap();
+        heap.free(b).unwrap();
+        // The run was empty, so its pages went back
+        assert_eq!(heap.runs(), 1);
+        assert_eq!(heap.largest_alloc(), 4096 - 1024 - 1024);
+        assert_eq!(heap.live_bytes(), Some(1016));
+    }
+
+    #[test]
+    fn retention() {
+        let mut heap = BinAllocator::new(0, 4096, 256).retain(Retention::All);
+        let a = heap.malloc(40).unwrap();
+        heap.free(a).unwrap();
+        // The run stays in its bin, empty
+        assert_eq!(heap.runs(), 1);
+        assert_eq!(heap.retained(), 768);
+        assert_eq!(heap.free_space(), 4096);
+        assert_eq!(heap.largest_alloc(), 4096 - 768);
+
+        // Only its own class can use it
+        assert_eq!(heap.malloc(16), Some(768));
+        assert_eq!(heap.malloc(48), Some(0));
+        assert_eq!(heap.recycling.taken, 2);
+        assert_eq!(heap.recycling.reused, 1);
+        assert_eq!(heap.recycling.peak_retained, 768);
+    }
+}
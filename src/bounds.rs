@@ -0,0 +1,136 @@
+use crate::offline;
+use crate::trace::{Op, Trace};
+use crate::Allocator;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+/// Martello and Toth's L2 lower bound on the number of bins of
+/// `capacity` needed to pack `sizes`. Every size must fit in a bin.
+/// For each threshold k, items bigger than `capacity - k` need a bin
+/// to themselves, items bigger than half a bin can't share one, and
+/// items of at least k that don't fit in the space those leave
+/// need more bins
+pub fn l2(sizes: &[usize], capacity: usize) -> usize {
+    let half = capacity / 2;
+    let mut thresholds: Vec<usize> = sizes.iter().copied().filter(|&s| s <= half).collect();
+    thresholds.push(0);
+    thresholds.sort_unstable();
+    thresholds.dedup();
+
+    thresholds
+        .into_iter()
+        .map(|k| {
+            let alone = sizes.iter().filter(|&&s| s > capacity - k).count();
+            let big: Vec<usize> = sizes
+                .iter()
+                .copied()
+                .filter(|&s| s <= capacity - k && s > half)
+                .collect();
+            let small: usize = sizes.iter().filter(|&&s| s <= half && s >= k).sum();
+            let room = big.len() * capacity - big.iter().sum::<usize>();
+            let extra = small.saturating_sub(room);
+            alone + big.len() + extra.div_ceil(capacity)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Least heap an allocator that never lets a block cross a `page`
+/// boundary could replay the trace in: the most pages the L2 bound
+/// says any instant's live blocks need. None if some allocation is
+/// bigger than a page
+pub fn paged(trace: &Trace, page: usize) -> Option<usize> {
+    let mut live: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut pages = 0;
+
+    for &op in trace.ops.iter() {
+        match op {
+            Op::Malloc { id, .. } | Op::Calloc { id, .. } => {
+                let size = op.bytes().unwrap();
+                if size > page {
+                    return None;
+                }
+                live.insert(id, size);
+                let sizes: Vec<usize> = live.values().copied().collect();
+                pages = pages.max(l2(&sizes, page));
+            }
+            Op::Free { id } => {
+                live.remove(&id);
+            }
+        }
+    }
+
+    Some(pages * page)
+}
+
+/// The best lower bound on the heap any allocator needs for the
+/// trace: its peak live bytes
+pub fn floor(trace: &Trace) -> usize {
+    offline::peak_live(trace)
+}
+
+/// The smallest size in `sizes` that `make` can build an allocator
+/// from which replays the whole trace without a malloc failing.
+/// Assumes a bigger size never fails where a smaller one didn't,
+/// and binary searches on that
+pub fn min_heap<T: Allocator>(
+    trace: &Trace,
+    sizes: RangeInclusive<usize>,
+    make: impl Fn(usize) -> T,
+) -> Option<usize> {
+    let fits = |size: usize| {
+        let mut allocator = make(size);
+        trace
+            .replay(&mut allocator)
+            .iter()
+            .all(|event| event.op.bytes().is_none() || event.block.is_some())
+    };
+
+    let (mut lo, mut hi) = (*sizes.start(), *sizes.end());
+    if lo > hi || !fits(hi) {
+        return None;
+    }
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if fits(mid) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    Some(lo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::Policy;
+
+    #[test]
+    fn bin_packing() {
+        // Three items over half a bin need three bins, even though
+        // they would fit in two by total size
+        assert_eq!(l2(&[6, 6, 6], 10), 3);
+        assert_eq!(l2(&[6, 6, 6, 3], 10), 3);
+        assert_eq!(l2(&[6, 6, 6, 4, 4, 4], 10), 3);
+        assert_eq!(l2(&[6, 6, 6, 5, 5, 5], 10), 5);
+        assert_eq!(l2(&[], 10), 0);
+    }
+
+    #[test]
+    fn heap_bounds() {
+        let trace: Trace = "malloc 0 60\nmalloc 1 60\nfree 0\nmalloc 2 30\nmalloc 3 60"
+            .parse()
+            .unwrap();
+        assert_eq!(floor(&trace), 150);
+        assert_eq!(paged(&trace, 100), Some(200));
+        assert_eq!(paged(&trace, 50), None);
+
+        // With first fit the 30 takes the start of the hole the first
+        // 60 left, so the last 60 has to go after the second one
+        let first_fit = |size| FreeList::new(0, size, true).policy(Policy::First);
+        assert_eq!(min_heap(&trace, 1..=1000, first_fit), Some(180));
+        assert!(min_heap(&trace, 1..=100, first_fit).is_none());
+    }
+}
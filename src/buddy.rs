@@ -1,5 +1,8 @@
-use super::Allocator;
-use std::collections::HashMap;
+use super::{AllocError, AllocFailure, Allocator, Churn, FreeRangeError};
+use crate::wcet::WorstCase;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
 
 #[derive(Debug, Copy, Clone)]
 struct Block {
@@ -13,18 +16,32 @@ impl Block {
         Self { addr, size_class }
     }
 
-    pub fn buddy(&self) -> Self {
+    // Blocks are aligned to their size relative to the start of the
+    // heap, not to address 0
+    pub fn buddy(&self, base_addr: usize) -> Self {
         Self {
-            addr: self.addr ^ (1 << self.size_class),
+            addr: base_addr + ((self.addr - base_addr) ^ (1 << self.size_class)),
             size_class: self.size_class,
         }
     }
 }
 
+/// Which free block of a level a malloc takes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Order {
+    /// The one freed or split off longest ago
+    Fifo,
+    /// The one freed or split off most recently
+    Lifo,
+    /// The one at the lowest address
+    Address,
+}
+
 #[derive(Debug, Clone)]
 struct Level {
     blocks: Vec<Block>,
     size_class: usize,
+    order: Order,
 }
 
 impl Level {
@@ -32,6 +49,7 @@ impl Level {
         Self {
             blocks: Vec::new(),
             size_class,
+            order: Order::Fifo,
         }
     }
 
@@ -39,24 +57,140 @@ impl Level {
         !self.blocks.is_empty()
     }
 
+    // Address ordered levels are kept sorted, so the lowest block is
+    // always first
     fn add(&mut self, block: Block) {
-        self.blocks.push(block);
+        match self.order {
+            Order::Fifo | Order::Lifo => self.blocks.push(block),
+            Order::Address => {
+                let i = self.blocks.partition_point(|b| b.addr < block.addr);
+                self.blocks.insert(i, block);
+            }
+        }
     }
 
-    fn pop_front(&mut self) -> Block {
-        self.blocks.remove(0)
+    fn take(&mut self) -> Block {
+        match self.order {
+            Order::Fifo | Order::Address => self.blocks.remove(0),
+            Order::Lifo => self.blocks.pop().unwrap(),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct BuddyAllocator {
+    base_addr: usize,
     min_size: usize,
     max_size: usize,
     levels: Vec<Level>,
-    sizemap: HashMap<usize, (usize, usize)>,
+    sizemap: BTreeMap<usize, (usize, usize)>,
+    // Whether frees merge with their buddy right away, or leave it
+    // to `coalesce`
+    coalesce: bool,
+    // Whether a malloc that finds no free block merges what frees
+    // left unmerged and looks again
+    lazy: bool,
+    churn: Churn,
+}
+
+/// Why a `BuddyBuilder` couldn't build
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SizeError {
+    /// Block and heap sizes have to be powers of two
+    NotPowerOfTwo(usize),
+    /// The smallest block is bigger than the heap
+    MinBlockTooBig {
+        min_block_bytes: usize,
+        heap_bytes: usize,
+    },
+}
+
+impl fmt::Display for SizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SizeError::NotPowerOfTwo(bytes) => write!(f, "{} is not a power of two", bytes),
+            SizeError::MinBlockTooBig {
+                min_block_bytes,
+                heap_bytes,
+            } => write!(
+                f,
+                "The smallest block of {} bytes doesn't fit in a heap of {}",
+                min_block_bytes, heap_bytes
+            ),
+        }
+    }
+}
+
+impl Error for SizeError {}
+
+/// Where a `BuddyAllocator` starts and how big it and its smallest
+/// blocks are, in bytes. Settings can go in any order, and are only
+/// checked by `build`. Starts out as a 32K heap of blocks of at
+/// least 32 bytes at address 0
+#[derive(Debug, Copy, Clone)]
+pub struct BuddyBuilder {
+    base_addr: usize,
+    heap_bytes: usize,
+    min_block_bytes: usize,
+}
+
+impl Default for BuddyBuilder {
+    fn default() -> Self {
+        Self {
+            base_addr: 0,
+            heap_bytes: 32768,
+            min_block_bytes: 32,
+        }
+    }
+}
+
+impl BuddyBuilder {
+    /// Where the heap starts. Blocks are aligned to their size from
+    /// there, so only a base aligned to the heap size gives blocks
+    /// aligned to their size in the address space
+    pub fn base_addr(mut self, base_addr: usize) -> Self {
+        self.base_addr = base_addr;
+        self
+    }
+
+    pub fn heap_bytes(mut self, bytes: usize) -> Self {
+        self.heap_bytes = bytes;
+        self
+    }
+
+    /// What every malloc is rounded up to at least
+    pub fn min_block_bytes(mut self, bytes: usize) -> Self {
+        self.min_block_bytes = bytes;
+        self
+    }
+
+    pub fn build(self) -> Result<BuddyAllocator, SizeError> {
+        for bytes in [self.heap_bytes, self.min_block_bytes] {
+            if !bytes.is_power_of_two() {
+                return Err(SizeError::NotPowerOfTwo(bytes));
+            }
+        }
+        if self.min_block_bytes > self.heap_bytes {
+            return Err(SizeError::MinBlockTooBig {
+                min_block_bytes: self.min_block_bytes,
+                heap_bytes: self.heap_bytes,
+            });
+        }
+
+        let mut buddy = BuddyAllocator::new(
+            self.min_block_bytes.trailing_zeros() as usize,
+            self.heap_bytes.trailing_zeros() as usize,
+        );
+        buddy.base_addr = self.base_addr;
+        buddy.reset().unwrap();
+        Ok(buddy)
+    }
 }
 
 impl BuddyAllocator {
+    /// Blocks from 2^min_size to 2^max_size bytes, over a heap of
+    /// 2^max_size bytes from address 0. Starts out handing out free
+    /// blocks first in, first out
     pub fn new(min_size: usize, max_size: usize) -> Self {
         let mut levels = Vec::with_capacity(max_size - min_size + 1);
 
@@ -69,47 +203,134 @@ impl BuddyAllocator {
         levels.push(last_level);
 
         Self {
+            base_addr: 0,
             min_size,
             max_size,
             levels,
-            sizemap: HashMap::new(),
+            sizemap: BTreeMap::new(),
+            coalesce: true,
+            lazy: false,
+            churn: Churn::default(),
         }
     }
 
-    fn size_class_to_index(&self, size_class: usize) -> usize {
-        size_class - self.min_size
+    /// Settings for an allocator sized in bytes, like `FreeList::new`,
+    /// instead of by powers of two
+    pub fn builder() -> BuddyBuilder {
+        BuddyBuilder::default()
+    }
+
+    /// Where the heap starts
+    pub fn start(&self) -> usize {
+        self.base_addr
+    }
+
+    /// Bytes it manages, from `start`
+    pub fn size(&self) -> usize {
+        1 << self.max_size
+    }
+
+    /// Address and size of each free block, in address order, for
+    /// tools that draw or check the heap
+    pub fn free_blocks(&self) -> impl Iterator<Item = (usize, usize)> {
+        let mut blocks: Vec<(usize, usize)> = self
+            .levels
+            .iter()
+            .flat_map(|level| level.blocks.iter())
+            .map(|block| (block.addr, 1 << block.size_class))
+            .collect();
+        blocks.sort_unstable();
+        blocks.into_iter()
+    }
+
+    /// Address and size of each allocation, rounded up to its block,
+    /// in address order
+    pub fn allocated_blocks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.sizemap
+            .iter()
+            .map(|(&addr, &(size_class, _))| (addr, 1 << size_class))
+    }
+
+    /// With false, frees leave blocks unmerged until `coalesce` is
+    /// called, so a malloc can fail on space that merging would free
+    pub fn coalesce_on_free(mut self, coalesce: bool) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Leave freed blocks unmerged on their level, and merge buddies
+    /// only when a malloc finds no free block big enough. Those
+    /// merges count as deferred in `churn`
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self.coalesce = !lazy;
+        self
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        for level in self.levels.iter_mut() {
+            level.order = order;
+            if order == Order::Address {
+                level.blocks.sort_by_key(|b| b.addr);
+            }
+        }
+        self
     }
 
-    fn check_size(&self, size: usize) -> bool {
+    /// Allocate memory at exactly `addr`. The address has to be a
+    /// multiple of the block size the request rounds up to from the
+    /// start of the heap, and that block has to be free
+    pub fn malloc_at(&mut self, addr: usize, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
         let mut j = (size as f32).log2().ceil() as usize;
         if j > self.max_size {
-            return false;
+            return None;
         } else if j < self.min_size {
             j = self.min_size;
         }
 
-        let idx = self.size_class_to_index(j);
-        if self.levels[idx].has_available_block() {
-            return true;
+        let offset = match addr.checked_sub(self.base_addr) {
+            Some(offset) if offset.is_multiple_of(1 << j) => offset,
+            _ => return None,
+        };
+        if offset + (1 << j) > 1 << self.max_size {
+            return None;
         }
 
-        let mut curr_size_class = j + 1;
+        // Find the free block that contains addr, smallest first
+        let (size_class, i) = (j..=self.max_size).find_map(|size_class| {
+            let block_addr = self.base_addr + (offset & !((1 << size_class) - 1));
+            self.levels[self.size_class_to_index(size_class)]
+                .blocks
+                .iter()
+                .position(|b| b.addr == block_addr)
+                .map(|i| (size_class, i))
+        })?;
 
-        // Go up the levels to find free space
-        while curr_size_class <= self.max_size {
+        let idx = self.size_class_to_index(size_class);
+        self.levels[idx].blocks.remove(i);
+
+        // Split down, freeing the half that doesn't hold addr
+        for curr_size_class in (j..size_class).rev() {
             let idx = self.size_class_to_index(curr_size_class);
-            if self.levels[idx].has_available_block() {
-                break;
-            }
-            curr_size_class += 1;
+            let block_addr = self.base_addr + (offset & !((1 << curr_size_class) - 1));
+            let block = Block::new(block_addr, curr_size_class);
+            self.levels[idx].add(block.buddy(self.base_addr));
+            self.churn.splits += 1;
         }
 
-        curr_size_class != self.max_size + 1
+        self.sizemap.insert(addr, (j, (1 << j) - size));
+        Some(addr)
+    }
+
+    fn size_class_to_index(&self, size_class: usize) -> usize {
+        size_class - self.min_size
     }
 }
 
 impl Allocator for BuddyAllocator {
     fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
         // Smallest power that can accommodate the requested size
         let mut j = (size as f32).log2().ceil() as usize;
         // Too big
@@ -125,7 +346,7 @@ impl Allocator for BuddyAllocator {
         // Check if current level has free space
         let idx = self.size_class_to_index(j);
         if self.levels[idx].has_available_block() {
-            let block = self.levels[idx].pop_front();
+            let block = self.levels[idx].take();
             self.sizemap.insert(block.addr, (j, diff));
             return Some(block.addr);
         }
@@ -142,20 +363,30 @@ impl Allocator for BuddyAllocator {
             curr_size_class += 1;
         }
 
-        // No free space
+        // No free space, unless merging what frees left unmerged
+        // makes some
         if curr_size_class == self.max_size + 1 {
+            if self.lazy {
+                let merges = self.coalesce();
+                self.churn.deferred += merges;
+                if merges > 0 {
+                    self.churn.mallocs -= 1;
+                    return self.malloc(size);
+                }
+            }
             return None;
         }
 
-        let mut block = self.levels[idx].pop_front();
+        let mut block = self.levels[idx].take();
         curr_size_class -= 1;
         while curr_size_class >= j {
             idx = self.size_class_to_index(curr_size_class);
             let block1 = Block::new(block.addr, curr_size_class);
-            let buddy = block1.buddy();
+            let buddy = block1.buddy(self.base_addr);
 
             block = block1;
             self.levels[idx].add(buddy);
+            self.churn.splits += 1;
 
             if curr_size_class == 0 {
                 break;
@@ -168,20 +399,72 @@ impl Allocator for BuddyAllocator {
         Some(block.addr)
     }
 
-    fn free(&mut self, ptr: usize) -> Result<(), &str> {
-        let (mut size_class, _) = self.sizemap.remove(&ptr).ok_or("pointer not found")?;
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let j = ((size as f32).log2().ceil() as usize).max(self.min_size);
+        if j > self.max_size {
+            return Err(AllocFailure::Oversized);
+        }
+        self.malloc(size).ok_or(if self.free_space() < 1 << j {
+            AllocFailure::Exhausted
+        } else {
+            AllocFailure::NoFit
+        })
+    }
+
+    /// Blocks start at a multiple of their own size from the start of
+    /// the heap. If that is aligned, an aligned request takes a block
+    /// of at least `align` bytes, and the rest of it counts as
+    /// internal fragmentation. Otherwise it takes a block of its own
+    /// size at an aligned address, if blocks of that size can start
+    /// at one at all
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        if !align.is_power_of_two() {
+            self.churn.mallocs += 1;
+            return None;
+        }
+        if !self.base_addr.is_multiple_of(align) {
+            let j = ((size as f32).log2().ceil() as usize).max(self.min_size);
+            let addr = self.free_blocks().find_map(|(addr, len)| {
+                let start = addr.checked_next_multiple_of(align)?;
+                let fits = start + (1 << j) <= addr + len;
+                (fits && (start - self.base_addr).is_multiple_of(1 << j)).then_some(start)
+            });
+            return match addr {
+                Some(addr) => self.malloc_at(addr, size),
+                None => {
+                    self.churn.mallocs += 1;
+                    None
+                }
+            };
+        }
+        let ptr = self.malloc(size.max(align))?;
+        let (size_class, _) = self.sizemap[&ptr];
+        self.sizemap
+            .insert(ptr, (size_class, (1 << size_class) - size));
+        Some(ptr)
+    }
+
+    fn free(&mut self, mut ptr: usize) -> Result<(), AllocError> {
+        let (mut size_class, _) = self
+            .sizemap
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
 
         while size_class <= self.max_size {
             let i = self.size_class_to_index(size_class);
             let block = Block::new(ptr, size_class);
+            let buddy_addr = block.buddy(self.base_addr).addr;
             let buddy = self.levels[i]
                 .blocks
                 .iter()
-                .position(|b| b.addr == block.buddy().addr);
+                .position(|b| b.addr == buddy_addr);
 
-            // If found buddy in free list, then we can coalesce
-            if let Some(buddy_index) = buddy {
+            // If found buddy in free list, then we can coalesce.
+            // The merged block starts at the lower of the two
+            if let (true, Some(buddy_index)) = (self.coalesce, buddy) {
                 self.levels[i].blocks.remove(buddy_index);
+                self.churn.merges += 1;
+                ptr = ptr.min(buddy_addr);
             } else {
                 self.levels[i].add(block);
                 break;
@@ -192,12 +475,76 @@ impl Allocator for BuddyAllocator {
         Ok(())
     }
 
-    fn largest_alloc(&self) -> usize {
-        (1..=(1 << self.max_size) + 1)
-            .into_iter()
-            .find(|&x| !self.check_size(x))
+    // Allocated blocks can't be split, so only a range covering
+    // the whole allocation can be freed
+    fn free_range(&mut self, addr: usize, len: usize) -> Result<(), FreeRangeError> {
+        let (&start, &(size_class, diff)) = self
+            .sizemap
+            .range(..=addr)
+            .next_back()
+            .filter(|(&start, &(size_class, _))| addr < start + (1 << size_class))
+            .ok_or(FreeRangeError::NotAllocated)?;
+
+        if addr + len > start + (1 << size_class) {
+            Err(FreeRangeError::OutOfBounds)
+        } else if addr != start || len < (1 << size_class) - diff {
+            Err(FreeRangeError::Unsupported)
+        } else {
+            self.free(start).unwrap();
+            Ok(())
+        }
+    }
+
+    // Can only shrink to a smaller size class. The upper halves
+    // split off along the way become free blocks
+    fn shrink(&mut self, ptr: usize, new_size: usize) -> Result<usize, FreeRangeError> {
+        let &(size_class, diff) = self.sizemap.get(&ptr).ok_or(FreeRangeError::NotAllocated)?;
+        if new_size == 0 || new_size > (1 << size_class) - diff {
+            return Err(FreeRangeError::OutOfBounds);
+        }
+
+        let mut j = (new_size as f32).log2().ceil() as usize;
+        if j < self.min_size {
+            j = self.min_size;
+        }
+
+        for curr_size_class in j..size_class {
+            let idx = self.size_class_to_index(curr_size_class);
+            let block = Block::new(ptr, curr_size_class);
+            self.levels[idx].add(block.buddy(self.base_addr));
+            self.churn.splits += 1;
+        }
+        self.sizemap.insert(ptr, (j, (1 << j) - new_size));
+
+        Ok((1 << size_class) - (1 << j))
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.sizemap
+            .get(&ptr)
+            .map(|&(size_class, _)| 1 << size_class)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        for level in self.levels.iter_mut() {
+            level.blocks.clear();
+        }
+        self.levels
+            .last_mut()
             .unwrap()
-            - 1
+            .add(Block::new(self.base_addr, self.max_size));
+        self.sizemap.clear();
+        self.churn = Churn::default();
+        Ok(())
+    }
+
+    // Anything up to the biggest free block fits in it
+    fn largest_alloc(&self) -> usize {
+        self.levels
+            .iter()
+            .rev()
+            .find(|level| level.has_available_block())
+            .map_or(0, |level| 1 << level.size_class)
     }
 
     fn free_space(&self) -> usize {
@@ -211,11 +558,69 @@ impl Allocator for BuddyAllocator {
         self.sizemap.iter().map(|(&_, &(_, diff))| diff).sum()
     }
 
-    fn print(&self) {
-        self.levels
-            .iter()
-            .rev()
-            .for_each(|level| println!("Size class {}: {:?}", level.size_class, level.blocks));
+    fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(
+            self.sizemap
+                .values()
+                .map(|&(size_class, diff)| (1 << size_class) - diff)
+                .sum(),
+        )
+    }
+
+    // Merge pairs of free buddies a level at a time from the bottom,
+    // so merged blocks can merge again one level up
+    fn coalesce(&mut self) -> usize {
+        let base_addr = self.base_addr;
+        let mut merges = 0;
+        for idx in 0..self.levels.len() - 1 {
+            let free: BTreeSet<usize> = self.levels[idx].blocks.iter().map(|b| b.addr).collect();
+            let (pairs, rest): (Vec<Block>, Vec<Block>) = self.levels[idx]
+                .blocks
+                .iter()
+                .partition(|b| free.contains(&b.buddy(base_addr).addr));
+            self.levels[idx].blocks = rest;
+            for block in pairs.iter().filter(|b| b.addr < b.buddy(base_addr).addr) {
+                self.levels[idx + 1].add(Block::new(block.addr, block.size_class + 1));
+                merges += 1;
+            }
+        }
+        self.churn.merges += merges;
+        merges
+    }
+
+    fn render(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// The free blocks of each level, largest size class first
+impl fmt::Display for BuddyAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for level in self.levels.iter().rev() {
+            let addrs: Vec<String> = level.blocks.iter().map(|b| b.addr.to_string()).collect();
+            writeln!(f, "Size class {}: [{}]", level.size_class, addrs.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A malloc splits at most once for each size class, and so does a
+/// free merge. Merges left to later mallocs pile up without bound
+impl WorstCase for BuddyAllocator {
+    fn worst_op(&self) -> Option<Churn> {
+        if self.lazy && !self.coalesce {
+            return None;
+        }
+        let classes = self.max_size - self.min_size;
+        Some(Churn {
+            splits: classes,
+            merges: if self.coalesce { classes } else { 0 },
+            ..Churn::default()
+        })
     }
 }
 
@@ -304,6 +709,7 @@ mod test {
             buddy.malloc(1);
         }
         assert_eq!(buddy.internal_frag(), 4);
+        assert_eq!(buddy.live_bytes(), Some(4));
     }
 
     #[test]
@@ -360,4 +766,271 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn malloc_at() {
+        let mut buddy = BuddyAllocator::new(0, 3);
+        // Not aligned to its block size
+        assert!(buddy.malloc_at(2, 4).is_none());
+        assert!(buddy.malloc_at(8, 1).is_none());
+
+        assert_eq!(buddy.malloc_at(5, 1), Some(5));
+        assert_eq!(buddy.free_space(), 7);
+        assert!(buddy.malloc_at(5, 1).is_none());
+        assert!(buddy.malloc_at(4, 2).is_none());
+        assert_eq!(buddy.malloc_at(0, 4), Some(0));
+        assert_eq!(buddy.malloc(1), Some(4));
+
+        assert!(buddy.free(5).is_ok());
+        assert!(buddy.free(4).is_ok());
+        assert_eq!(buddy.largest_alloc(), 4);
+    }
+
+    #[test]
+    fn free_range() {
+        let mut buddy = BuddyAllocator::new(0, 3);
+        let ptr = buddy.malloc(3).unwrap();
+
+        assert_eq!(buddy.free_range(4, 1), Err(FreeRangeError::NotAllocated));
+        assert_eq!(buddy.free_range(2, 4), Err(FreeRangeError::OutOfBounds));
+        assert_eq!(buddy.free_range(1, 2), Err(FreeRangeError::Unsupported));
+
+        assert!(buddy.free_range(ptr, 3).is_ok());
+        assert_eq!(buddy.free_space(), 8);
+    }
+
+    #[test]
+    fn reset() {
+        let mut buddy = BuddyAllocator::new(4, 8).order(Order::Address);
+        let a = buddy.malloc(16).unwrap();
+        buddy.malloc(100).unwrap();
+        buddy.free(a).unwrap();
+
+        buddy.reset().unwrap();
+        assert_eq!(buddy.free_space(), 256);
+        assert_eq!(buddy.largest_alloc(), 256);
+        assert_eq!(buddy.internal_frag(), 0);
+        assert_eq!(buddy.churn(), Churn::default());
+        assert_eq!(buddy.malloc(256), Some(0));
+    }
+
+    #[test]
+    fn blocks() {
+        let mut buddy = BuddyAllocator::new(4, 8);
+        buddy.malloc(16).unwrap();
+        buddy.malloc(100).unwrap();
+
+        assert_eq!(
+            buddy.free_blocks().collect::<Vec<_>>(),
+            vec![(16, 16), (32, 32), (64, 64)]
+        );
+        assert_eq!(
+            buddy.allocated_blocks().collect::<Vec<_>>(),
+            vec![(0, 16), (128, 128)]
+        );
+    }
+
+    #[test]
+    fn malloc_aligned() {
+        let mut buddy = BuddyAllocator::new(4, 10);
+        assert_eq!(buddy.malloc(16), Some(0));
+        // Takes a whole 128 byte block to be 128 aligned
+        assert_eq!(buddy.malloc_aligned(16, 128), Some(128));
+        assert_eq!(buddy.internal_frag(), 112);
+        assert_eq!(buddy.live_bytes(), Some(32));
+        assert!(buddy.malloc_aligned(16, 3).is_none());
+        buddy.free(128).unwrap();
+        assert_eq!(buddy.internal_frag(), 0);
+    }
+
+    #[test]
+    fn shrink() {
+        let mut buddy = BuddyAllocator::new(1, 4);
+        let ptr = buddy.malloc(9).unwrap();
+        assert_eq!(buddy.shrink(ptr, 10), Err(FreeRangeError::OutOfBounds));
+
+        // Same size class, nothing to give back
+        assert_eq!(buddy.shrink(ptr, 9), Ok(0));
+        assert_eq!(buddy.internal_frag(), 7);
+
+        assert_eq!(buddy.shrink(ptr, 3), Ok(12));
+        assert_eq!(buddy.internal_frag(), 1);
+        assert_eq!(buddy.free_space(), 12);
+        assert_eq!(buddy.largest_alloc(), 8);
+
+        assert!(buddy.free(ptr).is_ok());
+        assert_eq!(buddy.largest_alloc(), 16);
+    }
+
+    #[test]
+    fn churn() {
+        let mut buddy = BuddyAllocator::new(0, 2);
+        let ptr = buddy.malloc(1).unwrap();
+        buddy.free(ptr).unwrap();
+
+        let churn = buddy.churn();
+        assert_eq!((churn.mallocs, churn.splits, churn.merges), (1, 2, 2));
+        assert_eq!(churn.per_thousand(), (2000.0, 2000.0));
+    }
+
+    #[test]
+    fn order() {
+        // Free 0 and then 8, which aren't buddies, and see which one
+        // the next malloc gets
+        let reuse = |order| {
+            let mut buddy = BuddyAllocator::new(2, 4).order(order);
+            let ptrs: Vec<usize> = (0..4).map(|_| buddy.malloc(4).unwrap()).collect();
+            assert_eq!(ptrs, vec![0, 4, 8, 12]);
+            buddy.free(8).unwrap();
+            buddy.free(0).unwrap();
+            buddy.malloc(4).unwrap()
+        };
+        assert_eq!(reuse(Order::Fifo), 8);
+        assert_eq!(reuse(Order::Lifo), 0);
+        assert_eq!(reuse(Order::Address), 0);
+
+        let mut buddy = BuddyAllocator::new(2, 4).order(Order::Lifo);
+        for _ in 0..4 {
+            buddy.malloc(4).unwrap();
+        }
+        buddy.free(0).unwrap();
+        buddy.free(12).unwrap();
+        buddy.free(4).unwrap();
+        assert_eq!(buddy.malloc(4), Some(12));
+
+        for order in [Order::Fifo, Order::Lifo, Order::Address] {
+            let mut buddy = BuddyAllocator::new(0, 3).order(order);
+            let ptrs: Vec<usize> = (0..8).map(|_| buddy.malloc(1).unwrap()).collect();
+            for &ptr in ptrs.iter().rev() {
+                buddy.free(ptr).unwrap();
+            }
+            assert_eq!(buddy.largest_alloc(), 8);
+        }
+    }
+
+    #[test]
+    fn coalesce() {
+        let mut buddy = BuddyAllocator::new(0, 3).coalesce_on_free(false);
+        let ptrs: Vec<usize> = (0..8).map(|_| buddy.malloc(1).unwrap()).collect();
+        for &ptr in ptrs.iter().take(6) {
+            buddy.free(ptr).unwrap();
+        }
+        assert_eq!(buddy.free_space(), 6);
+        assert_eq!(buddy.largest_alloc(), 1);
+        assert!(buddy.malloc(4).is_none());
+
+        // Three pairs, then the first two of those merge again
+        assert_eq!(buddy.coalesce(), 4);
+        assert_eq!(buddy.largest_alloc(), 4);
+        assert_eq!(buddy.free_space(), 6);
+        assert_eq!(buddy.coalesce(), 0);
+        assert_eq!(buddy.malloc(4), Some(0));
+
+        // Merging as it frees leaves nothing to do
+        let mut buddy = BuddyAllocator::new(0, 3);
+        let ptr = buddy.malloc(1).unwrap();
+        buddy.free(ptr).unwrap();
+        assert_eq!(buddy.coalesce(), 0);
+        assert_eq!(buddy.largest_alloc(), 8);
+    }
+
+    #[test]
+    fn lazy() {
+        let mut buddy = BuddyAllocator::new(0, 3).lazy(true);
+        let ptrs: Vec<usize> = (0..8).map(|_| buddy.malloc(1).unwrap()).collect();
+        for &ptr in ptrs.iter().take(6) {
+            buddy.free(ptr).unwrap();
+        }
+        assert_eq!(buddy.churn().merges, 0);
+
+        // Nothing of size 4 is free until the pairs merge
+        assert_eq!(buddy.malloc(4), Some(0));
+        assert_eq!(buddy.churn().deferred, 4);
+        assert_eq!(buddy.malloc(2), Some(4));
+        assert!(buddy.malloc(2).is_none());
+        let churn = buddy.churn();
+        assert_eq!((churn.mallocs, churn.merges, churn.deferred), (11, 4, 4));
+    }
+
+    #[test]
+    fn builder() {
+        // A heap that starts halfway through a block of its own size,
+        // so buddies are found from the start of the heap. Settings
+        // go in any order
+        let mut buddy = BuddyAllocator::builder()
+            .min_block_bytes(32)
+            .base_addr(0x1080)
+            .heap_bytes(256)
+            .build()
+            .unwrap();
+        assert_eq!((buddy.start(), buddy.size()), (0x1080, 256));
+        let a = buddy.malloc(20).unwrap();
+        let b = buddy.malloc(32).unwrap();
+        assert_eq!((a, b), (0x1080, 0x10a0));
+        assert_eq!(buddy.malloc(64), Some(0x10c0));
+        assert_eq!(buddy.malloc_at(0x1100, 128), Some(0x1100));
+        assert_eq!(buddy.malloc_at(0x1000, 32), None);
+
+        buddy.free(a).unwrap();
+        buddy.free(b).unwrap();
+        assert_eq!(buddy.free_blocks().collect::<Vec<_>>(), vec![(0x1080, 64)]);
+        assert_eq!(buddy.churn().merges, 1);
+        buddy.reset().unwrap();
+        assert_eq!(buddy.free_blocks().collect::<Vec<_>>(), vec![(0x1080, 256)]);
+
+        let sized = |heap_bytes, min_block_bytes| {
+            BuddyAllocator::builder()
+                .heap_bytes(heap_bytes)
+                .min_block_bytes(min_block_bytes)
+                .build()
+                .map(|buddy| buddy.size())
+        };
+        assert_eq!(sized(32768, 32), Ok(32768));
+        assert_eq!(sized(32768, 48), Err(SizeError::NotPowerOfTwo(48)));
+        assert_eq!(
+            sized(16, 32),
+            Err(SizeError::MinBlockTooBig {
+                min_block_bytes: 32,
+                heap_bytes: 16
+            })
+        );
+    }
+
+    #[test]
+    fn unaligned_base() {
+        // Blocks of 16 all start at 1 past a multiple of 16
+        let build = |base_addr| {
+            BuddyAllocator::builder()
+                .base_addr(base_addr)
+                .heap_bytes(1024)
+                .min_block_bytes(8)
+                .build()
+                .unwrap()
+        };
+        let mut buddy = build(0x1001);
+        assert_eq!(buddy.malloc_aligned(16, 16), None);
+        assert_eq!(buddy.free_space(), 1024);
+
+        // Blocks of 8 can start at a multiple of 16
+        let mut buddy = build(0x1008);
+        assert_eq!(buddy.malloc_aligned(16, 16), None);
+        let ptr = buddy.malloc_aligned(8, 16).unwrap();
+        assert_eq!(ptr, 0x1010);
+        assert_eq!(buddy.alloc_size(ptr), Some(8));
+        buddy.free(ptr).unwrap();
+        assert_eq!(buddy.largest_alloc(), 1024);
+    }
+
+    #[test]
+    fn try_malloc() {
+        let mut buddy = BuddyAllocator::new(2, 4);
+        assert_eq!(buddy.try_malloc(17), Err(AllocFailure::Oversized));
+
+        let ptrs: Vec<usize> = (0..4).map(|_| buddy.try_malloc(4).unwrap()).collect();
+        buddy.free(ptrs[0]).unwrap();
+        buddy.free(ptrs[3]).unwrap();
+        // 8 bytes free, but the two blocks aren't buddies
+        assert_eq!(buddy.try_malloc(8), Err(AllocFailure::NoFit));
+        assert_eq!(buddy.try_malloc(16), Err(AllocFailure::Exhausted));
+    }
 }
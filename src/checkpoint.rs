@@ -0,0 +1,166 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn, FreeRangeError};
+
+/// A heap state saved by `Checkpointed::checkpoint`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StateId(usize);
+
+/// Wraps an allocator so its state can be saved and gone back to,
+/// to see what would have happened from the same heap had the ops
+/// after some point been different, like freeing in another order.
+/// Every checkpoint is a full copy of the allocator, and stays valid
+/// after a rollback, so any number of branches can start from it.
+/// Anything kept outside the heap, like the live pointers of a
+/// workload, has to be saved along with the id
+#[derive(Debug, Clone)]
+pub struct Checkpointed<T> {
+    allocator: T,
+    states: Vec<T>,
+}
+
+impl<T: Allocator + Clone> Checkpointed<T> {
+    pub fn new(allocator: T) -> Self {
+        Self {
+            allocator,
+            states: Vec::new(),
+        }
+    }
+
+    /// Save the heap as it is now, counters included
+    pub fn checkpoint(&mut self) -> StateId {
+        self.states.push(self.allocator.clone());
+        StateId(self.states.len() - 1)
+    }
+
+    /// Put the heap back the way it was at the checkpoint. Whatever
+    /// happened since is lost, unless it was checkpointed too
+    pub fn rollback(&mut self, id: StateId) {
+        self.allocator = self.state(id).clone();
+    }
+
+    /// The heap as it was at the checkpoint, to look at or to run a
+    /// branch on apart from this one
+    pub fn state(&self, id: StateId) -> &T {
+        match self.states.get(id.0) {
+            Some(state) => state,
+            None => panic!("No checkpoint {}", id.0),
+        }
+    }
+
+    pub fn checkpoints(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.allocator
+    }
+}
+
+impl<T: Allocator + Clone> Allocator for Checkpointed<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.allocator.malloc(size)
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.allocator.try_malloc(size)
+    }
+
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        self.allocator.malloc_aligned(size, align)
+    }
+
+    fn malloc_contiguous(&mut self, n: usize, block_size: usize) -> Option<usize> {
+        self.allocator.malloc_contiguous(n, block_size)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        self.allocator.free(ptr)
+    }
+
+    fn free_range(&mut self, addr: usize, len: usize) -> Result<(), FreeRangeError> {
+        self.allocator.free_range(addr, len)
+    }
+
+    fn shrink(&mut self, ptr: usize, new_size: usize) -> Result<usize, FreeRangeError> {
+        self.allocator.shrink(ptr, new_size)
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.allocator.alloc_size(ptr)
+    }
+
+    /// Checkpoints are of the heap before the reset, so they are
+    /// dropped with it
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.allocator.reset()?;
+        self.states.clear();
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        self.allocator.churn()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        self.allocator.live_bytes()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    fn render(&self) -> String {
+        self.allocator.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::Policy;
+
+    #[test]
+    fn rollback() {
+        let mut heap = Checkpointed::new(FreeList::new(0, 100, false).policy(Policy::First));
+        let ptrs: Vec<usize> = (0..4).map(|_| heap.malloc(20).unwrap()).collect();
+        let before = heap.checkpoint();
+
+        // Freeing every other allocation leaves holes nothing bigger
+        // than 20 fits in
+        heap.free(ptrs[0]).unwrap();
+        heap.free(ptrs[2]).unwrap();
+        assert_eq!(heap.largest_alloc(), 20);
+        let holes = heap.checkpoint();
+
+        // Freeing two next to each other instead, and merging them,
+        // leaves room for 40
+        heap.rollback(before);
+        assert_eq!(heap.free_space(), 20);
+        heap.free(ptrs[1]).unwrap();
+        heap.free(ptrs[2]).unwrap();
+        assert_eq!(heap.coalesce(), 1);
+        assert_eq!(heap.largest_alloc(), 40);
+
+        // Both branches are still there to go back to
+        assert_eq!(heap.state(holes).largest_alloc(), 20);
+        heap.rollback(before);
+        assert!(heap.free(ptrs[1]).is_ok());
+        assert_eq!(heap.checkpoints(), 2);
+        assert_eq!(heap.churn().merges, 0);
+
+        heap.reset().unwrap();
+        assert_eq!(heap.checkpoints(), 0);
+    }
+}
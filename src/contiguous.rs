@@ -0,0 +1,236 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+
+/// Where the blocks of a multi-block malloc ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Blocks {
+    /// All of them in one run, starting here
+    Contiguous(usize),
+    /// Each on its own, because no run was free
+    Scattered(Vec<usize>),
+}
+
+impl Blocks {
+    /// The address to free them all with
+    pub fn first(&self) -> usize {
+        match self {
+            Blocks::Contiguous(ptr) => *ptr,
+            Blocks::Scattered(ptrs) => ptrs[0],
+        }
+    }
+}
+
+/// The multi-block mallocs of one order, that is of up to `2^order`
+/// blocks
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageOrder {
+    pub requests: usize,
+    /// Requests no run of free blocks could serve
+    pub failures: usize,
+    /// Of those, the ones served by scattering the blocks instead
+    pub scattered: usize,
+}
+
+impl PageOrder {
+    pub fn failure_rate(&self) -> f32 {
+        self.failures as f32 / self.requests.max(1) as f32
+    }
+}
+
+/// Counts how often `malloc_contiguous` fails at each order, the way
+/// the kernel's page allocator reports higher order failures, which
+/// are what buddy systems set out to keep rare. With `scatter` on,
+/// `malloc_blocks` falls back to allocating the blocks one by one,
+/// like a scatter-gather list, and freeing the first frees them all
+#[derive(Debug, Clone)]
+pub struct Contiguous<T> {
+    allocator: T,
+    scatter: bool,
+    // Every block of each scattered allocation, by its first
+    scattered: BTreeMap<usize, Vec<usize>>,
+    pub orders: BTreeMap<usize, PageOrder>,
+}
+
+impl<T: Allocator> Contiguous<T> {
+    pub fn new(allocator: T) -> Self {
+        Self {
+            allocator,
+            scatter: false,
+            scattered: BTreeMap::new(),
+            orders: BTreeMap::new(),
+        }
+    }
+
+    /// Allocate the blocks one by one when no run of them is free
+    pub fn scatter(mut self, scatter: bool) -> Self {
+        self.scatter = scatter;
+        self
+    }
+
+    /// Like `malloc_contiguous`, but hands out the blocks scattered
+    /// if they don't fit in one run and `scatter` is on
+    pub fn malloc_blocks(&mut self, n: usize, block_size: usize) -> Option<Blocks> {
+        if let Some(ptr) = self.malloc_contiguous(n, block_size) {
+            return Some(Blocks::Contiguous(ptr));
+        }
+        if !self.scatter || n == 0 {
+            return None;
+        }
+
+        let mut ptrs = Vec::with_capacity(n);
+        for _ in 0..n {
+            match self.allocator.malloc(block_size) {
+                Some(ptr) => ptrs.push(ptr),
+                None => {
+                    for ptr in ptrs {
+                        let _ = self.allocator.free(ptr);
+                    }
+                    return None;
+                }
+            }
+        }
+
+        self.orders.entry(order(n)).or_default().scattered += 1;
+        self.scattered.insert(ptrs[0], ptrs.clone());
+        Some(Blocks::Scattered(ptrs))
+    }
+
+    /// Fraction of the requests of this order that found no run
+    pub fn failure_rate(&self, order: usize) -> f32 {
+        self.orders
+            .get(&order)
+            .map_or(0.0, |order| order.failure_rate())
+    }
+
+    pub fn into_inner(self) -> T {
+        self.allocator
+    }
+}
+
+// The order of `n` blocks, rounded up to a power of two like the
+// kernel does
+fn order(n: usize) -> usize {
+    n.next_power_of_two().trailing_zeros() as usize
+}
+
+impl<T: Allocator> Allocator for Contiguous<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.allocator.malloc(size)
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.allocator.try_malloc(size)
+    }
+
+    fn malloc_contiguous(&mut self, n: usize, block_size: usize) -> Option<usize> {
+        let result = self.allocator.malloc_contiguous(n, block_size);
+        let order = self.orders.entry(order(n)).or_default();
+        order.requests += 1;
+        if result.is_none() {
+            order.failures += 1;
+        }
+        result
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        match self.scattered.remove(&ptr) {
+            Some(ptrs) => ptrs
+                .into_iter()
+                .try_for_each(|ptr| self.allocator.free(ptr)),
+            None => self.allocator.free(ptr),
+        }
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        match self.scattered.get(&ptr) {
+            Some(ptrs) => ptrs.iter().map(|&ptr| self.allocator.alloc_size(ptr)).sum(),
+            None => self.allocator.alloc_size(ptr),
+        }
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.allocator.reset()?;
+        self.scattered.clear();
+        self.orders.clear();
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        self.allocator.churn()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        self.allocator.live_bytes()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    fn render(&self) -> String {
+        self.allocator.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+    use crate::freelist::FreeList;
+    use crate::Policy;
+
+    #[test]
+    fn orders() {
+        // Eight pages of 16 bytes, with every other one left allocated
+        let list = FreeList::new(0, 128, true).policy(Policy::First);
+        let mut heap = Contiguous::new(list).scatter(true);
+        let pages: Vec<usize> = (0..8).map(|_| heap.malloc(16).unwrap()).collect();
+        for &page in pages.iter().step_by(2) {
+            heap.free(page).unwrap();
+        }
+
+        assert_eq!(heap.malloc_contiguous(1, 16), Some(0));
+        assert_eq!(heap.malloc_contiguous(2, 16), None);
+        // Three of the four free pages are left, but none are next to
+        // each other
+        assert_eq!(
+            heap.malloc_blocks(2, 16),
+            Some(Blocks::Scattered(vec![32, 64]))
+        );
+        assert_eq!(heap.malloc_blocks(2, 16), None);
+        assert_eq!(heap.free_space(), 16);
+        assert_eq!(
+            heap.orders[&1],
+            PageOrder {
+                requests: 3,
+                failures: 3,
+                scattered: 1,
+            }
+        );
+        assert_eq!(heap.failure_rate(0), 0.0);
+
+        // Freeing the first of the scattered pages frees both
+        assert_eq!(heap.alloc_size(32), Some(32));
+        heap.free(32).unwrap();
+        assert_eq!(heap.free_space(), 48);
+        assert!(heap.free(64).is_err());
+
+        // A buddy allocator rounds the run up to a power of two
+        let mut buddy = Contiguous::new(BuddyAllocator::new(4, 7));
+        let ptr = buddy.malloc_contiguous(3, 16).unwrap();
+        assert_eq!(buddy.alloc_size(ptr), Some(64));
+        assert_eq!(buddy.orders[&2].requests, 1);
+    }
+}
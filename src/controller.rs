@@ -0,0 +1,112 @@
+/// How full the heap should be at each op, as a fraction of it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Occupancy {
+    /// The same level the whole run
+    Constant(f64),
+    /// Rise linearly from empty to `peak` over `over` ops, then swing
+    /// `amplitude` above and below it, one cycle every `period` ops
+    RampOscillate {
+        peak: f64,
+        over: usize,
+        amplitude: f64,
+        period: usize,
+    },
+}
+
+impl Occupancy {
+    pub fn at(&self, op: usize) -> f64 {
+        match *self {
+            Occupancy::Constant(level) => level,
+            Occupancy::RampOscillate {
+                peak,
+                over,
+                amplitude,
+                period,
+            } => {
+                if op < over {
+                    peak * op as f64 / over as f64
+                } else {
+                    let phase = (op - over) as f64 / period.max(1) as f64;
+                    peak + amplitude * (phase * std::f64::consts::TAU).sin()
+                }
+            }
+        }
+    }
+}
+
+/// A proportional-integral controller picking the chance that the
+/// next op is a malloc, so occupancy follows a target instead of
+/// drifting wherever a fixed ratio takes it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Controller {
+    gain: f64,
+    integral_gain: f64,
+    integral: f64,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new(5.0, 0.01)
+    }
+}
+
+impl Controller {
+    pub fn new(gain: f64, integral_gain: f64) -> Self {
+        Self {
+            gain,
+            integral_gain,
+            integral: 0.0,
+        }
+    }
+
+    /// The malloc ratio for the next op. Kept between 0.05 and 0.95
+    /// so mallocs and frees both keep happening, and the integral is
+    /// bounded so a target the heap can't reach doesn't wind it up
+    pub fn ratio(&mut self, target: f64, occupancy: f64) -> f64 {
+        let error = target - occupancy;
+        self.integral = (self.integral + self.integral_gain * error).clamp(-0.5, 0.5);
+        (0.5 + self.gain * error + self.integral).clamp(0.05, 0.95)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::metrics::Metrics;
+    use crate::workloads;
+
+    #[test]
+    fn trajectory() {
+        let target = Occupancy::RampOscillate {
+            peak: 0.8,
+            over: 100,
+            amplitude: 0.1,
+            period: 40,
+        };
+        assert_eq!(target.at(0), 0.0);
+        assert!((target.at(50) - 0.4).abs() < 1e-9);
+        assert!((target.at(100) - 0.8).abs() < 1e-9);
+        assert!((target.at(110) - 0.9).abs() < 1e-9);
+        assert!((target.at(130) - 0.7).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tracks_target() {
+        let mut controller = Controller::default();
+        assert!(controller.ratio(0.9, 0.1) > 0.9);
+        assert!(controller.ratio(0.1, 0.9) < 0.1);
+
+        let results = workloads::controlled(
+            FreeList::new(0, 32768, true),
+            Occupancy::Constant(0.5),
+            2000,
+            Controller::default(),
+            Metrics::new(),
+            &mut rand::thread_rng(),
+        );
+        assert_eq!(results.malloc_fails, 0);
+        let error = results.value("tracking_error");
+        assert!(error < 0.1, "{}", error);
+    }
+}
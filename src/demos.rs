@@ -1,5 +1,13 @@
 use crate::buddy::BuddyAllocator;
 use crate::freelist::FreeList;
+use crate::linked::LinkedFreeList;
+use crate::metrics::{LatencyModel, Metric, Metrics};
+use crate::presets::{self, EMBEDDED_HEAP};
+use crate::reservation::Reserving;
+use crate::rng::Generator;
+use crate::tlsf::Tlsf;
+use crate::vmem::Arena;
+use crate::workloads::{self, Warm};
 use crate::{Allocator, Policy};
 
 pub fn freelist(coalesce: bool) {
@@ -21,7 +29,7 @@ pub fn freelist(coalesce: bool) {
             println!("malloc({}) returned {}", i, ptr);
             ptrs.push(ptr);
         } else {
-            ptr = ptrs[i.abs() as usize];
+            ptr = ptrs[i.unsigned_abs()];
             println!("Freeing ptr {}", ptr);
             list.free(ptr).expect("Free failed");
         }
@@ -33,6 +41,89 @@ pub fn freelist(coalesce: bool) {
     println!("External fragmentation: {}", list.external_frag());
 }
 
+pub fn linked() {
+    println!("Demoing the linked freelist, 8 byte words\n");
+    let mut list = LinkedFreeList::new(0, 512);
+    list.print();
+    println!();
+
+    let mut ptrs = Vec::new();
+    for &size in [20, 60, 20, 100].iter() {
+        let ptr = list.malloc(size).unwrap();
+        println!("malloc({}) returned {}", size, ptr);
+        ptrs.push(ptr);
+    }
+    list.print();
+    println!();
+
+    // Freed blocks go on the head of the list, and the middle free
+    // merges with both of its neighbours
+    for &i in [0, 2, 1].iter() {
+        println!("Freeing ptr {}", ptrs[i]);
+        list.free(ptrs[i]).expect("Free failed");
+        list.print();
+        println!();
+    }
+
+    println!("Header and footer bytes: {}", list.overhead());
+    println!("Internal fragmentation: {}", list.internal_frag());
+    println!("External fragmentation: {}", list.external_frag());
+}
+
+pub fn corruption() {
+    println!("Demoing header corruption in the linked freelist, 8 byte words\n");
+    let mut list = LinkedFreeList::new(0, 256);
+    let ptrs: Vec<usize> = (0..3).map(|_| list.malloc(16).unwrap()).collect();
+    println!("malloc(16) three times returned {:?}", ptrs);
+    list.print();
+    println!();
+
+    // Headers are a size word and then the magic number, right
+    // before the payload
+    let (size, magic) = (ptrs[1] - 16, ptrs[1] - 8);
+    println!(
+        "Bug: the program writes 24 zero bytes past the end of {}, over its footer and the header of {}",
+        ptrs[0], ptrs[1]
+    );
+    for addr in [ptrs[0] + 16, size, magic] {
+        list.poke(addr, 0);
+    }
+    match list.free(ptrs[1]) {
+        Ok(()) => println!("free({}) went through", ptrs[1]),
+        Err(e) => println!("free({}) refused: {}", ptrs[1], e),
+    }
+    println!("The block leaks, but the free list is untouched");
+    list.print();
+    println!();
+
+    let mut list = LinkedFreeList::new(0, 256);
+    let ptrs: Vec<usize> = (0..3).map(|_| list.malloc(16).unwrap()).collect();
+    println!(
+        "Starting over. Bug: a stray store puts 80 in the size word of {}, leaving its magic number alone",
+        ptrs[1]
+    );
+    list.poke(size, 80);
+    list.free(ptrs[1]).expect("Free failed");
+    println!(
+        "free({}) went through, and merged a block of 80 bytes with the free space after it",
+        ptrs[1]
+    );
+    list.print();
+    println!("The free block now covers {}, which is still live", ptrs[2]);
+    let ptr = list.malloc(40).unwrap();
+    println!(
+        "malloc(40) returned {}, and its footer and the next free header are inside {}",
+        ptr, ptrs[2]
+    );
+    list.print();
+    println!();
+
+    println!("Checking the magic number caught the first bug, but not the second.");
+    println!("Real allocators check more: glibc compares the size in a header with");
+    println!("the copy the next block keeps, much like the footers here, and aborts");
+    println!("the program when they disagree.");
+}
+
 pub fn buddy() {
     println!("Demoing buddy allocator\n");
     let mut buddy = BuddyAllocator::new(0, 3);
@@ -58,3 +149,142 @@ pub fn buddy() {
     println!("Internal fragmentation: {}", buddy.internal_frag());
     println!("External fragmentation: {}", buddy.external_frag());
 }
+
+pub fn tlsf() {
+    println!("Demoing TLSF allocator\n");
+    let mut tlsf = Tlsf::new(0, 2048);
+    tlsf.print();
+    println!();
+
+    let mut ptrs = Vec::new();
+    for &size in [100, 1000, 300].iter() {
+        let ptr = tlsf.malloc(size).unwrap();
+        println!("malloc({}) returned {}", size, ptr);
+        ptrs.push(ptr);
+    }
+    tlsf.print();
+    println!();
+
+    tlsf.free(ptrs[1]).unwrap();
+    println!("After freeing ptr {}", ptrs[1]);
+    tlsf.print();
+    // The free block is big enough, but sits in the list below the
+    // one a request for it searches from
+    println!("malloc(1000) returned {:?}", tlsf.malloc(1000));
+    println!("Largest alloc: {}", tlsf.largest_alloc());
+    println!("Internal fragmentation: {}", tlsf.internal_frag());
+    println!("External fragmentation: {}", tlsf.external_frag());
+}
+
+pub fn reservation() {
+    println!("Demoing reservations\n");
+    let mut heap = Reserving::new(FreeList::new(0, 1024, true).align(4));
+
+    let id = heap.reserve(256).unwrap();
+    println!("Reserved 256 bytes for critical allocations");
+    heap.print();
+
+    let ptr = heap.malloc(700).unwrap();
+    println!("malloc(700) returned {}", ptr);
+    println!(
+        "malloc(100) {}",
+        match heap.malloc(100) {
+            Some(ptr) => format!("returned {}", ptr),
+            None => "failed, only reserved space is left".to_string(),
+        }
+    );
+
+    let ptr = heap.alloc_from_reservation(id, 100).unwrap();
+    println!("alloc_from_reservation(100) returned {}", ptr);
+    heap.print();
+
+    println!("Reservation utilization: {}", heap.utilization(id).unwrap());
+}
+
+pub fn scan() {
+    println!("Demoing freelist search\n");
+
+    for &policy in [Policy::First, Policy::Next, Policy::Best].iter() {
+        println!("{:?} fit", policy);
+        let mut list = FreeList::new(0, 256, false).policy(policy);
+
+        // Leave holes of 8, 64, 16 and 32 bytes
+        let ptrs: Vec<usize> = [8, 8, 64, 8, 16, 8, 32, 8]
+            .iter()
+            .map(|&size| list.malloc(size).unwrap())
+            .collect();
+        for ptr in ptrs.into_iter().step_by(2) {
+            list.free(ptr).unwrap();
+        }
+
+        for &size in [16, 8, 24].iter() {
+            let ptr = list.malloc(size).unwrap();
+            println!("malloc({}) returned {}", size, ptr);
+            list.print_scan();
+        }
+        println!();
+    }
+}
+
+pub fn vmem() {
+    println!("Demoing vmem arenas\n");
+
+    // PIDs below 300 are reserved, like on Linux
+    let mut pids = Arena::new(300, 32768 - 300, 1).quantum_cache(1);
+    let procs: Vec<usize> = (0..4).map(|_| pids.malloc(1).unwrap()).collect();
+    println!("Started processes {:?}", procs);
+    pids.free(procs[1]).unwrap();
+    println!("Process {} exited", procs[1]);
+    println!("Next process gets {}", pids.malloc(1).unwrap());
+    pids.print();
+
+    // Ephemeral ports in two spans, handed out in blocks of 8
+    let mut ports = Arena::new(32768, 8192, 8);
+    ports.add(49152, 16384).unwrap();
+    let block = ports.alloc_at(49152, 100).unwrap();
+    println!(
+        "\nReserved ports {} to {}, {} ports of rounding",
+        block,
+        block + 103,
+        ports.internal_frag()
+    );
+    println!("malloc(20000) {:?}", ports.malloc(20000));
+    println!("malloc(10000) {:?}", ports.malloc(10000));
+    ports.print();
+}
+
+pub fn embedded() {
+    println!(
+        "Demoing allocators for a {} byte embedded heap\n",
+        EMBEDDED_HEAP
+    );
+
+    // The same seeded ops on each, so the output never changes
+    for (name, make) in presets::embedded() {
+        let metrics = Metrics::new()
+            .register(LatencyModel::default())
+            .register(LatencyModel::default().worst_case());
+        let results = workloads::random_from(
+            Warm::cold(make()),
+            0.5,
+            8..=128,
+            metrics,
+            &mut Generator::ChaCha.seeded(0),
+        );
+        let latency = |name| match results.metric(name) {
+            Some(Metric::Value(value)) => *value,
+            _ => 0.0,
+        };
+
+        println!("{}", name);
+        println!(
+            "  {} malloc fails, {} bytes of internal fragmentation, {:.3} external fragmentation",
+            results.malloc_fails, results.internal_frag, results.external_frag
+        );
+        println!(
+            "  Modeled latency: {:.1} ns mean, {:.1} ns worst case",
+            latency("latency_model"),
+            latency("worst_latency")
+        );
+    }
+}
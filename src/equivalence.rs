@@ -0,0 +1,188 @@
+use crate::rng::Generator;
+use crate::trace::{self, Op, Trace};
+use crate::{AllocFailure, Allocator};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What a malloc or free returned
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Malloc(Result<usize, AllocFailure>),
+    /// Whether the free worked. Frees of ids that never got a block
+    /// don't reach the allocator, and count as failed
+    Free(bool),
+}
+
+/// Everything outside an allocator can see of it after one op
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Observation {
+    pub outcome: Outcome,
+    pub free_space: usize,
+    pub largest_alloc: usize,
+    pub internal_frag: usize,
+}
+
+/// The first op of a replay after which two allocators could be
+/// told apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub op: usize,
+    pub a: Observation,
+    pub b: Observation,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "op {}: {:?} against {:?}", self.op, self.a, self.b)
+    }
+}
+
+// Replays a trace one op at a time, keeping its own map of ids to
+// addresses
+struct Replay<T> {
+    allocator: T,
+    live: BTreeMap<usize, usize>,
+}
+
+impl<T: Allocator> Replay<T> {
+    fn step(&mut self, op: Op) -> Observation {
+        let outcome = match op {
+            Op::Malloc { id, .. } | Op::Calloc { id, .. } => {
+                let result = match op {
+                    Op::Calloc { count, size, .. } => self.allocator.try_calloc(count, size),
+                    _ => self.allocator.try_malloc(op.bytes().unwrap()),
+                };
+                if let Ok(ptr) = result {
+                    self.live.insert(id, ptr);
+                }
+                Outcome::Malloc(result)
+            }
+            Op::Free { id } => Outcome::Free(
+                self.live
+                    .remove(&id)
+                    .is_some_and(|ptr| self.allocator.free(ptr).is_ok()),
+            ),
+        };
+        Observation {
+            outcome,
+            free_space: self.allocator.free_space(),
+            largest_alloc: self.allocator.largest_alloc(),
+            internal_frag: self.allocator.internal_frag(),
+        }
+    }
+}
+
+/// Replay a trace on two allocators side by side and check they
+/// return the same addresses and failures and report the same free
+/// space and fragmentation after every op. A new backend or wrapper
+/// that should behave exactly like an existing allocator can be
+/// held to it this way
+pub fn compare<A: Allocator, B: Allocator>(a: A, b: B, trace: &Trace) -> Result<(), Divergence> {
+    let mut a = Replay {
+        allocator: a,
+        live: BTreeMap::new(),
+    };
+    let mut b = Replay {
+        allocator: b,
+        live: BTreeMap::new(),
+    };
+
+    for (i, &op) in trace.ops.iter().enumerate() {
+        let (seen_a, seen_b) = (a.step(op), b.step(op));
+        if seen_a != seen_b {
+            return Err(Divergence {
+                op: i,
+                a: seen_a,
+                b: seen_b,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// `compare` on a random trace at `ratio` for each seed, drawn from
+/// ChaCha so a failing seed reproduces anywhere. Returns the first
+/// seed whose trace told the allocators apart
+pub fn compare_seeded<A: Allocator, B: Allocator>(
+    make_a: impl Fn() -> A,
+    make_b: impl Fn() -> B,
+    ratio: f64,
+    seeds: impl IntoIterator<Item = u64>,
+) -> Result<(), (u64, Divergence)> {
+    for seed in seeds {
+        let trace = trace::random_memory(ratio, &mut Generator::ChaCha.seeded(seed));
+        compare(make_a(), make_b(), &trace).map_err(|divergence| (seed, divergence))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::faults::Faulty;
+    use crate::freelist::FreeList;
+    use crate::metadata::Tagged;
+    use crate::reservation::Reserving;
+    use crate::rounding::{Rounded, Rounding};
+    use crate::Policy;
+
+    fn freelist() -> FreeList {
+        FreeList::new(0, 32768, true)
+            .align(32)
+            .policy(Policy::First)
+    }
+
+    #[test]
+    fn transparent_wrappers() {
+        // Wrappers doing nothing have to be invisible
+        for &ratio in [0.5, 0.7].iter() {
+            let seeds = 0..5;
+            assert!(
+                compare_seeded(freelist, || Box::new(freelist()), ratio, seeds.clone()).is_ok()
+            );
+            assert!(compare_seeded(
+                freelist,
+                || Rounded::new(freelist(), Rounding::None),
+                ratio,
+                seeds.clone()
+            )
+            .is_ok());
+            assert!(compare_seeded(
+                freelist,
+                || Tagged::<_, ()>::new(freelist()),
+                ratio,
+                seeds.clone()
+            )
+            .is_ok());
+            assert!(compare_seeded(
+                freelist,
+                || Reserving::new(freelist()),
+                ratio,
+                seeds.clone()
+            )
+            .is_ok());
+            assert!(compare_seeded(freelist, || Faulty::new(freelist(), 1), ratio, seeds).is_ok());
+        }
+    }
+
+    #[test]
+    fn divergence() {
+        let trace: Trace = "malloc 0 64\nmalloc 1 32\nmalloc 2 64\nfree 0\nmalloc 3 32"
+            .parse()
+            .unwrap();
+        let best = FreeList::new(0, 192, true);
+        let divergence = compare(
+            best,
+            FreeList::new(0, 192, true).policy(Policy::First),
+            &trace,
+        )
+        .unwrap_err();
+        assert_eq!(divergence.op, 4);
+        assert_eq!(divergence.a.outcome, Outcome::Malloc(Ok(160)));
+        assert_eq!(divergence.b.outcome, Outcome::Malloc(Ok(0)));
+
+        let (seed, _) =
+            compare_seeded(freelist, || freelist().policy(Policy::Best), 0.7, 0..10).unwrap_err();
+        assert!(seed < 10);
+    }
+}
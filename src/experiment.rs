@@ -0,0 +1,291 @@
+use crate::metrics::Metrics;
+use crate::rng::Generator;
+use crate::stats::Aggregate;
+use crate::synth::Synth;
+use crate::trace::Trace;
+use crate::workloads::{self, Results, Warm};
+use crate::Allocator;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// What every run of an experiment does to the heap
+#[derive(Debug, Clone)]
+pub enum Workload {
+    /// 32 byte allocations, freed last in first out, with a chance of
+    /// `ratio` that each op is a malloc
+    Stack { ratio: f64 },
+    /// Sizes drawn uniformly from `sizes`, freed in random order
+    Random {
+        ratio: f64,
+        sizes: RangeInclusive<usize>,
+    },
+    /// Cycles of heavy churn and quiet, as in `workloads::cycles`
+    Cycles {
+        cycles: usize,
+        churn: usize,
+        keep: f64,
+        release: f64,
+    },
+    /// A new trace from the generator for every seed
+    Synthetic(Synth),
+    /// The same trace for every seed
+    Trace(Trace),
+}
+
+// Makes a fresh heap for each run
+type Factory = Box<dyn Fn() -> Box<dyn Allocator>>;
+
+/// Runs one workload against several allocators, once per seed, and
+/// collects the results, so a comparison that would take a bench in
+/// the binary takes a few lines in a notebook or a test
+pub struct Experiment {
+    workload: Workload,
+    allocators: Vec<(String, Factory)>,
+    seeds: Vec<u64>,
+    rng: Generator,
+    metrics: bool,
+}
+
+impl Default for Experiment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Experiment {
+    /// No allocators yet, and a single run with seed 0 of the random
+    /// workload `bench random` runs by default
+    pub fn new() -> Self {
+        Self {
+            workload: Workload::Random {
+                ratio: 0.5,
+                sizes: 32..=128,
+            },
+            allocators: Vec::new(),
+            seeds: vec![0],
+            rng: Generator::default(),
+            metrics: false,
+        }
+    }
+
+    /// Add an allocator to compare. Every run starts from a copy of
+    /// it as it is now
+    pub fn allocator<T: Allocator + Clone + 'static>(mut self, name: &str, allocator: T) -> Self {
+        self.allocators.push((
+            name.to_string(),
+            Box::new(move || Box::new(allocator.clone())),
+        ));
+        self
+    }
+
+    pub fn workload(mut self, workload: Workload) -> Self {
+        self.workload = workload;
+        self
+    }
+
+    /// Seeds of the runs, one run per allocator for each
+    pub fn seeds(mut self, seeds: impl IntoIterator<Item = u64>) -> Self {
+        self.seeds = seeds.into_iter().collect();
+        self
+    }
+
+    /// Which generator the seeds go into. ChaCha by default
+    pub fn rng(mut self, rng: Generator) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Run the built in metric collectors too
+    pub fn metrics(mut self, metrics: bool) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn run(&self) -> Outcome {
+        let trials = self
+            .allocators
+            .iter()
+            .map(|(name, factory)| Trials {
+                allocator: name.clone(),
+                seeds: self.seeds.clone(),
+                runs: self
+                    .seeds
+                    .iter()
+                    .map(|&seed| self.run_one(factory(), seed))
+                    .collect(),
+            })
+            .collect();
+
+        Outcome {
+            workload: self.workload.clone(),
+            trials,
+        }
+    }
+
+    fn run_one(&self, allocator: Box<dyn Allocator>, seed: u64) -> Results {
+        let mut rng = self.rng.seeded(seed);
+        let metrics = if self.metrics {
+            Metrics::builtin()
+        } else {
+            Metrics::new()
+        };
+
+        match &self.workload {
+            Workload::Stack { ratio } => {
+                workloads::stack_from(Warm::cold(allocator), *ratio, metrics, &mut rng)
+            }
+            Workload::Random { ratio, sizes } => workloads::random_from(
+                Warm::cold(allocator),
+                *ratio,
+                sizes.clone(),
+                metrics,
+                &mut rng,
+            ),
+            Workload::Cycles {
+                cycles,
+                churn,
+                keep,
+                release,
+            } => workloads::cycles(
+                allocator, *cycles, *churn, *keep, *release, metrics, &mut rng,
+            ),
+            Workload::Synthetic(synth) => {
+                workloads::replayed(allocator, &synth.generate(&mut rng), metrics)
+            }
+            Workload::Trace(trace) => workloads::replayed(allocator, trace, metrics),
+        }
+    }
+}
+
+/// Every run of one allocator in an experiment
+#[derive(Debug, Clone)]
+pub struct Trials {
+    pub allocator: String,
+    /// Seed of each run, in the same order as the runs
+    pub seeds: Vec<u64>,
+    pub runs: Aggregate,
+}
+
+/// What an experiment found, with the allocators in the order they
+/// were added
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub workload: Workload,
+    pub trials: Vec<Trials>,
+}
+
+impl Outcome {
+    /// The runs of the allocator with this name
+    pub fn get(&self, allocator: &str) -> Option<&Aggregate> {
+        self.trials
+            .iter()
+            .find(|trials| trials.allocator == allocator)
+            .map(|trials| &trials.runs)
+    }
+}
+
+/// A table of the means over the runs, one row per allocator
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .trials
+            .iter()
+            .map(|trials| trials.allocator.len())
+            .max()
+            .unwrap_or(0)
+            .max("allocator".len());
+        writeln!(
+            f,
+            "{:<width$} {:>12} {:>12} {:>14} {:>14}",
+            "allocator",
+            "malloc fails",
+            "free fails",
+            "internal frag",
+            "external frag",
+            width = width
+        )?;
+        for trials in self.trials.iter() {
+            let runs = &trials.runs;
+            writeln!(
+                f,
+                "{:<width$} {:>12.1} {:>12.1} {:>14.1} {:>14.3}",
+                trials.allocator,
+                runs.mean(|r| r.malloc_fails as f32),
+                runs.mean(|r| r.free_fails as f32),
+                runs.mean(|r| r.internal_frag as f32),
+                runs.mean(|r| r.external_frag),
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+    use crate::stack::StackAllocator;
+
+    #[test]
+    fn experiment() {
+        let experiment = Experiment::new()
+            .workload(Workload::Stack { ratio: 0.7 })
+            .allocator("stack", StackAllocator::new(0, 4096))
+            .allocator("buddy", BuddyAllocator::new(5, 12))
+            .seeds(0..4);
+        let outcome = experiment.run();
+        assert_eq!(outcome.trials.len(), 2);
+        assert_eq!(outcome.trials[1].seeds, vec![0, 1, 2, 3]);
+
+        // A stack never fails a free in a stack workload
+        let stack = outcome.get("stack").unwrap();
+        assert_eq!(stack.len(), 4);
+        assert_eq!(stack.mean(|r| r.free_fails as f32), 0.0);
+        assert!(outcome.get("tlsf").is_none());
+
+        // Seeded, so running it again gives the same runs
+        let again = experiment.run();
+        let fails = |outcome: &Outcome| {
+            outcome
+                .get("buddy")
+                .unwrap()
+                .series(|r| r.malloc_fails as f32)
+        };
+        assert_eq!(fails(&outcome), fails(&again));
+        assert!(outcome.to_string().starts_with("allocator "));
+
+        let trace: Trace = "malloc 0 100\nmalloc 1 5000\nfree 0".parse().unwrap();
+        let outcome = experiment.workload(Workload::Trace(trace)).run();
+        assert_eq!(
+            outcome
+                .get("buddy")
+                .unwrap()
+                .series(|r| r.malloc_fails as f32),
+            vec![1.0; 4]
+        );
+    }
+
+    #[test]
+    fn cycles() {
+        let outcome = Experiment::new()
+            .workload(Workload::Cycles {
+                cycles: 20,
+                churn: 200,
+                keep: 0.05,
+                release: 0.5,
+            })
+            .allocator("buddy", BuddyAllocator::new(4, 13))
+            .run();
+        let results = &outcome.get("buddy").unwrap().runs[0];
+        let (largest, free) = (
+            results.series("largest_alloc"),
+            results.series("free_space"),
+        );
+        assert_eq!((largest.len(), free.len()), (20, 20));
+        for (largest, free) in largest.iter().zip(free) {
+            assert!(largest <= free);
+        }
+        assert!(results.drift().abs() < 0.1);
+    }
+}
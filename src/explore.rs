@@ -0,0 +1,166 @@
+use crate::regressions::{Checker, Violation};
+use crate::trace::{Op, Trace};
+use crate::Allocator;
+
+/// Model checks an allocator on a tiny heap by trying every sequence
+/// of ops up to some length: at each step, a malloc of each of the
+/// given sizes, or a free of each live allocation. Every state is
+/// checked with the same invariants as `regressions::check`
+#[derive(Debug, Clone)]
+pub struct Explorer<T> {
+    allocator: T,
+    base: usize,
+    size: usize,
+    sizes: Vec<usize>,
+    depth: usize,
+    restores: bool,
+}
+
+impl<T: Allocator + Clone> Explorer<T> {
+    /// Explore `allocator`, which manages [base, base + size), with
+    /// mallocs of every size from 1 to the heap size, 4 ops deep
+    pub fn new(allocator: T, base: usize, size: usize) -> Self {
+        Self {
+            allocator,
+            base,
+            size,
+            sizes: (1..=size).collect(),
+            depth: 4,
+            restores: false,
+        }
+    }
+
+    pub fn sizes(mut self, sizes: &[usize]) -> Self {
+        self.sizes = sizes.to_vec();
+        self
+    }
+
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Also check that once everything is freed, the free space and
+    /// the largest free block are back to what they were at the
+    /// start. Only holds for allocators that coalesce as they free
+    pub fn restores(mut self, restores: bool) -> Self {
+        self.restores = restores;
+        self
+    }
+
+    /// How many states the full depth search checked, or the shortest
+    /// trace that breaks an invariant. Each depth is searched in full
+    /// before the next, so no shorter trace breaks one
+    pub fn run(&self) -> Result<usize, (Trace, Violation)> {
+        let mut states = 0;
+        for depth in 1..=self.depth {
+            states = 0;
+            let mut trace = Trace::new();
+            self.visit(
+                &self.allocator,
+                &Checker::new(self.base, self.size),
+                &mut trace,
+                depth,
+                &mut states,
+            )?;
+        }
+        Ok(states)
+    }
+
+    // Try every op from this state, and everything after it, until
+    // the trace is `depth` ops long
+    fn visit(
+        &self,
+        allocator: &T,
+        checker: &Checker,
+        trace: &mut Trace,
+        depth: usize,
+        states: &mut usize,
+    ) -> Result<(), (Trace, Violation)> {
+        if trace.len() == depth {
+            return Ok(());
+        }
+
+        let mallocs = self.sizes.iter().map(|&size| Op::Malloc {
+            id: trace.len(),
+            size,
+        });
+        let frees = checker.live().map(|id| Op::Free { id });
+        for op in mallocs.chain(frees).collect::<Vec<Op>>() {
+            let mut allocator = allocator.clone();
+            let mut checker = checker.clone();
+            trace.push(op);
+            *states += 1;
+
+            let mut result = checker.step(&mut allocator, trace.len() - 1, op);
+            if result.is_ok() && self.restores && checker.live().next().is_none() {
+                result = self.restored(&allocator, trace.len() - 1);
+            }
+            if let Err(violation) = result {
+                return Err((trace.clone(), violation));
+            }
+
+            self.visit(&allocator, &checker, trace, depth, states)?;
+            trace.ops.pop();
+        }
+        Ok(())
+    }
+
+    fn restored(&self, allocator: &T, op: usize) -> Result<(), Violation> {
+        let start = (self.allocator.free_space(), self.allocator.largest_alloc());
+        let now = (allocator.free_space(), allocator.largest_alloc());
+        if now == start {
+            Ok(())
+        } else {
+            Err(Violation {
+                op,
+                message: format!(
+                    "nothing live, but {} bytes free and {} in one block instead of {} and {}",
+                    now.0, now.1, start.0, start.1
+                ),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+    use crate::freelist::FreeList;
+    use crate::stack::StackAllocator;
+    use crate::Policy;
+
+    #[test]
+    fn correct_allocators() {
+        let buddy = Explorer::new(BuddyAllocator::new(0, 3), 0, 8)
+            .sizes(&[1, 2, 3, 8])
+            .depth(4)
+            .restores(true);
+        // 4 mallocs to start with, then at least 4 more from each
+        assert!(buddy.run().unwrap() > 4 + 16 + 64);
+
+        let freelist = Explorer::new(FreeList::new(0, 8, true).policy(Policy::First), 0, 8)
+            .depth(3)
+            .restores(true);
+        assert!(freelist.run().is_ok());
+
+        // Frees below the top of a stack are turned down, not broken
+        let stack = Explorer::new(StackAllocator::new(0, 8), 0, 8).depth(4);
+        assert!(stack.run().is_ok());
+    }
+
+    #[test]
+    fn shortest_counterexample() {
+        // Without coalescing, the first free leaves the heap split
+        let explorer = Explorer::new(FreeList::new(0, 8, false), 0, 8)
+            .sizes(&[2, 4])
+            .depth(4)
+            .restores(true);
+        let (trace, violation) = explorer.run().unwrap_err();
+        assert_eq!(trace.to_string(), "malloc 0 2\nfree 0\n");
+        assert_eq!(violation.op, 1);
+
+        assert!(explorer.restores(false).run().is_ok());
+    }
+}
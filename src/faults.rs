@@ -0,0 +1,264 @@
+use crate::{AllocError, Allocator, Churn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+
+/// Wraps an allocator and injects faults, so workload logic can be
+/// tested against an allocator that misbehaves. Mallocs and frees
+/// can fail spuriously, and frees can be delayed so the memory only
+/// comes back some number of ops later
+#[derive(Debug, Clone)]
+pub struct Faulty<T> {
+    allocator: T,
+    rng: StdRng,
+    malloc_fail: f64,
+    free_fail: f64,
+    free_delay: usize,
+    // Frees waiting to reach the allocator, with the op they are due
+    pending: VecDeque<(usize, usize)>,
+    ops: usize,
+    pub injected_malloc_fails: usize,
+    pub injected_free_fails: usize,
+    pub late_free_fails: usize,
+}
+
+impl<T: Allocator> Faulty<T> {
+    /// No faults are injected until they are turned on
+    pub fn new(allocator: T, seed: u64) -> Self {
+        Self {
+            allocator,
+            rng: StdRng::seed_from_u64(seed),
+            malloc_fail: 0.0,
+            free_fail: 0.0,
+            free_delay: 0,
+            pending: VecDeque::new(),
+            ops: 0,
+            injected_malloc_fails: 0,
+            injected_free_fails: 0,
+            late_free_fails: 0,
+        }
+    }
+
+    /// Probability that a malloc fails even though it would fit
+    pub fn malloc_failures(mut self, probability: f64) -> Self {
+        self.malloc_fail = probability;
+        self
+    }
+
+    /// Probability that a free fails and leaves the pointer allocated
+    pub fn free_failures(mut self, probability: f64) -> Self {
+        self.free_fail = probability;
+        self
+    }
+
+    /// Hold on to freed memory for this many ops before it is
+    /// really freed
+    pub fn delay_frees(mut self, ops: usize) -> Self {
+        self.free_delay = ops;
+        self
+    }
+
+    /// Apply every delayed free now
+    pub fn flush(&mut self) {
+        while let Some((_, ptr)) = self.pending.pop_front() {
+            if self.allocator.free(ptr).is_err() {
+                self.late_free_fails += 1;
+            }
+        }
+    }
+
+    pub fn into_inner(mut self) -> T {
+        self.flush();
+        self.allocator
+    }
+
+    // Advance the op clock, applying delayed frees that are due
+    fn tick(&mut self) {
+        self.ops += 1;
+        while let Some(&(due, ptr)) = self.pending.front() {
+            if due > self.ops {
+                break;
+            }
+            self.pending.pop_front();
+            if self.allocator.free(ptr).is_err() {
+                self.late_free_fails += 1;
+            }
+        }
+    }
+}
+
+impl<T: Allocator> Allocator for Faulty<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.tick();
+        if self.rng.gen_bool(self.malloc_fail) {
+            self.injected_malloc_fails += 1;
+            return None;
+        }
+        self.allocator.malloc(size)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        self.tick();
+        if self.rng.gen_bool(self.free_fail) {
+            self.injected_free_fails += 1;
+            return Err(AllocError::Injected);
+        }
+
+        if self.free_delay == 0 {
+            self.allocator.free(ptr)
+        } else if self.pending.iter().any(|&(_, p)| p == ptr) {
+            Err(AllocError::DoubleFree(ptr))
+        } else {
+            self.pending.push_back((self.ops + self.free_delay, ptr));
+            Ok(())
+        }
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.allocator.alloc_size(ptr)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.allocator.reset()?;
+        self.pending.clear();
+        self.ops = 0;
+        self.injected_malloc_fails = 0;
+        self.injected_free_fails = 0;
+        self.late_free_fails = 0;
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        self.allocator.churn()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        self.allocator.live_bytes()
+    }
+
+    fn render(&self) -> String {
+        self.allocator.render()
+    }
+}
+
+/// What a workload does when a free fails
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Recovery {
+    /// Give up on the pointer, leaking its memory
+    Leak,
+    /// Try again up to this many more times, then leak
+    Retry(usize),
+}
+
+impl Recovery {
+    /// Free the pointer, recovering from failures with this policy.
+    /// Returns how many attempts failed, and whether it was freed
+    pub fn free<T: Allocator>(&self, allocator: &mut T, ptr: usize) -> (usize, bool) {
+        let attempts = match self {
+            Recovery::Leak => 1,
+            Recovery::Retry(retries) => retries + 1,
+        };
+
+        for failed in 0..attempts {
+            if allocator.free(ptr).is_ok() {
+                return (failed, true);
+            }
+        }
+        (attempts, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::stack::StackAllocator;
+    use crate::workloads::Warm;
+
+    #[test]
+    fn no_faults() {
+        let mut heap = Faulty::new(FreeList::new(0, 100, true), 0);
+        let ptr = heap.malloc(10).unwrap();
+        assert!(heap.free(ptr).is_ok());
+        assert!(heap.free(ptr).is_err());
+        assert_eq!(heap.injected_malloc_fails + heap.injected_free_fails, 0);
+    }
+
+    #[test]
+    fn injected_failures() {
+        let mut heap = Faulty::new(FreeList::new(0, 100, true), 0).malloc_failures(1.0);
+        assert!(heap.malloc(10).is_none());
+        assert_eq!(heap.injected_malloc_fails, 1);
+
+        let mut heap = Faulty::new(FreeList::new(0, 100, true), 0).free_failures(1.0);
+        let ptr = heap.malloc(10).unwrap();
+        assert!(heap.free(ptr).is_err());
+        assert_eq!(heap.free_space(), 90);
+        assert_eq!(Recovery::Retry(2).free(&mut heap, ptr), (3, false));
+        assert_eq!(heap.injected_free_fails, 4);
+    }
+
+    #[test]
+    fn delayed_frees() {
+        let mut heap = Faulty::new(FreeList::new(0, 100, true), 0).delay_frees(2);
+        let ptr = heap.malloc(10).unwrap();
+        assert!(heap.free(ptr).is_ok());
+        assert!(heap.free(ptr).is_err());
+        assert_eq!(heap.free_space(), 90);
+
+        // Due two ops after the free, so it lands just before this malloc
+        let again = heap.malloc(10).unwrap();
+        assert_eq!(again, ptr);
+        assert_eq!(heap.free_space(), 90);
+
+        assert!(heap.free(again).is_ok());
+        let list = heap.into_inner();
+        assert_eq!(list.free_space(), 100);
+    }
+
+    #[test]
+    fn warm_free_fails() {
+        // Every free fails, so the checkerboard stays full
+        let heap = Faulty::new(FreeList::new(0, 320, true), 0).free_failures(1.0);
+        let warm = Warm::checkerboard(heap, 32);
+        assert_eq!((warm.live.len(), warm.free_fails), (10, 5));
+        assert_eq!(warm.allocator.free_space(), 0);
+
+        // What is left in live is exactly what is still allocated
+        let heap = Faulty::new(FreeList::new(0, 1024, true), 0).free_failures(0.5);
+        let warm = Warm::aged(heap, 200, 0.5, 8..=32, &mut StdRng::seed_from_u64(1));
+        assert!(warm.free_fails > 0);
+        assert_eq!(warm.free_fails, warm.allocator.injected_free_fails);
+        let mut list = warm.allocator.into_inner();
+        for &ptr in warm.live.iter() {
+            assert!(list.free(ptr).is_ok());
+        }
+        assert_eq!(list.free_space(), 1024);
+
+        // A stack only frees its top, and aging frees anything
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut warm = Warm::aged(StackAllocator::new(0, 4096), 200, 0.6, 8..=32, &mut rng);
+        assert!(warm.free_fails > 0);
+        warm.live.sort_unstable();
+        for &ptr in warm.live.iter().rev() {
+            assert!(warm.allocator.free(ptr).is_ok());
+        }
+        assert_eq!(warm.allocator.free_space(), 4096);
+    }
+}
@@ -1,6 +1,13 @@
-use crate::{Allocator, Policy};
+use crate::aligned::AlignmentLoss;
+use crate::segtree::FreeIndex;
+use crate::wcet::WorstCase;
+use crate::{AllocError, AllocFailure, Allocator, Churn, FreeRangeError, Policy};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Write};
 
 #[derive(Debug, Copy, Clone)]
 struct FreeNode {
@@ -14,14 +21,44 @@ impl FreeNode {
     }
 }
 
+// Knuth's boundary tags, kept for the free blocks only. The header
+// of a block is found by its start address and gives its index in
+// the list, and the footer by its end address gives its start
+#[derive(Debug, Clone)]
+struct Tags {
+    overhead: usize,
+    heads: HashMap<usize, usize>,
+    feet: HashMap<usize, usize>,
+}
+
 #[derive(Debug, Clone)]
 pub struct FreeList {
+    base_addr: usize,
     max_size: usize,
     align: usize,
     policy: Policy,
     coalesce: bool,
     freelist: Vec<FreeNode>,
-    sizemap: HashMap<usize, (usize, usize)>,
+    sizemap: BTreeMap<usize, (usize, usize)>,
+    // Alignment and leading padding of each allocation made by
+    // `malloc_aligned`. The padding sits before the address, and
+    // isn't in the size the sizemap has
+    aligned: BTreeMap<usize, (usize, usize)>,
+    // Index the last malloc started searching from, and how
+    // many nodes it looked at
+    last_scan: (usize, usize),
+    // Where the last malloc ended, so next fit can pick up from
+    // there. An address instead of an index stays valid whatever
+    // frees and coalescing do to the list
+    rover: usize,
+    tags: Option<Tags>,
+    // Kept in step with every malloc and free once `indexed`
+    index: Option<FreeIndex>,
+    // Size and address of every free node, once `by_size`
+    sizes: Option<BTreeSet<(usize, usize)>>,
+    // Picks the block for random fit, seeded again on a reset
+    rng: StdRng,
+    churn: Churn,
 }
 
 impl FreeList {
@@ -32,12 +69,21 @@ impl FreeList {
         let freelist = vec![FreeNode::new(base_addr, max_size)];
 
         FreeList {
+            base_addr,
             max_size,
             align: 0,
             policy: Policy::Best,
             coalesce,
             freelist,
-            sizemap: HashMap::new(),
+            sizemap: BTreeMap::new(),
+            aligned: BTreeMap::new(),
+            last_scan: (0, 0),
+            rover: base_addr,
+            tags: None,
+            index: None,
+            sizes: None,
+            rng: StdRng::seed_from_u64(0),
+            churn: Churn::default(),
         }
     }
 
@@ -48,29 +94,426 @@ impl FreeList {
 
     pub fn policy(mut self, policy: Policy) -> Self {
         self.policy = policy;
+        self.reseed();
         self
     }
 
-    // For this to even work well, the freelist
-    // needs to be sorted by address
-    fn coalesce(&mut self) {
-        let mut newlist = Vec::new();
-        let mut curr = self.freelist[0];
+    /// Where the heap starts
+    pub fn start(&self) -> usize {
+        self.base_addr
+    }
+
+    /// Bytes it manages, from `start`
+    pub fn size(&self) -> usize {
+        self.max_size
+    }
+
+    // Start random fit over from its seed
+    fn reseed(&mut self) {
+        if let Policy::Random { seed } = self.policy {
+            self.rng = StdRng::seed_from_u64(seed);
+        }
+    }
+
+    /// Give every block a header and footer taking up `overhead`
+    /// bytes between them, counted as internal fragmentation. A free
+    /// then finds its free neighbours from their tags and merges
+    /// with them in O(1), instead of sorting the list. The list is
+    /// no longer kept in address order: freed blocks go on the end,
+    /// and first fit takes the oldest free block that fits
+    pub fn boundary_tags(mut self, overhead: usize) -> Self {
+        self.tags = Some(Tags {
+            overhead,
+            heads: HashMap::new(),
+            feet: HashMap::new(),
+        });
+        self.retag();
+        self
+    }
+
+    /// Keep an index of the free space in `granule` byte pieces up to
+    /// date as the list changes, so `malloc_in_range` can turn down
+    /// windows without enough free space without searching
+    pub fn indexed(mut self, granule: usize) -> Self {
+        self.index = Some(self.free_index(granule));
+        self
+    }
+
+    /// Keep the free nodes ordered by size as well as in the list, so
+    /// best fit finds its node in O(log n) instead of scanning every
+    /// node. Of the nodes that fit best, it takes the one at the
+    /// highest address, as the scan of an address ordered list would
+    pub fn by_size(mut self) -> Self {
+        self.sizes = Some(BTreeSet::new());
+        self.reindex();
+        self
+    }
 
-        for node in self.freelist[1..].iter() {
-            if node.addr == curr.addr + curr.size {
-                curr = FreeNode::new(curr.addr, curr.size + node.size);
+    /// Like `malloc`, but the whole block has to lie in [lo, hi), for
+    /// memory that has to come from a window of the address space,
+    /// like DMA buffers or memory local to a NUMA node. Takes the
+    /// lowest address in the window that fits, splitting the free
+    /// block on either side as needed
+    pub fn malloc_in_range(&mut self, size: usize, lo: usize, hi: usize) -> Option<usize> {
+        let need = match self.block_size(size) {
+            Some(need) if size > 0 => need,
+            _ => {
+                self.churn.mallocs += 1;
+                return None;
+            }
+        };
+        let enough = self
+            .index
+            .as_ref()
+            .is_none_or(|index| index.free_bytes(lo..hi) >= need);
+
+        let addr = if !enough {
+            None
+        } else {
+            // In address order, the nodes that end after lo are all
+            // after the ones that don't, and the search can stop at hi
+            let sorted = self.tags.is_none();
+            let first = if sorted {
+                self.freelist
+                    .partition_point(|node| node.addr + node.size <= lo)
             } else {
-                newlist.push(curr);
-                curr = *node;
+                0
+            };
+            self.freelist[first..]
+                .iter()
+                .take_while(|node| !sorted || node.addr < hi)
+                .map(|node| (node.addr.max(lo), (node.addr + node.size).min(hi)))
+                .filter(|&(start, end)| start + need <= end)
+                .map(|(start, _)| start)
+                .min()
+        };
+
+        match addr {
+            Some(addr) => self.malloc_at(addr, size),
+            None => {
+                self.churn.mallocs += 1;
+                None
             }
         }
+    }
 
-        newlist.push(curr);
-        self.freelist = newlist;
+    /// Allocate memory at exactly `addr`. Returns None if any
+    /// part of the (aligned) range is not free
+    pub fn malloc_at(&mut self, addr: usize, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
+        if size == 0 {
+            return None;
+        }
+
+        let need = self.block_size(size)?;
+        let (size, diff) = (need, need - size);
+
+        let i = self
+            .freelist
+            .iter()
+            .position(|node| node.addr <= addr && addr + size <= node.addr + node.size)?;
+        let node = self.freelist.remove(i);
+        if node.size > size {
+            self.churn.splits += 1;
+        }
+
+        // Keep whatever is left on either side, in address order
+        let end = addr + size;
+        if end < node.addr + node.size {
+            self.freelist
+                .insert(i, FreeNode::new(end, node.addr + node.size - end));
+        }
+        if node.addr < addr {
+            self.freelist
+                .insert(i, FreeNode::new(node.addr, addr - node.addr));
+        }
+        // Rare enough not to bother keeping the tags up as we go
+        self.retag();
+        self.reindex();
+        if let Some(index) = self.index.as_mut() {
+            index.set_used(addr, size);
+        }
+
+        self.sizemap.insert(addr, (size, diff));
+        Some(addr)
+    }
+
+    /// Address and size of each free node, in list order. That is
+    /// address order unless boundary tags are on
+    pub(crate) fn free_nodes(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.freelist.iter().map(|node| (node.addr, node.size))
+    }
+
+    /// Address and size of each free block, in address order, for
+    /// tools that draw or check the heap
+    pub fn free_blocks(&self) -> impl Iterator<Item = (usize, usize)> {
+        let mut blocks: Vec<(usize, usize)> = self.free_nodes().collect();
+        blocks.sort_unstable();
+        blocks.into_iter()
+    }
+
+    /// Address and size of each allocation, padding included, in
+    /// address order. The padding `malloc_aligned` leaves before an
+    /// address is in neither these nor the free blocks
+    pub fn allocated_blocks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.sizemap.iter().map(|(&addr, &(size, _))| (addr, size))
+    }
+
+    /// How the free space splits up for a request of `size` bytes
+    /// that has to start at a multiple of `align`
+    pub fn alignment_loss(&self, size: usize, align: usize) -> AlignmentLoss {
+        AlignmentLoss::of(self.free_nodes(), size, align)
+    }
+
+    /// An index of the free space in `granule` byte pieces, for
+    /// queries on part of the heap. It is a snapshot, and does not
+    /// follow later mallocs and frees
+    pub fn free_index(&self, granule: usize) -> FreeIndex {
+        FreeIndex::from_free(self.base_addr, self.max_size, granule, self.free_nodes())
+    }
+
+    /// Slide live allocations down to merge the free space between
+    /// them, the way a compacting collector or a device memory
+    /// defragmenter would. In address order, every allocation not in
+    /// `pinned` moves to the lowest free address below it where it
+    /// fits, or that it can slide down to. Pinned allocations stay
+    /// put, and the rest move around them. Free space is merged as
+    /// it goes, even on a list that doesn't coalesce. Returns every
+    /// move as old and new address. Moves count as splits and
+    /// merges, but not as mallocs
+    pub fn compact(&mut self, pinned: &BTreeSet<usize>) -> Vec<(usize, usize)> {
+        self.compact_budget(pinned, usize::MAX)
+    }
+
+    /// Like `compact`, but stops once it has moved `budget` bytes,
+    /// like kcompactd doing a bounded amount of work every time it
+    /// wakes up. Allocations already packed at the bottom don't move
+    /// again, so calling it over and over picks up where it left off
+    pub fn compact_budget(
+        &mut self,
+        pinned: &BTreeSet<usize>,
+        budget: usize,
+    ) -> Vec<(usize, usize)> {
+        let live: Vec<(usize, (usize, usize))> = self
+            .sizemap
+            .iter()
+            .map(|(&addr, &block)| (addr, block))
+            .collect();
+        let mallocs = self.churn.mallocs;
+        let mut moves = Vec::new();
+        let mut moved = 0;
+
+        for (addr, (size, diff)) in live {
+            if moved >= budget {
+                break;
+            }
+            // Moving an aligned allocation would lose its alignment
+            if pinned.contains(&addr) || self.aligned.contains_key(&addr) {
+                continue;
+            }
+            let to = self
+                .freelist
+                .iter()
+                .filter(|node| {
+                    node.addr < addr && (node.size >= size || node.addr + node.size == addr)
+                })
+                .map(|node| node.addr)
+                .min();
+            let Some(to) = to else {
+                continue;
+            };
+
+            self.sizemap.remove(&addr);
+            self.release(addr, size);
+            if !self.coalesce {
+                Allocator::coalesce(self);
+            }
+            self.malloc_at(to, size - diff);
+            moves.push((addr, to));
+            moved += size;
+        }
+
+        self.churn.mallocs = mallocs;
+        moves
+    }
+
+    /// Where the last malloc started searching the freelist, and
+    /// how many free nodes it looked at
+    pub fn last_scan(&self) -> (usize, usize) {
+        self.last_scan
+    }
+
+    /// The freelist with the search start of the last malloc marked
+    /// `^`, and the other nodes it scanned marked `*`
+    pub fn render_scan(&self) -> String {
+        let mut out = self.render();
+
+        let len = self.freelist.len();
+        let (start, scanned) = self.last_scan;
+        let mut markers = String::new();
+        for i in 0..len {
+            // Scans wrap around the end of the list
+            let scanned = (i + len - start) % len < scanned;
+            let marker = match (i == start, scanned) {
+                (true, _) => "^",
+                (false, true) => "*",
+                (false, false) => "",
+            };
+            write!(
+                markers,
+                "{:^w$}{:<5}",
+                marker,
+                "",
+                w = self.node_width() + 10
+            )
+            .unwrap();
+        }
+        writeln!(out, "{}", markers.trim_end()).unwrap();
+        writeln!(out, "Last malloc scanned {} nodes", scanned).unwrap();
+        out
+    }
+
+    pub fn print_scan(&self) {
+        print!("{}", self.render_scan())
+    }
+
+    // Digits in the widest address or size in the freelist, and at
+    // least 4, so every node is drawn the same size
+    fn node_width(&self) -> usize {
+        self.freelist
+            .iter()
+            .map(|node| node.addr.max(node.size).to_string().len())
+            .max()
+            .unwrap_or(0)
+            .max(4)
+    }
+
+    // Bytes a request takes up, with its boundary tags and padded to
+    // the alignment, or None if that overflows
+    fn block_size(&self, size: usize) -> Option<usize> {
+        let size = size.checked_add(self.tags.as_ref().map_or(0, |tags| tags.overhead))?;
+        if self.align > 1 {
+            size.checked_next_multiple_of(self.align)
+        } else {
+            Some(size)
+        }
+    }
+
+    // Rebuild the tags of every free block from the list
+    fn retag(&mut self) {
+        if let Some(tags) = self.tags.as_mut() {
+            tags.heads.clear();
+            tags.feet.clear();
+            for (i, node) in self.freelist.iter().enumerate() {
+                tags.heads.insert(node.addr, i);
+                tags.feet.insert(node.addr + node.size, node.addr);
+            }
+        }
+    }
+
+    // Rebuild the size index from the list
+    fn reindex(&mut self) {
+        if let Some(sizes) = self.sizes.as_mut() {
+            sizes.clear();
+            sizes.extend(self.freelist.iter().map(|node| (node.size, node.addr)));
+        }
     }
 
-    fn best(&self, size: usize) -> Option<usize> {
+    // Put node `i` back in its place after it shrank from the front
+    fn replace(&mut self, i: usize, node: FreeNode) {
+        if let Some(sizes) = self.sizes.as_mut() {
+            let old = self.freelist[i];
+            sizes.remove(&(old.size, old.addr));
+            sizes.insert((node.size, node.addr));
+        }
+        if let Some(tags) = self.tags.as_mut() {
+            tags.heads.remove(&self.freelist[i].addr);
+            tags.heads.insert(node.addr, i);
+            tags.feet.insert(node.addr + node.size, node.addr);
+        }
+        self.freelist[i] = node;
+    }
+
+    // Take node `i` off the list. With boundary tags, the last node
+    // moves into its place so that this is O(1)
+    fn take(&mut self, i: usize) -> FreeNode {
+        if let Some(sizes) = self.sizes.as_mut() {
+            let node = self.freelist[i];
+            sizes.remove(&(node.size, node.addr));
+        }
+        let Some(tags) = self.tags.as_mut() else {
+            return self.freelist.remove(i);
+        };
+        let node = self.freelist.swap_remove(i);
+        tags.heads.remove(&node.addr);
+        tags.feet.remove(&(node.addr + node.size));
+        if let Some(moved) = self.freelist.get(i) {
+            tags.heads.insert(moved.addr, i);
+        }
+        node
+    }
+
+    // Put a range back on the freelist. With boundary tags, the
+    // neighbours' tags say whether they are free, so merging with
+    // them takes no search
+    fn release(&mut self, mut addr: usize, mut size: usize) {
+        if let Some(index) = self.index.as_mut() {
+            index.set_free(addr, size);
+        }
+        if let Some(tags) = self.tags.as_ref() {
+            let (prev, next) = if self.coalesce {
+                (
+                    tags.feet.get(&addr).map(|start| tags.heads[start]),
+                    tags.heads.get(&(addr + size)).copied(),
+                )
+            } else {
+                (None, None)
+            };
+            // Take the higher index first, so the other stays put
+            let mut neighbours: Vec<usize> = prev.into_iter().chain(next).collect();
+            neighbours.sort_unstable();
+            for i in neighbours.into_iter().rev() {
+                let node = self.take(i);
+                addr = addr.min(node.addr);
+                size += node.size;
+                self.churn.merges += 1;
+            }
+
+            let tags = self.tags.as_mut().unwrap();
+            tags.heads.insert(addr, self.freelist.len());
+            tags.feet.insert(addr + size, addr);
+            if let Some(sizes) = self.sizes.as_mut() {
+                sizes.insert((size, addr));
+            }
+            self.freelist.push(FreeNode::new(addr, size));
+            return;
+        }
+
+        if let Some(sizes) = self.sizes.as_mut() {
+            sizes.insert((size, addr));
+        }
+        self.freelist.push(FreeNode::new(addr, size));
+        self.freelist.sort_unstable_by_key(|node| node.addr);
+
+        // Coalesce if the flag is set
+        if self.coalesce {
+            self.coalesce();
+        }
+    }
+
+    // Returns the index of the best node, and how many nodes
+    // were looked at to find it
+    fn best(&self, size: usize) -> (Option<usize>, usize) {
+        if let Some(sizes) = self.sizes.as_ref() {
+            return match sizes.range((size, 0)..).next() {
+                Some(&(best, _)) => {
+                    let &(_, addr) = sizes.range(..=(best, usize::MAX)).next_back().unwrap();
+                    (Some(self.position(addr)), 1)
+                }
+                None => (None, 0),
+            };
+        }
+
         let mut bestsize = self.max_size;
         let mut idx: Option<usize> = None;
 
@@ -81,60 +524,133 @@ impl FreeList {
             }
         }
 
-        idx
+        (idx, self.freelist.len())
+    }
+
+    // Index of the free node at `addr`
+    fn position(&self, addr: usize) -> usize {
+        match self.tags.as_ref() {
+            Some(tags) => tags.heads[&addr],
+            None => self
+                .freelist
+                .binary_search_by_key(&addr, |node| node.addr)
+                .unwrap(),
+        }
     }
 
-    fn first(&self, size: usize) -> Option<usize> {
+    fn first(&self, size: usize) -> (Option<usize>, usize) {
         for (i, node) in self.freelist.iter().enumerate() {
             if size <= node.size {
-                return Some(i);
+                return (Some(i), i + 1);
             }
         }
-        None
+        (None, self.freelist.len())
     }
 
-    fn check_size(&self, mut size: usize) -> bool {
-        if self.align > 1 {
-            let left = size % self.align;
-            if left != 0 {
-                size += self.align - left;
-            }
+    // Every node has to be looked at to know which ones fit
+    fn random(&mut self, size: usize) -> (Option<usize>, usize) {
+        let fits: Vec<usize> = (0..self.freelist.len())
+            .filter(|&i| size <= self.freelist[i].size)
+            .collect();
+        let idx = match fits.len() {
+            0 => None,
+            n => Some(fits[self.rng.gen_range(0..n)]),
+        };
+        (idx, self.freelist.len())
+    }
+
+    fn bounded(&self, size: usize, budget: usize, fallback: bool) -> (Option<usize>, usize) {
+        let scanned = budget.min(self.freelist.len());
+        if let Some(i) = self.freelist[..scanned]
+            .iter()
+            .position(|node| size <= node.size)
+        {
+            return (Some(i), i + 1);
         }
+        let largest = (0..self.freelist.len())
+            .max_by_key(|&i| self.freelist[i].size)
+            .filter(|&i| fallback && size <= self.freelist[i].size);
+        (largest, scanned)
+    }
 
-        let idx = match self.policy {
-            Policy::Best => self.best(size),
-            Policy::First => self.first(size),
-        };
+    // The first node that isn't wholly below the rover, or the head
+    // of the list if the rover is past every node
+    fn next_start(&self) -> usize {
+        self.freelist
+            .iter()
+            .position(|node| node.addr + node.size > self.rover)
+            .unwrap_or(0)
+    }
 
-        idx.is_some()
+    fn next(&self, size: usize, start: usize) -> (Option<usize>, usize) {
+        let len = self.freelist.len();
+        for scanned in 0..len {
+            let i = (start + scanned) % len;
+            if size <= self.freelist[i].size {
+                return (Some(i), scanned + 1);
+            }
+        }
+        (None, len)
     }
-}
 
-impl Allocator for FreeList {
-    fn malloc(&mut self, mut size: usize) -> Option<usize> {
-        let mut diff = 0;
-        if self.align > 1 {
-            let left = size % self.align;
-            if left != 0 {
-                diff = self.align - left;
-                size += diff;
+    // Returns the index the search started from, the index of the
+    // node it picked, and how many nodes it looked at
+    fn search(&mut self, size: usize) -> (usize, Option<usize>, usize) {
+        match self.policy {
+            Policy::Best => {
+                let (idx, scanned) = self.best(size);
+                (0, idx, scanned)
+            }
+            Policy::First => {
+                let (idx, scanned) = self.first(size);
+                (0, idx, scanned)
+            }
+            Policy::Next => {
+                let start = self.next_start();
+                let (idx, scanned) = self.next(size, start);
+                (start, idx, scanned)
+            }
+            Policy::Bounded { budget, fallback } => {
+                let (idx, scanned) = self.bounded(size, budget, fallback);
+                (0, idx, scanned)
+            }
+            Policy::Random { .. } => {
+                let (idx, scanned) = self.random(size);
+                (0, idx, scanned)
             }
         }
+    }
+}
 
-        let idx = match self.policy {
-            Policy::Best => self.best(size),
-            Policy::First => self.first(size),
+impl Allocator for FreeList {
+    /// Nothing is handed out for 0 bytes, since the block would have
+    /// the same address as whatever is malloced next
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
+        let need = match self.block_size(size) {
+            Some(need) if size > 0 => need,
+            _ => return None,
         };
+        let (size, diff) = (need, need - size);
+
+        let (start, idx, scanned) = self.search(size);
+        self.last_scan = (start, scanned);
+        self.churn.scanned += scanned;
 
         if let Some(i) = idx {
             let node = self.freelist[i];
             self.sizemap.insert(node.addr, (size, diff));
+            if let Some(index) = self.index.as_mut() {
+                index.set_used(node.addr, size);
+            }
+            self.rover = node.addr + size;
             match size.cmp(&node.size) {
                 Ordering::Equal => {
-                    self.freelist.remove(i);
+                    self.take(i);
                 }
                 Ordering::Less => {
-                    self.freelist[i] = FreeNode::new(node.addr + size, node.size - size);
+                    self.churn.splits += 1;
+                    self.replace(i, FreeNode::new(node.addr + size, node.size - size));
                 }
                 Ordering::Greater => panic!("Not possible"),
             }
@@ -145,73 +661,296 @@ impl Allocator for FreeList {
         None
     }
 
-    fn free(&mut self, ptr: usize) -> Result<(), &str> {
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let needed = match self.block_size(size) {
+            Some(needed) if needed <= self.max_size => needed,
+            _ => return Err(AllocFailure::Oversized),
+        };
+        self.malloc(size).ok_or(if self.free_space() < needed {
+            AllocFailure::Exhausted
+        } else {
+            AllocFailure::NoFit
+        })
+    }
+
+    /// Takes the first node, or with best fit the smallest, or with
+    /// random fit any one, that has room for the request at an
+    /// aligned address inside it. The node is split after the
+    /// request, and the bytes before the aligned address stay with
+    /// the allocation as padding, counted as internal fragmentation.
+    /// An alignment of 1 is a plain `malloc`. The list's own
+    /// alignment only pads sizes, so it doesn't make any other
+    /// alignment a plain `malloc`
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        if !align.is_power_of_two() {
+            self.churn.mallocs += 1;
+            return None;
+        }
+        if align == 1 {
+            return self.malloc(size);
+        }
+        self.churn.mallocs += 1;
+        if size == 0 {
+            return None;
+        }
+
+        let need = self
+            .block_size(size)
+            .filter(|&need| need <= self.max_size)?;
+        let diff = need - size;
+        let mut fits = self
+            .freelist
+            .iter()
+            .enumerate()
+            .filter_map(|(i, node)| Some((i, node, node.addr.checked_next_multiple_of(align)?)))
+            .filter(|&(_, node, start)| start + need <= node.addr + node.size);
+        let (found, scanned) = match self.policy {
+            Policy::Best => (
+                fits.min_by_key(|&(_, node, _)| node.size),
+                self.freelist.len(),
+            ),
+            Policy::Random { .. } => (
+                fits.collect::<Vec<_>>().choose(&mut self.rng).copied(),
+                self.freelist.len(),
+            ),
+            _ => {
+                let found = fits.next();
+                (found, found.map_or(self.freelist.len(), |(i, _, _)| i + 1))
+            }
+        };
+        self.last_scan = (0, scanned);
+        self.churn.scanned += scanned;
+        let (i, &node, start) = found?;
+
+        let end = start + need;
+        if let Some(index) = self.index.as_mut() {
+            index.set_used(node.addr, end - node.addr);
+        }
+        self.rover = end;
+        if end < node.addr + node.size {
+            self.churn.splits += 1;
+            self.replace(i, FreeNode::new(end, node.addr + node.size - end));
+        } else {
+            self.take(i);
+        }
+
+        self.sizemap.insert(start, (need, diff));
+        self.aligned.insert(start, (align, start - node.addr));
+        Some(start)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
         // Get the size from the sizemap, remove it
         // from map if exist else, return err
-        let (size, _) = self.sizemap.remove(&ptr).ok_or("Pointer not found")?;
+        let (size, _) = self
+            .sizemap
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        let lead = self.aligned.remove(&ptr).map_or(0, |(_, lead)| lead);
 
         // insert back
-        self.freelist.push(FreeNode::new(ptr, size));
-        self.freelist.sort_unstable_by_key(|node| node.addr);
+        self.release(ptr - lead, size + lead);
 
-        // Coalesce if the flag is set
-        if self.coalesce {
-            self.coalesce()
+        Ok(())
+    }
+
+    fn free_range(&mut self, addr: usize, len: usize) -> Result<(), FreeRangeError> {
+        let (&start, &(size, diff)) = self
+            .sizemap
+            .range(..=addr)
+            .next_back()
+            .filter(|(&start, &(size, _))| addr < start + size)
+            .ok_or(FreeRangeError::NotAllocated)?;
+        let end = start + size;
+        if len == 0 || addr + len > end {
+            return Err(FreeRangeError::OutOfBounds);
         }
 
+        // Alignment padding is at the end of the allocation, so
+        // each piece left over keeps the part of it that it covers
+        let pad_start = end - diff;
+        let padding = |lo: usize, hi: usize| hi.saturating_sub(lo.max(pad_start));
+
+        self.sizemap.remove(&start);
+        self.churn.splits += 1;
+        if start < addr {
+            self.sizemap
+                .insert(start, (addr - start, padding(start, addr)));
+        }
+        if addr + len < end {
+            self.sizemap
+                .insert(addr + len, (end - addr - len, padding(addr + len, end)));
+        }
+
+        // Leading padding goes with the first piece, or back with
+        // the range if that was the first piece
+        match self.aligned.remove(&start) {
+            Some(aligned) if start < addr => {
+                self.aligned.insert(start, aligned);
+                self.release(addr, len);
+            }
+            Some((_, lead)) => self.release(addr - lead, len + lead),
+            None => self.release(addr, len),
+        }
+        Ok(())
+    }
+
+    fn shrink(&mut self, ptr: usize, new_size: usize) -> Result<usize, FreeRangeError> {
+        let &(size, diff) = self.sizemap.get(&ptr).ok_or(FreeRangeError::NotAllocated)?;
+        if new_size == 0 || new_size > size - diff {
+            return Err(FreeRangeError::OutOfBounds);
+        }
+
+        // No bigger than the block it shrinks, so it can't overflow
+        let kept = self.block_size(new_size).unwrap();
+        let new_diff = kept - new_size;
+        self.sizemap.insert(ptr, (kept, new_diff));
+        if kept < size {
+            self.churn.splits += 1;
+            self.release(ptr + kept, size - kept);
+        }
+
+        Ok(size - kept)
+    }
+
+    /// The padded size. The leading padding of an aligned
+    /// allocation is before `ptr`, so it isn't counted
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.sizemap.get(&ptr).map(|&(size, _)| size)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.freelist = vec![FreeNode::new(self.base_addr, self.max_size)];
+        self.sizemap.clear();
+        self.aligned.clear();
+        self.last_scan = (0, 0);
+        self.rover = self.base_addr;
+        self.churn = Churn::default();
+        self.reseed();
+        self.retag();
+        self.reindex();
+        if let Some(index) = self.index.as_mut() {
+            index.set_free(self.base_addr, self.max_size);
+        }
         Ok(())
     }
 
+    /// The biggest request the largest free block takes once padded
+    /// and tagged, out of the blocks a bounded search without a
+    /// fallback gets to look at
     fn largest_alloc(&self) -> usize {
-        (1..=self.max_size + 1)
-            .into_iter()
-            .find(|&x| !self.check_size(x))
-            .unwrap()
-            - 1
+        let nodes = match self.policy {
+            Policy::Bounded {
+                budget,
+                fallback: false,
+            } => &self.freelist[..budget.min(self.freelist.len())],
+            _ => &self.freelist[..],
+        };
+        let largest = nodes.iter().map(|node| node.size).max().unwrap_or(0);
+        let overhead = self.tags.as_ref().map_or(0, |tags| tags.overhead);
+        (largest - largest % self.align.max(1)).saturating_sub(overhead)
     }
 
     fn free_space(&self) -> usize {
         self.freelist.iter().map(|node| node.size).sum()
     }
 
+    /// Padding at the end of every allocation, and before the
+    /// aligned ones
     fn internal_frag(&self) -> usize {
-        self.sizemap.iter().map(|(&_, &(_, diff))| diff).sum()
+        let leads: usize = self.aligned.values().map(|&(_, lead)| lead).sum();
+        self.sizemap
+            .iter()
+            .map(|(&_, &(_, diff))| diff)
+            .sum::<usize>()
+            + leads
     }
 
-    fn print(&self) {
-        let len = self.freelist.len();
+    fn churn(&self) -> Churn {
+        self.churn
+    }
 
-        for i in 0..len {
-            print!("\u{250c}{:\u{2500}<12}\u{2510}", "");
-            if i == len - 1 {
-                println!();
-            } else {
-                print!("{:<5}", "");
-            }
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.sizemap.values().map(|&(size, diff)| size - diff).sum())
+    }
+
+    // For this to even work well, the freelist
+    // needs to be sorted by address
+    fn coalesce(&mut self) -> usize {
+        if self.tags.is_some() {
+            self.freelist.sort_unstable_by_key(|node| node.addr);
         }
-        for i in 0..len {
-            print!("\u{2502} addr: {:<4} \u{2502}", self.freelist[i].addr);
-            if i == len - 1 {
-                println!();
-            } else {
-                print!(" --\\ ");
+        let mut merges = 0;
+        let mut newlist: Vec<FreeNode> = Vec::with_capacity(self.freelist.len());
+
+        for node in self.freelist.iter() {
+            match newlist.last_mut() {
+                Some(curr) if node.addr == curr.addr + curr.size => {
+                    if let Some(sizes) = self.sizes.as_mut() {
+                        sizes.remove(&(curr.size, curr.addr));
+                        sizes.remove(&(node.size, node.addr));
+                        sizes.insert((curr.size + node.size, curr.addr));
+                    }
+                    curr.size += node.size;
+                    merges += 1;
+                }
+                _ => newlist.push(*node),
             }
         }
-        for i in 0..len {
-            print!("\u{2502} size: {:<4} \u{2502}", self.freelist[i].size);
-            if i == len - 1 {
-                println!();
-            } else {
-                print!(" --/ ");
-            }
+
+        self.freelist = newlist;
+        self.retag();
+        self.churn.merges += merges;
+        merges
+    }
+
+    fn render(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// The free nodes as boxes in list order, each linked to the next
+impl fmt::Display for FreeList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.node_width();
+        let edge = "\u{2500}".repeat(width + 8);
+        let row = |cell: &dyn Fn(&FreeNode) -> String, link: &str| {
+            let cells: Vec<String> = self.freelist.iter().map(cell).collect();
+            cells.join(link)
+        };
+
+        if self.freelist.is_empty() {
+            return Ok(());
         }
-        for i in 0..len {
-            print!("\u{2514}{:\u{2500}<12}\u{2518}", "");
-            if i == len - 1 {
-                println!();
-            } else {
-                print!("{:<5}", "");
-            }
+        let top = row(&|_| format!("\u{250c}{}\u{2510}", edge), "     ");
+        let addrs = row(
+            &|node| format!("\u{2502} addr: {:<w$} \u{2502}", node.addr, w = width),
+            " --\\ ",
+        );
+        let sizes = row(
+            &|node| format!("\u{2502} size: {:<w$} \u{2502}", node.size, w = width),
+            " --/ ",
+        );
+        let bottom = row(&|_| format!("\u{2514}{}\u{2518}", edge), "     ");
+        for line in [top, addrs, sizes, bottom] {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Only a search on a budget is bounded. A malloc then splits at most
+/// one block, and a free merges with at most its two neighbours
+impl WorstCase for FreeList {
+    fn worst_op(&self) -> Option<Churn> {
+        match self.policy {
+            Policy::Bounded { budget, .. } => Some(Churn {
+                splits: 1,
+                merges: if self.coalesce { 2 } else { 0 },
+                scanned: budget,
+                ..Churn::default()
+            }),
+            _ => None,
         }
     }
 }
@@ -261,6 +1000,112 @@ mod tests {
         assert_eq!(list.internal_frag(), 2);
         list.free(ptrs.pop().unwrap()).unwrap();
         assert_eq!(list.internal_frag(), 1);
+        assert_eq!(list.live_bytes(), Some(7));
+    }
+
+    #[test]
+    fn reset() {
+        let mut list = FreeList::new(10, 100, true)
+            .policy(Policy::Next)
+            .indexed(10)
+            .by_size();
+        let a = list.malloc(20).unwrap();
+        list.malloc_aligned(10, 32).unwrap();
+        list.free(a).unwrap();
+
+        list.reset().unwrap();
+        assert_eq!(list.free_space(), 100);
+        assert_eq!(list.largest_alloc(), 100);
+        assert_eq!(list.churn(), Churn::default());
+        assert_eq!(list.alloc_size(a), None);
+        // The rover and the index start over too
+        assert_eq!(list.malloc_in_range(100, 10, 110), Some(10));
+    }
+
+    #[test]
+    fn blocks() {
+        let mut list = FreeList::new(0, 100, true)
+            .policy(Policy::First)
+            .boundary_tags(0);
+        list.malloc(20).unwrap();
+        let b = list.malloc(30).unwrap();
+        list.malloc(10).unwrap();
+        list.free(b).unwrap();
+
+        // The tags put the list out of address order, the blocks aren't
+        assert_eq!(
+            list.free_blocks().collect::<Vec<_>>(),
+            vec![(20, 30), (60, 40)]
+        );
+        assert_eq!(
+            list.allocated_blocks().collect::<Vec<_>>(),
+            vec![(0, 20), (50, 10)]
+        );
+    }
+
+    #[test]
+    fn display() {
+        let mut list = FreeList::new(0, 100, true).policy(Policy::First);
+        let a = list.malloc(10).unwrap();
+        list.malloc(10).unwrap();
+        list.free(a).unwrap();
+
+        let drawing = format!("{}", list);
+        assert_eq!(drawing, list.render());
+        let lines: Vec<&str> = drawing.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("\u{2502} addr: 0    \u{2502} --\\ \u{2502} addr: 20"));
+        // Nothing free, nothing drawn
+        let mut full = FreeList::new(0, 10, true);
+        full.malloc(10).unwrap();
+        assert!(full.to_string().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let mut list = FreeList::new(0, 100, true).policy(Policy::First);
+        let a = list.checked_malloc(60).unwrap();
+        assert_eq!(
+            list.checked_malloc(50),
+            Err(AllocError::OutOfMemory {
+                requested: 50,
+                largest: 40
+            })
+        );
+        list.free(a).unwrap();
+        let err = list.free(a).unwrap_err();
+        assert_eq!(err, AllocError::InvalidPointer(0));
+        assert_eq!(err.to_string(), "Pointer 0 not found");
+    }
+
+    #[test]
+    fn malloc_aligned() {
+        let mut list = FreeList::new(0, 512, true).policy(Policy::First);
+        let a = list.malloc(10).unwrap();
+        // The 54 bytes before 64 go with the allocation
+        let b = list.malloc_aligned(100, 64).unwrap();
+        assert_eq!(b, 64);
+        assert_eq!(list.internal_frag(), 54);
+        assert_eq!(list.free_space(), 512 - 164);
+        assert_eq!(list.live_bytes(), Some(110));
+        assert!(list.malloc_aligned(8, 3).is_none());
+
+        // Freeing the front of it frees the padding too
+        list.free_range(b, 50).unwrap();
+        assert_eq!(list.internal_frag(), 0);
+        assert_eq!(list.free_space(), 512 - 10 - 50);
+        list.free(b + 50).unwrap();
+        list.free(a).unwrap();
+        assert_eq!(list.largest_alloc(), 512);
+
+        // Already aligned, so there is no padding
+        assert_eq!(list.malloc_aligned(100, 256), Some(0));
+        assert_eq!(list.internal_frag(), 0);
+
+        // Padding sizes to 4 doesn't put blocks at multiples of 4
+        let mut list = FreeList::new(1, 100, true).align(4);
+        assert_eq!(list.malloc_aligned(4, 4), Some(4));
+        assert_eq!(list.internal_frag(), 3);
     }
 
     #[test]
@@ -272,4 +1117,378 @@ mod tests {
         list.malloc(1);
         assert_eq!(list.largest_alloc(), 80);
     }
+
+    #[test]
+    fn malloc_at() {
+        let mut list = FreeList::new(1000, 100, false).align(4);
+        assert_eq!(list.malloc_at(1010, 6), Some(1010));
+        assert_eq!(list.free_space(), 92);
+        assert_eq!(list.freelist[0].addr, 1000);
+        assert_eq!(list.freelist[0].size, 10);
+        assert_eq!(list.freelist[1].addr, 1018);
+
+        // Overlaps the allocation, or runs off the end of the heap
+        assert!(list.malloc_at(1016, 4).is_none());
+        assert!(list.malloc_at(1000, 10).is_none());
+        assert!(list.malloc_at(1096, 8).is_none());
+
+        assert_eq!(list.malloc_at(1000, 8), Some(1000));
+        assert!(list.free(1010).is_ok());
+        assert_eq!(list.free_space(), 92);
+    }
+
+    #[test]
+    fn malloc_in_range() {
+        let mut list = FreeList::new(0, 1000, true)
+            .policy(Policy::First)
+            .indexed(50);
+        let a = list.malloc_in_range(100, 300, 600).unwrap();
+        assert_eq!(a, 300);
+        let nodes: Vec<(usize, usize)> = list.free_nodes().collect();
+        assert_eq!(nodes, vec![(0, 300), (400, 600)]);
+
+        // 50 bytes free on each side of the window, but not together
+        assert_eq!(list.malloc_in_range(100, 250, 420), None);
+        assert_eq!(list.malloc_in_range(50, 250, 420), Some(250));
+        list.free(a).unwrap();
+        assert_eq!(list.malloc_in_range(600, 0, 1000), Some(300));
+        assert_eq!(list.malloc_in_range(300, 0, 300), None);
+        assert_eq!(list.churn().mallocs, 5);
+
+        let index = list.index.as_ref().unwrap();
+        assert_eq!(index.free_bytes(0..1000), list.free_space());
+        assert_eq!(index.largest_free(0..1000), Some((0, 250)));
+    }
+
+    #[test]
+    fn free_range() {
+        let mut list = FreeList::new(1000, 100, true).align(4);
+        let ptr = list.malloc(14).unwrap();
+        assert_eq!(list.internal_frag(), 2);
+
+        assert_eq!(list.free_range(999, 1), Err(FreeRangeError::NotAllocated));
+        assert_eq!(list.free_range(1010, 8), Err(FreeRangeError::OutOfBounds));
+
+        // Punch a hole in the middle
+        assert!(list.free_range(1004, 4).is_ok());
+        assert_eq!(list.free_space(), 88);
+        assert_eq!(list.internal_frag(), 2);
+        assert!(list.free_range(1004, 1).is_err());
+
+        // The tail holds the padding, and frees on its own
+        assert!(list.free(1008).is_ok());
+        assert_eq!(list.internal_frag(), 0);
+        assert!(list.free(ptr).is_ok());
+        assert_eq!(list.free_space(), 100);
+        assert_eq!(list.freelist.len(), 1);
+    }
+
+    #[test]
+    fn shrink() {
+        let mut list = FreeList::new(1000, 100, false).align(4);
+        let ptr = list.malloc(30).unwrap();
+        assert_eq!(list.shrink(0, 10), Err(FreeRangeError::NotAllocated));
+        assert_eq!(list.shrink(ptr, 31), Err(FreeRangeError::OutOfBounds));
+
+        assert_eq!(list.shrink(ptr, 9), Ok(20));
+        assert_eq!(list.internal_frag(), 3);
+        assert_eq!(list.free_space(), 88);
+        assert_eq!(list.shrink(ptr, 9), Ok(0));
+
+        assert!(list.free(ptr).is_ok());
+        assert_eq!(list.free_space(), 100);
+    }
+
+    #[test]
+    fn last_scan() {
+        let mut list = FreeList::new(0, 100, false).policy(Policy::First);
+        let ptrs: Vec<usize> = (0..4).map(|_| list.malloc(10).unwrap()).collect();
+        list.free(ptrs[0]).unwrap();
+        list.free(ptrs[2]).unwrap();
+
+        // Freelist is now 0, 20, 40
+        list.malloc(20).unwrap();
+        assert_eq!(list.last_scan(), (0, 3));
+        list.malloc(5).unwrap();
+        assert_eq!(list.last_scan(), (0, 1));
+
+        let mut list = list.policy(Policy::Best);
+        list.malloc(5).unwrap();
+        assert_eq!(list.last_scan(), (0, 3));
+    }
+
+    #[test]
+    fn bounded() {
+        let mut list = FreeList::new(0, 100, false);
+        let ptrs: Vec<usize> = (0..4).map(|_| list.malloc(10).unwrap()).collect();
+        list.free(ptrs[0]).unwrap();
+        list.free(ptrs[2]).unwrap();
+
+        // Only the two small holes are in budget
+        let mut failing = list.clone().policy(Policy::Bounded {
+            budget: 2,
+            fallback: false,
+        });
+        assert_eq!(failing.largest_alloc(), 10);
+        assert_eq!(failing.malloc(20), None);
+        assert_eq!(failing.last_scan(), (0, 2));
+        assert_eq!(failing.malloc(5), Some(0));
+        assert_eq!(failing.last_scan(), (0, 1));
+
+        let mut falling_back = list.policy(Policy::Bounded {
+            budget: 2,
+            fallback: true,
+        });
+        assert_eq!(falling_back.malloc(20), Some(40));
+        assert_eq!(falling_back.last_scan(), (0, 2));
+        assert_eq!(falling_back.malloc(50), None);
+    }
+
+    #[test]
+    fn random_fit() {
+        // Leaves holes at 0, 40 and 80, then fills them, checking
+        // each malloc looked at every free block
+        let run = |list: &mut FreeList| -> Vec<usize> {
+            let ptrs: Vec<usize> = (0..5).map(|_| list.malloc(20).unwrap()).collect();
+            assert_eq!(ptrs, vec![0, 20, 40, 60, 80]);
+            for &ptr in [ptrs[0], ptrs[2], ptrs[4]].iter() {
+                list.free(ptr).unwrap();
+            }
+            assert_eq!(list.malloc(30), None);
+            (0..3)
+                .map(|_| {
+                    let holes = list.free_nodes().count();
+                    let ptr = list.malloc(20).unwrap();
+                    assert_eq!(list.last_scan(), (0, holes));
+                    ptr
+                })
+                .collect()
+        };
+
+        let mut list = FreeList::new(0, 100, false).policy(Policy::Random { seed: 7 });
+        let picked = run(&mut list);
+        let mut sorted = picked.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, vec![0, 40, 80]);
+
+        // The same seed picks the same holes, after a reset too
+        let fresh = FreeList::new(0, 100, false).policy(Policy::Random { seed: 7 });
+        assert_eq!(run(&mut fresh.clone()), picked);
+        list.reset().unwrap();
+        assert_eq!(run(&mut list), picked);
+    }
+
+    #[test]
+    fn next_fit() {
+        let first = FreeList::new(0, 100, false).policy(Policy::First);
+        let next = first.clone().policy(Policy::Next);
+        for mut list in vec![first, next].into_iter() {
+            let ptrs: Vec<usize> = (0..4).map(|_| list.malloc(10).unwrap()).collect();
+            list.free(ptrs[0]).unwrap();
+            list.free(ptrs[2]).unwrap();
+            // Freelist is now 0, 20, 40. First fit goes back to the
+            // head, next fit carries on from the last malloc
+            let ptr = list.malloc(5).unwrap();
+            match list.policy {
+                Policy::Next => assert_eq!((ptr, list.last_scan()), (40, (2, 1))),
+                _ => assert_eq!((ptr, list.last_scan()), (0, (0, 1))),
+            }
+        }
+
+        let mut list = FreeList::new(0, 100, false).policy(Policy::Next);
+        let ptrs: Vec<usize> = [10, 10, 10, 70]
+            .iter()
+            .map(|&size| list.malloc(size).unwrap())
+            .collect();
+        list.free(ptrs[0]).unwrap();
+        list.free(ptrs[2]).unwrap();
+
+        // The rover is past the end, so the search wraps to the head
+        assert_eq!(list.malloc(5), Some(0));
+        assert_eq!(list.malloc(8), Some(20));
+        assert_eq!(list.last_scan(), (0, 2));
+        // Freelist is now 5, 28. Starts at 28, which is too small,
+        // and wraps around to 5
+        assert_eq!(list.malloc(4), Some(5));
+        assert_eq!(list.last_scan(), (1, 2));
+    }
+
+    #[test]
+    fn next_fit_coalescing() {
+        let mut list = FreeList::new(0, 100, true).policy(Policy::Next);
+        let a = list.malloc(10).unwrap();
+        let b = list.malloc(10).unwrap();
+        list.free(b).unwrap();
+        // 10..20 merged with the rest of the heap, which the rover
+        // was pointing into, so the search starts from the merged node
+        assert_eq!(list.malloc(5), Some(10));
+        list.free(a).unwrap();
+        assert_eq!(list.malloc(5), Some(15));
+        assert_eq!(list.last_scan(), (1, 1));
+    }
+
+    #[test]
+    fn churn() {
+        let mut list = FreeList::new(0, 100, true);
+        let small = list.malloc(10).unwrap();
+        // Takes the rest of the node without splitting it
+        let rest = list.malloc(90).unwrap();
+        list.free(small).unwrap();
+        list.free(rest).unwrap();
+
+        assert_eq!(
+            list.churn(),
+            Churn {
+                mallocs: 2,
+                splits: 1,
+                merges: 1,
+                deferred: 0,
+                scanned: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn coalesce() {
+        let mut list = FreeList::new(0, 100, false);
+        let ptrs: Vec<usize> = (0..4).map(|_| list.malloc(25).unwrap()).collect();
+        for &ptr in ptrs.iter().take(3) {
+            list.free(ptr).unwrap();
+        }
+        assert_eq!(list.largest_alloc(), 25);
+        assert!(list.malloc(50).is_none());
+
+        assert_eq!(list.coalesce(), 2);
+        assert_eq!(list.churn().merges, 2);
+        assert_eq!(list.largest_alloc(), 75);
+        assert_eq!(list.coalesce(), 0);
+
+        list.free(ptrs[3]).unwrap();
+        assert_eq!(list.coalesce(), 1);
+        assert_eq!(list.malloc(100), Some(0));
+        // Nothing free at all
+        assert_eq!(list.coalesce(), 0);
+    }
+
+    #[test]
+    fn try_malloc() {
+        let mut list = FreeList::new(0, 100, true).align(8);
+        assert_eq!(list.try_malloc(97), Err(AllocFailure::Oversized));
+        // Padding these would overflow
+        assert_eq!(list.try_malloc(usize::MAX), Err(AllocFailure::Oversized));
+        assert_eq!(list.malloc(usize::MAX - 1), None);
+        assert_eq!(list.try_calloc(1, usize::MAX), Err(AllocFailure::Oversized));
+        assert_eq!(list.calloc(1, usize::MAX), None);
+        assert_eq!(list.malloc_aligned(usize::MAX, 64), None);
+        assert_eq!(list.free_space(), 100);
+
+        let ptrs: Vec<usize> = (0..3).map(|_| list.try_malloc(24).unwrap()).collect();
+        list.free(ptrs[1]).unwrap();
+        // 52 bytes are free, but in holes of 24 and 28
+        assert_eq!(list.try_malloc(25), Err(AllocFailure::NoFit));
+        assert_eq!(list.try_malloc(60), Err(AllocFailure::Exhausted));
+    }
+
+    #[test]
+    fn compact() {
+        let mut list = FreeList::new(0, 100, false).policy(Policy::First);
+        let ptrs: Vec<usize> = (0..4).map(|_| list.malloc(20).unwrap()).collect();
+        list.free(ptrs[0]).unwrap();
+        list.free(ptrs[2]).unwrap();
+
+        // Nothing fits below the last one but the hole at 0
+        let mut pinned = BTreeSet::new();
+        pinned.insert(ptrs[1]);
+        assert_eq!(list.clone().compact(&pinned), vec![(60, 0)]);
+
+        let moves = list.compact(&BTreeSet::new());
+        assert_eq!(moves, vec![(20, 0), (60, 20)]);
+        assert_eq!(list.free_nodes().collect::<Vec<_>>(), vec![(40, 60)]);
+        assert_eq!(list.churn().mallocs, 4);
+        assert!(list.free(20).is_ok());
+
+        // Each call moves one block of 20, then stops
+        let mut list = FreeList::new(0, 100, true).policy(Policy::First);
+        let ptrs: Vec<usize> = (0..4).map(|_| list.malloc(20).unwrap()).collect();
+        list.free(ptrs[0]).unwrap();
+        list.free(ptrs[2]).unwrap();
+        let pinned = BTreeSet::new();
+        assert_eq!(list.compact_budget(&pinned, 10), vec![(20, 0)]);
+        assert_eq!(list.compact_budget(&pinned, 10), vec![(60, 20)]);
+        assert!(list.compact_budget(&pinned, 10).is_empty());
+        assert_eq!(list.largest_alloc(), 60);
+    }
+
+    #[test]
+    fn boundary_tags() {
+        let mut list = FreeList::new(0, 1000, true)
+            .policy(Policy::First)
+            .boundary_tags(16);
+        let ptrs: Vec<usize> = (0..4).map(|_| list.malloc(10).unwrap()).collect();
+        assert_eq!(ptrs, vec![0, 26, 52, 78]);
+        assert_eq!(list.internal_frag(), 64);
+        assert_eq!(list.live_bytes(), Some(40));
+
+        // Freed blocks go on the end, and merge with both neighbours
+        list.free(ptrs[0]).unwrap();
+        list.free(ptrs[2]).unwrap();
+        list.free(ptrs[1]).unwrap();
+        let nodes: Vec<(usize, usize)> = list.free_nodes().collect();
+        assert_eq!(nodes, vec![(104, 896), (0, 78)]);
+        assert_eq!(list.churn().merges, 2);
+
+        // First fit goes by list order, not address
+        assert_eq!(list.malloc(10), Some(104));
+        list.free(ptrs[3]).unwrap();
+        list.free(104).unwrap();
+        assert_eq!(list.free_nodes().collect::<Vec<_>>(), vec![(0, 1000)]);
+        assert_eq!(list.churn().merges, 5);
+
+        let mut list = FreeList::new(0, 100, true).align(8).boundary_tags(8);
+        assert_eq!(list.try_malloc(93), Err(AllocFailure::Oversized));
+        assert_eq!(list.malloc(10), Some(0));
+        assert_eq!(list.internal_frag(), 14);
+        assert_eq!(list.largest_alloc(), 64);
+        assert_eq!(list.malloc(65), None);
+        assert!(list.malloc(64).is_some());
+    }
+
+    #[test]
+    fn by_size() {
+        use rand::prelude::*;
+
+        // The index has to pick the same node the scan does, through
+        // splits, frees, coalescing and shrinking
+        for coalesce in [true, false] {
+            let mut rng = StdRng::seed_from_u64(1);
+            let mut scan = FreeList::new(0, 4096, coalesce).align(8);
+            let mut index = scan.clone().by_size();
+            let mut ptrs = Vec::new();
+            for _ in 0..2000 {
+                if rng.gen_bool(0.6) {
+                    let size = rng.gen_range(1..=128);
+                    let ptr = scan.malloc(size);
+                    assert_eq!(index.malloc(size), ptr);
+                    ptrs.extend(ptr);
+                } else if !ptrs.is_empty() {
+                    let ptr = ptrs.swap_remove(rng.gen_range(0..ptrs.len()));
+                    if rng.gen_bool(0.2) {
+                        assert_eq!(scan.shrink(ptr, 1), index.shrink(ptr, 1));
+                        ptrs.push(ptr);
+                    } else {
+                        scan.free(ptr).unwrap();
+                        index.free(ptr).unwrap();
+                    }
+                }
+            }
+        }
+
+        let mut list = FreeList::new(0, 100, true).boundary_tags(0).by_size();
+        let a = list.malloc(40).unwrap();
+        list.malloc(10).unwrap();
+        list.free(a).unwrap();
+        // 50 bytes are free at the end, and 40 at the start fit best
+        assert_eq!(list.malloc(30), Some(0));
+        assert_eq!(list.malloc(45), Some(50));
+    }
 }
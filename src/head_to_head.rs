@@ -0,0 +1,186 @@
+use crate::workloads::Results;
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt::Write;
+
+/// Two allocators managing adjacent halves of one address space.
+/// The low one owns every address below `boundary` and the high one
+/// the rest. Each malloc is routed to the low allocator with
+/// probability `share`, and frees go to whichever owns the pointer.
+/// Both see the same stream of requests in the same run, so
+/// comparing them is free of run to run noise. A malloc that fails
+/// is not retried on the other side
+#[derive(Debug, Clone)]
+pub struct HeadToHead<A, B> {
+    low: A,
+    high: B,
+    boundary: usize,
+    share: f64,
+    rng: StdRng,
+    results: [Results; 2],
+}
+
+impl<A: Allocator, B: Allocator> HeadToHead<A, B> {
+    pub fn new(low: A, high: B, boundary: usize, seed: u64) -> Self {
+        Self {
+            low,
+            high,
+            boundary,
+            share: 0.5,
+            rng: StdRng::seed_from_u64(seed),
+            results: Default::default(),
+        }
+    }
+
+    /// Chance of a malloc going to the low allocator
+    pub fn share(mut self, share: f64) -> Self {
+        if !(0.0..=1.0).contains(&share) {
+            panic!("Share must be between 0 and 1");
+        }
+        self.share = share;
+        self
+    }
+
+    pub fn low(&self) -> &A {
+        &self.low
+    }
+
+    pub fn high(&self) -> &B {
+        &self.high
+    }
+
+    /// Failures, fragmentation and churn of the low and the high
+    /// allocator so far
+    pub fn results(&self) -> [Results; 2] {
+        let mut low = self.results[0].clone();
+        low.internal_frag = self.low.internal_frag();
+        low.external_frag = self.low.external_frag();
+        low.churn = self.low.churn();
+
+        let mut high = self.results[1].clone();
+        high.internal_frag = self.high.internal_frag();
+        high.external_frag = self.high.external_frag();
+        high.churn = self.high.churn();
+
+        [low, high]
+    }
+}
+
+impl<A: Allocator, B: Allocator> Allocator for HeadToHead<A, B> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let (side, result) = if self.rng.gen_bool(self.share) {
+            (0, self.low.try_malloc(size))
+        } else {
+            (1, self.high.try_malloc(size))
+        };
+        self.results[side].malloc(size, result);
+        result
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let (side, result) = if ptr < self.boundary {
+            (0, self.low.free(ptr))
+        } else {
+            (1, self.high.free(ptr))
+        };
+        if result.is_err() {
+            self.results[side].free_fails += 1;
+        }
+        result
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.low.largest_alloc().max(self.high.largest_alloc())
+    }
+
+    fn free_space(&self) -> usize {
+        self.low.free_space() + self.high.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.low.internal_frag() + self.high.internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        let (low, high) = (self.low.churn(), self.high.churn());
+        Churn {
+            mallocs: low.mallocs + high.mallocs,
+            splits: low.splits + high.splits,
+            merges: low.merges + high.merges,
+            deferred: low.deferred + high.deferred,
+            scanned: low.scanned + high.scanned,
+        }
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.low.live_bytes()? + self.high.live_bytes()?)
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.low.coalesce() + self.high.coalesce()
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Below {}:", self.boundary).unwrap();
+        out.push_str(&self.low.render());
+        writeln!(out, "From {}:", self.boundary).unwrap();
+        out.push_str(&self.high.render());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+    use crate::freelist::FreeList;
+
+    #[test]
+    fn routing() {
+        let heap = HeadToHead::new(
+            BuddyAllocator::new(5, 10),
+            FreeList::new(1024, 1024, true),
+            1024,
+            0,
+        );
+
+        let mut low_only = heap.clone().share(1.0);
+        let ptr = low_only.malloc(100).unwrap();
+        assert!(ptr < 1024);
+        assert_eq!(low_only.high().free_space(), 1024);
+
+        let mut high_only = heap.share(0.0);
+        let ptr = high_only.malloc(100).unwrap();
+        assert!(ptr >= 1024);
+        assert!(high_only.free(ptr).is_ok());
+        assert!(high_only.free(ptr).is_err());
+        assert_eq!(high_only.free_space(), 2048);
+    }
+
+    #[test]
+    fn results() {
+        let mut heap = HeadToHead::new(
+            BuddyAllocator::new(5, 10),
+            FreeList::new(1024, 1024, true),
+            1024,
+            0,
+        )
+        .share(1.0);
+        assert!(heap.malloc(100).is_some());
+        assert_eq!(heap.try_malloc(2000), Err(AllocFailure::Oversized));
+        assert!(heap.free(2000).is_err());
+
+        let [low, high] = heap.results();
+        assert_eq!(low.malloc_fails, 1);
+        assert_eq!(low.failures.oversized, 1);
+        assert_eq!(low.internal_frag, 28);
+        assert_eq!(high.free_fails, 1);
+        assert_eq!(high.malloc_fails, 0);
+    }
+}
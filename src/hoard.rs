@@ -0,0 +1,389 @@
+use crate::bins::size_classes;
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// Blocks of one size class, owned by one heap. An empty superblock
+// in the global heap has no class, and can be carved up again for
+// any
+#[derive(Debug, Clone)]
+struct Superblock {
+    owner: usize,
+    class: Option<usize>,
+    free: Vec<usize>,
+    used: usize,
+}
+
+/// A simulation of Hoard, after Berger et al. The heap is cut into
+/// superblocks, each holding blocks of one size class. Every logical
+/// thread has a heap of superblocks of its own, and the global heap
+/// holds the rest. A malloc takes a block from the fullest fitting
+/// superblock of its thread's heap, or moves one over from the
+/// global heap. A free goes back to the superblock's owner, and
+/// when a thread heap has more than `slack` superblocks' worth of
+/// free space and is less than `1 - empty_fraction` full, its
+/// emptiest superblock moves to the global heap. That bounds the
+/// blowup, memory held over memory live, at the cost of moving
+/// superblocks back and forth. Requests bigger than half a
+/// superblock are not supported
+#[derive(Debug, Clone)]
+pub struct HoardSim {
+    base: usize,
+    superblock: usize,
+    classes: Vec<usize>,
+    blocks: Vec<Superblock>,
+    thread: usize,
+    empty_fraction: f64,
+    slack: usize,
+    // Bytes in use in the superblocks of each heap, global first
+    used: Vec<usize>,
+    // Requested size, rounded size and thread of each live block
+    live: BTreeMap<usize, (usize, usize, usize)>,
+    peak_live: usize,
+    peak_held: usize,
+    /// Superblocks moved between a thread heap and the global heap
+    pub moves: usize,
+    churn: Churn,
+}
+
+impl HoardSim {
+    /// Manage [base, base + size) in superblocks of `superblock`
+    /// bytes for `threads` threads. What doesn't make up a whole
+    /// superblock is left out
+    pub fn new(base: usize, size: usize, threads: usize, superblock: usize) -> Self {
+        if threads == 0 || superblock < 32 || size < superblock {
+            panic!("Need a thread and a superblock of at least 32 bytes");
+        }
+
+        Self {
+            base,
+            superblock,
+            classes: size_classes(superblock / 2),
+            blocks: vec![
+                Superblock {
+                    owner: 0,
+                    class: None,
+                    free: Vec::new(),
+                    used: 0,
+                };
+                size / superblock
+            ],
+            thread: 0,
+            empty_fraction: 0.25,
+            slack: 1,
+            used: vec![0; threads + 1],
+            live: BTreeMap::new(),
+            peak_live: 0,
+            peak_held: 0,
+            moves: 0,
+            churn: Churn::default(),
+        }
+    }
+
+    /// How empty a thread heap has to get before it gives a
+    /// superblock back. 1 means never
+    pub fn empty_fraction(mut self, fraction: f64) -> Self {
+        self.empty_fraction = fraction;
+        self
+    }
+
+    /// Superblocks' worth of free space a thread heap keeps anyway
+    pub fn slack(mut self, slack: usize) -> Self {
+        self.slack = slack;
+        self
+    }
+
+    pub fn threads(&self) -> usize {
+        self.used.len() - 1
+    }
+
+    /// Make later mallocs on behalf of this thread
+    pub fn switch_thread(&mut self, thread: usize) {
+        if thread >= self.threads() {
+            panic!("No thread {}", thread);
+        }
+        self.thread = thread;
+    }
+
+    /// Bytes in superblocks a thread heap owns or that have live
+    /// blocks, which is what the process would be holding
+    pub fn held(&self) -> usize {
+        let held = self.blocks.iter().filter(|b| b.owner > 0 || b.used > 0);
+        held.count() * self.superblock
+    }
+
+    /// The most memory held at once over the most live at once
+    pub fn blowup(&self) -> f32 {
+        self.peak_held as f32 / self.peak_live.max(1) as f32
+    }
+
+    /// Superblocks with live blocks from more than one thread, which
+    /// those threads would falsely share cache lines in
+    pub fn shared(&self) -> usize {
+        let mut threads: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        for (&ptr, &(_, _, thread)) in self.live.iter() {
+            threads
+                .entry((ptr - self.base) / self.superblock)
+                .or_default()
+                .insert(thread);
+        }
+        threads.values().filter(|set| set.len() > 1).count()
+    }
+
+    /// Superblocks with any live blocks
+    pub fn in_use(&self) -> usize {
+        self.blocks.iter().filter(|b| b.used > 0).count()
+    }
+
+    fn bytes_used(&self, i: usize) -> usize {
+        let b = &self.blocks[i];
+        b.class.map_or(0, |class| b.used * self.classes[class])
+    }
+
+    // The fullest superblock of a heap with a free block of a class
+    fn fullest(&self, heap: usize, class: usize) -> Option<usize> {
+        (0..self.blocks.len())
+            .filter(|&i| {
+                let b = &self.blocks[i];
+                b.owner == heap && b.class == Some(class) && !b.free.is_empty()
+            })
+            .max_by_key(|&i| (self.blocks[i].used, usize::MAX - i))
+    }
+
+    fn carve(&mut self, i: usize, class: usize) {
+        let size = self.classes[class];
+        let addr = self.base + i * self.superblock;
+        let b = &mut self.blocks[i];
+        b.class = Some(class);
+        b.free = (0..self.superblock / size)
+            .rev()
+            .map(|j| addr + j * size)
+            .collect();
+        self.churn.splits += 1;
+    }
+
+    fn give(&mut self, i: usize, heap: usize) {
+        let bytes = self.bytes_used(i);
+        let b = &mut self.blocks[i];
+        self.used[b.owner] -= bytes;
+        self.used[heap] += bytes;
+        b.owner = heap;
+        if heap == 0 && b.used == 0 {
+            b.class = None;
+            b.free.clear();
+        }
+        self.moves += 1;
+    }
+
+    // Give the emptiest superblock of a thread heap back to the
+    // global heap if the heap has got too empty
+    fn release(&mut self, heap: usize) {
+        let owned = self.blocks.iter().filter(|b| b.owner == heap).count();
+        let held = owned * self.superblock;
+        let used = self.used[heap];
+        if used + self.slack * self.superblock >= held
+            || used as f64 >= (1.0 - self.empty_fraction) * held as f64
+        {
+            return;
+        }
+        let emptiest = (0..self.blocks.len())
+            .filter(|&i| self.blocks[i].owner == heap)
+            .min_by_key(|&i| self.bytes_used(i));
+        if let Some(i) = emptiest {
+            self.give(i, 0);
+        }
+    }
+
+    fn track_peaks(&mut self) {
+        let live = self.live.values().map(|&(requested, _, _)| requested).sum();
+        self.peak_live = self.peak_live.max(live);
+        self.peak_held = self.peak_held.max(self.held());
+    }
+}
+
+impl Allocator for HoardSim {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.churn.mallocs += 1;
+        if size == 0 {
+            return Err(AllocFailure::NoFit);
+        }
+        let class = self
+            .classes
+            .iter()
+            .position(|&class| class >= size)
+            .ok_or(AllocFailure::Oversized)?;
+        let heap = self.thread + 1;
+
+        let i = match self.fullest(heap, class) {
+            Some(i) => i,
+            None => {
+                let global = self.fullest(0, class).or_else(|| {
+                    let empty = (0..self.blocks.len())
+                        .find(|&i| self.blocks[i].owner == 0 && self.blocks[i].used == 0)?;
+                    self.carve(empty, class);
+                    Some(empty)
+                });
+                let Some(i) = global else {
+                    return Err(if self.free_space() < size {
+                        AllocFailure::Exhausted
+                    } else {
+                        AllocFailure::NoFit
+                    });
+                };
+                self.give(i, heap);
+                i
+            }
+        };
+
+        let b = &mut self.blocks[i];
+        let ptr = b.free.pop().unwrap();
+        b.used += 1;
+        let rounded = self.classes[class];
+        self.used[heap] += rounded;
+        self.live.insert(ptr, (size, rounded, self.thread));
+        self.track_peaks();
+        Ok(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let (_, rounded, _) = self
+            .live
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        let i = (ptr - self.base) / self.superblock;
+        let b = &mut self.blocks[i];
+        b.free.push(ptr);
+        b.used -= 1;
+        let heap = b.owner;
+        self.used[heap] -= rounded;
+        if b.used == 0 {
+            self.churn.merges += 1;
+            if heap == 0 {
+                b.class = None;
+                b.free.clear();
+            }
+        }
+
+        if heap > 0 {
+            self.release(heap);
+        }
+        self.track_peaks();
+        Ok(())
+    }
+
+    /// The largest class if any superblock is empty, or else the
+    /// largest with a free block
+    fn largest_alloc(&self) -> usize {
+        if self.blocks.iter().any(|b| b.used == 0) {
+            return *self.classes.last().unwrap();
+        }
+        self.blocks
+            .iter()
+            .filter(|b| !b.free.is_empty())
+            .filter_map(|b| b.class.map(|class| self.classes[class]))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Empty superblocks, and the free blocks of the rest
+    fn free_space(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|b| match b.class {
+                Some(_) if b.used == 0 => self.superblock,
+                Some(class) => b.free.len() * self.classes[class],
+                None => self.superblock,
+            })
+            .sum()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.live
+            .values()
+            .map(|(requested, rounded, _)| rounded - requested)
+            .sum()
+    }
+
+    /// Carving a superblock up for a class counts as a split, and
+    /// one emptying out as a merge
+    fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().map(|&(requested, _, _)| requested).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for heap in 0..self.used.len() {
+            let owned: Vec<usize> = (0..self.blocks.len())
+                .filter(|&i| self.blocks[i].owner == heap)
+                .collect();
+            writeln!(
+                out,
+                "heap {}: {} bytes used in superblocks {:?}",
+                heap, self.used[heap], owned
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_heaps() {
+        let mut heap = HoardSim::new(0, 1024, 2, 256);
+        assert_eq!(heap.try_malloc(200), Err(AllocFailure::Oversized));
+        let a = heap.malloc(30).unwrap();
+        heap.switch_thread(1);
+        let b = heap.malloc(30).unwrap();
+        // Each thread got a superblock of its own from the global heap
+        assert_eq!((a, b), (0, 256));
+        assert_eq!(heap.moves, 2);
+        assert_eq!(heap.held(), 512);
+        assert_eq!(heap.internal_frag(), 4);
+
+        // Freed to the owner, not the thread freeing it
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        assert_eq!(heap.moves, 2);
+        assert_eq!(heap.held(), 512);
+        assert_eq!(heap.blowup(), 512.0 / 60.0);
+    }
+
+    #[test]
+    fn give_back() {
+        let mut heap = HoardSim::new(0, 1024, 1, 256).slack(0);
+        let ptrs: Vec<usize> = (0..16).map(|_| heap.malloc(32).unwrap()).collect();
+        assert_eq!(heap.held(), 512);
+        for &ptr in ptrs[..10].iter() {
+            heap.free(ptr).unwrap();
+        }
+        // Down to 6 of 16 blocks used, and the empty superblock went
+        // back to the global heap
+        assert_eq!(heap.moves, 3);
+        assert_eq!(heap.held(), 256);
+        assert_eq!(heap.live_bytes(), Some(192));
+
+        // Another thread can pick the old blocks up and share them
+        let mut heap = HoardSim::new(0, 1024, 2, 256).empty_fraction(0.5).slack(0);
+        let a = heap.malloc(32).unwrap();
+        let b = heap.malloc(32).unwrap();
+        heap.free(a).unwrap();
+        assert_eq!(heap.moves, 2);
+        heap.switch_thread(1);
+        assert_eq!(heap.malloc(32), Some(a));
+        assert_eq!(heap.shared(), 1);
+        heap.free(b).unwrap();
+        assert_eq!(heap.shared(), 0);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::buddy::BuddyAllocator;
+use crate::freelist::FreeList;
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+/// Requests smaller than `threshold` go to a buddy allocator, and
+/// the rest to a free list, the way real allocators keep small
+/// objects apart from large ones. Each side manages its own range
+/// of addresses, from wherever it was built to start, and a free
+/// goes to the side whose range holds the pointer. A malloc that
+/// fails on one side is not retried on the other. Everything the
+/// `Allocator` interface reports covers both sides together
+#[derive(Debug, Clone)]
+pub struct HybridAllocator {
+    small: BuddyAllocator,
+    large: FreeList,
+    threshold: usize,
+    /// Mallocs routed to each side
+    pub small_mallocs: usize,
+    pub large_mallocs: usize,
+}
+
+/// Why `HybridAllocator::new` turned down its two sides: the
+/// addresses each one manages, which overlap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    pub small: Range<usize>,
+    pub large: Range<usize>,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The buddy allocator's [{}, {}) overlaps the free list's [{}, {})",
+            self.small.start, self.small.end, self.large.start, self.large.end
+        )
+    }
+}
+
+impl Error for Overlap {}
+
+impl HybridAllocator {
+    /// Fails if the two sides manage any of the same addresses, since
+    /// both could hand out the same block and a free couldn't tell
+    /// which side it came from
+    pub fn new(small: BuddyAllocator, large: FreeList, threshold: usize) -> Result<Self, Overlap> {
+        let overlap = Overlap {
+            small: small.start()..small.start() + small.size(),
+            large: large.start()..large.start() + large.size(),
+        };
+        if overlap.small.start < overlap.large.end && overlap.large.start < overlap.small.end {
+            return Err(overlap);
+        }
+        Ok(Self {
+            small,
+            large,
+            threshold,
+            small_mallocs: 0,
+            large_mallocs: 0,
+        })
+    }
+
+    pub fn small(&self) -> &BuddyAllocator {
+        &self.small
+    }
+
+    pub fn large(&self) -> &FreeList {
+        &self.large
+    }
+}
+
+impl Allocator for HybridAllocator {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        if size < self.threshold {
+            self.small_mallocs += 1;
+            self.small.try_malloc(size)
+        } else {
+            self.large_mallocs += 1;
+            self.large.try_malloc(size)
+        }
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let small = self.small.start()..self.small.start() + self.small.size();
+        if small.contains(&ptr) {
+            self.small.free(ptr)
+        } else {
+            self.large.free(ptr)
+        }
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.small.largest_alloc().max(self.large.largest_alloc())
+    }
+
+    fn free_space(&self) -> usize {
+        self.small.free_space() + self.large.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.small.internal_frag() + self.large.internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        let (small, large) = (self.small.churn(), self.large.churn());
+        Churn {
+            mallocs: small.mallocs + large.mallocs,
+            splits: small.splits + large.splits,
+            merges: small.merges + large.merges,
+            deferred: small.deferred + large.deferred,
+            scanned: small.scanned + large.scanned,
+        }
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.small.live_bytes()? + self.large.live_bytes()?)
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.small.coalesce() + self.large.coalesce()
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Under {} bytes:", self.threshold).unwrap();
+        out.push_str(&self.small.render());
+        writeln!(out, "From {} bytes:", self.threshold).unwrap();
+        out.push_str(&self.large.render());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routing() {
+        let mut heap = HybridAllocator::new(
+            BuddyAllocator::new(4, 10),
+            FreeList::new(1024, 1024, true),
+            64,
+        )
+        .unwrap();
+        let small = heap.malloc(40).unwrap();
+        let large = heap.malloc(64).unwrap();
+        assert!(small < 1024);
+        assert_eq!(large, 1024);
+        assert_eq!((heap.small_mallocs, heap.large_mallocs), (1, 1));
+
+        // Rounding up to 64 in the buddy allocator is all the waste
+        assert_eq!(heap.internal_frag(), 24);
+        assert_eq!(heap.live_bytes(), Some(104));
+        assert_eq!(heap.free_space(), 2048 - 64 - 64);
+        assert_eq!(heap.churn().mallocs, 2);
+
+        assert!(heap.free(large).is_ok());
+        assert!(heap.free(large).is_err());
+        assert!(heap.free(small).is_ok());
+        assert_eq!(heap.free_space(), 2048);
+        assert_eq!(heap.try_malloc(2000), Err(AllocFailure::Oversized));
+    }
+
+    #[test]
+    fn overlap() {
+        let small = || BuddyAllocator::builder().base_addr(4096).heap_bytes(1024);
+        let large = |start| FreeList::new(start, 1024, true);
+        assert_eq!(
+            HybridAllocator::new(small().build().unwrap(), large(4608), 64).unwrap_err(),
+            Overlap {
+                small: 4096..5120,
+                large: 4608..5632,
+            }
+        );
+        assert!(HybridAllocator::new(small().build().unwrap(), large(3584), 64).is_err());
+        assert!(HybridAllocator::new(small().build().unwrap(), large(5120), 64).is_ok());
+
+        // Either side can go first
+        let mut heap = HybridAllocator::new(small().build().unwrap(), large(3072), 64).unwrap();
+        let small_ptr = heap.malloc(40).unwrap();
+        assert!((4096..5120).contains(&small_ptr));
+        assert_eq!(heap.malloc(100), Some(3072));
+        assert!(heap.free(small_ptr).is_ok());
+    }
+}
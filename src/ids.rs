@@ -0,0 +1,200 @@
+use crate::freelist::FreeList;
+use crate::{AllocError, Allocator, Churn, Policy};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, VecDeque};
+
+/// Which free id an `IdSpace` hands out next
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IdPolicy {
+    /// The lowest free id, like file descriptors
+    Lowest,
+    /// Any free id, picked uniformly
+    Random,
+    /// The lowest free id, but freed ids are held back until this
+    /// many more have been freed after them
+    AvoidRecent(usize),
+}
+
+/// Allocates from a space of identifiers such as PIDs, file
+/// descriptors or port numbers, on top of a coalescing freelist.
+/// Most requests are for a single id. Tracks reuse latency, the
+/// number of ops between an id being freed and handed out again,
+/// since a short one lets stale references see a new owner
+#[derive(Debug, Clone)]
+pub struct IdSpace {
+    list: FreeList,
+    policy: IdPolicy,
+    rng: StdRng,
+    live: BTreeMap<usize, usize>,
+    held: VecDeque<(usize, usize)>,
+    ops: usize,
+    freed_at: BTreeMap<usize, usize>,
+    latencies: Vec<usize>,
+}
+
+impl IdSpace {
+    pub fn new(base: usize, count: usize, seed: u64) -> Self {
+        Self {
+            list: FreeList::new(base, count, true).policy(Policy::First),
+            policy: IdPolicy::Lowest,
+            rng: StdRng::seed_from_u64(seed),
+            live: BTreeMap::new(),
+            held: VecDeque::new(),
+            ops: 0,
+            freed_at: BTreeMap::new(),
+            latencies: Vec::new(),
+        }
+    }
+
+    pub fn policy(mut self, policy: IdPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Ops between the free and the reuse of every reused id
+    pub fn reuse_latencies(&self) -> &[usize] {
+        &self.latencies
+    }
+
+    pub fn mean_reuse_latency(&self) -> f32 {
+        self.latencies.iter().sum::<usize>() as f32 / self.latencies.len().max(1) as f32
+    }
+
+    pub fn min_reuse_latency(&self) -> Option<usize> {
+        self.latencies.iter().copied().min()
+    }
+
+    // Pick a random run of `count` free ids, every possible start
+    // being equally likely
+    fn random(&mut self, count: usize) -> Option<usize> {
+        let starts = |size: usize| (size + 1).saturating_sub(count);
+        let total: usize = self.list.free_nodes().map(|(_, size)| starts(size)).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = self.rng.gen_range(0..total);
+        let (addr, _) = self
+            .list
+            .free_nodes()
+            .find(|&(_, size)| {
+                if pick < starts(size) {
+                    return true;
+                }
+                pick -= starts(size);
+                false
+            })
+            .unwrap();
+        self.list.malloc_at(addr + pick, count)
+    }
+}
+
+impl Allocator for IdSpace {
+    fn malloc(&mut self, count: usize) -> Option<usize> {
+        self.ops += 1;
+        let id = match self.policy {
+            IdPolicy::Random => self.random(count),
+            _ => self.list.malloc(count),
+        }?;
+
+        for id in id..id + count {
+            if let Some(freed) = self.freed_at.remove(&id) {
+                self.latencies.push(self.ops - freed);
+            }
+        }
+        self.live.insert(id, count);
+        Some(id)
+    }
+
+    fn free(&mut self, id: usize) -> Result<(), AllocError> {
+        self.ops += 1;
+        let count = self
+            .live
+            .remove(&id)
+            .ok_or(AllocError::InvalidPointer(id))?;
+        for id in id..id + count {
+            self.freed_at.insert(id, self.ops);
+        }
+
+        let hold = match self.policy {
+            IdPolicy::AvoidRecent(hold) => hold,
+            _ => 0,
+        };
+        self.held.push_back((id, count));
+        while self.held.len() > hold {
+            let (id, _) = self.held.pop_front().unwrap();
+            self.list.free(id).unwrap();
+        }
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.list.largest_alloc()
+    }
+
+    /// Held back ids are not counted, since they can't be handed out
+    fn free_space(&self) -> usize {
+        self.list.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        0
+    }
+
+    fn churn(&self) -> Churn {
+        self.list.churn()
+    }
+
+    fn render(&self) -> String {
+        self.list.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowest() {
+        let mut space = IdSpace::new(0, 8, 0);
+        let ids: Vec<usize> = (0..3).map(|_| space.malloc(1).unwrap()).collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert!(space.free(1).is_ok());
+        assert!(space.free(1).is_err());
+        assert_eq!(space.malloc(1), Some(1));
+        // Freed on op 4, reused on op 6
+        assert_eq!(space.reuse_latencies(), &[2]);
+    }
+
+    #[test]
+    fn avoid_recent() {
+        let mut space = IdSpace::new(0, 8, 0).policy(IdPolicy::AvoidRecent(2));
+        for _ in 0..4 {
+            space.malloc(1).unwrap();
+        }
+        space.free(1).unwrap();
+        assert!(space.free(1).is_err());
+        assert_eq!(space.malloc(1), Some(4));
+        assert_eq!(space.free_space(), 3);
+
+        // Two more frees push 1 out of the hold
+        space.free(2).unwrap();
+        space.free(3).unwrap();
+        assert_eq!(space.malloc(1), Some(1));
+        assert_eq!(space.min_reuse_latency(), Some(5));
+    }
+
+    #[test]
+    fn random() {
+        let mut space = IdSpace::new(10, 4, 0).policy(IdPolicy::Random);
+        let mut ids: Vec<usize> = (0..4).map(|_| space.malloc(1).unwrap()).collect();
+        assert!(space.malloc(1).is_none());
+        ids.sort_unstable();
+        assert_eq!(ids, vec![10, 11, 12, 13]);
+
+        space.free(12).unwrap();
+        assert!(space.malloc(2).is_none());
+        assert_eq!(space.malloc(1), Some(12));
+    }
+}
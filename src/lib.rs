@@ -1,14 +1,260 @@
 #![forbid(unsafe_code)]
+pub mod aging;
+pub mod aligned;
+pub mod arrivals;
+pub mod audit;
+pub mod bins;
+pub mod bounds;
 pub mod buddy;
+pub mod checkpoint;
+pub mod contiguous;
+pub mod controller;
 pub mod demos;
+pub mod equivalence;
+pub mod experiment;
+pub mod explore;
+pub mod faults;
 pub mod freelist;
+pub mod head_to_head;
+pub mod hoard;
+pub mod hybrid;
+pub mod ids;
+pub mod linked;
+pub mod locality;
+pub mod manifest;
+pub mod manpage;
+pub mod maps;
+pub mod metadata;
+pub mod metrics;
+pub mod mimalloc;
+pub mod minimize;
+pub mod mobility;
+pub mod numa;
+pub mod observer;
+pub mod offline;
+pub mod paging;
+pub mod percpu;
+pub mod pools;
 pub mod prelude;
+pub mod presets;
+pub mod quickfit;
+pub mod region;
+pub mod regressions;
+pub mod report;
+pub mod reservation;
+pub mod rng;
+pub mod rounding;
+pub mod schema;
+pub mod segregated;
+pub mod segtree;
+pub mod snapshot;
+pub mod spans;
+pub mod stack;
+pub mod stats;
+pub mod synth;
+pub mod tlsf;
+pub mod trace;
+pub mod usage;
+pub mod vmem;
+pub mod wcet;
 pub mod workloads;
 
+use std::error::Error;
+use std::fmt;
+
 #[derive(Debug, Copy, Clone)]
 pub enum Policy {
     Best,
     First,
+    /// First fit, but searching from where the last malloc left off
+    /// and wrapping around the end of the list
+    Next,
+    /// First fit that gives up after looking at `budget` free
+    /// blocks, for a bound on how long a malloc can take. Then it
+    /// fails, or with `fallback` takes the largest free block
+    /// instead, which the allocator is taken to keep at hand
+    Bounded {
+        budget: usize,
+        fallback: bool,
+    },
+    /// Any free block that fits, picked uniformly with a generator
+    /// seeded from `seed`, like the heap layout randomization of
+    /// hardened allocators. Also a baseline any smarter policy
+    /// ought to beat
+    Random {
+        seed: u64,
+    },
+}
+
+/// Why part of an allocation could not be freed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FreeRangeError {
+    /// No allocation contains the start of the range
+    NotAllocated,
+    /// The range runs past the end of the allocation
+    OutOfBounds,
+    /// The allocator cannot split live allocations
+    Unsupported,
+}
+
+/// Why a malloc failed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocFailure {
+    /// Bigger than the allocator could ever hand out
+    Oversized,
+    /// There is enough free space in total, but no free block fits
+    NoFit,
+    /// Not enough free space is left in total
+    Exhausted,
+}
+
+/// Why a free, a reset or a checked malloc went wrong
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocError {
+    /// Nothing is allocated at the pointer
+    InvalidPointer(usize),
+    /// The pointer was already freed. Only allocators that remember
+    /// freed pointers can tell this from an invalid one
+    DoubleFree(usize),
+    OutOfMemory {
+        requested: usize,
+        /// The largest block that could have been handed out
+        largest: usize,
+    },
+    /// The allocator only frees the most recent allocation, and the
+    /// pointer is `depth` allocations below `top`
+    OutOfOrder {
+        ptr: usize,
+        depth: usize,
+        top: usize,
+    },
+    /// The header of the block at the pointer has been overwritten
+    Corrupted(usize),
+    /// A failure injected on purpose
+    Injected,
+    /// The allocator can't do this at all
+    Unsupported,
+    /// No region or reservation by that name
+    NotFound,
+    /// A span to add is empty, or overlaps one already there
+    BadSpan { base: usize, size: usize },
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocError::InvalidPointer(ptr) => write!(f, "Pointer {} not found", ptr),
+            AllocError::DoubleFree(ptr) => write!(f, "Pointer {} already freed", ptr),
+            AllocError::OutOfMemory { requested, largest } => write!(
+                f,
+                "Could not allocate {} bytes, the largest free block is {}",
+                requested, largest
+            ),
+            AllocError::OutOfOrder { ptr, depth, top } => write!(
+                f,
+                "Pointer {} is {} below the top of the stack, only {} can be freed",
+                ptr, depth, top
+            ),
+            AllocError::Corrupted(ptr) => write!(f, "Bad magic number in the header of {}", ptr),
+            AllocError::Injected => write!(f, "Injected failure"),
+            AllocError::Unsupported => write!(f, "Not supported by this allocator"),
+            AllocError::NotFound => write!(f, "Nothing by that name"),
+            AllocError::BadSpan { base, size } => write!(
+                f,
+                "Span of {} bytes at {} is empty or overlaps another",
+                size, base
+            ),
+        }
+    }
+}
+
+impl Error for AllocError {}
+
+/// Running totals of how often an allocator split and merged free
+/// blocks, as a proxy for how much its metadata churns
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Churn {
+    pub mallocs: usize,
+    pub splits: usize,
+    pub merges: usize,
+    /// Of the merges, those put off from a free until a malloc
+    /// needed the space
+    pub deferred: usize,
+    /// Free blocks looked at while searching for one that fits, for
+    /// allocators that search a list instead of going straight to a
+    /// block
+    pub scanned: usize,
+}
+
+impl Churn {
+    /// What happened between an earlier snapshot and this one
+    pub fn since(self, before: Churn) -> Churn {
+        Churn {
+            mallocs: self.mallocs - before.mallocs,
+            splits: self.splits - before.splits,
+            merges: self.merges - before.merges,
+            deferred: self.deferred - before.deferred,
+            scanned: self.scanned - before.scanned,
+        }
+    }
+
+    /// Splits and merges per 1000 mallocs
+    pub fn per_thousand(&self) -> (f32, f32) {
+        let mallocs = self.mallocs.max(1) as f32;
+        (
+            1000.0 * self.splits as f32 / mallocs,
+            1000.0 * self.merges as f32 / mallocs,
+        )
+    }
+}
+
+/// What an allocator built on spans does with one once everything
+/// in it is freed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Retention {
+    /// Give it straight back to the level below
+    Release,
+    /// Hold on to up to this many empty spans for later mallocs, and
+    /// give back the rest
+    Keep(usize),
+    /// Hold on to every empty span
+    All,
+}
+
+/// Running totals of what happened to the spans of an allocator
+/// built on them, and the most bytes held at once in empty ones,
+/// which is what retention costs in memory
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Recycling {
+    /// Spans taken from the level below
+    pub taken: usize,
+    /// Times a span became fully free, and of those, how often it
+    /// was given back and how often kept
+    pub emptied: usize,
+    pub returned: usize,
+    pub retained: usize,
+    /// Times a malloc went into a kept empty span
+    pub reused: usize,
+    pub peak_retained: usize,
+}
+
+impl Recycling {
+    /// Count a span emptying, with `kept` empty spans already held,
+    /// and say whether to keep it
+    pub(crate) fn empty(&mut self, retention: Retention, kept: usize) -> bool {
+        self.emptied += 1;
+        let keep = match retention {
+            Retention::Release => false,
+            Retention::Keep(count) => kept < count,
+            Retention::All => true,
+        };
+        if keep {
+            self.retained += 1;
+        } else {
+            self.returned += 1;
+        }
+        keep
+    }
 }
 
 pub trait Allocator {
@@ -16,9 +262,107 @@ pub trait Allocator {
     /// if space cannot be allocated
     fn malloc(&mut self, size: usize) -> Option<usize>;
 
+    /// Like `malloc`, but says why it failed. Allocators that know
+    /// their largest possible request should report `Oversized`;
+    /// by default a failure is put down to running out of space if
+    /// less than `size` is free, and to no block fitting otherwise
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.malloc(size).ok_or_else(|| {
+            if self.free_space() < size {
+                AllocFailure::Exhausted
+            } else {
+                AllocFailure::NoFit
+            }
+        })
+    }
+
+    /// Like `malloc`, but the address has to be a multiple of
+    /// `align`, which must be a power of two. By default the
+    /// allocator places the request as usual, and the malloc fails
+    /// if the address it picked isn't aligned
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        if !align.is_power_of_two() {
+            return None;
+        }
+        let ptr = self.malloc(size)?;
+        if ptr.is_multiple_of(align) {
+            return Some(ptr);
+        }
+        let _ = self.free(ptr);
+        None
+    }
+
+    /// Allocate `count` elements of `size` bytes each, zero filled.
+    /// Returns None if `count * size` overflows or space cannot be
+    /// allocated. Like most C libraries, a product of 0 still gets a
+    /// pointer of its own that can be freed, by asking for 1 byte.
+    /// Nothing is really zeroed, `trace::zeroing` counts what would
+    /// have been
+    fn calloc(&mut self, count: usize, size: usize) -> Option<usize> {
+        self.malloc(count.checked_mul(size)?.max(1))
+    }
+
+    /// Like `calloc`, but says why it failed. A product that
+    /// overflows could never be handed out, so it is `Oversized`
+    fn try_calloc(&mut self, count: usize, size: usize) -> Result<usize, AllocFailure> {
+        let bytes = count.checked_mul(size).ok_or(AllocFailure::Oversized)?;
+        self.try_malloc(bytes.max(1))
+    }
+
+    /// Allocate `n` blocks of `block_size` bytes each, one right
+    /// after the other, like a kernel allocation of a higher order of
+    /// pages. Returns the address of the first. By default this is a
+    /// single malloc of all the blocks, which fails if no free block
+    /// is big enough even when there are `n` free blocks elsewhere
+    fn malloc_contiguous(&mut self, n: usize, block_size: usize) -> Option<usize> {
+        self.malloc(n.checked_mul(block_size)?)
+    }
+
+    /// Like `try_malloc`, but the error says how much was asked for
+    /// and the largest block there was, and can be reported like
+    /// any other error
+    fn checked_malloc(&mut self, size: usize) -> Result<usize, AllocError> {
+        self.try_malloc(size).map_err(|_| AllocError::OutOfMemory {
+            requested: size,
+            largest: self.largest_alloc(),
+        })
+    }
+
     /// Frees the memory for the given pointer. Returns
     /// an error if the pointer doesn't exist
-    fn free(&mut self, ptr: usize) -> Result<(), &str>;
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError>;
+
+    /// Frees `len` bytes starting at `addr`, which can be in the
+    /// middle of an allocation. Whatever is left on either side
+    /// stays allocated, like `munmap` on part of a mapping
+    fn free_range(&mut self, _addr: usize, _len: usize) -> Result<(), FreeRangeError> {
+        Err(FreeRangeError::Unsupported)
+    }
+
+    /// Shrinks an allocation in place to `new_size`, giving the
+    /// tail back to the free pool. Returns how many bytes were
+    /// actually recovered, which can be less than asked for
+    /// because of alignment or size classes
+    fn shrink(&mut self, _ptr: usize, _new_size: usize) -> Result<usize, FreeRangeError> {
+        Err(FreeRangeError::Unsupported)
+    }
+
+    /// Bytes actually backing the allocation at `ptr`, padding and
+    /// rounding included, or None if nothing is allocated there.
+    /// Less what was asked for, this is the slack of one allocation,
+    /// where `internal_frag` only gives the total. Allocators that
+    /// don't keep track report None
+    fn alloc_size(&self, _ptr: usize) -> Option<usize> {
+        None
+    }
+
+    /// Free everything and put the heap back the way it was built,
+    /// counters included, but keep its configuration, so many runs
+    /// can share one allocator that is expensive to build. Returns
+    /// an error, and changes nothing, if the allocator can't
+    fn reset(&mut self) -> Result<(), AllocError> {
+        Err(AllocError::Unsupported)
+    }
 
     /// Get the the largest amount of memory that is
     /// possible to allocate
@@ -31,12 +375,231 @@ pub trait Allocator {
     /// Get the amount of internal fragmentation
     fn internal_frag(&self) -> usize;
 
-    /// If there is free space, get a measure
-    /// of the external fragmentation
+    /// Splits and merges done so far. Allocators that don't count
+    /// them report nothing
+    fn churn(&self) -> Churn {
+        Churn::default()
+    }
+
+    /// Bytes requested by the live allocations, without padding.
+    /// Allocators that don't keep track report None
+    fn live_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    /// Merge every run of adjacent free blocks now, instead of
+    /// waiting for frees to do it. Returns how many merges it took,
+    /// which are counted in `churn` too. Allocators that always
+    /// coalesce as they free have nothing left to merge
+    fn coalesce(&mut self) -> usize {
+        0
+    }
+
+    /// Get a measure of the external fragmentation. A full
+    /// heap has no free space to fragment, so it counts as 0
     fn external_frag(&self) -> f32 {
+        if self.free_space() == 0 {
+            return 0.0;
+        }
         1.0 - (self.largest_alloc() as f32 / self.free_space() as f32)
     }
 
-    /// Print the allocator. Too lazy to implement Display
-    fn print(&self);
+    /// The free structures of the allocator drawn as text. The same
+    /// heap always draws the same way, so drawings can be compared
+    /// against saved copies and used without going through stdout
+    fn render(&self) -> String;
+
+    /// Print what `render` draws
+    fn print(&self) {
+        print!("{}", self.render())
+    }
+}
+
+/// An allocator that groups allocations into named regions, so a
+/// whole region can be freed in one call instead of one allocation
+/// at a time
+pub trait Regional: Allocator {
+    /// Make later mallocs go into the region, starting it if there
+    /// isn't one by that name yet
+    fn enter_region(&mut self, name: &str);
+
+    /// Free everything in the region at once. Returns `NotFound` if
+    /// there is no region by that name
+    fn free_region(&mut self, name: &str) -> Result<(), AllocError>;
+}
+
+/// A boxed allocator is an allocator, so allocators of different
+/// types can be kept together and picked at run time
+impl<T: Allocator + ?Sized> Allocator for Box<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        (**self).malloc(size)
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        (**self).try_malloc(size)
+    }
+
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        (**self).malloc_aligned(size, align)
+    }
+
+    fn calloc(&mut self, count: usize, size: usize) -> Option<usize> {
+        (**self).calloc(count, size)
+    }
+
+    fn try_calloc(&mut self, count: usize, size: usize) -> Result<usize, AllocFailure> {
+        (**self).try_calloc(count, size)
+    }
+
+    fn malloc_contiguous(&mut self, n: usize, block_size: usize) -> Option<usize> {
+        (**self).malloc_contiguous(n, block_size)
+    }
+
+    fn checked_malloc(&mut self, size: usize) -> Result<usize, AllocError> {
+        (**self).checked_malloc(size)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        (**self).free(ptr)
+    }
+
+    fn free_range(&mut self, addr: usize, len: usize) -> Result<(), FreeRangeError> {
+        (**self).free_range(addr, len)
+    }
+
+    fn shrink(&mut self, ptr: usize, new_size: usize) -> Result<usize, FreeRangeError> {
+        (**self).shrink(ptr, new_size)
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        (**self).alloc_size(ptr)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        (**self).reset()
+    }
+
+    fn largest_alloc(&self) -> usize {
+        (**self).largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        (**self).free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        (**self).internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        (**self).churn()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        (**self).live_bytes()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        (**self).coalesce()
+    }
+
+    fn external_frag(&self) -> f32 {
+        (**self).external_frag()
+    }
+
+    fn render(&self) -> String {
+        (**self).render()
+    }
+
+    fn print(&self) {
+        (**self).print()
+    }
+}
+
+/// So is a borrowed one, so a workload can run on an allocator and
+/// hand it back to be measured or reset for the next run
+impl<T: Allocator + ?Sized> Allocator for &mut T {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        (**self).malloc(size)
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        (**self).try_malloc(size)
+    }
+
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        (**self).malloc_aligned(size, align)
+    }
+
+    fn calloc(&mut self, count: usize, size: usize) -> Option<usize> {
+        (**self).calloc(count, size)
+    }
+
+    fn try_calloc(&mut self, count: usize, size: usize) -> Result<usize, AllocFailure> {
+        (**self).try_calloc(count, size)
+    }
+
+    fn malloc_contiguous(&mut self, n: usize, block_size: usize) -> Option<usize> {
+        (**self).malloc_contiguous(n, block_size)
+    }
+
+    fn checked_malloc(&mut self, size: usize) -> Result<usize, AllocError> {
+        (**self).checked_malloc(size)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        (**self).free(ptr)
+    }
+
+    fn free_range(&mut self, addr: usize, len: usize) -> Result<(), FreeRangeError> {
+        (**self).free_range(addr, len)
+    }
+
+    fn shrink(&mut self, ptr: usize, new_size: usize) -> Result<usize, FreeRangeError> {
+        (**self).shrink(ptr, new_size)
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        (**self).alloc_size(ptr)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        (**self).reset()
+    }
+
+    fn largest_alloc(&self) -> usize {
+        (**self).largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        (**self).free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        (**self).internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        (**self).churn()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        (**self).live_bytes()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        (**self).coalesce()
+    }
+
+    fn external_frag(&self) -> f32 {
+        (**self).external_frag()
+    }
+
+    fn render(&self) -> String {
+        (**self).render()
+    }
+
+    fn print(&self) {
+        (**self).print()
+    }
 }
@@ -0,0 +1,384 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// The heap is simulated as 8 byte words. Every block starts with a
+// header of its size and a magic number, and ends with a footer
+// holding its size again, so a free can find the block before it.
+// Free blocks keep the next and previous pointers of the list in
+// the two words after their header
+const WORD: usize = 8;
+const HEADER: usize = 2 * WORD;
+const FOOTER: usize = WORD;
+const OVERHEAD: usize = HEADER + FOOTER;
+// Room for the header, both pointers and the footer
+const MIN_BLOCK: usize = HEADER + 2 * WORD + FOOTER;
+const MAGIC: usize = 1234567;
+const FREE: usize = 7654321;
+const NULL: usize = usize::MAX;
+
+/// The textbook explicit free list, as in OSTEP and CS:APP, with its
+/// metadata stored in the heap it manages. Free blocks form a doubly
+/// linked list through pointers kept inside them, new free blocks go
+/// at the head, and malloc takes the first block that fits. Headers
+/// and footers take up 24 bytes of every allocated block, which is
+/// counted as internal fragmentation
+#[derive(Debug, Clone)]
+pub struct LinkedFreeList {
+    base_addr: usize,
+    words: Vec<usize>,
+    head: usize,
+    // Bytes asked for by each live allocation, by payload address.
+    // Only for reporting; the allocator itself goes by the headers
+    requested: BTreeMap<usize, usize>,
+    churn: Churn,
+}
+
+impl LinkedFreeList {
+    /// Manage [base_addr, base_addr + size), rounded down to whole
+    /// words. Starts out as one free block
+    pub fn new(base_addr: usize, size: usize) -> Self {
+        let size = size - size % WORD;
+        if size < MIN_BLOCK {
+            panic!("Heap too small to hold a free block");
+        }
+        let mut list = Self {
+            base_addr,
+            words: vec![0; size / WORD],
+            head: NULL,
+            requested: BTreeMap::new(),
+            churn: Churn::default(),
+        };
+        list.set_block(base_addr, size, FREE);
+        list.link(base_addr);
+        list
+    }
+
+    /// Bytes of headers and footers in the live allocations
+    pub fn overhead(&self) -> usize {
+        self.requested.len() * OVERHEAD
+    }
+
+    /// The word at `addr`, whatever it holds
+    pub fn peek(&self, addr: usize) -> usize {
+        self.read(addr)
+    }
+
+    /// Store `value` in the word at `addr` the way a buggy program
+    /// would, headers, footers and list pointers included
+    pub fn poke(&mut self, addr: usize, value: usize) {
+        self.write(addr, value);
+    }
+
+    fn read(&self, addr: usize) -> usize {
+        self.words[(addr - self.base_addr) / WORD]
+    }
+
+    fn write(&mut self, addr: usize, value: usize) {
+        self.words[(addr - self.base_addr) / WORD] = value;
+    }
+
+    fn end(&self) -> usize {
+        self.base_addr + self.words.len() * WORD
+    }
+
+    fn set_block(&mut self, addr: usize, size: usize, magic: usize) {
+        self.write(addr, size);
+        self.write(addr + WORD, magic);
+        self.write(addr + size - FOOTER, size);
+    }
+
+    fn next(&self, addr: usize) -> usize {
+        self.read(addr + HEADER)
+    }
+
+    fn prev(&self, addr: usize) -> usize {
+        self.read(addr + HEADER + WORD)
+    }
+
+    fn set_next(&mut self, addr: usize, next: usize) {
+        self.write(addr + HEADER, next);
+    }
+
+    fn set_prev(&mut self, addr: usize, prev: usize) {
+        self.write(addr + HEADER + WORD, prev);
+    }
+
+    // Push a free block on the head of the list
+    fn link(&mut self, addr: usize) {
+        self.set_next(addr, self.head);
+        self.set_prev(addr, NULL);
+        if self.head != NULL {
+            self.set_prev(self.head, addr);
+        }
+        self.head = addr;
+    }
+
+    // Point the neighbours of `old` in the list at `new` instead, or
+    // at each other if `new` is NULL
+    fn replace(&mut self, old: usize, new: usize) {
+        let (next, prev) = (self.next(old), self.prev(old));
+        let (to_next, to_prev) = if new == NULL {
+            (next, prev)
+        } else {
+            self.set_next(new, next);
+            self.set_prev(new, prev);
+            (new, new)
+        };
+        if prev == NULL {
+            self.head = to_next;
+        } else {
+            self.set_next(prev, to_next);
+        }
+        if next != NULL {
+            self.set_prev(next, to_prev);
+        }
+    }
+
+    fn unlink(&mut self, addr: usize) {
+        self.replace(addr, NULL);
+    }
+
+    // Free block addresses in list order
+    fn free_blocks(&self) -> Vec<usize> {
+        let mut blocks = Vec::new();
+        let mut addr = self.head;
+        while addr != NULL {
+            blocks.push(addr);
+            addr = self.next(addr);
+        }
+        blocks
+    }
+}
+
+impl Allocator for LinkedFreeList {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
+        if size == 0 {
+            return None;
+        }
+        let need = (size.div_ceil(WORD) * WORD + OVERHEAD).max(MIN_BLOCK);
+
+        let mut addr = self.head;
+        while addr != NULL && self.read(addr) < need {
+            self.churn.scanned += 1;
+            addr = self.next(addr);
+        }
+        if addr == NULL {
+            return None;
+        }
+        self.churn.scanned += 1;
+
+        // Split off the tail if it can hold a free block. It takes the
+        // place of the whole block in the list
+        let block = self.read(addr);
+        if block - need >= MIN_BLOCK {
+            self.set_block(addr + need, block - need, FREE);
+            self.replace(addr, addr + need);
+            self.set_block(addr, need, MAGIC);
+            self.churn.splits += 1;
+        } else {
+            self.unlink(addr);
+            self.set_block(addr, block, MAGIC);
+        }
+
+        self.requested.insert(addr + HEADER, size);
+        Some(addr + HEADER)
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        if size.div_ceil(WORD) * WORD + OVERHEAD > self.words.len() * WORD {
+            return Err(AllocFailure::Oversized);
+        }
+        self.malloc(size).ok_or(if self.free_space() < size {
+            AllocFailure::Exhausted
+        } else {
+            AllocFailure::NoFit
+        })
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        if ptr < self.base_addr + HEADER
+            || ptr >= self.end()
+            || !(ptr - self.base_addr).is_multiple_of(WORD)
+        {
+            return Err(AllocError::InvalidPointer(ptr));
+        }
+        // Only the magic number tells a block header from anything
+        // else, and anything else is no block to free
+        if self.read(ptr - HEADER + WORD) != MAGIC {
+            return Err(AllocError::Corrupted(ptr));
+        }
+        if self.requested.remove(&ptr).is_none() {
+            return Err(AllocError::InvalidPointer(ptr));
+        }
+        let mut addr = ptr - HEADER;
+        let mut size = self.read(addr);
+
+        // The next block's header is right after this one, and the
+        // previous block's footer right before
+        let next = addr + size;
+        if next < self.end() && self.read(next + WORD) == FREE {
+            self.unlink(next);
+            size += self.read(next);
+            self.churn.merges += 1;
+        }
+        if addr > self.base_addr {
+            let prev = addr - self.read(addr - FOOTER);
+            if self.read(prev + WORD) == FREE {
+                self.unlink(prev);
+                addr = prev;
+                size += self.read(prev);
+                self.churn.merges += 1;
+            }
+        }
+
+        self.set_block(addr, size, FREE);
+        self.link(addr);
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.free_blocks()
+            .into_iter()
+            .map(|addr| self.read(addr) - OVERHEAD)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Whole free blocks, headers and all
+    fn free_space(&self) -> usize {
+        self.free_blocks()
+            .into_iter()
+            .map(|addr| self.read(addr))
+            .sum()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.requested
+            .iter()
+            .map(|(&ptr, &size)| self.read(ptr - HEADER) - size)
+            .sum()
+    }
+
+    fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.requested.values().sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let show = |addr: usize| {
+            if addr == NULL {
+                "NULL".to_string()
+            } else {
+                addr.to_string()
+            }
+        };
+        writeln!(out, "head: {}", show(self.head)).unwrap();
+        for addr in self.free_blocks() {
+            writeln!(
+                out,
+                "  [addr {}, size {}, next {}, prev {}]",
+                addr,
+                self.read(addr),
+                show(self.next(addr)),
+                show(self.prev(addr))
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malloc() {
+        let mut list = LinkedFreeList::new(0, 1024);
+        // Payloads start after the 16 byte header, and each block is
+        // rounded up to words with 24 bytes of headers on top
+        assert_eq!(list.malloc(10), Some(16));
+        assert_eq!(list.malloc(100), Some(56));
+        assert_eq!(list.overhead(), 48);
+        assert_eq!(list.internal_frag(), 30 + 28);
+        assert_eq!(list.live_bytes(), Some(110));
+        assert_eq!(list.free_space(), 1024 - 40 - 128);
+        assert_eq!(list.largest_alloc(), 1024 - 40 - 128 - 24);
+        assert!(list.malloc(1024).is_none());
+        assert_eq!(list.try_malloc(1001), Err(AllocFailure::Oversized));
+    }
+
+    #[test]
+    fn free() {
+        let mut list = LinkedFreeList::new(0, 1024);
+        let ptrs: Vec<usize> = (0..4).map(|_| list.malloc(16).unwrap()).collect();
+        assert!(list.free(ptrs[0] + 8).is_err());
+        assert!(list.free(ptrs[0]).is_ok());
+        assert!(list.free(ptrs[0]).is_err());
+        assert!(list.free(ptrs[2]).is_ok());
+
+        // Newest at the head, with the tail of the heap last
+        assert_eq!(list.free_blocks(), vec![80, 0, 160]);
+        assert_eq!(list.prev(80), NULL);
+        assert_eq!(list.prev(0), 80);
+        assert_eq!(list.next(160), NULL);
+
+        // Merges with both neighbours into the head of the list
+        assert!(list.free(ptrs[1]).is_ok());
+        assert_eq!(list.free_blocks(), vec![0, 160]);
+        assert_eq!(list.read(0), 120);
+        assert!(list.free(ptrs[3]).is_ok());
+        assert_eq!(list.free_blocks(), vec![0]);
+        assert_eq!(list.free_space(), 1024);
+        assert_eq!(list.churn().merges, 4);
+    }
+
+    #[test]
+    fn first_fit_reuse() {
+        let mut list = LinkedFreeList::new(0, 1024);
+        let small = list.malloc(8).unwrap();
+        list.malloc(8).unwrap();
+        let big = list.malloc(200).unwrap();
+        list.malloc(8).unwrap();
+        list.free(big).unwrap();
+        list.free(small).unwrap();
+
+        // The small hole is at the head now, but doesn't fit
+        assert_eq!(list.malloc(100), Some(big));
+        // What is left of the big hole is 96 bytes. Splitting 88 off
+        // would leave too little for a free block, so it all goes
+        assert_eq!(list.malloc(60), Some(big + 128));
+        assert_eq!(list.free_blocks(), vec![0, 344]);
+        assert_eq!(list.internal_frag(), 32 + 28 + 36 + 32);
+    }
+
+    #[test]
+    fn corrupted_header() {
+        let mut list = LinkedFreeList::new(0, 256);
+        let ptrs: Vec<usize> = (0..3).map(|_| list.malloc(16).unwrap()).collect();
+        let magic = ptrs[1] - HEADER + WORD;
+        list.poke(magic, 0);
+        assert_eq!(list.free(ptrs[1]), Err(AllocError::Corrupted(ptrs[1])));
+        list.poke(magic, MAGIC);
+        assert!(list.free(ptrs[1]).is_ok());
+
+        // A bigger size with the magic number intact gets through,
+        // and the free block swallows the live one after it
+        list = LinkedFreeList::new(0, 256);
+        let ptrs: Vec<usize> = (0..3).map(|_| list.malloc(16).unwrap()).collect();
+        list.poke(ptrs[1] - HEADER, 80);
+        assert!(list.free(ptrs[1]).is_ok());
+        assert_eq!(list.free_blocks(), vec![40]);
+        assert_eq!(list.read(40), 216);
+        assert_eq!(list.malloc(40), Some(56));
+        // The rest of the free block starts inside the live payload
+        assert_eq!(list.free_blocks(), vec![104]);
+        assert!((ptrs[2]..ptrs[2] + 16).contains(&104));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::trace::{Event, Op};
+use std::collections::BTreeMap;
+
+/// Pages referenced by each event. A malloc or free touches every
+/// page its block overlaps; failed ops touch nothing
+fn references(events: &[Event], page: usize) -> Vec<Vec<usize>> {
+    events
+        .iter()
+        .map(|event| match event.block {
+            Some((addr, size)) if size > 0 => (addr / page..=(addr + size - 1) / page).collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Every page the events reference, in order, as a reference string
+/// for page replacement. An op that touches several pages references
+/// them from the lowest up
+pub fn reference_string(events: &[Event], page: usize) -> Vec<usize> {
+    references(events, page).into_iter().flatten().collect()
+}
+
+/// Denning's average working set size s(τ) for each window τ. The
+/// working set W(t, τ) is the set of distinct pages referenced by
+/// the ops in (t - τ, t], and s(τ) is its size averaged over t
+pub fn working_set(events: &[Event], page: usize, windows: &[usize]) -> Vec<(usize, f32)> {
+    let refs = references(events, page);
+
+    windows
+        .iter()
+        .map(|&window| {
+            let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+            let mut total = 0;
+
+            for t in 0..refs.len() {
+                for &p in refs[t].iter() {
+                    *counts.entry(p).or_insert(0) += 1;
+                }
+                if t >= window {
+                    for p in refs[t - window].iter() {
+                        let count = counts.get_mut(p).unwrap();
+                        *count -= 1;
+                        if *count == 0 {
+                            counts.remove(p);
+                        }
+                    }
+                }
+                total += counts.len();
+            }
+
+            (window, total as f32 / refs.len().max(1) as f32)
+        })
+        .collect()
+}
+
+/// How often each region of the heap was handed out over time. The
+/// first `heap` bytes are cut into `regions` equal regions and the
+/// events into `epochs` equal spans, and `counts[e][r]` is how many
+/// mallocs in epoch e returned a block overlapping region r
+pub fn placement(events: &[Event], heap: usize, regions: usize, epochs: usize) -> Vec<Vec<usize>> {
+    let width = heap.div_ceil(regions).max(1);
+    let per_epoch = events.len().div_ceil(epochs).max(1);
+    let mut counts = vec![vec![0; regions]; epochs];
+
+    for (t, event) in events.iter().enumerate() {
+        if let (Op::Malloc { .. } | Op::Calloc { .. }, Some((addr, size))) = (event.op, event.block)
+        {
+            let first = (addr / width).min(regions - 1);
+            let last = ((addr + size.max(1) - 1) / width).min(regions - 1);
+            for count in counts[t / per_epoch][first..=last].iter_mut() {
+                *count += 1;
+            }
+        }
+    }
+
+    counts
+}
+
+/// Draw placement counts as rows of shade characters, darker for
+/// regions that were handed out more. Every map is shaded against
+/// the same `max`, so maps of different allocators can be compared
+pub fn render_heatmap(counts: &[Vec<usize>], max: usize) -> String {
+    let shades = [' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+    let mut out = String::new();
+
+    for row in counts.iter() {
+        out.push('|');
+        for &count in row.iter() {
+            let shade = match count {
+                0 => 0,
+                _ => 1 + (count * (shades.len() - 2)) / max.max(1),
+            };
+            out.push(shades[shade.min(shades.len() - 1)]);
+        }
+        out.push_str("|\n");
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(addr: usize, size: usize) -> Event {
+        Event {
+            op: Op::Malloc { id: 0, size },
+            block: Some((addr, size)),
+        }
+    }
+
+    #[test]
+    fn working_set() {
+        // Pages 0, 1, 0, 2
+        let events = [event(0, 4), event(4, 4), event(0, 4), event(9, 1)];
+        let curve = super::working_set(&events, 4, &[1, 2, 4]);
+
+        assert_eq!(curve[0], (1, 1.0));
+        // {0}, {0, 1}, {0, 1}, {0, 2}
+        assert_eq!(curve[1], (2, 7.0 / 4.0));
+        // {0}, {0, 1}, {0, 1}, {0, 1, 2}
+        assert_eq!(curve[2], (4, 8.0 / 4.0));
+
+        assert_eq!(reference_string(&events, 4), vec![0, 1, 0, 2]);
+        assert_eq!(reference_string(&[event(2, 4)], 4), vec![0, 1]);
+    }
+
+    #[test]
+    fn placement() {
+        let mut events = vec![event(0, 4), event(0, 2), event(0, 16)];
+        events.push(Event {
+            op: Op::Free { id: 0 },
+            block: Some((0, 16)),
+        });
+        let counts = super::placement(&events, 16, 4, 2);
+
+        assert_eq!(counts[0], vec![2, 0, 0, 0]);
+        // Frees are not placements
+        assert_eq!(counts[1], vec![1, 1, 1, 1]);
+        assert_eq!(render_heatmap(&counts, 2), "|@   |\n|++++|\n");
+    }
+}
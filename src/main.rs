@@ -1,151 +1,4558 @@
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, Shell, SubCommand};
+use freespace_sim::audit::AddressLog;
+use freespace_sim::explore::Explorer;
+use freespace_sim::manifest::{Archive, Manifest};
+use freespace_sim::manpage;
+use freespace_sim::minimize::minimize;
 use freespace_sim::prelude::*;
+use freespace_sim::regressions;
+use freespace_sim::rng::SeededRng;
+use freespace_sim::schema::Table;
+use freespace_sim::trace::Op;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::process;
 
-fn print_results(results_vec: Vec<Results>) {
-    let len = results_vec.len();
+fn print_results(runs: &Aggregate) {
     println!(
         "Average malloc fails: {}",
-        results_vec.iter().map(|r| r.malloc_fails).sum::<usize>() as f32 / len as f32
-    );
-    println!(
-        "Average free fails: {}",
-        results_vec.iter().map(|r| r.free_fails).sum::<usize>() as f32 / len as f32
+        runs.mean(|r| r.malloc_fails as f32)
     );
+    println!("Average free fails: {}", runs.mean(|r| r.free_fails as f32));
     println!(
         "Average internal fragmentation: {}",
-        results_vec.iter().map(|r| r.internal_frag).sum::<usize>() as f32 / len as f32
+        runs.mean(|r| r.internal_frag as f32)
     );
     println!(
         "Average external fragmentation: {}",
-        results_vec.iter().map(|r| r.external_frag).sum::<f32>() / len as f32
+        runs.mean(|r| r.external_frag)
+    );
+    if runs.runs.iter().any(|r| r.malloc_fails > 0) {
+        println!(
+            "  Oversized: {}, no block fits: {}, exhausted: {}",
+            runs.failures(AllocFailure::Oversized).mean(),
+            runs.failures(AllocFailure::NoFit).mean(),
+            runs.failures(AllocFailure::Exhausted).mean()
+        );
+        let classes: Vec<String> = runs
+            .size_classes()
+            .iter()
+            .filter(|(_, class)| class.failures.total() > 0)
+            .map(|(size, class)| {
+                format!(
+                    "up to {}: {:.1}% of {}",
+                    size,
+                    100.0 * class.failures.total() as f32 / class.mallocs as f32,
+                    class.mallocs
+                )
+            })
+            .collect();
+        println!("  Failed by size: {}", classes.join(", "));
+        let pressure: Vec<String> = runs
+            .pressure()
+            .iter()
+            .map(|(step, pressure)| format!("{}%: {:.1}%", step, 100.0 * pressure.failure_rate()))
+            .collect();
+        if !pressure.is_empty() {
+            println!("  Bytes failed by heap occupancy: {}", pressure.join(", "));
+        }
+    }
+    println!(
+        "Average splits per 1000 mallocs: {}",
+        runs.mean(|r| r.churn.per_thousand().0)
+    );
+    println!(
+        "Average merges per 1000 mallocs: {}",
+        runs.mean(|r| r.churn.per_thousand().1)
+    );
+    println!(
+        "Average mallocs: {}, frees: {}, splits: {}, merges: {}",
+        runs.mean(|r| r.usage.mallocs as f32),
+        runs.mean(|r| r.usage.frees as f32),
+        runs.mean(|r| r.usage.splits as f32),
+        runs.mean(|r| r.usage.merges as f32)
+    );
+    println!(
+        "Average peak live bytes: {}, peak allocations: {}",
+        runs.mean(|r| r.usage.peak_live_bytes as f32),
+        runs.mean(|r| r.usage.peak_allocations as f32)
+    );
+    print_phase(runs, &format!("first {} ops", workloads::COLD_OPS), |r| {
+        r.cold
+    });
+    print_phase(runs, "rest of the run", |r| r.steady);
+    println!(
+        "Average wall clock time: {:.3} ms, {:.0} ops per second",
+        runs.mean(|r| r.elapsed.as_secs_f32() * 1000.0),
+        runs.mean(|r| r.throughput())
+    );
+    print_metrics(runs);
+}
+
+// One line for the cold start or the steady state of the runs
+fn print_phase(runs: &Aggregate, name: &str, phase: impl Fn(&Results) -> Phase) {
+    println!(
+        "  In the {}: {} malloc fails, {} splits and {} merges per 1000 mallocs, {} external fragmentation",
+        name,
+        runs.mean(|r| phase(r).malloc_fails as f32),
+        runs.mean(|r| phase(r).churn.per_thousand().0),
+        runs.mean(|r| phase(r).churn.per_thousand().1),
+        runs.mean(|r| phase(r).external_frag)
+    );
+}
+
+// Whatever the collectors of the first run reported, averaged or
+// added up over all the runs
+fn print_metrics(runs: &Aggregate) {
+    let first = match runs.runs.first() {
+        Some(first) => first,
+        None => return,
+    };
+    for (name, metric) in first.metrics.iter() {
+        match metric {
+            Metric::Value(_) => println!("Average {}: {}", name, runs.metric(name).mean()),
+            Metric::Series(_) => {
+                let series: Vec<String> = runs
+                    .mean_series(name)
+                    .iter()
+                    .map(|value| format!("{:.3}", value))
+                    .collect();
+                println!("Average {}: {}", name, series.join(" "));
+            }
+            Metric::Histogram(_) => {
+                let counts: Vec<String> = runs
+                    .histogram(name)
+                    .iter()
+                    .map(|(bucket, count)| format!("{}:{}", bucket, count))
+                    .collect();
+                println!("Total {}: {}", name, counts.join(" "));
+            }
+        }
+    }
+}
+
+// Warm up a heap as asked for on the command line: `checkerboard`,
+// `aged:<ops>` of churn at the bench's ratio, a trace file to
+// replay, or nothing for an empty heap
+fn warm_up<T: Allocator>(
+    allocator: T,
+    warm: Option<&str>,
+    ratio: f64,
+    rng: &mut SeededRng,
+) -> Warm<T> {
+    match warm {
+        None => Warm::cold(allocator),
+        Some("checkerboard") => Warm::checkerboard(allocator, 32),
+        Some(aged) if aged.starts_with("aged:") => Warm::aged(
+            allocator,
+            aged["aged:".len()..]
+                .parse()
+                .expect("Could not parse number of ops"),
+            ratio,
+            32..=128,
+            rng,
+        ),
+        Some(path) => Warm::from_trace(
+            allocator,
+            &fs::read_to_string(path)
+                .expect("Could not read trace")
+                .parse()
+                .expect("Could not parse trace"),
+        ),
+    }
+}
+
+// The stack and random benches on every general purpose allocator,
+// one run per seed in the manifest, so the same manifest always
+// gives the same numbers. Returns the runs with the manifest
+fn bench_manifest(manifest: &Manifest) -> Archive {
+    let shape = match manifest.bench.as_str() {
+        "stack" => "Fixed",
+        "random" => "Random",
+        other => {
+            eprintln!("Unknown bench \"{}\" in manifest", other);
+            process::exit(1);
+        }
+    };
+
+    println!(
+        "{} size allocation with {}% malloc",
+        shape,
+        manifest.ratio * 100.0
+    );
+    let mut archive = Archive::new(manifest.clone());
+    for (name, make) in presets::general() {
+        println!("\n{} results", name);
+        let runs = seeded_runs(make, manifest);
+        print_results(&runs);
+        archive.add(name, &runs);
+    }
+    archive
+}
+
+// What a stack or random bench saved, without running it again
+fn show_archive(archive: &Archive) {
+    let manifest = &archive.manifest;
+    println!(
+        "{} bench with {}% malloc, saved with schema version {}",
+        manifest.bench,
+        manifest.ratio * 100.0,
+        archive.schema_version
     );
+    for (name, records) in archive.results.iter() {
+        let runs: Aggregate = records.iter().map(|record| record.results()).collect();
+        println!("\n{} results", name);
+        println!(
+            "Average malloc fails: {}",
+            runs.mean(|r| r.malloc_fails as f32)
+        );
+        println!("Average free fails: {}", runs.mean(|r| r.free_fails as f32));
+        println!(
+            "Average internal fragmentation: {}",
+            runs.mean(|r| r.internal_frag as f32)
+        );
+        println!(
+            "Average external fragmentation: {}",
+            runs.mean(|r| r.external_frag)
+        );
+        print_metrics(&runs);
+    }
+}
+
+// The heap is warmed up afresh for every seed, the same way each time
+fn seeded_runs(make: presets::Make, manifest: &Manifest) -> Aggregate {
+    manifest
+        .seeds
+        .iter()
+        .map(|&seed| {
+            let warm = warm_up(
+                Rounded::new(make(), manifest.rounding.clone()),
+                manifest.warm.as_deref(),
+                manifest.ratio,
+                &mut manifest.rng.seeded(manifest.warm_seed),
+            );
+            let mut rng = manifest.rng.seeded(seed);
+            let metrics = if manifest.metrics {
+                Metrics::builtin()
+            } else {
+                Metrics::new()
+            };
+            match manifest.bench.as_str() {
+                "stack" => workloads::stack_from(warm, manifest.ratio, metrics, &mut rng),
+                _ => workloads::random_from(warm, manifest.ratio, 32..=128, metrics, &mut rng),
+            }
+        })
+        .collect()
 }
 
-// Benches take the average of 5 runs
-fn bench_random(ratio: f64) {
+fn bench_shrink(ratio: f64) {
     let num_runs = 5;
-    let freelist = FreeList::new(0, 32768, true)
+    let mut freelist = FreeList::new(0, 32768, true)
         .align(32)
         .policy(Policy::First);
-    let mut results_vec = Vec::new();
-    for _ in 0..num_runs {
-        results_vec.push(workloads::random_memory(freelist.clone(), ratio));
+    let mut buddy = BuddyAllocator::new(5, 15);
+
+    println!(
+        "Random size allocation with {}% malloc, shrinking half of the frees\n",
+        ratio * 100.0
+    );
+    for (name, runs) in [
+        (
+            "Free list",
+            workloads::trials(&mut freelist, num_runs, |heap| {
+                workloads::shrinking(heap, ratio, Metrics::new(), &mut rand::thread_rng())
+            }),
+        ),
+        (
+            "Buddy allocator",
+            workloads::trials(&mut buddy, num_runs, |heap| {
+                workloads::shrinking(heap, ratio, Metrics::new(), &mut rand::thread_rng())
+            }),
+        ),
+    ] {
+        let requested = runs.mean(|r| r.value("shrink_requested"));
+        let recovered = runs.mean(|r| r.value("shrink_recovered"));
+        println!("{} results", name);
+        print_results(&runs);
+        println!("Average bytes released by shrinking: {}", requested);
+        println!("Average bytes recovered by shrinking: {}\n", recovered);
     }
+}
+
+// Each allocator merging free blocks as it frees them, against the
+// same allocator leaving them until a malloc fails
+fn bench_coalesce(ratio: f64) {
+    let freelist = |coalesce| {
+        FreeList::new(0, 32768, coalesce)
+            .align(32)
+            .policy(Policy::First)
+    };
+    let buddy = BuddyAllocator::new(5, 15);
 
     println!("Random size allocation with {}% malloc\n", ratio * 100.0);
-    println!("Free list results");
-    print_results(results_vec);
+    print_coalescing("Free list, coalescing on free", freelist(true), ratio);
+    print_coalescing("Free list, coalescing on failure", freelist(false), ratio);
+    print_coalescing("Buddy allocator, coalescing on free", buddy.clone(), ratio);
+    print_coalescing(
+        "Buddy allocator, coalescing on failure",
+        buddy.clone().coalesce_on_free(false),
+        ratio,
+    );
+    print_coalescing("Buddy allocator, lazy coalescing", buddy.lazy(true), ratio);
+}
+
+fn print_coalescing<T: Allocator + Clone>(name: &str, allocator: T, ratio: f64) {
+    let num_runs = 5;
+    let runs: Aggregate = (0..num_runs)
+        .map(|_| {
+            workloads::coalescing(
+                allocator.clone(),
+                ratio,
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+    println!("{} results", name);
+    print_results(&runs);
+    println!(
+        "Average coalesces after a failed malloc: {}",
+        runs.mean(|r| r.value("coalesces"))
+    );
+    println!(
+        "Average mallocs rescued by coalescing: {}",
+        runs.mean(|r| r.value("rescued"))
+    );
+    println!(
+        "Average merges the allocator deferred to a malloc: {}\n",
+        runs.mean(|r| r.churn.deferred as f32)
+    );
+}
+
+// Compacting the heap when a malloc fails, with more and more of it
+// pinned in place
+fn bench_compact(ratio: f64, pinned: &[f64]) {
+    let num_runs = 5;
+    let freelist = FreeList::new(0, 32768, true)
+        .align(32)
+        .policy(Policy::First);
+
+    println!(
+        "Random size allocation with {}% malloc, compacting when a malloc fails\n",
+        ratio * 100.0
+    );
+    println!(
+        "{:>8} {:>10} {:>12} {:>10} {:>12} {:>14} {:>10}",
+        "pinned", "fails", "compactions", "rescued", "moved bytes", "consolidation", "ext frag"
+    );
+    for &fraction in pinned {
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| {
+                workloads::compacting(
+                    freelist.clone(),
+                    ratio,
+                    fraction,
+                    Metrics::new(),
+                    &mut rand::thread_rng(),
+                )
+            })
+            .collect();
+        println!(
+            "{:>7.0}% {:>10.1} {:>12.1} {:>10.1} {:>12.1} {:>14.4} {:>10.4}",
+            fraction * 100.0,
+            runs.mean(|r| r.malloc_fails as f32),
+            runs.mean(|r| r.value("compactions")),
+            runs.mean(|r| r.value("rescued")),
+            runs.mean(|r| r.value("moved_bytes")),
+            runs.mean(|r| r.value("consolidation")),
+            runs.mean(|r| r.external_frag)
+        );
+    }
+}
+
+// First fit with a shrinking budget of free blocks to scan, failing
+// or falling back to the largest block once it runs out
+fn bench_budget(ratio: f64) {
+    let num_runs = 5;
+    let freelist = FreeList::new(0, 32768, true).align(32);
+
+    println!(
+        "Random size allocation with {}% malloc, first fit on a budget\n",
+        ratio * 100.0
+    );
+    println!(
+        "{:>9} {:>32} {:>32}",
+        "", "failing past the budget", "falling back to the largest"
+    );
+    println!(
+        "{:>9} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "budget", "fails", "ext frag", "scanned", "fails", "ext frag", "scanned"
+    );
+    let unbounded = (0..num_runs)
+        .map(|_| {
+            workloads::searching(
+                freelist.clone().policy(Policy::First),
+                ratio,
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect::<Aggregate>();
+    let row = |budget: String, failing: &Aggregate, falling_back: &Aggregate| {
+        let scanned = |r: &Results| r.value("nodes_scanned") / r.value("searches").max(1.0);
+        println!(
+            "{:>9} {:>10.1} {:>10.4} {:>10.2} {:>10.1} {:>10.4} {:>10.2}",
+            budget,
+            failing.mean(|r| r.malloc_fails as f32),
+            failing.mean(|r| r.external_frag),
+            failing.mean(scanned),
+            falling_back.mean(|r| r.malloc_fails as f32),
+            falling_back.mean(|r| r.external_frag),
+            falling_back.mean(scanned)
+        );
+    };
+    row("unbounded".to_string(), &unbounded, &unbounded);
+
+    for budget in [32, 16, 8, 4, 2, 1] {
+        let [failing, falling_back] = [false, true].map(|fallback| {
+            (0..num_runs)
+                .map(|_| {
+                    let list = freelist
+                        .clone()
+                        .policy(Policy::Bounded { budget, fallback });
+                    workloads::searching(list, ratio, Metrics::new(), &mut rand::thread_rng())
+                })
+                .collect::<Aggregate>()
+        });
+        row(budget.to_string(), &failing, &falling_back);
+    }
+}
+
+// The same traces replayed on allocators with and without a bound
+// on their search, timed by the cost model, for each deadline
+fn bench_deadlines(ratio: f64, search: f32, deadlines: &[f32]) {
+    let mut rng = rand::thread_rng();
+    let traces: Vec<Trace> = (0..5)
+        .map(|_| trace::random_memory(ratio, &mut rng))
+        .collect();
+    let model = LatencyModel::default().search(search);
+    let names = [
+        "tlsf",
+        "buddy",
+        "first fit",
+        "best fit",
+        "random fit",
+        "bounded first fit",
+    ];
+    // Random fit is seeded once for each row, so every column of it
+    // replays the same picks
+    let runs = |i: usize, seed: u64, metrics: &dyn Fn() -> Metrics| -> Aggregate {
+        traces
+            .iter()
+            .map(|trace| {
+                let allocator: Box<dyn Allocator> = match i {
+                    0 => Box::new(Tlsf::new(0, 32768)),
+                    1 => Box::new(BuddyAllocator::new(5, 15)),
+                    2 => Box::new(FreeList::new(0, 32768, true).policy(Policy::First)),
+                    3 => Box::new(FreeList::new(0, 32768, true)),
+                    4 => Box::new(FreeList::new(0, 32768, true).policy(Policy::Random { seed })),
+                    _ => Box::new(FreeList::new(0, 32768, true).policy(Policy::Bounded {
+                        budget: 4,
+                        fallback: true,
+                    })),
+                };
+                workloads::replayed(allocator, trace, metrics())
+            })
+            .collect()
+    };
+
+    println!(
+        "Random size allocation with {}% malloc, {} per free block searched\n",
+        ratio * 100.0,
+        search
+    );
+    print!(
+        "{:<18} {:>8} {:>8} {:>8}",
+        "allocator", "fails", "mean", "worst"
+    );
+    for deadline in deadlines {
+        print!(" {:>8}", format!("<= {}", deadline));
+    }
     println!();
+    for (i, name) in names.iter().enumerate() {
+        let seed = rand::random();
+        let timed = runs(i, seed, &|| {
+            Metrics::new()
+                .register(model.clone())
+                .register(model.clone().worst_case())
+        });
+        print!(
+            "{:<18} {:>8.1} {:>8.1} {:>8.1}",
+            name,
+            timed.mean(|r| r.malloc_fails as f32),
+            timed.metric("latency_model").mean(),
+            timed.metric("worst_latency").mean()
+        );
+        for &deadline in deadlines {
+            let missed = runs(i, seed, &|| {
+                Metrics::new().register(Deadlines::new(model.clone(), deadline))
+            });
+            print!(" {:>8.4}", missed.metric("deadline_misses").mean());
+        }
+        println!();
+    }
+}
 
-    let buddy = BuddyAllocator::new(5, 15);
-    let mut results_vec = Vec::new();
-    for _ in 0..num_runs {
-        results_vec.push(workloads::random_memory(buddy.clone(), ratio));
+fn bench_aligned(ratio: f64) {
+    let num_runs = 5;
+    let aligns = [8, 16, 16, 64, 64, 4096];
+
+    println!(
+        "Random size allocation with {}% malloc, aligned to one of {:?}\n",
+        ratio * 100.0,
+        aligns
+    );
+    for (name, classes) in [
+        ("Single free list", &[][..]),
+        ("Free list bucketed by alignment", &[16, 64, 4096][..]),
+    ] {
+        let list = AlignedFreeList::new(0, 65536, classes);
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| {
+                workloads::aligned(
+                    list.clone(),
+                    ratio,
+                    &aligns,
+                    Metrics::new(),
+                    &mut rand::thread_rng(),
+                )
+            })
+            .collect();
+        let scanned = runs.mean(|r| r.value("nodes_scanned") / r.value("searches").max(1.0));
+        println!("{} results", name);
+        print_results(&runs);
+        println!("Average free blocks scanned per malloc: {}\n", scanned);
     }
 
-    println!("Buddy allocator results");
-    print_results(results_vec);
+    let list = FreeList::new(0, 65536, true).policy(Policy::First);
+    let runs: Aggregate = (0..num_runs)
+        .map(|_| {
+            workloads::aligned_padded(
+                list.clone(),
+                ratio,
+                &aligns,
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+    let scanned = runs.mean(|r| r.value("nodes_scanned") / r.value("searches").max(1.0));
+    println!("Free list padding blocks to align them results");
+    print_results(&runs);
+    println!("Average free blocks scanned per malloc: {}", scanned);
 }
 
-fn bench_stack(ratio: f64) {
+// With no quick sizes every malloc searches the free list, which
+// makes the plain free list to compare against
+fn bench_quick_fit(ratio: f64, sizes: &[usize]) {
     let num_runs = 5;
     let freelist = FreeList::new(0, 32768, true)
         .align(32)
         .policy(Policy::First);
-    let mut results_vec = Vec::new();
-    for _ in 0..num_runs {
-        results_vec.push(workloads::stack(freelist.clone(), ratio));
+
+    println!(
+        "Random size allocation with {}% malloc, mostly of sizes {:?}\n",
+        ratio * 100.0,
+        sizes
+    );
+    for (name, quick) in [("Free list", &[][..]), ("Quick fit", sizes)] {
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| {
+                let heap = QuickFit::new(freelist.clone(), quick);
+                workloads::quick_fit(heap, ratio, sizes, Metrics::new(), &mut rand::thread_rng())
+            })
+            .collect();
+        let hit_rate = runs.mean(|r| {
+            r.value("cache_hits") / (r.value("cache_hits") + r.value("cache_misses")).max(1.0)
+        });
+        println!("{} results", name);
+        print_results(&runs);
+        println!("Average quick list hit rate: {}", hit_rate);
+        println!(
+            "Average quick list flushes: {}",
+            runs.mean(|r| r.value("cache_flushes"))
+        );
+        println!(
+            "Average free list searches: {}",
+            runs.mean(|r| r.value("searches"))
+        );
+        println!(
+            "Average free blocks scanned: {}\n",
+            runs.mean(|r| r.value("nodes_scanned"))
+        );
+    }
+}
+
+fn bench_per_cpu(ratio: f64, cpus: usize, threshold: usize) {
+    let num_runs = 5;
+    let freelist = FreeList::new(0, 32768, true)
+        .align(32)
+        .policy(Policy::First);
+    let buddy = BuddyAllocator::new(5, 15);
+
+    println!(
+        "Size class allocation with {}% malloc on {} CPUs, flushing past {} cached blocks\n",
+        ratio * 100.0,
+        cpus,
+        threshold
+    );
+    for (name, runs) in [
+        (
+            "Free list",
+            (0..num_runs)
+                .map(|_| {
+                    let cached = PerCpu::new(freelist.clone(), cpus).threshold(threshold);
+                    workloads::per_cpu(cached, ratio, Metrics::new(), &mut rand::thread_rng())
+                })
+                .collect::<Aggregate>(),
+        ),
+        (
+            "Buddy allocator",
+            (0..num_runs)
+                .map(|_| {
+                    let cached = PerCpu::new(buddy.clone(), cpus).threshold(threshold);
+                    workloads::per_cpu(cached, ratio, Metrics::new(), &mut rand::thread_rng())
+                })
+                .collect(),
+        ),
+    ] {
+        let hit_rate = runs.mean(|r| {
+            r.value("cache_hits") / (r.value("cache_hits") + r.value("cache_misses")).max(1.0)
+        });
+        let flushes = runs.mean(|r| r.value("cache_flushes"));
+        println!("{} results", name);
+        print_results(&runs);
+        println!("Average cache hit rate: {}", hit_rate);
+        println!("Average cache flushes: {}\n", flushes);
+    }
+}
+
+fn bench_ids(ratio: f64, count: usize) {
+    let num_runs = 5;
+
+    println!(
+        "Id allocation with {}% malloc from {} ids\n",
+        ratio * 100.0,
+        count
+    );
+    for (name, policy) in [
+        ("Lowest free id", IdPolicy::Lowest),
+        ("Random id", IdPolicy::Random),
+        (
+            "Lowest id, holding back 32 freed ids",
+            IdPolicy::AvoidRecent(32),
+        ),
+    ] {
+        let runs: Aggregate = (0..num_runs)
+            .map(|run| {
+                workloads::ids(
+                    IdSpace::new(0, count, run).policy(policy),
+                    ratio,
+                    Metrics::new(),
+                    &mut rand::thread_rng(),
+                )
+            })
+            .collect();
+        let latency = runs.mean(|r| r.value("mean_reuse_latency"));
+        let shortest = runs.metric("min_reuse_latency").min();
+        println!("{} results", name);
+        print_results(&runs);
+        println!("Average reuse latency: {} ops", latency);
+        match shortest {
+            Some(shortest) => println!("Shortest reuse latency: {} ops\n", shortest),
+            None => println!("No id was reused\n"),
+        }
+    }
+}
+
+// Segregated free lists with more and more of the class boundaries,
+// from a single first fit list up to a class starting at each one
+fn bench_segregated(ratio: f64, bounds: &[usize]) {
+    let num_runs = 5;
+    let sizes = 16..=512;
+
+    println!(
+        "Random size allocation from {} to {} bytes with {}% malloc\n",
+        sizes.start(),
+        sizes.end(),
+        ratio * 100.0
+    );
+    println!(
+        "{:>8} {:>10} {:>8} {:>10} {:>10} {:>10}",
+        "classes", "last from", "fails", "ext frag", "scanned", "splits"
+    );
+    for i in 0..=bounds.len() {
+        let list = SegregatedList::new(0, 32768).classes(bounds[..i].to_vec());
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| {
+                workloads::segregated(
+                    list.clone(),
+                    ratio,
+                    sizes.clone(),
+                    Metrics::new(),
+                    &mut rand::thread_rng(),
+                )
+            })
+            .collect();
+        println!(
+            "{:>8} {:>10} {:>8.1} {:>10.4} {:>10.2} {:>10.2}",
+            i + 1,
+            list.bounds()[i],
+            runs.mean(|r| r.malloc_fails as f32),
+            runs.mean(|r| r.external_frag),
+            runs.mean(|r| r.value("nodes_scanned") / r.value("searches").max(1.0)),
+            runs.mean(|r| r.churn.splits as f32 / r.churn.mallocs.max(1) as f32)
+        );
     }
+}
+
+// Critical mallocs from reservations of each size, with the rest
+// of the heap left to regular mallocs. No reservation sends the
+// critical mallocs to the heap with everything else
+fn bench_reservation(ratio: f64, critical: f64, sizes: &[usize]) {
+    let num_runs = 5;
+
+    println!(
+        "Random size allocation with {}% malloc, {}% of mallocs critical\n",
+        ratio * 100.0,
+        critical * 100.0
+    );
+    println!(
+        "{:>9} {:>10} {:>10} {:>10} {:>12}",
+        "reserved", "fails", "critical", "ext frag", "utilization"
+    );
+    for &size in sizes {
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| {
+                let mut heap = Reserving::new(FreeList::new(0, 32768, true).align(32));
+                let id = heap.reserve(size);
+                workloads::reserved(
+                    heap,
+                    id,
+                    ratio,
+                    critical,
+                    Metrics::new(),
+                    &mut rand::thread_rng(),
+                )
+            })
+            .collect();
+        println!(
+            "{:>9} {:>10.1} {:>10.1} {:>10.4} {:>11.1}%",
+            size,
+            runs.mean(|r| r.malloc_fails as f32),
+            runs.metric("critical_fails").mean(),
+            runs.mean(|r| r.external_frag),
+            100.0 * runs.metric("reservation_utilization").mean()
+        );
+    }
+}
+
+// Both allocators get 16K of the same 32K address space and share
+// every run, so each pair of results saw the same requests
+fn bench_head_to_head(ratio: f64, share: f64) {
+    let num_runs = 5;
+    let (buddy, freelist): (Aggregate, Aggregate) = (0..num_runs)
+        .map(|run| {
+            // The buddy allocator always starts at 0, so it gets the
+            // low half
+            let heap = HeadToHead::new(
+                BuddyAllocator::new(5, 14),
+                FreeList::new(16384, 16384, true)
+                    .align(32)
+                    .policy(Policy::First),
+                16384,
+                run,
+            )
+            .share(1.0 - share);
+            let [buddy, freelist] = workloads::head_to_head(heap, ratio, &mut rand::thread_rng());
+            (buddy, freelist)
+        })
+        .unzip();
 
-    println!("Fixed size allocation with {}% malloc\n", ratio * 100.0);
+    println!(
+        "Random size allocation with {}% malloc, {}% of mallocs to the free list\n",
+        ratio * 100.0,
+        share * 100.0
+    );
     println!("Free list results");
-    print_results(results_vec);
+    print_results(&freelist);
+    println!();
+    println!("Buddy allocator results");
+    print_results(&buddy);
+}
+
+// Mallocs pinned to windows of the address space: a small low one
+// for DMA, each half for a NUMA node, or anywhere
+fn bench_regions(ratio: f64) {
+    let num_runs = 5;
+    let list = FreeList::new(0, 32768, true)
+        .align(32)
+        .policy(Policy::First)
+        .indexed(64);
+    let windows = [
+        ("DMA", 0..4096),
+        ("Node 0", 0..16384),
+        ("Node 1", 16384..32768),
+        ("Anywhere", 0..32768),
+    ];
+    let ranges: Vec<Range<usize>> = windows.iter().map(|(_, range)| range.clone()).collect();
+
+    println!(
+        "Random size allocation with {}% malloc, each malloc from one of {} windows\n",
+        ratio * 100.0,
+        windows.len()
+    );
+    let runs: Aggregate = (0..num_runs)
+        .map(|_| {
+            workloads::regions(
+                list.clone(),
+                ratio,
+                &ranges,
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+    print_results(&runs);
     println!();
 
-    let buddy = BuddyAllocator::new(5, 15);
-    let mut results_vec = Vec::new();
-    for _ in 0..num_runs {
-        results_vec.push(workloads::stack(buddy.clone(), ratio));
+    println!(
+        "{:>10} {:>16} {:>10} {:>10} {:>14}",
+        "window", "range", "mallocs", "fails", "failed bytes"
+    );
+    let [mallocs, bytes, malloc_fails, failed_bytes] = [
+        "window_mallocs",
+        "window_bytes",
+        "window_malloc_fails",
+        "window_failed_bytes",
+    ]
+    .map(|name| runs.histogram(name));
+    for (i, (name, range)) in windows.iter().enumerate() {
+        let count = |counts: &BTreeMap<usize, usize>| counts.get(&i).copied().unwrap_or(0);
+        let window = Pressure {
+            mallocs: count(&mallocs),
+            bytes: count(&bytes),
+            malloc_fails: count(&malloc_fails),
+            failed_bytes: count(&failed_bytes),
+        };
+        println!(
+            "{:>10} {:>16} {:>10} {:>9.1}% {:>13.1}%",
+            name,
+            format!("{}-{}", range.start, range.end),
+            window.mallocs,
+            100.0 * window.malloc_fails as f32 / window.mallocs.max(1) as f32,
+            100.0 * window.failure_rate()
+        );
     }
+}
+
+// jemalloc's size classes against the buddy allocator's powers of
+// two, on heaps of the same size
+fn bench_bins(ratio: f64, page: usize) {
+    let num_runs = 5;
+    let bins = BinAllocator::new(0, 32768, page);
+    let buddy = BuddyAllocator::new(5, 15);
+
+    println!("Random size allocation with {}% malloc\n", ratio * 100.0);
+    let runs: Aggregate = (0..num_runs)
+        .map(|_| workloads::random_memory(bins.clone(), ratio, &mut rand::thread_rng()))
+        .collect();
+    println!("Size class bins with {} byte pages results", page);
+    print_results(&runs);
+    println!();
 
+    let runs: Aggregate = (0..num_runs)
+        .map(|_| workloads::random_memory(buddy.clone(), ratio, &mut rand::thread_rng()))
+        .collect();
     println!("Buddy allocator results");
-    print_results(results_vec);
+    print_results(&runs);
 }
 
-fn main() {
-    let matches = App::new("Free space simulator")
-        .author("Calvin")
-        .about("Simulates a list based freelist and buddy allocator")
-        .version("0.1.0")
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .subcommand(
-            SubCommand::with_name("demo")
-                .setting(AppSettings::SubcommandRequiredElseHelp)
-                .about("Demo an allocator")
-                .subcommand(
-                    SubCommand::with_name("freelist")
-                        .about("Run the freelist")
-                        .arg(
-                            Arg::with_name("coalesce")
-                                .long("coalesce")
-                                .short("c")
-                                .help("Enable coalescing"),
-                        ),
-                )
-                .subcommand(SubCommand::with_name("buddy").about("Run the buddy allocator")),
-        )
-        .subcommand(
-            SubCommand::with_name("bench")
-                .about("Run a workload on freelist and buddy")
-                .setting(AppSettings::SubcommandRequiredElseHelp)
-                .subcommand(
-                    SubCommand::with_name("stack")
-                        .about("A workload that resembles a stack")
-                        .arg(
-                            Arg::with_name("ratio")
-                                .long("ratio")
-                                .short("r")
-                                .default_value("0.5")
-                                .takes_value(true),
-                        ),
+// Hoard with thread heaps giving superblocks back sooner or later,
+// down to never
+fn bench_hoard(ratio: f64, threads: usize, fractions: &[f64]) {
+    let num_runs = 5;
+
+    println!(
+        "Random size allocation with {}% malloc on {} threads\n",
+        ratio * 100.0,
+        threads
+    );
+    println!(
+        "{:>10} {:>10} {:>10} {:>10} {:>10} {:>14}",
+        "emptiness", "fails", "int frag", "blowup", "moves", "false sharing"
+    );
+    for &fraction in fractions {
+        let heap = HoardSim::new(0, 32768, threads, 1024).empty_fraction(fraction);
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| workloads::hoard(heap.clone(), ratio, Metrics::new(), &mut rand::thread_rng()))
+            .collect();
+        println!(
+            "{:>10} {:>10.1} {:>10.1} {:>10.3} {:>10.1} {:>13.1}%",
+            fraction,
+            runs.mean(|r| r.malloc_fails as f32),
+            runs.mean(|r| r.internal_frag as f32),
+            runs.mean(|r| r.value("blowup")),
+            runs.mean(|r| r.value("superblock_moves")),
+            100.0 * runs.mean(|r| r.value("false_sharing"))
+        );
+    }
+}
+
+// Best fit scanning the whole list, and finding its node in the
+// index by size, on heaps aged into many free blocks first
+fn bench_bestfit(ratio: f64, heaps: &[usize]) {
+    let num_runs = 5;
+
+    println!("Random size allocation with {}% malloc\n", ratio * 100.0);
+    println!(
+        "{:>10} {:>10} {:>12} {:>12} {:>12} {:>12}",
+        "heap", "fails", "scanned", "indexed", "scan ms", "indexed ms"
+    );
+    for &heap in heaps {
+        let list = FreeList::new(0, heap, true).align(32);
+        let (scan, indexed): (Vec<Results>, Vec<Results>) = (0..num_runs)
+            .map(|_| {
+                // Churn three in four mallocs until about three quarters
+                // of the heap is live, full of holes in between
+                let mut rng = rand::thread_rng();
+                let aged = Warm::aged(list.clone(), heap / 64, 0.75, 32..=128, &mut rng).allocator;
+                (
+                    workloads::searching(
+                        aged.clone(),
+                        ratio,
+                        Metrics::new(),
+                        &mut rand::thread_rng(),
+                    ),
+                    workloads::searching(
+                        aged.by_size(),
+                        ratio,
+                        Metrics::new(),
+                        &mut rand::thread_rng(),
+                    ),
                 )
-                .subcommand(
-                    SubCommand::with_name("random")
-                        .about("A workload that mallocs random amount of memory")
-                        .arg(
-                            Arg::with_name("ratio")
-                                .long("ratio")
-                                .short("r")
-                                .default_value("0.5")
-                                .takes_value(true),
-                        ),
-                ),
-        )
-        .get_matches();
+            })
+            .unzip();
+        let (scan, indexed): (Aggregate, Aggregate) =
+            (scan.into_iter().collect(), indexed.into_iter().collect());
+        let per_malloc = |r: &Results| r.value("nodes_scanned") / r.value("searches").max(1.0);
+        println!(
+            "{:>10} {:>10.1} {:>12.1} {:>12.1} {:>12.3} {:>12.3}",
+            heap,
+            scan.mean(|r| r.malloc_fails as f32),
+            scan.mean(per_malloc),
+            indexed.mean(per_malloc),
+            scan.mean(|r| r.elapsed.as_secs_f32() * 1000.0),
+            indexed.mean(|r| r.elapsed.as_secs_f32() * 1000.0)
+        );
+    }
+}
 
-    match matches.subcommand() {
-        ("demo", Some(demo)) => match demo.subcommand() {
-            ("freelist", Some(freelist)) => demos::freelist(freelist.is_present("coalesce")),
-            ("buddy", Some(_)) => demos::buddy(),
-            _ => unreachable!(),
-        },
-        ("bench", Some(bench)) => match bench.subcommand() {
-            ("random", Some(random)) => bench_random(
-                random
-                    .value_of("ratio")
-                    .unwrap()
-                    .parse()
-                    .expect("Could not parse input"),
-            ),
-            ("stack", Some(stack)) => bench_stack(
-                stack
-                    .value_of("ratio")
+// A mimalloc model next to jemalloc style bins on the same heap,
+// then how full the mimalloc pages were over the runs
+fn bench_mimalloc(ratio: f64, segment: usize, page: usize) {
+    let num_runs = 5;
+    let heap = Mimalloc::new(0, 32768, segment, page);
+
+    println!("Random size allocation with {}% malloc\n", ratio * 100.0);
+    let runs: Aggregate = (0..num_runs)
+        .map(|_| workloads::mimalloc(heap.clone(), ratio, Metrics::new(), &mut rand::thread_rng()))
+        .collect();
+    println!(
+        "mimalloc with {} byte segments of {} byte pages results",
+        segment, page
+    );
+    print_results(&runs);
+    println!();
+
+    let bins: Aggregate = (0..num_runs)
+        .map(|_| {
+            workloads::random_memory(
+                BinAllocator::new(0, 32768, page),
+                ratio,
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+    println!("Size class bins with {} byte pages results", page);
+    print_results(&bins);
+    println!();
+
+    let occupancy = runs.histogram("page_occupancy");
+    let total: usize = occupancy.values().sum();
+    println!("{:>10} {:>10}", "occupancy", "pages");
+    for (step, count) in occupancy {
+        println!(
+            "{:>9}% {:>9.1}%",
+            step,
+            100.0 * count as f32 / total.max(1) as f32
+        );
+    }
+}
+
+// A span heap next to the buddy allocator and free list it is built
+// from, then where the span heap's fragmentation came from
+fn bench_spans(ratio: f64, span: usize) {
+    let num_runs = 5;
+    let heap = SpanHeap::new(BuddyAllocator::new(5, 15), span);
+
+    println!(
+        "Small and large allocation with {}% malloc\n",
+        ratio * 100.0
+    );
+    let runs: Aggregate = (0..num_runs)
+        .map(|_| workloads::spans(heap.clone(), ratio, Metrics::new(), &mut rand::thread_rng()))
+        .collect();
+    println!("Span heap with {} byte spans results", span);
+    print_results(&runs);
+    println!();
+
+    let buddy: Aggregate = (0..num_runs)
+        .map(|_| {
+            workloads::small_and_large(
+                BuddyAllocator::new(5, 15),
+                ratio,
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+    println!("Buddy allocator results");
+    print_results(&buddy);
+    println!();
+
+    let list = FreeList::new(0, 32768, true).policy(Policy::First);
+    let lists: Aggregate = (0..num_runs)
+        .map(|_| {
+            workloads::small_and_large(list.clone(), ratio, Metrics::new(), &mut rand::thread_rng())
+        })
+        .collect();
+    println!("Free list first fit results");
+    print_results(&lists);
+    println!();
+
+    println!(
+        "{:>10} {:>10} {:>10} {:>10}",
+        "level", "int frag", "free", "ext frag"
+    );
+    for (i, &(name, _)) in heap.levels().iter().enumerate() {
+        println!(
+            "{:>10} {:>10.1} {:>10.1} {:>10.1}",
+            name,
+            runs.mean(|r| r.series("level_internal")[i]),
+            runs.mean(|r| r.series("level_free")[i]),
+            runs.mean(|r| r.series("level_external")[i])
+        );
+    }
+}
+
+// Span heap spans and bin runs under each retention policy, and how
+// often they emptied, went back, stayed and were used again
+fn bench_recycling(ratio: f64, retentions: &[Retention]) {
+    let num_runs = 5;
+
+    println!(
+        "Small and large allocation with {}% malloc\n",
+        ratio * 100.0
+    );
+    for name in ["Span heap", "Size class bins"] {
+        println!("{}", name);
+        println!(
+            "{:>10} {:>8} {:>8} {:>8} {:>9} {:>9} {:>8} {:>13}",
+            "retention",
+            "fails",
+            "taken",
+            "emptied",
+            "returned",
+            "retained",
+            "reused",
+            "peak retained"
+        );
+        for &retention in retentions {
+            let runs: Aggregate = (0..num_runs)
+                .map(|_| match name {
+                    "Span heap" => workloads::spans(
+                        SpanHeap::new(BuddyAllocator::new(5, 15), 2048).retain(retention),
+                        ratio,
+                        Metrics::new(),
+                        &mut rand::thread_rng(),
+                    ),
+                    _ => workloads::bins(
+                        BinAllocator::new(0, 32768, 1024).retain(retention),
+                        ratio,
+                        Metrics::new(),
+                        &mut rand::thread_rng(),
+                    ),
+                })
+                .collect();
+            let label = match retention {
+                Retention::Release => "release".to_string(),
+                Retention::Keep(count) => format!("keep {}", count),
+                Retention::All => "all".to_string(),
+            };
+            println!(
+                "{:>10} {:>8.1} {:>8.1} {:>8.1} {:>9.1} {:>9.1} {:>8.1} {:>13.1}",
+                label,
+                runs.mean(|r| r.malloc_fails as f32),
+                runs.mean(|r| r.value("recycling_taken")),
+                runs.mean(|r| r.value("recycling_emptied")),
+                runs.mean(|r| r.value("recycling_returned")),
+                runs.mean(|r| r.value("recycling_retained")),
+                runs.mean(|r| r.value("recycling_reused")),
+                runs.mean(|r| r.value("recycling_peak_retained"))
+            );
+        }
+        println!();
+    }
+}
+
+// A free list that doesn't coalesce on its own, with each free routed
+// by a few policies: straight back, coalescing every time, caching
+// small blocks, and caching young blocks while coalescing old ones
+fn bench_aging(ratio: f64) {
+    let num_runs = 5;
+    let list = FreeList::new(0, 32768, false)
+        .align(32)
+        .policy(Policy::First);
+    type Router = fn(&Freed) -> Route;
+    let policies: [(&str, Router); 4] = [
+        ("free", |_| Route::Free),
+        ("coalesce", |_| Route::Coalesce),
+        ("cache small", |block| {
+            if block.size <= 32 && block.cached < 1024 {
+                Route::Cache
+            } else {
+                Route::Free
+            }
+        }),
+        ("young/old", |block| {
+            if block.age < 20 && block.cached < 1024 {
+                Route::Cache
+            } else if block.age > 200 {
+                Route::Coalesce
+            } else {
+                Route::Free
+            }
+        }),
+    ];
+
+    println!("Size class allocation with {}% malloc\n", ratio * 100.0);
+    println!(
+        "{:>12} {:>8} {:>9} {:>9} {:>8} {:>8} {:>10} {:>8}",
+        "policy", "fails", "ext frag", "hit rate", "cached", "freed", "coalesced", "merges"
+    );
+    for (name, policy) in policies {
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| {
+                workloads::aging(
+                    Aging::new(list.clone(), policy),
+                    ratio,
+                    Metrics::new(),
+                    &mut rand::thread_rng(),
+                )
+            })
+            .collect();
+        println!(
+            "{:>12} {:>8.1} {:>9.3} {:>9.3} {:>8.1} {:>8.1} {:>10.1} {:>8.1}",
+            name,
+            runs.mean(|r| r.malloc_fails as f32),
+            runs.mean(|r| r.external_frag),
+            runs.mean(|r| r.value("cache_hits")
+                / (r.value("cache_hits") + r.value("cache_misses")).max(1.0)),
+            runs.mean(|r| r.value("routed_cached")),
+            runs.mean(|r| r.value("routed_freed")),
+            runs.mean(|r| r.value("routed_coalesced")),
+            runs.mean(|r| r.churn.merges as f32)
+        );
+    }
+}
+
+// Every allocator of the device memory preset on the same mix of
+// textures and buffers
+fn bench_device(frames: usize) {
+    let num_runs = 5;
+
+    println!(
+        "Device memory: {}M heap, {}K pages, {} frames of textures and buffers\n",
+        presets::DEVICE_HEAP >> 20,
+        presets::DEVICE_PAGE >> 10,
+        frames
+    );
+    for (name, make) in presets::device() {
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| workloads::device(make(), frames, Metrics::new(), &mut rand::thread_rng()))
+            .collect();
+        println!("{} results", name);
+        print_results(&runs);
+        println!();
+    }
+}
+
+// One allocation stream per node, busier on higher nodes, with a
+// fraction of mallocs asking for a node other than their own
+fn bench_numa(ratio: f64, nodes: usize, remote: f64) {
+    let num_runs = 5;
+    let heap = Numa::new(0, 32768, nodes);
+
+    println!(
+        "Random size allocation with {}% malloc on {} nodes, {}% of mallocs remote\n",
+        ratio * 100.0,
+        nodes,
+        remote * 100.0
+    );
+    let runs: Aggregate = (0..num_runs)
+        .map(|_| {
+            workloads::numa(
+                heap.clone(),
+                ratio,
+                remote,
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+    print_results(&runs);
+
+    let local = runs.mean(|r| r.value("local_mallocs"));
+    let elsewhere = runs.mean(|r| r.value("remote_mallocs"));
+    println!(
+        "Average mallocs on the home node: {}, elsewhere: {} ({:.1}% local)",
+        local,
+        elsewhere,
+        100.0 * local / (local + elsewhere).max(1.0)
+    );
+    let imbalance: Vec<String> = runs
+        .mean_series("node_imbalance")
+        .iter()
+        .map(|value| format!("{:.3}", value))
+        .collect();
+    println!(
+        "Average node imbalance every {} ops: {}",
+        workloads::SAMPLE_EVERY,
+        imbalance.join(" ")
+    );
+}
+
+// Small requests to a buddy allocator in the low half of the heap
+// and the rest to a free list in the high half, at each threshold
+fn bench_hybrid(ratio: f64, thresholds: &[usize]) {
+    let num_runs = 5;
+
+    println!("Random size allocation with {}% malloc\n", ratio * 100.0);
+    for &threshold in thresholds {
+        let heap = HybridAllocator::new(
+            BuddyAllocator::new(5, 14),
+            FreeList::new(16384, 16384, true)
+                .align(32)
+                .policy(Policy::First),
+            threshold,
+        )
+        .unwrap();
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| workloads::random_memory(heap.clone(), ratio, &mut rand::thread_rng()))
+            .collect();
+        println!("Buddy allocator under {} bytes results", threshold);
+        print_results(&runs);
+        println!();
+    }
+}
+
+// The same workloads on buddy allocators that only differ in which
+// free block of a level they hand out, then the working set of one
+// shared trace on each
+fn bench_buddy_order(ratio: f64, page: usize) {
+    let num_runs = 5;
+    let orders = [
+        ("FIFO", Order::Fifo),
+        ("LIFO", Order::Lifo),
+        ("Lowest address", Order::Address),
+    ];
+
+    println!("Random size allocation with {}% malloc\n", ratio * 100.0);
+    for &(name, order) in orders.iter() {
+        let buddy = BuddyAllocator::new(5, 15).order(order);
+        let runs: Aggregate = (0..num_runs)
+            .map(|_| workloads::random_memory(buddy.clone(), ratio, &mut rand::thread_rng()))
+            .collect();
+        println!("{} results", name);
+        print_results(&runs);
+        println!();
+    }
+
+    let windows: Vec<usize> = (0..10).map(|i| 1 << i).collect();
+    let trace = trace::random_memory(ratio, &mut rand::thread_rng());
+    let curves: Vec<Vec<(usize, f32)>> = orders
+        .iter()
+        .map(|&(_, order)| {
+            let mut buddy = BuddyAllocator::new(5, 15).order(order);
+            locality::working_set(&trace.replay(&mut buddy), page, &windows)
+        })
+        .collect();
+
+    println!(
+        "Average working set size in {} byte pages over {} ops\n",
+        page,
+        trace.len()
+    );
+    println!(
+        "{:>8} {:>10} {:>10} {:>10}",
+        "window", "fifo", "lifo", "address"
+    );
+    for (i, &window) in windows.iter().enumerate() {
+        println!(
+            "{:>8} {:>10.2} {:>10.2} {:>10.2}",
+            window, curves[0][i].1, curves[1][i].1, curves[2][i].1
+        );
+    }
+}
+
+// Mallocs arrive on a simulation clock and are freed after a random
+// lifetime, so how full the heap is follows the arrival rate
+fn bench_arrivals(process: Arrivals, lifetime: f64, correlation: f64) {
+    let num_runs = 5;
+    let arrivals = 2000;
+    let freelist: Aggregate = (0..num_runs)
+        .map(|_| {
+            let freelist = FreeList::new(0, 32768, true)
+                .align(32)
+                .policy(Policy::First);
+            workloads::timed(
+                freelist,
+                &process,
+                arrivals,
+                lifetime,
+                correlation,
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+    let buddy: Aggregate = (0..num_runs)
+        .map(|_| {
+            let buddy = BuddyAllocator::new(5, 15);
+            workloads::timed(
+                buddy,
+                &process,
+                arrivals,
+                lifetime,
+                correlation,
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+
+    println!(
+        "{} random size mallocs living {} on average, size and lifetime correlated by {}\n",
+        arrivals, lifetime, correlation
+    );
+    println!("Free list results");
+    print_results(&freelist);
+    print_loads(&freelist);
+    println!();
+    println!("Buddy allocator results");
+    print_results(&buddy);
+    print_loads(&buddy);
+}
+
+// A controller sets the malloc ratio before every op so the heap
+// fills up to `peak` and then swings around it
+fn bench_controlled(peak: f64, amplitude: f64, period: usize) {
+    let num_runs = 5;
+    let ops = 4000;
+    let target = Occupancy::RampOscillate {
+        peak,
+        over: ops / 4,
+        amplitude,
+        period,
+    };
+    let freelist: Aggregate = (0..num_runs)
+        .map(|_| {
+            let freelist = FreeList::new(0, 32768, true)
+                .align(32)
+                .policy(Policy::First);
+            workloads::controlled(
+                freelist,
+                target,
+                ops,
+                Controller::default(),
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+    let buddy: Aggregate = (0..num_runs)
+        .map(|_| {
+            let buddy = BuddyAllocator::new(5, 15);
+            workloads::controlled(
+                buddy,
+                target,
+                ops,
+                Controller::default(),
+                Metrics::new(),
+                &mut rand::thread_rng(),
+            )
+        })
+        .collect();
+
+    println!(
+        "Random size allocation ramping to {}% full, swinging {}% every {} ops\n",
+        peak * 100.0,
+        amplitude * 100.0,
+        period
+    );
+    for (name, runs) in [("Free list", freelist), ("Buddy allocator", buddy)] {
+        println!("{} results", name);
+        print_results(&runs);
+        println!(
+            "Average gap from the target occupancy: {}",
+            runs.mean(|r| r.value("tracking_error"))
+        );
+        println!();
+    }
+}
+
+// Synthetic traces that differ only in how size and lifetime are
+// correlated, replayed on the free list and the buddy allocator
+fn bench_cycles(cycles: usize, churn: usize, keep: f64, release: f64) {
+    let run = |allocator: Box<dyn Allocator>| {
+        workloads::cycles(
+            allocator,
+            cycles,
+            churn,
+            keep,
+            release,
+            Metrics::new(),
+            &mut rand::thread_rng(),
+        )
+    };
+    let names = ["first fit", "best fit", "random fit", "buddy", "TLSF"];
+    let runs: Vec<Aggregate> = (0..names.len())
+        .map(|i| {
+            (0..5)
+                .map(|_| {
+                    run(match i {
+                        0 => Box::new(FreeList::new(0, 32768, true).policy(Policy::First)),
+                        1 => Box::new(FreeList::new(0, 32768, true).policy(Policy::Best)),
+                        2 => Box::new(FreeList::new(0, 32768, true).policy(Policy::Random {
+                            seed: rand::random(),
+                        })),
+                        3 => Box::new(BuddyAllocator::new(4, 15)),
+                        _ => Box::new(Tlsf::new(0, 32768)),
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    println!(
+        "{} cycles of {} ops of churn, {}% of mallocs long lived, {}% of those freed in each quiet phase\n",
+        cycles,
+        churn,
+        keep * 100.0,
+        release * 100.0
+    );
+    println!("External fragmentation after each quiet phase");
+    print!("{:>6}", "cycle");
+    for name in names {
+        print!(" {:>10}", name);
+    }
+    println!();
+    let step = (cycles / 10).max(1);
+    for cycle in (0..cycles).filter(|cycle| cycle % step == 0 || cycle + 1 == cycles) {
+        print!("{:>6}", cycle + 1);
+        for runs in runs.iter() {
+            print!(" {:>10.3}", runs.mean(|r| r.series("quiet_frag")[cycle]));
+        }
+        println!();
+    }
+
+    print!("{:>6}", "drift");
+    for runs in runs.iter() {
+        print!(" {:>10.4}", runs.mean(|r| r.drift()));
+    }
+    println!();
+    print!("{:>6}", "fails");
+    for runs in runs.iter() {
+        print!(" {:>10.1}", runs.mean(|r| r.malloc_fails as f32));
+    }
+    println!();
+    println!("\nDrift is the growth in fragmentation per cycle over the second half. Near 0 it has converged");
+}
+
+fn bench_pages(ratio: f64, max_order: usize, scatter: bool) {
+    let page = 4096;
+    let heap = 1 << 20;
+    let run = |allocator: Box<dyn Allocator>| {
+        workloads::pages(
+            Contiguous::new(allocator).scatter(scatter),
+            ratio,
+            page,
+            max_order,
+            Metrics::new(),
+            &mut rand::thread_rng(),
+        )
+    };
+    let names = ["first fit", "best fit", "buddy", "TLSF"];
+    let runs: Vec<Aggregate> = (0..names.len())
+        .map(|i| {
+            (0..5)
+                .map(|_| {
+                    run(match i {
+                        0 => Box::new(FreeList::new(0, heap, true).policy(Policy::First)),
+                        1 => Box::new(FreeList::new(0, heap, true).policy(Policy::Best)),
+                        2 => Box::new(BuddyAllocator::new(12, 20)),
+                        _ => Box::new(Tlsf::new(0, heap)),
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    println!(
+        "Runs of {} byte pages up to order {} in a 1M heap with {}% malloc{}\n",
+        page,
+        max_order,
+        ratio * 100.0,
+        if scatter {
+            ", scattering runs that don't fit"
+        } else {
+            ""
+        }
+    );
+    println!("Fraction of requests of each order with no run free");
+    print!("{:>9}", "order");
+    for name in names {
+        print!(" {:>10}", name);
+    }
+    println!();
+    for order in 0..=max_order {
+        print!("{:>9}", order);
+        for runs in runs.iter() {
+            print!(" {:>10.3}", runs.mean(|r| failure_rate(r, order)));
+        }
+        println!();
+    }
+
+    print!("{:>9}", "fails");
+    for runs in runs.iter() {
+        print!(" {:>10.1}", runs.mean(|r| r.malloc_fails as f32));
+    }
+    println!();
+    if scatter {
+        print!("{:>9}", "scattered");
+        for runs in runs.iter() {
+            print!(
+                " {:>10.1}",
+                runs.mean(|r| (0..=max_order)
+                    .map(|order| r.count("order_scattered", order))
+                    .sum::<usize>() as f32)
+            );
+        }
+        println!();
+    }
+}
+
+// Kernel style charts of how often each order of pages fails as a
+// heap of single pages ages
+fn bench_orders(ops: usize, occupancy: f64, max_order: usize) {
+    let page = 4096;
+    let heap = 1 << 22;
+    let run = |allocator: Box<dyn Allocator>| {
+        workloads::high_order(
+            Contiguous::new(allocator),
+            ops,
+            occupancy,
+            page,
+            max_order,
+            Metrics::new(),
+            &mut rand::thread_rng(),
+        )
+    };
+    let names = [
+        "buddy",
+        "first fit",
+        "best fit",
+        "vmem instant",
+        "vmem best",
+    ];
+    let runs: Vec<Aggregate> = (0..names.len())
+        .map(|i| {
+            (0..5)
+                .map(|_| {
+                    run(match i {
+                        0 => Box::new(BuddyAllocator::new(12, 22)),
+                        1 => Box::new(FreeList::new(0, heap, true).policy(Policy::First)),
+                        2 => Box::new(FreeList::new(0, heap, true).policy(Policy::Best)),
+                        3 => Box::new(Arena::new(0, heap, page)),
+                        _ => Box::new(Arena::new(0, heap, page).fit(Fit::Best)),
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    println!(
+        "{} ops of churn on 4M of {} byte pages kept {}% full, probing every order up to {} every {} ops\n",
+        ops,
+        page,
+        occupancy * 100.0,
+        max_order,
+        workloads::SAMPLE_EVERY
+    );
+    let probes = ops / workloads::SAMPLE_EVERY;
+    let window = (probes / 10).max(1);
+    for (name, runs) in names.iter().zip(runs.iter()) {
+        println!("{}: fraction of probes of each order that failed", name);
+        print!("{:>8}", "ops");
+        for order in 1..=max_order {
+            print!(" {:>6}", order);
+        }
+        println!();
+        for start in (0..probes).step_by(window) {
+            let end = (start + window).min(probes);
+            print!("{:>8}", end * workloads::SAMPLE_EVERY);
+            for order in 1..=max_order {
+                let name = format!("probe_failed_{}", order);
+                let failed = runs.mean(|r| {
+                    r.series(&name)[start..end].iter().sum::<f32>() / (end - start) as f32
+                });
+                print!(" {:>6.2}", failed);
+            }
+            println!();
+        }
+        println!();
+    }
+
+    println!("Fraction of all probes that failed");
+    print!("{:>8}", "order");
+    for name in names {
+        print!(" {:>12}", name);
+    }
+    println!();
+    for order in 1..=max_order {
+        print!("{:>8}", order);
+        for runs in runs.iter() {
+            print!(" {:>12.3}", runs.mean(|r| failure_rate(r, order)));
+        }
+        println!();
+    }
+}
+
+// The orders bench on a first fit free list, with some pages pinned
+// and the rest compacted in the background with each budget
+fn bench_kcompactd(
+    ops: usize,
+    occupancy: f64,
+    unmovable: f64,
+    budgets: &[usize],
+    max_order: usize,
+) {
+    let page = workloads::PAGE_SIZE;
+    let list = FreeList::new(0, 1 << 22, true).policy(Policy::First);
+    let runs: Vec<Aggregate> = budgets
+        .iter()
+        .map(|&budget| {
+            (0..5)
+                .map(|_| {
+                    workloads::kcompactd(
+                        list.clone(),
+                        ops,
+                        occupancy,
+                        Compaction {
+                            unmovable,
+                            budget: budget * page,
+                        },
+                        max_order,
+                        Metrics::new(),
+                        &mut rand::thread_rng(),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    println!(
+        "{} ops of churn on 4M of {} byte pages kept {}% full, {}% unmovable, compacting every {} ops\n",
+        ops,
+        page,
+        occupancy * 100.0,
+        unmovable * 100.0,
+        workloads::SAMPLE_EVERY
+    );
+    println!("Fraction of probes of each order that failed, by pages moved per wakeup");
+    print!("{:>8}", "budget");
+    for order in 1..=max_order {
+        print!(" {:>6}", order);
+    }
+    println!(" {:>8} {:>8}", "moved", "fails");
+    for (budget, runs) in budgets.iter().zip(runs.iter()) {
+        print!("{:>8}", budget);
+        for order in 1..=max_order {
+            print!(" {:>6.3}", runs.mean(|r| failure_rate(r, order)));
+        }
+        println!(
+            " {:>8.2} {:>8.1}",
+            runs.mean(|r| r.value("moved_bytes") / page as f32 / r.value("compactions").max(1.0)),
+            runs.mean(|r| r.malloc_fails as f32)
+        );
+    }
+
+    println!(
+        "\nFraction of order {} probes that failed over time",
+        max_order
+    );
+    print!("{:>8}", "ops");
+    for budget in budgets {
+        print!(" {:>6}", budget);
+    }
+    println!();
+    let probes = ops / workloads::SAMPLE_EVERY;
+    let window = (probes / 10).max(1);
+    for start in (0..probes).step_by(window) {
+        let end = (start + window).min(probes);
+        print!("{:>8}", end * workloads::SAMPLE_EVERY);
+        for runs in runs.iter() {
+            let name = format!("probe_failed_{}", max_order);
+            let failed = runs
+                .mean(|r| r.series(&name)[start..end].iter().sum::<f32>() / (end - start) as f32);
+            print!(" {:>6.2}", failed);
+        }
+        println!();
+    }
+}
+
+// The orders bench's background churn in three mobility classes,
+// with pageblocks grouped by class and without
+fn bench_mobility(ops: usize, occupancy: f64, unmovable: f64, reclaimable: f64, block: usize) {
+    let page = workloads::PAGE_SIZE;
+    let names = ["grouped", "ungrouped"];
+    let runs: Vec<Aggregate> = [true, false]
+        .iter()
+        .map(|&grouping| {
+            let heap = Grouped::new(0, 1 << 22, block * page).grouping(grouping);
+            (0..5)
+                .map(|_| {
+                    workloads::mobility(
+                        heap.clone(),
+                        ops,
+                        occupancy,
+                        unmovable,
+                        reclaimable,
+                        Metrics::new(),
+                        &mut rand::thread_rng(),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    println!(
+        "{} ops of churn on 4M of {} byte pages kept {}% full, {}% unmovable, {}% reclaimable, in pageblocks of {} pages\n",
+        ops,
+        page,
+        occupancy * 100.0,
+        unmovable * 100.0,
+        reclaimable * 100.0,
+        block
+    );
+    println!(
+        "Fraction of pageblocks mixing classes, and holding an unmovable allocation, over time"
+    );
+    print!("{:>8}", "");
+    for name in names.iter() {
+        print!(" {:>21}", name);
+    }
+    print!("\n{:>8}", "ops");
+    for _ in names.iter() {
+        print!(" {:>10} {:>10}", "mixed", "unmovable");
+    }
+    println!();
+    let series: Vec<(Vec<f32>, Vec<f32>)> = runs
+        .iter()
+        .map(|runs| {
+            (
+                runs.mean_series("mixed_blocks"),
+                runs.mean_series("unmovable_blocks"),
+            )
+        })
+        .collect();
+    let samples = ops / workloads::SAMPLE_EVERY;
+    let window = (samples / 10).max(1);
+    for start in (0..samples).step_by(window) {
+        let end = (start + window).min(samples);
+        print!("{:>8}", end * workloads::SAMPLE_EVERY);
+        for (mixed, pinned) in series.iter() {
+            let mean =
+                |series: &[f32]| series[start..end].iter().sum::<f32>() / (end - start) as f32;
+            print!(" {:>10.3} {:>10.3}", mean(mixed), mean(pinned));
+        }
+        println!();
+    }
+
+    println!();
+    for (name, runs) in names.iter().zip(runs.iter()) {
+        println!(
+            "{:<10} malloc fails: {:>6.1}, fallbacks: {:>7.1}",
+            name,
+            runs.mean(|r| r.malloc_fails as f32),
+            runs.mean(|r| r.value("fallbacks"))
+        );
+    }
+}
+
+fn bench_synthetic(correlations: &[f64], occupancy: f64, lifetime: f64) {
+    let num_runs = 5;
+    let synth = Synth::new(
+        synth::Distribution::LogUniform { min: 16, max: 2048 },
+        synth::Distribution::Exponential { mean: lifetime },
+    )
+    .ops(20000)
+    .occupancy(32768, Occupancy::Constant(occupancy));
+
+    println!(
+        "Log uniform sizes from 16 to 2048 bytes living {} ops on average, {}% of the heap live\n",
+        lifetime,
+        occupancy * 100.0
+    );
+    println!(
+        "{:>11} {:>9} {:>9} {:>14} {:>15} {:>11} {:>12}",
+        "correlation",
+        "measured",
+        "lifetime",
+        "free list fails",
+        "free list frag",
+        "buddy fails",
+        "buddy frag"
+    );
+    for &correlation in correlations {
+        let traces: Vec<Trace> = (0..num_runs)
+            .map(|_| {
+                synth
+                    .clone()
+                    .correlation(correlation)
+                    .generate(&mut rand::thread_rng())
+            })
+            .collect();
+        let stats: Vec<TraceStats> = traces.iter().map(synth::measure).collect();
+        let freelist: Aggregate = traces
+            .iter()
+            .map(|trace| {
+                let freelist = FreeList::new(0, 32768, true)
+                    .align(32)
+                    .policy(Policy::First);
+                workloads::replayed(freelist, trace, Metrics::new())
+            })
+            .collect();
+        let buddy: Aggregate = traces
+            .iter()
+            .map(|trace| workloads::replayed(BuddyAllocator::new(5, 15), trace, Metrics::new()))
+            .collect();
+
+        println!(
+            "{:>11} {:>9.2} {:>9.1} {:>14.1} {:>15.3} {:>11.1} {:>12.3}",
+            correlation,
+            stats.iter().map(|s| s.correlation).sum::<f32>() / num_runs as f32,
+            stats.iter().map(|s| s.mean_lifetime).sum::<f32>() / num_runs as f32,
+            freelist.mean(|r| r.malloc_fails as f32),
+            freelist.mean(|r| r.steady.external_frag),
+            buddy.mean(|r| r.malloc_fails as f32),
+            buddy.mean(|r| r.steady.external_frag)
+        );
+    }
+}
+
+// Fraction of a run's requests for `order` pages that found no run
+fn failure_rate(r: &Results, order: usize) -> f32 {
+    r.count("order_failures", order) as f32 / r.count("order_requests", order).max(1) as f32
+}
+
+// Results at each load level, averaged over the runs
+fn print_loads(runs: &Aggregate) {
+    let levels = runs
+        .runs
+        .first()
+        .map_or(0, |run| run.series("load_rate").len());
+    for level in 0..levels {
+        println!(
+            "At {} arrivals per unit of time: {} mallocs, {} failed, {} live bytes, {} external fragmentation",
+            runs.runs[0].series("load_rate")[level],
+            runs.mean(|r| r.series("load_mallocs")[level]),
+            runs.mean(|r| r.series("load_malloc_fails")[level]),
+            runs.mean(|r| r.series("load_live_bytes")[level]),
+            runs.mean(|r| r.series("load_external_frag")[level])
+        );
+    }
+}
+
+// Sweeps the largest request size and prints csv, so internal
+// and external fragmentation can be plotted against each other
+fn analyze_tradeoff(ratio: f64, min_size: usize) {
+    let num_runs = 5;
+    let freelist = FreeList::new(0, 32768, true)
+        .align(32)
+        .policy(Policy::First);
+    let buddy = BuddyAllocator::new(5, 15);
+
+    let mut table = Table::new(&[
+        "min_size",
+        "max_size",
+        "allocator",
+        "malloc_fails",
+        "internal_frag",
+        "external_frag",
+    ]);
+    for max_size in (4..=12).map(|i| 1 << i).filter(|&max| max >= min_size) {
+        let freelist_results: Aggregate = (0..num_runs)
+            .map(|_| {
+                workloads::random_sized(
+                    freelist.clone(),
+                    ratio,
+                    min_size..=max_size,
+                    &mut rand::thread_rng(),
+                )
+            })
+            .collect();
+        let buddy_results: Aggregate = (0..num_runs)
+            .map(|_| {
+                workloads::random_sized(
+                    buddy.clone(),
+                    ratio,
+                    min_size..=max_size,
+                    &mut rand::thread_rng(),
+                )
+            })
+            .collect();
+
+        for (name, runs) in [("freelist", freelist_results), ("buddy", buddy_results)].iter() {
+            table.push(vec![
+                min_size.to_string(),
+                max_size.to_string(),
+                name.to_string(),
+                runs.mean(|r| r.malloc_fails as f32).to_string(),
+                runs.mean(|r| r.internal_frag as f32).to_string(),
+                runs.mean(|r| r.external_frag).to_string(),
+            ]);
+        }
+    }
+    print!("{}", table);
+}
+
+// The costliest op of each allocator in each workload, by the cost
+// model, next to the most any op could cost on the ones with a bound
+fn analyze_wcet(ratio: f64, search: f32) {
+    // Boxed, with its bound worked out first
+    fn bounded<T: Allocator + WorstCase + 'static>(
+        allocator: T,
+    ) -> (Box<dyn Allocator>, Option<Churn>) {
+        let bound = allocator.worst_op();
+        (Box::new(allocator), bound)
+    }
+
+    let num_runs = 5;
+    let model = LatencyModel::default().search(search);
+    let names = [
+        "tlsf",
+        "buddy",
+        "first fit",
+        "best fit",
+        "bounded first fit",
+        "stack",
+    ];
+    let make = |i: usize| match i {
+        0 => bounded(Tlsf::new(0, 32768)),
+        1 => bounded(
+            BuddyAllocator::builder()
+                .heap_bytes(32768)
+                .min_block_bytes(32)
+                .build()
+                .unwrap(),
+        ),
+        2 => bounded(FreeList::new(0, 32768, true).policy(Policy::First)),
+        3 => bounded(FreeList::new(0, 32768, true)),
+        4 => bounded(FreeList::new(0, 32768, true).policy(Policy::Bounded {
+            budget: 4,
+            fallback: true,
+        })),
+        _ => bounded(StackAllocator::new(0, 32768)),
+    };
+
+    println!(
+        "Worst case cost of an op over {} runs, {}% malloc, {} per free block searched\n",
+        num_runs,
+        ratio * 100.0,
+        search
+    );
+    println!(
+        "{:<18} {:>8} {:>8} {:>8} {:>8}",
+        "allocator", "stack", "random", "aged", "bound"
+    );
+    for (i, name) in names.iter().enumerate() {
+        print!("{:<18}", name);
+        for workload in ["stack", "random", "aged"] {
+            // Aging frees in any order, which a stack can't
+            if *name == "stack" && workload == "aged" {
+                print!(" {:>8}", "-");
+                continue;
+            }
+            let mut rng = rand::thread_rng();
+            let runs: Aggregate = (0..num_runs)
+                .map(|_| {
+                    let metrics = Metrics::new().register(model.clone().worst_case());
+                    let allocator = make(i).0;
+                    match workload {
+                        "stack" => {
+                            workloads::stack_from(Warm::cold(allocator), ratio, metrics, &mut rng)
+                        }
+                        "random" => workloads::random_from(
+                            Warm::cold(allocator),
+                            ratio,
+                            32..=128,
+                            metrics,
+                            &mut rng,
+                        ),
+                        _ => workloads::random_from(
+                            Warm::aged(allocator, 2000, ratio, 32..=128, &mut rng),
+                            ratio,
+                            32..=128,
+                            metrics,
+                            &mut rng,
+                        ),
+                    }
+                })
+                .collect();
+            print!(" {:>8.1}", runs.metric("worst_latency").max().unwrap());
+        }
+        match make(i).1 {
+            Some(op) => println!(" {:>8.1}", model.cost(op)),
+            None => println!(" {:>8}", "none"),
+        }
+    }
+}
+
+// Ages an unpadded freelist, then prints csv of how its free space
+// splits up for requests of `size` bytes at each alignment, next to
+// the ordinary external fragmentation
+fn analyze_alignment(ratio: f64, size: usize) {
+    let num_runs = 5;
+    let freelist = FreeList::new(0, 32768, true).policy(Policy::First);
+    let heaps: Vec<FreeList> = (0..num_runs)
+        .map(|_| {
+            Warm::aged(
+                freelist.clone(),
+                2000,
+                ratio,
+                1..=128,
+                &mut rand::thread_rng(),
+            )
+            .allocator
+        })
+        .collect();
+    let average = |f: &dyn Fn(&FreeList) -> f32| heaps.iter().map(f).sum::<f32>() / num_runs as f32;
+
+    let mut table = Table::new(&[
+        "size",
+        "align",
+        "free_space",
+        "fits",
+        "misaligned",
+        "too_small",
+        "external_frag",
+    ]);
+    for &align in [1, 8, 16, 64, 256, 4096].iter() {
+        table.push(vec![
+            size.to_string(),
+            align.to_string(),
+            average(&|heap| heap.free_space() as f32).to_string(),
+            average(&|heap| heap.alignment_loss(size, align).fits as f32).to_string(),
+            average(&|heap| heap.alignment_loss(size, align).misaligned as f32).to_string(),
+            average(&|heap| heap.alignment_loss(size, align).too_small as f32).to_string(),
+            average(&|heap| heap.external_frag()).to_string(),
+        ]);
+    }
+    print!("{}", table);
+}
+
+// Replays a seeded trace and checks the addresses against a
+// recorded log, or against a second run if there is no log
+fn analyze_audit(seed: u64, allocator: &str, record: Option<&str>, verify: Option<&str>) {
+    let trace = trace::random_memory(0.5, &mut Generator::ChaCha.seeded(seed));
+    let replay = || AddressLog::record(&mut presets::make(allocator).unwrap(), &trace);
+
+    let log = replay();
+    if let Some(path) = record {
+        fs::write(path, log.to_string()).expect("Could not write log");
+        println!("Recorded {} mallocs to {}", log.addrs.len(), path);
+    }
+
+    let expected = match verify {
+        Some(path) => fs::read_to_string(path)
+            .expect("Could not read log")
+            .parse()
+            .expect("Could not parse log"),
+        None => replay(),
+    };
+    match expected.verify(&log) {
+        Ok(()) => println!("{} mallocs reproduced exactly", log.addrs.len()),
+        Err(divergence) => {
+            println!("Nondeterminism found: {}", divergence);
+            process::exit(1);
+        }
+    }
+}
+
+// The same trace freed one allocation at a time, and freed a region
+// at a time with regions of each size
+fn analyze_regions(trace: Trace, sizes: &[usize], chunk: usize) {
+    let list = FreeList::new(0, 32768, true).policy(Policy::First);
+    let failed = |events: &[trace::Event]| {
+        events
+            .iter()
+            .filter(|event| event.op.bytes().is_some() && event.block.is_none())
+            .count()
+    };
+
+    println!("Heap used for {} ops\n", trace.len());
+    let events = trace.replay(&mut list.clone());
+    println!(
+        "malloc and free: {} bytes, {} mallocs failing\n",
+        offline::footprint(&events),
+        failed(&events)
+    );
+
+    println!(
+        "{:>8} {:>10} {:>10} {:>10} {:>10}",
+        "mallocs", "footprint", "fails", "freed", "peak dead"
+    );
+    for &mallocs in sizes {
+        let mut heap = RegionAllocator::new(list.clone(), chunk);
+        let events = trace.replay_regions(&mut heap, mallocs);
+        println!(
+            "{:>8} {:>10} {:>10} {:>10} {:>10}",
+            mallocs,
+            offline::footprint(&events),
+            failed(&events),
+            heap.regions_freed,
+            heap.peak_dead
+        );
+    }
+}
+
+// How much heap each allocator needed for the trace, next to the
+// least any placement could need and what an offline placement that
+// knows every lifetime needs. Then how small a heap each one can
+// get by with
+fn analyze_offline(trace: Trace) {
+    let peak = offline::peak_live(&trace);
+    let placed = offline::place(&offline::lifetimes(&trace)).heap;
+    println!("Heap needed for {} ops\n", trace.len());
+    println!("Peak live bytes: {}", peak);
+    println!("Offline placement: {}", placed);
+
+    let mut freelist = FreeList::new(0, 32768, true)
+        .align(32)
+        .policy(Policy::First);
+    let mut buddy = BuddyAllocator::new(5, 15);
+    for (name, events) in [
+        ("Free list", trace.replay(&mut freelist)),
+        ("Buddy allocator", trace.replay(&mut buddy)),
+    ] {
+        let failed = events
+            .iter()
+            .filter(|event| event.op.bytes().is_some() && event.block.is_none())
+            .count();
+        let footprint = offline::footprint(&events);
+        print!(
+            "{}: {}, {} times the offline placement",
+            name,
+            footprint,
+            footprint as f32 / placed.max(1) as f32
+        );
+        if failed > 0 {
+            print!(" with {} mallocs failing", failed);
+        }
+        println!();
+    }
+
+    // Search for the smallest heap each allocator gets through the
+    // trace in, and compare it with the floor
+    let floor = bounds::floor(&trace);
+    println!();
+    match bounds::paged(&trace, 256) {
+        Some(paged) => println!(
+            "Lower bound: {}, or {} if blocks can't cross 256 byte pages",
+            floor, paged
+        ),
+        None => println!("Lower bound: {}", floor),
+    }
+    let freelist = bounds::min_heap(&trace, floor..=floor * 8, |size| {
+        FreeList::new(0, size, true).align(32).policy(Policy::First)
+    });
+    let buddy = bounds::min_heap(&trace, 5..=usize::BITS as usize - 2, |order| {
+        BuddyAllocator::new(5, order)
+    })
+    .map(|order| 1 << order);
+    for (name, smallest) in [("free list", freelist), ("buddy", buddy)] {
+        match smallest {
+            Some(size) => println!(
+                "Smallest {} heap: {}, {} times the lower bound",
+                name,
+                size,
+                size as f32 / floor.max(1) as f32
+            ),
+            None => println!("No {} heap fits the trace", name),
+        }
+    }
+}
+
+fn analyze_maps(trace: Trace, allocator: &str) {
+    let mut allocator = presets::make(allocator).unwrap();
+    let map = AddressMap::record(&mut allocator, &trace, 0, presets::HEAP);
+    print!("{}", map.slack(&allocator));
+}
+
+// Why the last failing malloc of a trace failed, on the chosen
+// allocator
+fn analyze_drilldown(trace: Trace, allocator: &str, top: usize) {
+    let make = || presets::make(allocator).unwrap();
+    match Drilldown::record(make, &trace, 0, presets::HEAP) {
+        Some(report) => print!("{}", report.top(top)),
+        None => println!("No malloc failed in {} ops", trace.len()),
+    }
+}
+
+// How many bytes the callocs of a trace would have to zero on each
+// allocator, counting memory it never handed out before as zero
+fn analyze_zeroing(trace: Trace) {
+    let callocs = trace
+        .ops
+        .iter()
+        .filter(|op| matches!(op, Op::Calloc { .. }))
+        .count();
+    println!("{} callocs in {} ops\n", callocs, trace.len());
+    println!(
+        "{:>16} {:>8} {:>10} {:>10} {:>8}",
+        "allocator", "fails", "asked", "to zero", "share"
+    );
+
+    let mut freelist = FreeList::new(0, 32768, true)
+        .align(32)
+        .policy(Policy::First);
+    let mut buddy = BuddyAllocator::new(5, 15);
+    let mut tlsf = Tlsf::new(0, 32768);
+    for (name, events) in [
+        ("Free list", trace.replay(&mut freelist)),
+        ("Buddy allocator", trace.replay(&mut buddy)),
+        ("TLSF", trace.replay(&mut tlsf)),
+    ] {
+        let failed = events
+            .iter()
+            .filter(|event| event.op.bytes().is_some() && event.block.is_none())
+            .count();
+        let (asked, dirty) = trace::zeroing(&events);
+        println!(
+            "{:>16} {:>8} {:>10} {:>10} {:>7.1}%",
+            name,
+            failed,
+            asked,
+            dirty,
+            100.0 * dirty as f32 / asked.max(1) as f32
+        );
+    }
+}
+
+// Shrink a trace that breaks an allocator down to the fewest ops
+// that still break it, printed as a trace with the violation in a
+// comment at the top
+fn analyze_minimize(trace: Trace) {
+    let (name, violation) = match regressions::first_violation(&trace) {
+        Some(found) => found,
+        None => {
+            println!("Every allocator replays the trace cleanly");
+            return;
+        }
+    };
+    eprintln!("{} failed at {}", name, violation);
+
+    let minimized = minimize(&trace, |trace| {
+        regressions::check_named(name, trace).is_err()
+    })
+    .unwrap();
+    let violation = regressions::check_named(name, &minimized).unwrap_err();
+    eprintln!("Minimized from {} to {} ops", trace.len(), minimized.len());
+    println!("# {}: {}", name, violation);
+    print!("{}", minimized);
+}
+
+// Model checks one allocator on a 16 byte heap. A violation is
+// printed like `analyze minimize` prints one, ready to be saved as
+// a regression trace
+fn analyze_explore(allocator: &str, depth: usize, sizes: &[usize]) {
+    let result = match allocator {
+        "freelist" => Explorer::new(FreeList::new(0, 16, true).policy(Policy::First), 0, 16)
+            .sizes(sizes)
+            .depth(depth)
+            .restores(true)
+            .run(),
+        "buddy" => Explorer::new(BuddyAllocator::new(0, 4), 0, 16)
+            .sizes(sizes)
+            .depth(depth)
+            .restores(true)
+            .run(),
+        "segregated" => Explorer::new(SegregatedList::new(0, 16).classes(vec![8]), 0, 16)
+            .sizes(sizes)
+            .depth(depth)
+            .restores(true)
+            .run(),
+        "tlsf" => Explorer::new(Tlsf::new(0, 16), 0, 16)
+            .sizes(sizes)
+            .depth(depth)
+            .restores(true)
+            .run(),
+        "stack" => Explorer::new(StackAllocator::new(0, 16), 0, 16)
+            .sizes(sizes)
+            .depth(depth)
+            .restores(true)
+            .run(),
+        // Every state has to be copied, and the heap small enough to
+        // get through them all, so the presets can't be used as they are
+        other => panic!("No 16 byte {} to explore", other),
+    };
+
+    match result {
+        Ok(states) => println!(
+            "Checked {} states up to {} ops deep, no violations",
+            states, depth
+        ),
+        Err((trace, violation)) => {
+            println!("# {}: {}", allocator, violation);
+            print!("{}", trace);
+            process::exit(1);
+        }
+    }
+}
+
+fn analyze_locality(trace: Trace, page: usize) {
+    let windows: Vec<usize> = (0..10).map(|i| 1 << i).collect();
+
+    let mut freelist = FreeList::new(0, 32768, true)
+        .align(32)
+        .policy(Policy::First);
+    let freelist_curve = locality::working_set(&trace.replay(&mut freelist), page, &windows);
+
+    let mut buddy = BuddyAllocator::new(5, 15);
+    let buddy_curve = locality::working_set(&trace.replay(&mut buddy), page, &windows);
+
+    println!(
+        "Average working set size in {} byte pages over {} ops\n",
+        page,
+        trace.len()
+    );
+    println!("{:>8} {:>10} {:>10}", "window", "freelist", "buddy");
+    for (&(window, f), &(_, b)) in freelist_curve.iter().zip(buddy_curve.iter()) {
+        println!("{:>8} {:>10.2} {:>10.2}", window, f, b);
+    }
+}
+
+// Page faults of the pages a trace touches on freelist and buddy,
+// under each replacement policy, with Belady's OPT as the floor
+fn analyze_paging(trace: Trace, page: usize, frames: &[usize]) {
+    let mut freelist = FreeList::new(0, 32768, true)
+        .align(32)
+        .policy(Policy::First);
+    let mut buddy = BuddyAllocator::new(5, 15);
+    let replays = [
+        ("freelist", trace.replay(&mut freelist)),
+        ("buddy", trace.replay(&mut buddy)),
+    ];
+
+    for (name, events) in replays.iter() {
+        let refs = locality::reference_string(events, page);
+        let pages: BTreeSet<usize> = refs.iter().copied().collect();
+        println!(
+            "{}: {} references to {} pages of {} bytes",
+            name,
+            refs.len(),
+            pages.len(),
+            page
+        );
+        let curves = fault_curves(&refs, frames);
+        println!("{}", paging::render_faults(&curves));
+    }
+}
+
+// Faults under every replacement policy for each number of frames
+fn fault_curves(refs: &[usize], frames: &[usize]) -> Vec<(Replacement, Vec<(usize, usize)>)> {
+    [Replacement::Fifo, Replacement::Lru, Replacement::Opt]
+        .iter()
+        .map(|&policy| {
+            let curve = paging::fault_curve(refs, frames.iter().copied(), policy);
+            (policy, curve)
+        })
+        .collect()
+}
+
+// Random reference strings until FIFO faults more with more frames
+fn analyze_belady(pages: usize, length: usize, tries: usize) {
+    let (refs, frames) = match paging::find_anomaly(pages, length, tries, &mut rand::thread_rng()) {
+        Some(found) => found,
+        None => {
+            println!(
+                "No anomaly in {} strings of {} references to {} pages",
+                tries, length, pages
+            );
+            return;
+        }
+    };
+    let refs_text: Vec<String> = refs.iter().map(usize::to_string).collect();
+    println!("Reference string: {}", refs_text.join(" "));
+    println!(
+        "FIFO faults more with {} frames than with {}\n",
+        frames + 1,
+        frames
+    );
+    let curves = fault_curves(&refs, &(1..=pages).collect::<Vec<usize>>());
+    print!("{}", paging::render_faults(&curves));
+}
+
+fn analyze_heatmap(trace: Trace, regions: usize, epochs: usize) {
+    let freelist = FreeList::new(0, 32768, true).align(32);
+    let replays = [
+        (
+            "Free list, first fit",
+            trace.replay(&mut freelist.clone().policy(Policy::First)),
+        ),
+        (
+            "Free list, best fit",
+            trace.replay(&mut freelist.clone().policy(Policy::Best)),
+        ),
+        (
+            "Buddy allocator",
+            trace.replay(&mut BuddyAllocator::new(5, 15)),
+        ),
+    ];
+
+    // Only draw as much of the heap as any allocator used
+    let heap = replays
+        .iter()
+        .flat_map(|(_, events)| events.iter())
+        .filter_map(|event| event.block.map(|(addr, size)| addr + size))
+        .max()
+        .unwrap_or(1);
+    let maps: Vec<Vec<Vec<usize>>> = replays
+        .iter()
+        .map(|(_, events)| locality::placement(events, heap, regions, epochs))
+        .collect();
+    let max = maps.iter().flatten().flatten().copied().max().unwrap_or(0);
+
+    println!(
+        "Mallocs per {} byte region over {} ops, time going down\n",
+        heap.div_ceil(regions),
+        trace.len()
+    );
+    for ((name, _), map) in replays.iter().zip(maps.iter()) {
+        println!("{}", name);
+        println!("0{:>width$}", heap, width = regions + 1);
+        print!("{}", locality::render_heatmap(map, max));
+        println!();
+    }
+}
+
+// Every command and its arguments. Parsing, shell completions and
+// the man page are all built from this
+// Checks a chance given on the command line is between 0 and 1
+fn chance(value: String) -> Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(chance) if (0.0..=1.0).contains(&chance) => Ok(()),
+        _ => Err(format!("{} is not a chance between 0 and 1", value)),
+    }
+}
+
+fn cli() -> App<'static, 'static> {
+    // Clap keeps the choices for as long as the app lives
+    let allocators: &'static [&'static str] = Box::leak(presets::names().into_boxed_slice());
+    App::new("Free space simulator")
+        .author("Calvin")
+        .about("Simulates a list based freelist and buddy allocator")
+        .version("0.1.0")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("demo")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .about("Demo an allocator")
+                .subcommand(
+                    SubCommand::with_name("freelist")
+                        .about("Run the freelist")
+                        .arg(
+                            Arg::with_name("coalesce")
+                                .long("coalesce")
+                                .short("c")
+                                .help("Enable coalescing"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("buddy").about("Run the buddy allocator"))
+                .subcommand(
+                    SubCommand::with_name("linked")
+                        .about("Run the freelist that keeps its pointers and headers in the heap"),
+                )
+                .subcommand(
+                    SubCommand::with_name("corruption")
+                        .about("Corrupt block headers in the linked freelist"),
+                )
+                .subcommand(SubCommand::with_name("tlsf").about("Run the two-level segregated fit allocator"))
+                .subcommand(
+                    SubCommand::with_name("scan").about("Show how each search policy scans the freelist"),
+                )
+                .subcommand(
+                    SubCommand::with_name("vmem").about("Use vmem arenas to hand out PIDs and ports"),
+                )
+                .subcommand(
+                    SubCommand::with_name("reservation")
+                        .about("Reserve space for critical allocations"),
+                )
+                .subcommand(
+                    SubCommand::with_name("embedded")
+                        .about("Compare allocators for a tiny embedded heap on worst case latency"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Run a workload on freelist and buddy")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("stack")
+                        .about("A workload that resembles a stack")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("round")
+                                .long("round")
+                                .short("R")
+                                .help("Request rounding: none, pow2, align:<n> or classes:<a>,<b>,...")
+                                .default_value("none")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("warm")
+                                .long("warm")
+                                .short("w")
+                                .help("Start from an aged heap: checkerboard, aged:<ops>, or a trace file to replay")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("metrics")
+                                .long("metrics")
+                                .short("m")
+                                .help("Also report the built in metric collectors"),
+                        )
+                        .arg(
+                            Arg::with_name("rng")
+                                .long("rng")
+                                .help("Generator for the seeded runs: chacha, stable across platforms and rand versions, or small, faster")
+                                .possible_values(&["chacha", "small"])
+                                .default_value("chacha")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("save")
+                                .long("save")
+                                .short("o")
+                                .help("Write the settings, seeds and results to a manifest `bench rerun` can repeat and `bench show` can print")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("shrink")
+                        .about("A random workload that shrinks allocations in place")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("coalesce")
+                        .about("Coalesce as blocks are freed, or only when a malloc fails")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("budget")
+                        .about("First fit with a bound on the free blocks each malloc scans")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.7")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("aligned")
+                        .about("Compare search lengths of aligned mallocs with and without alignment buckets")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("percpu")
+                        .about("A size class workload behind per-CPU caches")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("cpus")
+                                .long("cpus")
+                                .short("c")
+                                .default_value("4")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("threshold")
+                                .long("threshold")
+                                .short("t")
+                                .help("Cached blocks of one size a CPU holds before flushing")
+                                .default_value("8")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("quickfit")
+                        .about("Quick lists for common sizes in front of a free list")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("sizes")
+                                .long("sizes")
+                                .short("s")
+                                .help("Sizes that get a quick list, separated by commas")
+                                .default_value("16,32,48,64")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("headtohead")
+                        .about("Run the freelist and the buddy allocator on two halves of one address space")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("share")
+                                .long("share")
+                                .short("s")
+                                .help("Chance of a malloc going to the freelist")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("regions")
+                        .about("Mallocs restricted to windows of the address space")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.6")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("compact")
+                        .about("Compact the heap around pinned allocations when a malloc fails")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.7")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("pinned")
+                                .long("pinned")
+                                .short("p")
+                                .help("Fractions of allocations that can't be moved, separated by commas")
+                                .default_value("0,0.05,0.1,0.25,0.5,1")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("bins")
+                        .about("Compare jemalloc style size class bins with the buddy allocator")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("page")
+                                .long("page")
+                                .short("p")
+                                .help("Page size runs are made of")
+                                .default_value("1024")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("bestfit")
+                        .about("Best fit scanning the free list against best fit with an index by size")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("heaps")
+                                .long("heaps")
+                                .short("s")
+                                .help("Heap sizes, separated by commas")
+                                .default_value("32768,262144,2097152")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("hoard")
+                        .about("Per-thread heaps trading superblocks with a global heap, as in Hoard")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("threads")
+                                .long("threads")
+                                .short("t")
+                                .default_value("4")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("emptiness")
+                                .long("emptiness")
+                                .short("f")
+                                .help("How empty a thread heap gets before giving a superblock back, separated by commas")
+                                .default_value("0.125,0.25,0.5,1")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("mimalloc")
+                        .about("Model mimalloc's segments and pages next to size class bins")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("segment")
+                                .long("segment")
+                                .short("s")
+                                .help("Segment size")
+                                .default_value("8192")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("page")
+                                .long("page")
+                                .short("p")
+                                .help("Page size")
+                                .default_value("1024")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("spans")
+                        .about("Take spans from a buddy allocator and manage each with a free list")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("span")
+                                .long("span")
+                                .short("s")
+                                .help("Span size, a power of two")
+                                .default_value("2048")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("recycling")
+                        .about("Count spans and runs going back or kept once empty, under each retention policy")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("keep")
+                                .long("keep")
+                                .short("k")
+                                .help("Empty spans to keep, or all, separated by commas")
+                                .default_value("0,1,4,all")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("aging")
+                        .about("Route every free to a hot cache, the free list or a coalesce, by a few policies")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("device")
+                        .about("Textures and buffers in device memory, on the allocators a driver might use")
+                        .arg(
+                            Arg::with_name("frames")
+                                .long("frames")
+                                .short("f")
+                                .default_value("2000")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("numa")
+                        .about("Allocation streams with node affinity on a NUMA heap")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.6")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("nodes")
+                                .long("nodes")
+                                .short("n")
+                                .default_value("4")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("remote")
+                                .long("remote")
+                                .short("f")
+                                .help("Fraction of mallocs that ask for another node")
+                                .default_value("0.1")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("hybrid")
+                        .about("Route small mallocs to the buddy allocator and large ones to the freelist")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("thresholds")
+                                .long("thresholds")
+                                .short("t")
+                                .help("Sizes from which mallocs go to the freelist, separated by commas")
+                                .default_value("32,64,96,129")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("buddyorder")
+                        .about("Compare FIFO, LIFO and address ordered free blocks in the buddy allocator")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("page")
+                                .long("page")
+                                .short("p")
+                                .default_value("256")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("arrivals")
+                        .about("Time mallocs with a Poisson or bursty arrival process")
+                        .arg(
+                            Arg::with_name("process")
+                                .long("process")
+                                .short("p")
+                                .possible_values(&["poisson", "bursty"])
+                                .default_value("bursty")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("lifetime")
+                                .long("lifetime")
+                                .short("l")
+                                .help("Mean lifetime of an allocation, in units of time")
+                                .default_value("50")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("correlation")
+                                .long("correlation")
+                                .short("c")
+                                .help("Rank correlation between size and lifetime, from -1 to 1")
+                                .default_value("0")
+                                .allow_hyphen_values(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("burst")
+                                .long("burst")
+                                .short("b")
+                                .help("How many times faster mallocs arrive during a burst")
+                                .default_value("8")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("dwell")
+                                .long("dwell")
+                                .short("d")
+                                .help("Mean time between switching in and out of bursts")
+                                .default_value("200")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("controlled")
+                        .about("Steer the malloc ratio so occupancy follows a target")
+                        .arg(
+                            Arg::with_name("peak")
+                                .long("peak")
+                                .short("p")
+                                .help("Occupancy to ramp up to, as a fraction of the heap")
+                                .default_value("0.9")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("amplitude")
+                                .long("amplitude")
+                                .short("a")
+                                .help("How far occupancy swings above and below the peak")
+                                .default_value("0.05")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("period")
+                                .long("period")
+                                .short("T")
+                                .help("Ops per swing")
+                                .default_value("500")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("synthetic")
+                        .about("Replay synthetic traces with size and lifetime correlated by different amounts")
+                        .arg(
+                            Arg::with_name("correlation")
+                                .long("correlation")
+                                .short("c")
+                                .help("Comma separated rank correlations between size and lifetime")
+                                .default_value("-0.8,0,0.8")
+                                .allow_hyphen_values(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("occupancy")
+                                .long("occupancy")
+                                .short("o")
+                                .help("Most live bytes, as a fraction of the heap")
+                                .default_value("0.7")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("lifetime")
+                                .long("lifetime")
+                                .short("l")
+                                .help("Mean lifetime in ops")
+                                .default_value("60")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("cycles")
+                        .about("Alternate heavy churn and quiet phases to see if fragmentation converges")
+                        .arg(
+                            Arg::with_name("cycles")
+                                .long("cycles")
+                                .short("n")
+                                .help("Cycles of churn and quiet")
+                                .default_value("50")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("churn")
+                                .long("churn")
+                                .short("c")
+                                .help("Ops in each churn phase")
+                                .default_value("1000")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("keep")
+                                .long("keep")
+                                .short("k")
+                                .help("Chance a malloc is long lived")
+                                .default_value("0.02")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("release")
+                                .long("release")
+                                .short("r")
+                                .help("Chance a long lived allocation is freed in each quiet phase")
+                                .default_value("0.25")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("pages")
+                        .about("Allocate runs of pages of several orders and count failures by order")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.6")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("order")
+                                .long("order")
+                                .short("o")
+                                .help("Highest order, each half as likely as the one below")
+                                .default_value("4")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("scatter")
+                                .long("scatter")
+                                .short("s")
+                                .help("Allocate pages one by one when no run is free"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("orders")
+                        .about("Probe runs of pages of every order as a heap of single pages ages")
+                        .arg(
+                            Arg::with_name("ops")
+                                .long("ops")
+                                .short("n")
+                                .help("Ops of churn after the heap is filled")
+                                .default_value("20000")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("occupancy")
+                                .long("occupancy")
+                                .short("p")
+                                .help("Fraction of the pages kept allocated")
+                                .default_value("0.8")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("order")
+                                .long("order")
+                                .short("o")
+                                .help("Highest order to probe")
+                                .default_value("6")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("deadlines")
+                        .about("Replay the same traces against mallocs with deadlines, timed by the cost model")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.6")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("search")
+                                .long("search")
+                                .short("s")
+                                .help("Cost of each free block a search looks at")
+                                .default_value("5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("deadline")
+                                .long("deadline")
+                                .short("d")
+                                .help("Deadlines of every malloc to try, separated by commas")
+                                .default_value("30,50,100,200")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("mobility")
+                        .about("Group pages by mobility and count the pageblocks that mix classes")
+                        .arg(
+                            Arg::with_name("ops")
+                                .long("ops")
+                                .short("n")
+                                .help("Ops of churn after the heap is filled")
+                                .default_value("20000")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("occupancy")
+                                .long("occupancy")
+                                .short("p")
+                                .help("Fraction of the pages kept allocated")
+                                .default_value("0.8")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("unmovable")
+                                .long("unmovable")
+                                .short("u")
+                                .help("Chance an allocation is unmovable")
+                                .default_value("0.1")
+                                .takes_value(true)
+                                .validator(chance),
+                        )
+                        .arg(
+                            Arg::with_name("reclaimable")
+                                .long("reclaimable")
+                                .short("c")
+                                .help("Chance an allocation is reclaimable")
+                                .default_value("0.1")
+                                .takes_value(true)
+                                .validator(chance),
+                        )
+                        .arg(
+                            Arg::with_name("block")
+                                .long("block")
+                                .short("b")
+                                .help("Pages in a pageblock")
+                                .default_value("64")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("kcompactd")
+                        .about("Compact pages in the background with a budget and probe every order")
+                        .arg(
+                            Arg::with_name("ops")
+                                .long("ops")
+                                .short("n")
+                                .help("Ops of churn after the heap is filled")
+                                .default_value("20000")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("occupancy")
+                                .long("occupancy")
+                                .short("p")
+                                .help("Fraction of the pages kept allocated")
+                                .default_value("0.8")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("unmovable")
+                                .long("unmovable")
+                                .short("u")
+                                .help("Chance an allocation can't be moved")
+                                .default_value("0.1")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("budget")
+                                .long("budget")
+                                .short("b")
+                                .help("Pages moved per wakeup, separated by commas")
+                                .default_value("0,64,256,1024")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("order")
+                                .long("order")
+                                .short("o")
+                                .help("Highest order to probe")
+                                .default_value("6")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("ids")
+                        .about("Allocate from an id space with each id policy")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("count")
+                                .long("count")
+                                .short("n")
+                                .help("Number of ids in the space")
+                                .default_value("1024")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("segregated")
+                        .about("Segregated free lists, adding size classes one boundary at a time")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("classes")
+                                .long("classes")
+                                .short("c")
+                                .help("Smallest size of each class after the first, separated by commas")
+                                .default_value("32,64,128,256,512")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("reservation")
+                        .about("Serve critical mallocs from reservations of each size")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.7")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("critical")
+                                .long("critical")
+                                .short("c")
+                                .help("Chance a malloc is critical")
+                                .default_value("0.1")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("sizes")
+                                .long("sizes")
+                                .short("s")
+                                .help("Comma separated bytes to reserve")
+                                .default_value("0,1024,2048,4096,8192")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("rerun")
+                        .about("Repeat a stack or random bench exactly from its manifest")
+                        .arg(
+                            Arg::with_name("manifest")
+                                .help("Manifest written with --save")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Print the results saved in a manifest, from any schema version")
+                        .arg(
+                            Arg::with_name("manifest")
+                                .help("Manifest written with --save")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("random")
+                        .about("A workload that mallocs random amount of memory")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("round")
+                                .long("round")
+                                .short("R")
+                                .help("Request rounding: none, pow2, align:<n> or classes:<a>,<b>,...")
+                                .default_value("none")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("warm")
+                                .long("warm")
+                                .short("w")
+                                .help("Start from an aged heap: checkerboard, aged:<ops>, or a trace file to replay")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("metrics")
+                                .long("metrics")
+                                .short("m")
+                                .help("Also report the built in metric collectors"),
+                        )
+                        .arg(
+                            Arg::with_name("rng")
+                                .long("rng")
+                                .help("Generator for the seeded runs: chacha, stable across platforms and rand versions, or small, faster")
+                                .possible_values(&["chacha", "small"])
+                                .default_value("chacha")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("save")
+                                .long("save")
+                                .short("o")
+                                .help("Write the settings, seeds and results to a manifest `bench rerun` can repeat and `bench show` can print")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("analyze")
+                .about("Analyze the memory behavior of a trace")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("locality")
+                        .about("Working set size curves of a trace on freelist and buddy")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to replay. Defaults to a random workload")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("page")
+                                .long("page")
+                                .short("p")
+                                .default_value("256")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("paging")
+                        .about("Page faults of the pages a trace touches under FIFO, LRU and Belady's OPT")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to replay. Defaults to a random workload")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("page")
+                                .long("page")
+                                .short("p")
+                                .default_value("256")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("frames")
+                                .long("frames")
+                                .short("f")
+                                .help("Numbers of frames to fault on, separated by commas")
+                                .default_value("2,4,8,16,32,64")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("belady")
+                        .about("Search random reference strings for Belady's anomaly under FIFO")
+                        .arg(
+                            Arg::with_name("pages")
+                                .long("pages")
+                                .short("n")
+                                .help("Distinct pages in each string")
+                                .default_value("5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("length")
+                                .long("length")
+                                .short("l")
+                                .help("References in each string")
+                                .default_value("30")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("tries")
+                                .long("tries")
+                                .short("t")
+                                .help("Strings to try before giving up")
+                                .default_value("100000")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("offline")
+                        .about("Compare the heap freelist and buddy need for a trace with an offline placement")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to replay. Defaults to a random workload")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("regions")
+                        .about("Compare freeing a trace one allocation at a time with freeing whole regions")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to replay. Defaults to a random workload")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("mallocs")
+                                .long("mallocs")
+                                .short("m")
+                                .help("Mallocs in each region, separated by commas")
+                                .default_value("1,4,16,64")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("chunk")
+                                .long("chunk")
+                                .short("c")
+                                .help("Bytes regions take from the free list at a time")
+                                .default_value("1024")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("explore")
+                        .about("Check every sequence of ops up to some length on a 16 byte heap")
+                        .arg(
+                            Arg::with_name("allocator")
+                                .long("allocator")
+                                .short("a")
+                                .possible_values(allocators)
+                                .default_value("buddy")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("depth")
+                                .long("depth")
+                                .short("d")
+                                .help("Longest sequence of ops to try")
+                                .default_value("5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("sizes")
+                                .long("sizes")
+                                .short("s")
+                                .help("Request sizes to try at every step, separated by commas")
+                                .default_value("1,2,3,4,8,16")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("zeroing")
+                        .about("Count the bytes the callocs of a trace would have to zero on each allocator")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to replay")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("minimize")
+                        .about("Shrink a trace that breaks an allocator to the fewest ops that still do")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to minimize")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("drilldown")
+                        .about("Break down why the last failing malloc of a trace failed, and what might help")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to replay. Defaults to a random workload")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.7")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("allocator")
+                                .long("allocator")
+                                .short("a")
+                                .possible_values(allocators)
+                                .default_value("freelist")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("top")
+                                .long("top")
+                                .short("n")
+                                .help("Entries in each list")
+                                .default_value("5")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("maps")
+                        .about("Print what a trace leaves allocated, like /proc/self/maps")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to replay. Defaults to a random workload")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("allocator")
+                                .long("allocator")
+                                .short("a")
+                                .possible_values(allocators)
+                                .default_value("freelist")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("heatmap")
+                        .about("Show which parts of the heap each allocator hands out over time")
+                        .arg(
+                            Arg::with_name("trace")
+                                .long("trace")
+                                .short("t")
+                                .help("Trace file to replay. Defaults to a random workload")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("width")
+                                .long("width")
+                                .short("w")
+                                .help("Number of address regions")
+                                .default_value("64")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("epochs")
+                                .long("epochs")
+                                .short("e")
+                                .help("Number of time steps")
+                                .default_value("20")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("audit")
+                        .about("Check that a seeded trace always gets the same addresses")
+                        .arg(
+                            Arg::with_name("seed")
+                                .long("seed")
+                                .short("s")
+                                .default_value("0")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("allocator")
+                                .long("allocator")
+                                .short("a")
+                                .possible_values(allocators)
+                                .default_value("freelist")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("record")
+                                .long("record")
+                                .short("o")
+                                .help("Write the address log to a file")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("verify")
+                                .long("verify")
+                                .short("v")
+                                .help("Compare against a recorded address log")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("alignment")
+                        .about("Print how much free space is lost to alignment as csv")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("size")
+                                .long("size")
+                                .short("s")
+                                .help("Request size to check the free space against")
+                                .default_value("64")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("wcet")
+                        .about("Compare the costliest op of each allocator to its worst case bound")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.6")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("search")
+                                .long("search")
+                                .short("s")
+                                .help("Cost of each free block a search looks at")
+                                .default_value("5")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("tradeoff")
+                        .about("Sweep request sizes and print fragmentation as csv")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("min")
+                                .long("min")
+                                .short("m")
+                                .help("Smallest request size")
+                                .default_value("1")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Print shell completions for every command")
+                .arg(
+                    Arg::with_name("shell")
+                        .long("shell")
+                        .short("s")
+                        .possible_values(&Shell::variants())
+                        .default_value("bash")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(SubCommand::with_name("manpage").about("Print a man page for every command"))
+}
+
+fn main() {
+    let matches = cli().get_matches();
+
+    match matches.subcommand() {
+        ("demo", Some(demo)) => match demo.subcommand() {
+            ("freelist", Some(freelist)) => demos::freelist(freelist.is_present("coalesce")),
+            ("buddy", Some(_)) => demos::buddy(),
+            ("linked", Some(_)) => demos::linked(),
+            ("corruption", Some(_)) => demos::corruption(),
+            ("tlsf", Some(_)) => demos::tlsf(),
+            ("reservation", Some(_)) => demos::reservation(),
+            ("scan", Some(_)) => demos::scan(),
+            ("vmem", Some(_)) => demos::vmem(),
+            ("embedded", Some(_)) => demos::embedded(),
+            _ => unreachable!(),
+        },
+        ("bench", Some(bench)) => match bench.subcommand() {
+            (bench @ "random", Some(args)) | (bench @ "stack", Some(args)) => {
+                let mut rng = rand::thread_rng();
+                let manifest = Manifest {
+                    bench: bench.to_string(),
+                    ratio: args
+                        .value_of("ratio")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input"),
+                    rounding: args
+                        .value_of("round")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse rounding"),
+                    warm: args.value_of("warm").map(str::to_string),
+                    warm_seed: rng.gen(),
+                    metrics: args.is_present("metrics"),
+                    rng: args
+                        .value_of("rng")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse generator"),
+                    seeds: (0..5).map(|_| rng.gen()).collect(),
+                };
+                let archive = bench_manifest(&manifest);
+                if let Some(path) = args.value_of("save") {
+                    fs::write(path, archive.to_string()).expect("Could not write manifest");
+                    println!("\nSaved manifest and results to {}", path);
+                }
+            }
+            ("rerun", Some(rerun)) => {
+                bench_manifest(
+                    &fs::read_to_string(rerun.value_of("manifest").unwrap())
+                        .expect("Could not read manifest")
+                        .parse()
+                        .expect("Could not parse manifest"),
+                );
+            }
+            ("show", Some(show)) => show_archive(
+                &fs::read_to_string(show.value_of("manifest").unwrap())
+                    .expect("Could not read manifest")
+                    .parse()
+                    .expect("Could not parse manifest"),
+            ),
+            ("shrink", Some(shrink)) => bench_shrink(
+                shrink
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("percpu", Some(percpu)) => bench_per_cpu(
+                percpu
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                percpu
+                    .value_of("cpus")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse CPU count"),
+                percpu
+                    .value_of("threshold")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse threshold"),
+            ),
+            ("quickfit", Some(quickfit)) => bench_quick_fit(
+                quickfit
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &quickfit
+                    .value_of("sizes")
+                    .unwrap()
+                    .split(',')
+                    .map(|size| size.trim().parse().expect("Could not parse sizes"))
+                    .collect::<Vec<usize>>(),
+            ),
+            ("buddyorder", Some(order)) => bench_buddy_order(
+                order
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                order
+                    .value_of("page")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("headtohead", Some(headtohead)) => bench_head_to_head(
+                headtohead
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                headtohead
+                    .value_of("share")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse share"),
+            ),
+            ("regions", Some(regions)) => bench_regions(
+                regions
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("compact", Some(compact)) => bench_compact(
+                compact
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &compact
+                    .value_of("pinned")
+                    .unwrap()
+                    .split(',')
+                    .map(|fraction| {
+                        fraction
+                            .trim()
+                            .parse()
+                            .expect("Could not parse pinned fractions")
+                    })
+                    .collect::<Vec<f64>>(),
+            ),
+            ("bins", Some(bins)) => bench_bins(
+                bins.value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                bins.value_of("page")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse page size"),
+            ),
+            ("bestfit", Some(bestfit)) => bench_bestfit(
+                bestfit
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &bestfit
+                    .value_of("heaps")
+                    .unwrap()
+                    .split(',')
+                    .map(|heap| heap.trim().parse().expect("Could not parse heap sizes"))
+                    .collect::<Vec<usize>>(),
+            ),
+            ("hoard", Some(hoard)) => bench_hoard(
+                hoard
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                hoard
+                    .value_of("threads")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse threads"),
+                &hoard
+                    .value_of("emptiness")
+                    .unwrap()
+                    .split(',')
+                    .map(|fraction| {
+                        fraction
+                            .trim()
+                            .parse()
+                            .expect("Could not parse emptiness fractions")
+                    })
+                    .collect::<Vec<f64>>(),
+            ),
+            ("mimalloc", Some(mimalloc)) => bench_mimalloc(
+                mimalloc
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                mimalloc
+                    .value_of("segment")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse segment size"),
+                mimalloc
+                    .value_of("page")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse page size"),
+            ),
+            ("spans", Some(spans)) => bench_spans(
+                spans
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                spans
+                    .value_of("span")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse span size"),
+            ),
+            ("recycling", Some(recycling)) => bench_recycling(
+                recycling
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &recycling
+                    .value_of("keep")
+                    .unwrap()
+                    .split(',')
+                    .map(|keep| match keep.trim() {
+                        "all" => Retention::All,
+                        "0" => Retention::Release,
+                        count => {
+                            Retention::Keep(count.parse().expect("Could not parse spans to keep"))
+                        }
+                    })
+                    .collect::<Vec<Retention>>(),
+            ),
+            ("aging", Some(aging)) => bench_aging(
+                aging
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("device", Some(device)) => bench_device(
+                device
+                    .value_of("frames")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse frames"),
+            ),
+            ("numa", Some(numa)) => bench_numa(
+                numa.value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                numa.value_of("nodes")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse nodes"),
+                numa.value_of("remote")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse remote fraction"),
+            ),
+            ("hybrid", Some(hybrid)) => bench_hybrid(
+                hybrid
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &hybrid
+                    .value_of("thresholds")
+                    .unwrap()
+                    .split(',')
+                    .map(|size| size.trim().parse().expect("Could not parse thresholds"))
+                    .collect::<Vec<usize>>(),
+            ),
+            ("arrivals", Some(arrivals)) => {
+                let num = |name| {
+                    arrivals
+                        .value_of(name)
+                        .unwrap()
+                        .parse::<f64>()
+                        .expect("Could not parse input")
+                };
+                let process = match arrivals.value_of("process").unwrap() {
+                    "poisson" => Arrivals::poisson(1.0),
+                    "bursty" => Arrivals::bursty(1.0, num("burst"), num("dwell")),
+                    _ => unreachable!(),
+                };
+                bench_arrivals(process, num("lifetime"), num("correlation"))
+            }
+            ("synthetic", Some(synthetic)) => bench_synthetic(
+                &synthetic
+                    .value_of("correlation")
+                    .unwrap()
+                    .split(',')
+                    .map(|c| c.trim().parse().expect("Could not parse correlation"))
+                    .collect::<Vec<f64>>(),
+                synthetic
+                    .value_of("occupancy")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                synthetic
+                    .value_of("lifetime")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("cycles", Some(cycles)) => bench_cycles(
+                cycles
+                    .value_of("cycles")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                cycles
+                    .value_of("churn")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                cycles
+                    .value_of("keep")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                cycles
+                    .value_of("release")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("pages", Some(pages)) => bench_pages(
+                pages
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                pages
+                    .value_of("order")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                pages.is_present("scatter"),
+            ),
+            ("orders", Some(orders)) => bench_orders(
+                orders
+                    .value_of("ops")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                orders
+                    .value_of("occupancy")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                orders
+                    .value_of("order")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("deadlines", Some(deadlines)) => bench_deadlines(
+                deadlines
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                deadlines
+                    .value_of("search")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &deadlines
+                    .value_of("deadline")
+                    .unwrap()
+                    .split(',')
+                    .map(|deadline| deadline.trim().parse().expect("Could not parse deadlines"))
+                    .collect::<Vec<f32>>(),
+            ),
+            ("mobility", Some(mobility)) => {
+                // Each was checked to be a chance on its own, but
+                // movable allocations get what is left of the two
+                let chance = |name| mobility.value_of(name).unwrap().parse::<f64>().unwrap();
+                let (unmovable, reclaimable) = (chance("unmovable"), chance("reclaimable"));
+                if unmovable + reclaimable > 1.0 {
+                    clap::Error::with_description(
+                        "The unmovable and reclaimable chances add up to more than 1",
+                        clap::ErrorKind::ValueValidation,
+                    )
+                    .exit();
+                }
+                bench_mobility(
+                    mobility
+                        .value_of("ops")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input"),
+                    mobility
+                        .value_of("occupancy")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input"),
+                    unmovable,
+                    reclaimable,
+                    mobility
+                        .value_of("block")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input"),
+                )
+            }
+            ("kcompactd", Some(kcompactd)) => bench_kcompactd(
+                kcompactd
+                    .value_of("ops")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                kcompactd
+                    .value_of("occupancy")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                kcompactd
+                    .value_of("unmovable")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &kcompactd
+                    .value_of("budget")
+                    .unwrap()
+                    .split(',')
+                    .map(|budget| budget.trim().parse().expect("Could not parse budgets"))
+                    .collect::<Vec<usize>>(),
+                kcompactd
+                    .value_of("order")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("controlled", Some(controlled)) => bench_controlled(
+                controlled
+                    .value_of("peak")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                controlled
+                    .value_of("amplitude")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                controlled
+                    .value_of("period")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("reservation", Some(reservation)) => bench_reservation(
+                reservation
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                reservation
+                    .value_of("critical")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &reservation
+                    .value_of("sizes")
+                    .unwrap()
+                    .split(',')
+                    .map(|size| size.trim().parse().expect("Could not parse sizes"))
+                    .collect::<Vec<usize>>(),
+            ),
+            ("ids", Some(ids)) => bench_ids(
+                ids.value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                ids.value_of("count")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse id count"),
+            ),
+            ("coalesce", Some(coalesce)) => bench_coalesce(
+                coalesce
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("budget", Some(budget)) => bench_budget(
+                budget
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("aligned", Some(aligned)) => bench_aligned(
+                aligned
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("segregated", Some(segregated)) => bench_segregated(
+                segregated
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &segregated
+                    .value_of("classes")
+                    .unwrap()
+                    .split(',')
+                    .map(|bound| bound.trim().parse().expect("Could not parse classes"))
+                    .collect::<Vec<usize>>(),
+            ),
+            _ => unreachable!(),
+        },
+        ("analyze", Some(analyze)) => match analyze.subcommand() {
+            ("locality", Some(locality)) => {
+                let trace = match locality.value_of("trace") {
+                    Some(path) => fs::read_to_string(path)
+                        .expect("Could not read trace")
+                        .parse()
+                        .expect("Could not parse trace"),
+                    None => trace::random_memory(
+                        locality
+                            .value_of("ratio")
+                            .unwrap()
+                            .parse()
+                            .expect("Could not parse input"),
+                        &mut rand::thread_rng(),
+                    ),
+                };
+                analyze_locality(
+                    trace,
+                    locality
+                        .value_of("page")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input"),
+                )
+            }
+            ("paging", Some(paging)) => {
+                let trace = match paging.value_of("trace") {
+                    Some(path) => fs::read_to_string(path)
+                        .expect("Could not read trace")
+                        .parse()
+                        .expect("Could not parse trace"),
+                    None => trace::random_memory(
+                        paging
+                            .value_of("ratio")
+                            .unwrap()
+                            .parse()
+                            .expect("Could not parse input"),
+                        &mut rand::thread_rng(),
+                    ),
+                };
+                analyze_paging(
+                    trace,
+                    paging
+                        .value_of("page")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input"),
+                    &paging
+                        .value_of("frames")
+                        .unwrap()
+                        .split(',')
+                        .map(|frames| frames.trim().parse().expect("Could not parse frames"))
+                        .collect::<Vec<usize>>(),
+                )
+            }
+            ("belady", Some(belady)) => {
+                let number = |name: &str| -> usize {
+                    belady
+                        .value_of(name)
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input")
+                };
+                analyze_belady(number("pages"), number("length"), number("tries"))
+            }
+            ("offline", Some(offline)) => {
+                let trace = match offline.value_of("trace") {
+                    Some(path) => fs::read_to_string(path)
+                        .expect("Could not read trace")
+                        .parse()
+                        .expect("Could not parse trace"),
+                    None => trace::random_memory(
+                        offline
+                            .value_of("ratio")
+                            .unwrap()
+                            .parse()
+                            .expect("Could not parse input"),
+                        &mut rand::thread_rng(),
+                    ),
+                };
+                analyze_offline(trace)
+            }
+            ("regions", Some(regions)) => {
+                let trace = match regions.value_of("trace") {
+                    Some(path) => fs::read_to_string(path)
+                        .expect("Could not read trace")
+                        .parse()
+                        .expect("Could not parse trace"),
+                    None => trace::random_memory(
+                        regions
+                            .value_of("ratio")
+                            .unwrap()
+                            .parse()
+                            .expect("Could not parse input"),
+                        &mut rand::thread_rng(),
+                    ),
+                };
+                analyze_regions(
+                    trace,
+                    &regions
+                        .value_of("mallocs")
+                        .unwrap()
+                        .split(',')
+                        .map(|size| size.trim().parse().expect("Could not parse mallocs"))
+                        .collect::<Vec<usize>>(),
+                    regions
+                        .value_of("chunk")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse chunk"),
+                )
+            }
+            ("explore", Some(explore)) => analyze_explore(
+                explore.value_of("allocator").unwrap(),
+                explore
+                    .value_of("depth")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                &explore
+                    .value_of("sizes")
+                    .unwrap()
+                    .split(',')
+                    .map(|size| size.trim().parse().expect("Could not parse sizes"))
+                    .collect::<Vec<usize>>(),
+            ),
+            ("zeroing", Some(zeroing)) => analyze_zeroing(
+                fs::read_to_string(zeroing.value_of("trace").unwrap())
+                    .expect("Could not read trace")
+                    .parse()
+                    .expect("Could not parse trace"),
+            ),
+            ("minimize", Some(minimize)) => analyze_minimize(
+                fs::read_to_string(minimize.value_of("trace").unwrap())
+                    .expect("Could not read trace")
+                    .parse()
+                    .expect("Could not parse trace"),
+            ),
+            ("drilldown", Some(drilldown)) => {
+                let trace = match drilldown.value_of("trace") {
+                    Some(path) => fs::read_to_string(path)
+                        .expect("Could not read trace")
+                        .parse()
+                        .expect("Could not parse trace"),
+                    None => trace::random_memory(
+                        drilldown
+                            .value_of("ratio")
+                            .unwrap()
+                            .parse()
+                            .expect("Could not parse input"),
+                        &mut rand::thread_rng(),
+                    ),
+                };
+                analyze_drilldown(
+                    trace,
+                    drilldown.value_of("allocator").unwrap(),
+                    drilldown
+                        .value_of("top")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse top"),
+                )
+            }
+            ("maps", Some(maps)) => {
+                let trace = match maps.value_of("trace") {
+                    Some(path) => fs::read_to_string(path)
+                        .expect("Could not read trace")
+                        .parse()
+                        .expect("Could not parse trace"),
+                    None => trace::random_memory(
+                        maps.value_of("ratio")
+                            .unwrap()
+                            .parse()
+                            .expect("Could not parse input"),
+                        &mut rand::thread_rng(),
+                    ),
+                };
+                analyze_maps(trace, maps.value_of("allocator").unwrap())
+            }
+            ("heatmap", Some(heatmap)) => {
+                let trace = match heatmap.value_of("trace") {
+                    Some(path) => fs::read_to_string(path)
+                        .expect("Could not read trace")
+                        .parse()
+                        .expect("Could not parse trace"),
+                    None => trace::random_memory(
+                        heatmap
+                            .value_of("ratio")
+                            .unwrap()
+                            .parse()
+                            .expect("Could not parse input"),
+                        &mut rand::thread_rng(),
+                    ),
+                };
+                analyze_heatmap(
+                    trace,
+                    heatmap
+                        .value_of("width")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input"),
+                    heatmap
+                        .value_of("epochs")
+                        .unwrap()
+                        .parse()
+                        .expect("Could not parse input"),
+                )
+            }
+            ("audit", Some(audit)) => analyze_audit(
+                audit
+                    .value_of("seed")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                audit.value_of("allocator").unwrap(),
+                audit.value_of("record"),
+                audit.value_of("verify"),
+            ),
+            ("alignment", Some(alignment)) => analyze_alignment(
+                alignment
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                alignment
+                    .value_of("size")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("wcet", Some(wcet)) => analyze_wcet(
+                wcet.value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                wcet.value_of("search")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+            ),
+            ("tradeoff", Some(tradeoff)) => analyze_tradeoff(
+                tradeoff
+                    .value_of("ratio")
+                    .unwrap()
+                    .parse()
+                    .expect("Could not parse input"),
+                tradeoff
+                    .value_of("min")
                     .unwrap()
                     .parse()
                     .expect("Could not parse input"),
             ),
             _ => unreachable!(),
         },
+        ("completions", Some(completions)) => cli().gen_completions_to(
+            "freespace-sim",
+            completions.value_of("shell").unwrap().parse().unwrap(),
+            &mut io::stdout(),
+        ),
+        ("manpage", Some(_)) => print!("{}", manpage::render(&cli(), "freespace-sim")),
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,607 @@
+use crate::metrics::Metric;
+use crate::rng::Generator;
+use crate::rounding::Rounding;
+use crate::schema::SCHEMA_VERSION;
+use crate::stats::Aggregate;
+use crate::workloads::Results;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+/// Everything needed to run a bench again and get the same numbers:
+/// its settings, and the seed each run drew its ops from. Written
+/// and read as a flat JSON object
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// `stack` or `random`
+    pub bench: String,
+    pub ratio: f64,
+    pub rounding: Rounding,
+    /// How the heap was warmed up, as passed to `-w`
+    pub warm: Option<String>,
+    /// Seed for aging the heap with `aged:<ops>`
+    pub warm_seed: u64,
+    /// Whether the built in metric collectors ran
+    pub metrics: bool,
+    /// Generator the seeds are for. Version 2 manifests were saved
+    /// before it could be picked, and are read as ChaCha
+    pub rng: Generator,
+    pub seeds: Vec<u64>,
+}
+
+impl Manifest {
+    // The fields of the manifest, one per line, without a comma or a
+    // newline after the last so a file can add more
+    fn write_fields(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seeds: Vec<String> = self.seeds.iter().map(u64::to_string).collect();
+        writeln!(f, "  \"schema_version\": {},", SCHEMA_VERSION)?;
+        writeln!(f, "  \"bench\": {},", quote(&self.bench))?;
+        writeln!(f, "  \"ratio\": {},", self.ratio)?;
+        writeln!(f, "  \"rounding\": {},", quote(&self.rounding.to_string()))?;
+        match &self.warm {
+            Some(warm) => writeln!(f, "  \"warm\": {},", quote(warm))?,
+            None => writeln!(f, "  \"warm\": null,")?,
+        }
+        writeln!(f, "  \"warm_seed\": {},", self.warm_seed)?;
+        writeln!(f, "  \"metrics\": {},", self.metrics)?;
+        writeln!(f, "  \"rng\": {},", quote(&self.rng.to_string()))?;
+        write!(f, "  \"seeds\": [{}]", seeds.join(", "))
+    }
+
+    // Takes the manifest's fields out of a file of `version`, leaving
+    // the rest
+    fn from_fields(fields: &mut BTreeMap<String, Value>, version: u32) -> Result<Self, String> {
+        let rng = match fields.remove("rng") {
+            None if version == 2 => Generator::default(),
+            None => return Err("Missing \"rng\" in manifest".to_string()),
+            Some(Value::String(rng)) => rng.parse()?,
+            Some(_) => return Err("\"rng\" should be a string".to_string()),
+        };
+        let mut take = |key: &str| {
+            fields
+                .remove(key)
+                .ok_or(format!("Missing \"{}\" in manifest", key))
+        };
+        let number = |value: Value, key: &str| match value {
+            Value::Number(number) => Ok(number),
+            _ => Err(format!("\"{}\" should be a number", key)),
+        };
+        let string = |value: Value, key: &str| match value {
+            Value::String(string) => Ok(string),
+            _ => Err(format!("\"{}\" should be a string", key)),
+        };
+        let seed = |number: String| {
+            number
+                .parse::<u64>()
+                .map_err(|_| format!("Could not parse seed {}", number))
+        };
+
+        let bench = string(take("bench")?, "bench")?;
+        let ratio = number(take("ratio")?, "ratio")?
+            .parse()
+            .map_err(|_| "Could not parse ratio".to_string())?;
+        let rounding = string(take("rounding")?, "rounding")?.parse()?;
+        let warm = match take("warm")? {
+            Value::Null => None,
+            value => Some(string(value, "warm")?),
+        };
+        let warm_seed = seed(number(take("warm_seed")?, "warm_seed")?)?;
+        let metrics = match take("metrics")? {
+            Value::Bool(metrics) => metrics,
+            _ => return Err("\"metrics\" should be true or false".to_string()),
+        };
+        let seeds = match take("seeds")? {
+            Value::Array(values) => values
+                .into_iter()
+                .map(|value| seed(number(value, "seeds")?))
+                .collect::<Result<Vec<u64>, String>>()?,
+            _ => return Err("\"seeds\" should be an array".to_string()),
+        };
+
+        Ok(Manifest {
+            bench,
+            ratio,
+            rounding,
+            warm,
+            warm_seed,
+            metrics,
+            rng,
+            seeds,
+        })
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{{")?;
+        self.write_fields(f)?;
+        writeln!(f, "\n}}")
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Parser(s.chars().peekable()).object()?;
+        let version = schema_version(&mut fields)?;
+        Manifest::from_fields(&mut fields, version)
+    }
+}
+
+/// What one run found, as an `Archive` keeps it: the counts every
+/// bench reports, and the run's metrics by name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub malloc_fails: usize,
+    pub free_fails: usize,
+    pub internal_frag: usize,
+    pub external_frag: f32,
+    pub metrics: BTreeMap<String, Metric>,
+}
+
+impl Record {
+    /// The record as `Results`, with everything it doesn't keep left
+    /// at zero
+    pub fn results(&self) -> Results {
+        Results {
+            malloc_fails: self.malloc_fails,
+            free_fails: self.free_fails,
+            internal_frag: self.internal_frag,
+            external_frag: self.external_frag,
+            metrics: self.metrics.clone(),
+            ..Results::default()
+        }
+    }
+}
+
+impl From<&Results> for Record {
+    fn from(results: &Results) -> Self {
+        Record {
+            malloc_fails: results.malloc_fails,
+            free_fails: results.free_fails,
+            internal_frag: results.internal_frag,
+            external_frag: results.external_frag,
+            metrics: results.metrics.clone(),
+        }
+    }
+}
+
+/// A manifest with what each allocator's runs found, so experiments
+/// can be kept and read back by later versions of the crate. Files of
+/// every schema version up to `SCHEMA_VERSION` load, and metrics are
+/// kept by name, so metrics that come and go don't break old files.
+/// `bench rerun` reads the same file as a `Manifest`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Archive {
+    pub manifest: Manifest,
+    /// Version the file was saved with
+    pub schema_version: u32,
+    /// Each allocator's runs, in the order of the manifest's seeds
+    pub results: Vec<(String, Vec<Record>)>,
+}
+
+impl Archive {
+    pub fn new(manifest: Manifest) -> Self {
+        Archive {
+            manifest,
+            schema_version: SCHEMA_VERSION,
+            results: Vec::new(),
+        }
+    }
+
+    /// Adds the runs of one allocator
+    pub fn add(&mut self, allocator: &str, runs: &Aggregate) {
+        let records = runs.runs.iter().map(Record::from).collect();
+        self.results.push((allocator.to_string(), records));
+    }
+}
+
+impl fmt::Display for Archive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{{")?;
+        self.manifest.write_fields(f)?;
+        writeln!(f, ",\n  \"results\": [")?;
+        for (i, (allocator, records)) in self.results.iter().enumerate() {
+            writeln!(f, "    {{")?;
+            writeln!(f, "      \"allocator\": {},", quote(allocator))?;
+            writeln!(f, "      \"runs\": [")?;
+            for (j, record) in records.iter().enumerate() {
+                let comma = if j + 1 < records.len() { "," } else { "" };
+                writeln!(f, "        {}{}", record_json(record), comma)?;
+            }
+            writeln!(f, "      ]")?;
+            let comma = if i + 1 < self.results.len() { "," } else { "" };
+            writeln!(f, "    }}{}", comma)?;
+        }
+        writeln!(f, "  ]\n}}")
+    }
+}
+
+impl FromStr for Archive {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Parser(s.chars().peekable()).object()?;
+        let schema_version = schema_version(&mut fields)?;
+        let manifest = Manifest::from_fields(&mut fields, schema_version)?;
+        let results = match fields.remove("results") {
+            None => return Err("Missing \"results\"".to_string()),
+            Some(Value::Array(allocators)) => allocators
+                .into_iter()
+                .map(allocator_results)
+                .collect::<Result<_, String>>()?,
+            Some(_) => return Err("\"results\" should be an array".to_string()),
+        };
+        Ok(Archive {
+            manifest,
+            schema_version,
+            results,
+        })
+    }
+}
+
+// The version a file says it has, which must be one this build
+// knows how to read. Manifests were first saved with version 2
+fn schema_version(fields: &mut BTreeMap<String, Value>) -> Result<u32, String> {
+    let version = match fields.remove("schema_version") {
+        Some(Value::Number(number)) => number
+            .parse()
+            .map_err(|_| format!("Could not parse schema version {}", number))?,
+        Some(_) => return Err("\"schema_version\" should be a number".to_string()),
+        None => return Err("Missing \"schema_version\" in manifest".to_string()),
+    };
+    if !(2..=SCHEMA_VERSION).contains(&version) {
+        return Err(format!(
+            "Schema version {} is not one this build reads, which are 2 to {}",
+            version, SCHEMA_VERSION
+        ));
+    }
+    Ok(version)
+}
+
+fn record_json(record: &Record) -> String {
+    let metrics: Vec<String> = record
+        .metrics
+        .iter()
+        .map(|(name, metric)| {
+            let value = match metric {
+                Metric::Value(value) => value.to_string(),
+                Metric::Series(series) => {
+                    let values: Vec<String> = series.iter().map(f32::to_string).collect();
+                    format!("[{}]", values.join(", "))
+                }
+                Metric::Histogram(counts) => {
+                    let counts: Vec<String> = counts
+                        .iter()
+                        .map(|(bucket, count)| format!("\"{}\": {}", bucket, count))
+                        .collect();
+                    format!("{{{}}}", counts.join(", "))
+                }
+            };
+            format!("{}: {}", quote(name), value)
+        })
+        .collect();
+    format!(
+        "{{\"malloc_fails\": {}, \"free_fails\": {}, \"internal_frag\": {}, \"external_frag\": {}, \"metrics\": {{{}}}}}",
+        record.malloc_fails,
+        record.free_fails,
+        record.internal_frag,
+        record.external_frag,
+        metrics.join(", ")
+    )
+}
+
+fn allocator_results(value: Value) -> Result<(String, Vec<Record>), String> {
+    let mut fields = match value {
+        Value::Object(fields) => fields,
+        _ => return Err("Each allocator's results should be an object".to_string()),
+    };
+    let allocator = match fields.remove("allocator") {
+        Some(Value::String(allocator)) => allocator,
+        _ => return Err("Missing the allocator's name".to_string()),
+    };
+    let records = match fields.remove("runs") {
+        Some(Value::Array(runs)) => runs
+            .into_iter()
+            .map(record)
+            .collect::<Result<_, String>>()?,
+        _ => return Err(format!("Missing the runs of {}", allocator)),
+    };
+    Ok((allocator, records))
+}
+
+fn record(value: Value) -> Result<Record, String> {
+    let mut fields = match value {
+        Value::Object(fields) => fields,
+        _ => return Err("Each run should be an object".to_string()),
+    };
+    let mut take = |key: &str| match fields.remove(key) {
+        Some(Value::Number(number)) => Ok(number),
+        _ => Err(format!("Missing \"{}\" in a run", key)),
+    };
+    let count = |number: String| {
+        number
+            .parse()
+            .map_err(|_| format!("Could not parse count {}", number))
+    };
+    let malloc_fails = count(take("malloc_fails")?)?;
+    let free_fails = count(take("free_fails")?)?;
+    let internal_frag = count(take("internal_frag")?)?;
+    let external_frag = float(take("external_frag")?)?;
+    let metrics = match fields.remove("metrics") {
+        Some(Value::Object(metrics)) => metrics
+            .into_iter()
+            .map(|(name, value)| Ok((name, metric(value)?)))
+            .collect::<Result<_, String>>()?,
+        _ => return Err("Missing \"metrics\" in a run".to_string()),
+    };
+    Ok(Record {
+        malloc_fails,
+        free_fails,
+        internal_frag,
+        external_frag,
+        metrics,
+    })
+}
+
+// A number is a value, an array a series, and an object a histogram
+// keyed by bucket
+fn metric(value: Value) -> Result<Metric, String> {
+    match value {
+        Value::Number(number) => Ok(Metric::Value(float(number)?)),
+        Value::Array(values) => values
+            .into_iter()
+            .map(|value| match value {
+                Value::Number(number) => float(number),
+                _ => Err("A series should only have numbers".to_string()),
+            })
+            .collect::<Result<_, String>>()
+            .map(Metric::Series),
+        Value::Object(counts) => counts
+            .into_iter()
+            .map(|(bucket, count)| match (bucket.parse(), count) {
+                (Ok(bucket), Value::Number(count)) => count
+                    .parse()
+                    .map(|count| (bucket, count))
+                    .map_err(|_| format!("Could not parse count {}", count)),
+                _ => Err(format!("Could not parse bucket {}", bucket)),
+            })
+            .collect::<Result<_, String>>()
+            .map(Metric::Histogram),
+        _ => Err("A metric should be a number, an array or an object".to_string()),
+    }
+}
+
+fn float(number: String) -> Result<f32, String> {
+    number
+        .parse()
+        .map_err(|_| format!("Could not parse {}", number))
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Just enough JSON for a manifest and its results. Numbers are kept
+// as text so seeds don't lose precision going through a float
+#[derive(Debug)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+struct Parser<'a>(Peekable<Chars<'a>>);
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        while self.0.peek().is_some_and(|c| c.is_whitespace()) {
+            self.0.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_space();
+        match self.0.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("Expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("Expected '{}' but the manifest ended", expected)),
+        }
+    }
+
+    // Parses a list of items separated by commas up to `close`
+    fn list<T>(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let mut items = Vec::new();
+        self.skip_space();
+        if self.0.peek() == Some(&close) {
+            self.0.next();
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            self.skip_space();
+            match self.0.next() {
+                Some(',') => continue,
+                Some(c) if c == close => return Ok(items),
+                _ => return Err(format!("Expected ',' or '{}'", close)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<BTreeMap<String, Value>, String> {
+        self.expect('{')?;
+        let fields = self.list('}', |parser| {
+            parser.skip_space();
+            let key = parser.string()?;
+            parser.expect(':')?;
+            Ok((key, parser.value()?))
+        })?;
+        Ok(fields.into_iter().collect())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.0.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => string.extend(self.0.next()),
+                Some(c) => string.push(c),
+                None => return Err("Unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(&c) = self.0.peek() {
+            if !(c.is_alphanumeric() || "+-.".contains(c)) {
+                break;
+            }
+            word.push(c);
+            self.0.next();
+        }
+        word
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_space();
+        match self.0.peek() {
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('[') => {
+                self.0.next();
+                Ok(Value::Array(self.list(']', |parser| parser.value())?))
+            }
+            Some('{') => Ok(Value::Object(self.object()?)),
+            _ => match self.word().as_str() {
+                "null" => Ok(Value::Null),
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                "" => Err("Expected a value".to_string()),
+                number => Ok(Value::Number(number.to_string())),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let manifest = Manifest {
+            bench: "random".to_string(),
+            ratio: 0.55,
+            rounding: Rounding::SizeClass(vec![16, 64]),
+            warm: Some("aged:1000".to_string()),
+            warm_seed: 7,
+            metrics: true,
+            rng: Generator::Small,
+            seeds: vec![u64::MAX, 0, 42],
+        };
+        assert_eq!(manifest.to_string().parse(), Ok(manifest.clone()));
+
+        let cold = Manifest {
+            warm: None,
+            seeds: Vec::new(),
+            ..manifest
+        };
+        assert_eq!(cold.to_string().parse(), Ok(cold));
+    }
+
+    #[test]
+    fn parse() {
+        let text = r#"{"seeds":[1,2],"bench":"stack","ratio":0.5,"rounding":"pow2",
+                       "warm":null,"warm_seed":3,"metrics":false,"schema_version":2}"#;
+        let manifest: Manifest = text.parse().unwrap();
+        assert_eq!(manifest.bench, "stack");
+        assert_eq!(manifest.rounding, Rounding::PowerOfTwo);
+        assert_eq!(manifest.seeds, vec![1, 2]);
+        // Version 2 had no generator to pick
+        assert_eq!(manifest.rng, Generator::ChaCha);
+        let small: Manifest = text.replace("{", r#"{"rng":"small","#).parse().unwrap();
+        assert_eq!(small.rng, Generator::Small);
+
+        // From version 3 a manifest has to say which generator it used
+        let current = text.replace(r#""schema_version":2"#, r#""schema_version":3"#);
+        assert!(current.parse::<Manifest>().is_err());
+        let small: Manifest = current.replace("{", r#"{"rng":"small","#).parse().unwrap();
+        assert_eq!(small.rng, Generator::Small);
+
+        assert!(r#"{"bench": "stack"}"#.parse::<Manifest>().is_err());
+        assert!(text.replace("[1,2]", "[1,-2]").parse::<Manifest>().is_err());
+        assert!(text.replace("}", "").parse::<Manifest>().is_err());
+        assert!(text
+            .replace("{", r#"{"rng":"std","#)
+            .parse::<Manifest>()
+            .is_err());
+
+        // Only versions this build knows are read
+        assert!(text
+            .replace(r#","schema_version":2"#, "")
+            .parse::<Manifest>()
+            .is_err());
+        for version in ["1", "4", "\"2\""].iter() {
+            let text = text.replace(
+                r#""schema_version":2"#,
+                &format!(r#""schema_version":{}"#, version),
+            );
+            assert!(text.parse::<Manifest>().is_err());
+        }
+    }
+
+    #[test]
+    fn archive() {
+        let manifest = Manifest {
+            bench: "stack".to_string(),
+            ratio: 0.7,
+            rounding: Rounding::None,
+            warm: None,
+            warm_seed: 1,
+            metrics: true,
+            rng: Generator::ChaCha,
+            seeds: vec![5, 6],
+        };
+        let mut run = Results {
+            malloc_fails: 3,
+            internal_frag: 40,
+            external_frag: 0.25,
+            ..Results::default()
+        };
+        run.metrics
+            .insert("forecast".to_string(), Metric::Value(0.5));
+        run.metrics
+            .insert("frag".to_string(), Metric::Series(vec![0.1, 0.3]));
+        run.metrics.insert(
+            "sizes".to_string(),
+            Metric::Histogram(vec![(16, 2), (64, 1)].into_iter().collect()),
+        );
+        run.metrics
+            .insert("empty".to_string(), Metric::Series(Vec::new()));
+        let mut archive = Archive::new(manifest.clone());
+        archive.add(
+            "buddy",
+            &vec![run.clone(), Results::default()].into_iter().collect(),
+        );
+        archive.add("freelist", &Vec::new().into_iter().collect());
+        let loaded: Archive = archive.to_string().parse().unwrap();
+        assert_eq!(loaded, archive);
+        assert_eq!(loaded.results[0].1[0].results().metrics, run.metrics);
+
+        // The manifest still reads the file, results and all
+        assert_eq!(archive.to_string().parse(), Ok(manifest.clone()));
+
+        // A manifest without results is not an archive
+        assert!(manifest.to_string().parse::<Archive>().is_err());
+    }
+}
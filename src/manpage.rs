@@ -0,0 +1,143 @@
+use clap::App;
+use std::fmt::Write;
+
+/// A man page in roff for a clap command line, with a section for
+/// every subcommand at any depth, listing its options with their
+/// defaults and possible values the way `--help` shows them. Built
+/// from the same definitions as the parser, so it can't drift from
+/// what the binary accepts
+pub fn render(app: &App, bin: &str) -> String {
+    let meta = &app.p.meta;
+    let mut page = String::new();
+    let version = meta.version.unwrap_or("");
+    writeln!(
+        page,
+        ".TH {} 1 \"\" \"{} {}\"",
+        bin.to_uppercase(),
+        bin,
+        version
+    )
+    .unwrap();
+    writeln!(page, ".SH NAME").unwrap();
+    writeln!(page, "{} \\- {}", bin, escape(meta.about.unwrap_or(""))).unwrap();
+    writeln!(page, ".SH SYNOPSIS").unwrap();
+    writeln!(page, ".B {}\n\\fIcommand\\fR [\\fIoptions\\fR]", bin).unwrap();
+    if let Some(author) = meta.author {
+        writeln!(page, ".SH AUTHOR\n{}", escape(author)).unwrap();
+    }
+    writeln!(page, ".SH COMMANDS").unwrap();
+    for command in app.p.subcommands.iter() {
+        section(&mut page, command, bin);
+    }
+    page
+}
+
+// One subsection per command, then its subcommands under it
+fn section(page: &mut String, app: &App, parent: &str) {
+    let path = format!("{} {}", parent, app.get_name());
+    let p = &app.p;
+    if p.subcommands.is_empty() || p.meta.about.is_some() {
+        writeln!(page, ".SS \"{}\"", escape(&path)).unwrap();
+        if let Some(about) = p.meta.about {
+            writeln!(page, "{}", escape(about)).unwrap();
+        }
+    }
+
+    for pos in p.positionals.values() {
+        writeln!(page, ".TP\n\\fI{}\\fR", pos.b.name).unwrap();
+        writeln!(page, "{}", escape(pos.b.help.unwrap_or(""))).unwrap();
+    }
+    for flag in p.flags.iter() {
+        writeln!(page, ".TP\n{}", switches(flag.s.short, flag.s.long)).unwrap();
+        writeln!(page, "{}", escape(flag.b.help.unwrap_or(""))).unwrap();
+    }
+    for opt in p.opts.iter() {
+        writeln!(
+            page,
+            ".TP\n{} \\fI{}\\fR",
+            switches(opt.s.short, opt.s.long),
+            opt.b.name
+        )
+        .unwrap();
+        let mut help = opt.b.help.unwrap_or("").to_string();
+        if let Some(values) = &opt.v.possible_vals {
+            write!(help, " [possible values: {}]", values.join(", ")).unwrap();
+        }
+        if let Some(default) = opt.v.default_val {
+            write!(help, " [default: {}]", default.to_string_lossy()).unwrap();
+        }
+        writeln!(page, "{}", escape(help.trim())).unwrap();
+    }
+
+    for command in p.subcommands.iter() {
+        section(page, command, &path);
+    }
+}
+
+// The short and long switch of an argument, in bold
+fn switches(short: Option<char>, long: Option<&str>) -> String {
+    let names: Vec<String> = short
+        .map(|short| format!("\\fB\\-{}\\fR", short))
+        .into_iter()
+        .chain(long.map(|long| format!("\\fB\\-\\-{}\\fR", escape(long))))
+        .collect();
+    names.join(", ")
+}
+
+// Backslashes and dashes are special in roff, and so are a dot or a
+// quote at the start of a line
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, SubCommand};
+
+    #[test]
+    fn sections() {
+        let app = App::new("sim")
+            .version("1.0")
+            .about("Simulates allocators")
+            .subcommand(
+                SubCommand::with_name("bench").subcommand(
+                    SubCommand::with_name("spans")
+                        .about("Compare spans")
+                        .arg(
+                            Arg::with_name("ratio")
+                                .long("ratio")
+                                .short("r")
+                                .help("Chance of a malloc")
+                                .default_value("0.5")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("policy")
+                                .long("policy")
+                                .possible_values(&["first", "best"])
+                                .takes_value(true),
+                        ),
+                ),
+            );
+        let page = render(&app, "sim");
+        assert!(page.starts_with(".TH SIM 1 \"\" \"sim 1.0\"\n"));
+        assert!(page.contains("sim \\- Simulates allocators\n"));
+        // Commands with nothing of their own only show up in the
+        // paths of their subcommands
+        assert!(!page.contains(".SS \"sim bench\"\n"));
+        assert!(page.contains(
+            ".SS \"sim bench spans\"\nCompare spans\n\
+             .TP\n\\fB\\-r\\fR, \\fB\\-\\-ratio\\fR \\fIratio\\fR\n\
+             Chance of a malloc [default: 0.5]\n"
+        ));
+        assert!(
+            page.contains("\\fB\\-\\-policy\\fR \\fIpolicy\\fR\n[possible values: first, best]\n")
+        );
+    }
+}
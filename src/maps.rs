@@ -0,0 +1,216 @@
+use crate::trace::{Event, Op, Trace};
+use crate::Allocator;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A range of the heap that is either allocated or free
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    pub used: bool,
+    pub tag: String,
+}
+
+/// A snapshot of a heap laid out like `/proc/self/maps`, one range
+/// per line in address order. Allocations cover the bytes that were
+/// requested, so padding an allocator added shows up as free. Every
+/// byte of the heap is on exactly one line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressMap {
+    pub mappings: Vec<Mapping>,
+}
+
+impl AddressMap {
+    /// Build a map of [base, base + size) with the given allocations,
+    /// as address, size and tag. The gaps between them are free
+    pub fn new(
+        base: usize,
+        size: usize,
+        allocations: impl IntoIterator<Item = (usize, usize, String)>,
+    ) -> Self {
+        let mut allocations: Vec<(usize, usize, String)> = allocations.into_iter().collect();
+        allocations.sort_by_key(|&(addr, _, _)| addr);
+
+        let mut mappings = Vec::new();
+        let mut addr = base;
+        for (start, len, tag) in allocations {
+            if addr < start {
+                mappings.push(Mapping::free(addr, start));
+            }
+            mappings.push(Mapping {
+                start,
+                end: start + len,
+                used: true,
+                tag,
+            });
+            addr = start + len;
+        }
+        if addr < base + size {
+            mappings.push(Mapping::free(addr, base + size));
+        }
+
+        Self { mappings }
+    }
+
+    /// Map what a replay left allocated, tagging each allocation
+    /// with the id it has in the trace
+    pub fn from_events(events: &[Event], base: usize, size: usize) -> Self {
+        let mut live: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+        for event in events {
+            match (event.op, event.block) {
+                (Op::Malloc { id, .. } | Op::Calloc { id, .. }, Some(block)) => {
+                    live.insert(id, block);
+                }
+                (Op::Free { id }, Some(_)) => {
+                    live.remove(&id);
+                }
+                _ => {}
+            }
+        }
+
+        Self::new(
+            base,
+            size,
+            live.into_iter()
+                .map(|(id, (addr, len))| (addr, len, format!("[id {}]", id))),
+        )
+    }
+
+    /// Split the padding and rounding off the end of each allocation
+    /// into a mapping of its own, tagged `[slack]`, using what the
+    /// allocator says backs it. Nothing changes for allocators that
+    /// don't report their allocation sizes
+    pub fn slack(mut self, allocator: &dyn Allocator) -> Self {
+        let mut mappings = Vec::new();
+        let mut slack_end = 0;
+        for mut mapping in self.mappings.into_iter() {
+            if !mapping.used && mapping.start < slack_end {
+                let end = slack_end.min(mapping.end);
+                mappings.push(Mapping {
+                    start: mapping.start,
+                    end,
+                    used: true,
+                    tag: "[slack]".to_string(),
+                });
+                mapping.start = end;
+                if mapping.start == mapping.end {
+                    continue;
+                }
+            }
+            if mapping.used {
+                slack_end = allocator
+                    .alloc_size(mapping.start)
+                    .map_or(0, |size| mapping.start + size);
+            }
+            mappings.push(mapping);
+        }
+        self.mappings = mappings;
+        self
+    }
+
+    pub fn record<T: Allocator>(
+        allocator: &mut T,
+        trace: &Trace,
+        base: usize,
+        size: usize,
+    ) -> Self {
+        Self::from_events(&trace.replay(allocator), base, size)
+    }
+}
+
+impl Mapping {
+    fn free(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            used: false,
+            tag: "[free]".to_string(),
+        }
+    }
+}
+
+/// Allocated ranges are `rw-p` and free ones `---p`, like a guard
+/// mapping, followed by the size in bytes and the tag
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:08x}-{:08x} {} {:>8} {}",
+            self.start,
+            self.end,
+            if self.used { "rw-p" } else { "---p" },
+            self.end - self.start,
+            self.tag
+        )
+    }
+}
+
+impl fmt::Display for AddressMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for mapping in self.mappings.iter() {
+            writeln!(f, "{}", mapping)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+    use crate::freelist::FreeList;
+
+    #[test]
+    fn gaps_are_free() {
+        let map = AddressMap::new(
+            0,
+            100,
+            vec![(40, 10, "b".to_string()), (0, 20, "a".to_string())],
+        );
+        let ranges: Vec<(usize, usize, bool)> = map
+            .mappings
+            .iter()
+            .map(|m| (m.start, m.end, m.used))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (0, 20, true),
+                (20, 40, false),
+                (40, 50, true),
+                (50, 100, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn record() {
+        let trace: Trace = "malloc 0 16\nmalloc 1 32\nfree 0".parse().unwrap();
+        let map = AddressMap::record(&mut FreeList::new(0, 64, true), &trace, 0, 64);
+        assert_eq!(
+            map.to_string(),
+            "00000000-00000010 ---p       16 [free]\n\
+             00000010-00000030 rw-p       32 [id 1]\n\
+             00000030-00000040 ---p       16 [free]\n"
+        );
+    }
+
+    #[test]
+    fn slack() {
+        let trace: Trace = "malloc 0 20\nmalloc 1 40".parse().unwrap();
+        let mut buddy = BuddyAllocator::new(4, 7);
+        let map = AddressMap::record(&mut buddy, &trace, 0, 128);
+        assert_eq!(buddy.alloc_size(0), Some(32));
+        assert_eq!(buddy.alloc_size(20), None);
+        let map = map.slack(&buddy);
+        assert_eq!(
+            map.to_string(),
+            "00000000-00000014 rw-p       20 [id 0]\n\
+             00000014-00000020 rw-p       12 [slack]\n\
+             00000020-00000040 ---p       32 [free]\n\
+             00000040-00000068 rw-p       40 [id 1]\n\
+             00000068-00000080 rw-p       24 [slack]\n"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+
+/// Wraps an allocator with a side table holding user data of type
+/// `M` for each live allocation, so simulations don't each have to
+/// keep their own map from pointers to bookkeeping. Data is dropped
+/// when its allocation is freed
+#[derive(Debug, Clone)]
+pub struct Tagged<T, M> {
+    allocator: T,
+    table: BTreeMap<usize, Option<M>>,
+}
+
+impl<T: Allocator, M> Tagged<T, M> {
+    pub fn new(allocator: T) -> Self {
+        Self {
+            allocator,
+            table: BTreeMap::new(),
+        }
+    }
+
+    /// Allocate memory with data attached to it
+    pub fn malloc_with(&mut self, size: usize, meta: M) -> Option<usize> {
+        let ptr = self.allocator.malloc(size)?;
+        self.table.insert(ptr, Some(meta));
+        Some(ptr)
+    }
+
+    /// Attach data to a live allocation, returning what was
+    /// attached before. Errors if the pointer isn't allocated
+    pub fn attach(&mut self, ptr: usize, meta: M) -> Result<Option<M>, AllocError> {
+        let slot = self
+            .table
+            .get_mut(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        Ok(slot.replace(meta))
+    }
+
+    pub fn meta(&self, ptr: usize) -> Option<&M> {
+        self.table.get(&ptr)?.as_ref()
+    }
+
+    pub fn meta_mut(&mut self, ptr: usize) -> Option<&mut M> {
+        self.table.get_mut(&ptr)?.as_mut()
+    }
+
+    /// Free the allocation and hand back its data
+    pub fn free_take(&mut self, ptr: usize) -> Result<Option<M>, AllocError> {
+        self.allocator.free(ptr)?;
+        Ok(self.table.remove(&ptr).flatten())
+    }
+
+    /// Live allocations that have data attached, by address
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &M)> {
+        self.table
+            .iter()
+            .filter_map(|(&ptr, meta)| meta.as_ref().map(|meta| (ptr, meta)))
+    }
+
+    pub fn into_inner(self) -> T {
+        self.allocator
+    }
+}
+
+impl<T: Allocator, M> Allocator for Tagged<T, M> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let ptr = self.allocator.try_malloc(size)?;
+        self.table.insert(ptr, None);
+        Ok(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        self.allocator.free(ptr)?;
+        self.table.remove(&ptr);
+        Ok(())
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.allocator.alloc_size(ptr)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.allocator.reset()?;
+        self.table.clear();
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        self.allocator.churn()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        self.allocator.live_bytes()
+    }
+
+    fn render(&self) -> String {
+        self.allocator.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+
+    #[test]
+    fn attach_and_free() {
+        let mut heap: Tagged<_, &str> = Tagged::new(BuddyAllocator::new(0, 4));
+        let a = heap.malloc_with(4, "tenant a").unwrap();
+        let b = heap.malloc(4).unwrap();
+        assert_eq!(heap.meta(a), Some(&"tenant a"));
+        assert_eq!(heap.meta(b), None);
+
+        assert_eq!(heap.attach(b, "tenant b"), Ok(None));
+        assert_eq!(heap.attach(b, "tenant c"), Ok(Some("tenant b")));
+        assert!(heap.attach(15, "nobody").is_err());
+        assert_eq!(
+            heap.iter().collect::<Vec<_>>(),
+            [(a, &"tenant a"), (b, &"tenant c")]
+        );
+
+        assert_eq!(heap.free_take(b), Ok(Some("tenant c")));
+        assert!(heap.free(a).is_ok());
+        assert!(heap.free(a).is_err());
+        assert_eq!(heap.iter().count(), 0);
+    }
+
+    #[test]
+    fn meta_mut() {
+        let mut heap = Tagged::new(BuddyAllocator::new(0, 4));
+        let ptr = heap.malloc_with(2, 0).unwrap();
+        *heap.meta_mut(ptr).unwrap() += 1;
+        assert_eq!(heap.meta(ptr), Some(&1));
+    }
+}
@@ -0,0 +1,640 @@
+use crate::{AllocFailure, Allocator, Churn};
+use std::collections::{BTreeMap, VecDeque};
+
+/// What a collector reports at the end of a run
+#[derive(Debug, Clone, PartialEq)]
+pub enum Metric {
+    Value(f32),
+    Series(Vec<f32>),
+    /// Counts keyed by bucket
+    Histogram(BTreeMap<usize, usize>),
+}
+
+/// Watches every op of a workload and reports one metric when the
+/// run ends. Workloads tell every registered collector about each
+/// malloc and free after it happens, so a collector can look at the
+/// heap as the op left it
+pub trait MetricCollector {
+    /// Key the metric is stored under in `Results::metrics`
+    fn name(&self) -> &str;
+
+    /// Called once before the first op
+    fn start(&mut self, _heap: &dyn Allocator) {}
+
+    fn malloc(
+        &mut self,
+        _heap: &dyn Allocator,
+        _size: usize,
+        _result: Result<usize, AllocFailure>,
+    ) {
+    }
+
+    /// `freed` is false if the free failed
+    fn free(&mut self, _heap: &dyn Allocator, _ptr: usize, _freed: bool) {}
+
+    fn finish(&mut self, heap: &dyn Allocator) -> Metric;
+}
+
+/// The collectors registered for one run of a workload, and what
+/// the workload measured itself that no collector can see, like the
+/// compactions it ran or how an allocator it knows routed its frees.
+/// Both end up in `Results::metrics`, so a new bench adds metrics
+/// instead of fields
+#[derive(Default)]
+pub struct Metrics {
+    collectors: Vec<Box<dyn MetricCollector>>,
+    reported: BTreeMap<String, Metric>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every built in collector with its default settings
+    pub fn builtin() -> Self {
+        Self::new()
+            .register(FragSeries::every(100))
+            .register(SizeHistogram::new())
+            .register(LatencyModel::default())
+            .register(FailureForecast::new(50))
+    }
+
+    pub fn register(mut self, collector: impl MetricCollector + 'static) -> Self {
+        self.collectors.push(Box::new(collector));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.collectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.collectors.is_empty()
+    }
+
+    pub(crate) fn start(&mut self, heap: &dyn Allocator) {
+        for collector in self.collectors.iter_mut() {
+            collector.start(heap);
+        }
+    }
+
+    pub(crate) fn malloc(
+        &mut self,
+        heap: &dyn Allocator,
+        size: usize,
+        result: Result<usize, AllocFailure>,
+    ) {
+        for collector in self.collectors.iter_mut() {
+            collector.malloc(heap, size, result);
+        }
+    }
+
+    pub(crate) fn free(&mut self, heap: &dyn Allocator, ptr: usize, freed: bool) {
+        for collector in self.collectors.iter_mut() {
+            collector.free(heap, ptr, freed);
+        }
+    }
+
+    // Add `n` to a count the workload keeps under `name`
+    pub(crate) fn count(&mut self, name: &str, n: usize) {
+        match self
+            .reported
+            .entry(name.to_string())
+            .or_insert(Metric::Value(0.0))
+        {
+            Metric::Value(count) => *count += n as f32,
+            _ => panic!("{} is not a count", name),
+        }
+    }
+
+    // Report a value the workload worked out itself under `name`
+    pub(crate) fn report(&mut self, name: &str, value: f32) {
+        self.reported.insert(name.to_string(), Metric::Value(value));
+    }
+
+    // Add a sample to the series the workload keeps under `name`
+    pub(crate) fn sample(&mut self, name: &str, value: f32) {
+        match self
+            .reported
+            .entry(name.to_string())
+            .or_insert(Metric::Series(Vec::new()))
+        {
+            Metric::Series(series) => series.push(value),
+            _ => panic!("{} is not a series", name),
+        }
+    }
+
+    // Add `count` to a bucket of the histogram the workload keeps
+    // under `name`
+    pub(crate) fn tally(&mut self, name: &str, bucket: usize, count: usize) {
+        match self
+            .reported
+            .entry(name.to_string())
+            .or_insert(Metric::Histogram(BTreeMap::new()))
+        {
+            Metric::Histogram(counts) => *counts.entry(bucket).or_default() += count,
+            _ => panic!("{} is not a histogram", name),
+        }
+    }
+
+    pub(crate) fn finish(mut self, heap: &dyn Allocator) -> BTreeMap<String, Metric> {
+        let mut metrics = self.reported;
+        for collector in self.collectors.iter_mut() {
+            metrics.insert(collector.name().to_string(), collector.finish(heap));
+        }
+        metrics
+    }
+}
+
+/// External fragmentation sampled every `every` ops, and once more
+/// at the end
+#[derive(Debug, Clone)]
+pub struct FragSeries {
+    every: usize,
+    ops: usize,
+    series: Vec<f32>,
+}
+
+impl FragSeries {
+    pub fn every(ops: usize) -> Self {
+        if ops == 0 {
+            panic!("Need to sample at least every op");
+        }
+
+        Self {
+            every: ops,
+            ops: 0,
+            series: Vec::new(),
+        }
+    }
+
+    fn op(&mut self, heap: &dyn Allocator) {
+        self.ops += 1;
+        if self.ops.is_multiple_of(self.every) {
+            self.series.push(heap.external_frag());
+        }
+    }
+}
+
+impl MetricCollector for FragSeries {
+    fn name(&self) -> &str {
+        "frag_series"
+    }
+
+    fn malloc(&mut self, heap: &dyn Allocator, _: usize, _: Result<usize, AllocFailure>) {
+        self.op(heap);
+    }
+
+    fn free(&mut self, heap: &dyn Allocator, _: usize, _: bool) {
+        self.op(heap);
+    }
+
+    fn finish(&mut self, heap: &dyn Allocator) -> Metric {
+        let mut series = std::mem::take(&mut self.series);
+        series.push(heap.external_frag());
+        Metric::Series(series)
+    }
+}
+
+/// Requested sizes, bucketed by the power of two they round up to
+#[derive(Debug, Clone, Default)]
+pub struct SizeHistogram {
+    counts: BTreeMap<usize, usize>,
+}
+
+impl SizeHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MetricCollector for SizeHistogram {
+    fn name(&self) -> &str {
+        "size_histogram"
+    }
+
+    fn malloc(&mut self, _: &dyn Allocator, size: usize, _: Result<usize, AllocFailure>) {
+        *self.counts.entry(size.next_power_of_two()).or_default() += 1;
+    }
+
+    fn finish(&mut self, _: &dyn Allocator) -> Metric {
+        Metric::Histogram(std::mem::take(&mut self.counts))
+    }
+}
+
+/// A rough cost model for how long each op takes. Every op costs
+/// `base`, plus `split` and `merge` for each split and merge the
+/// allocator reports doing during it, and `search` for each free
+/// block it looked at. Reports the mean cost per op, in whatever
+/// unit the costs are given in, or with `worst_case` the cost of the
+/// slowest op
+#[derive(Debug, Clone)]
+pub struct LatencyModel {
+    base: f32,
+    split: f32,
+    merge: f32,
+    search: f32,
+    worst_case: bool,
+    last: Churn,
+    ops: usize,
+    total: f32,
+    worst: f32,
+}
+
+impl LatencyModel {
+    pub fn new(base: f32, split: f32, merge: f32) -> Self {
+        Self {
+            base,
+            split,
+            merge,
+            search: 0.0,
+            worst_case: false,
+            last: Churn::default(),
+            ops: 0,
+            total: 0.0,
+            worst: 0.0,
+        }
+    }
+
+    /// Report the slowest op instead of the mean, under
+    /// `worst_latency`, for when a deadline has to be met every time
+    pub fn worst_case(mut self) -> Self {
+        self.worst_case = true;
+        self
+    }
+
+    /// Also charge for each free block a search looked at, which
+    /// is free by default
+    pub fn search(mut self, search: f32) -> Self {
+        self.search = search;
+        self
+    }
+
+    /// What an op that split, merged and scanned this much costs
+    pub fn cost(&self, op: Churn) -> f32 {
+        self.base
+            + self.split * op.splits as f32
+            + self.merge * op.merges as f32
+            + self.search * op.scanned as f32
+    }
+
+    // What the last op cost
+    fn op(&mut self, heap: &dyn Allocator) -> f32 {
+        let churn = heap.churn();
+        let cost = self.cost(churn.since(self.last));
+        self.last = churn;
+        self.ops += 1;
+        self.total += cost;
+        self.worst = self.worst.max(cost);
+        cost
+    }
+}
+
+/// Costs in nanoseconds, guessed from a split or merge being a few
+/// pointer updates on top of the search
+impl Default for LatencyModel {
+    fn default() -> Self {
+        Self::new(20.0, 5.0, 5.0)
+    }
+}
+
+impl MetricCollector for LatencyModel {
+    fn name(&self) -> &str {
+        if self.worst_case {
+            "worst_latency"
+        } else {
+            "latency_model"
+        }
+    }
+
+    fn start(&mut self, heap: &dyn Allocator) {
+        self.last = heap.churn();
+    }
+
+    fn malloc(&mut self, heap: &dyn Allocator, _: usize, _: Result<usize, AllocFailure>) {
+        self.op(heap);
+    }
+
+    fn free(&mut self, heap: &dyn Allocator, _: usize, _: bool) {
+        self.op(heap);
+    }
+
+    fn finish(&mut self, _: &dyn Allocator) -> Metric {
+        if self.worst_case {
+            return Metric::Value(self.worst);
+        }
+        Metric::Value(self.total / self.ops.max(1) as f32)
+    }
+}
+
+/// Gives every malloc a deadline and times it with a `LatencyModel`,
+/// the way a real-time system would. A malloc that goes through but
+/// takes longer than its deadline is a latency failure, even though
+/// the space was there. Reports the fraction of mallocs that were
+pub struct Deadlines {
+    model: LatencyModel,
+    deadline: Box<dyn Fn(usize) -> f32>,
+    mallocs: usize,
+    missed: usize,
+}
+
+impl Deadlines {
+    /// The same deadline for every malloc
+    pub fn new(model: LatencyModel, deadline: f32) -> Self {
+        Self {
+            model,
+            deadline: Box::new(move |_| deadline),
+            mallocs: 0,
+            missed: 0,
+        }
+    }
+
+    /// A deadline for each malloc, given the size it asked for
+    pub fn by_size(mut self, deadline: impl Fn(usize) -> f32 + 'static) -> Self {
+        self.deadline = Box::new(deadline);
+        self
+    }
+}
+
+impl MetricCollector for Deadlines {
+    fn name(&self) -> &str {
+        "deadline_misses"
+    }
+
+    fn start(&mut self, heap: &dyn Allocator) {
+        self.model.start(heap);
+    }
+
+    fn malloc(&mut self, heap: &dyn Allocator, size: usize, result: Result<usize, AllocFailure>) {
+        let cost = self.model.op(heap);
+        self.mallocs += 1;
+        if result.is_ok() && cost > (self.deadline)(size) {
+            self.missed += 1;
+        }
+    }
+
+    // Frees have no deadline, but keep the model up to date
+    fn free(&mut self, heap: &dyn Allocator, _: usize, _: bool) {
+        self.model.op(heap);
+    }
+
+    fn finish(&mut self, _: &dyn Allocator) -> Metric {
+        Metric::Value(self.missed as f32 / self.mallocs.max(1) as f32)
+    }
+}
+
+/// Predicts, after every op, how many ops are left until the first
+/// malloc fails, by fitting a line to how the largest free block
+/// changed over the last `window` ops and seeing when it drops below
+/// the mean request size. At the end of the run, reports the fraction
+/// of predictions that were within `tolerance` of the truth. If no
+/// malloc failed, a prediction was right if it said the failure
+/// would come after the run ended, or never
+#[derive(Debug, Clone)]
+pub struct FailureForecast {
+    window: usize,
+    tolerance: f32,
+    ops: usize,
+    requested: (usize, usize),
+    largest: VecDeque<f32>,
+    predictions: Vec<(usize, Option<f32>)>,
+    failed_at: Option<usize>,
+}
+
+impl FailureForecast {
+    pub fn new(window: usize) -> Self {
+        if window < 2 {
+            panic!("Need a window of at least two ops to see a trend");
+        }
+
+        Self {
+            window,
+            tolerance: 0.25,
+            ops: 0,
+            requested: (0, 0),
+            largest: VecDeque::new(),
+            predictions: Vec::new(),
+            failed_at: None,
+        }
+    }
+
+    /// How far off a prediction can be, as a fraction of the ops
+    /// that were actually left
+    pub fn tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    // Least squares slope of the largest free block over the window
+    fn slope(&self) -> f32 {
+        let n = self.largest.len() as f32;
+        let mean_x = (n - 1.0) / 2.0;
+        let mean_y = self.largest.iter().sum::<f32>() / n;
+        let (mut num, mut den) = (0.0, 0.0);
+        for (x, y) in self.largest.iter().enumerate() {
+            let dx = x as f32 - mean_x;
+            num += dx * (y - mean_y);
+            den += dx * dx;
+        }
+        num / den
+    }
+
+    fn op(&mut self, heap: &dyn Allocator, failed: bool) {
+        if self.failed_at.is_some() {
+            return;
+        }
+        self.ops += 1;
+        if failed {
+            self.failed_at = Some(self.ops);
+            return;
+        }
+
+        let largest = heap.largest_alloc() as f32;
+        self.largest.push_back(largest);
+        if self.largest.len() > self.window {
+            self.largest.pop_front();
+        }
+        if self.largest.len() < 2 || self.requested.1 == 0 {
+            return;
+        }
+
+        let slope = self.slope();
+        let mean = self.requested.0 as f32 / self.requested.1 as f32;
+        // Shrinking by -slope an op, the largest block still fits a
+        // mean request for (largest - mean) / -slope more ops, and
+        // the op after the last of those fails
+        let prediction = if largest < mean {
+            Some(1.0)
+        } else if slope < 0.0 {
+            Some(((largest - mean) / -slope).floor() + 2.0)
+        } else {
+            None
+        };
+        self.predictions.push((self.ops, prediction));
+    }
+}
+
+impl MetricCollector for FailureForecast {
+    fn name(&self) -> &str {
+        "failure_forecast"
+    }
+
+    fn malloc(&mut self, heap: &dyn Allocator, size: usize, result: Result<usize, AllocFailure>) {
+        if self.failed_at.is_none() {
+            self.requested.0 += size;
+            self.requested.1 += 1;
+        }
+        self.op(heap, result.is_err());
+    }
+
+    fn free(&mut self, heap: &dyn Allocator, _: usize, _: bool) {
+        self.op(heap, false);
+    }
+
+    fn finish(&mut self, _: &dyn Allocator) -> Metric {
+        let right = self
+            .predictions
+            .iter()
+            .filter(|&&(op, prediction)| match (self.failed_at, prediction) {
+                (Some(failed), Some(predicted)) => {
+                    let left = (failed - op) as f32;
+                    (predicted - left).abs() <= self.tolerance * left
+                }
+                (Some(_), None) => false,
+                (None, Some(predicted)) => op as f32 + predicted > self.ops as f32,
+                (None, None) => true,
+            })
+            .count();
+        Metric::Value(right as f32 / self.predictions.len().max(1) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::Policy;
+
+    // Drive collectors by hand, the way a workload would
+    fn run(metrics: &mut Metrics, heap: &mut FreeList) {
+        metrics.start(heap);
+        let mut ptrs = Vec::new();
+        for size in [10, 20, 30] {
+            let result = heap.try_malloc(size);
+            metrics.malloc(heap, size, result);
+            ptrs.push(result.unwrap());
+        }
+        let freed = heap.free(ptrs[1]).is_ok();
+        metrics.free(heap, ptrs[1], freed);
+    }
+
+    #[test]
+    fn builtin() {
+        let mut heap = FreeList::new(0, 100, true);
+        let mut metrics = Metrics::new()
+            .register(FragSeries::every(2))
+            .register(SizeHistogram::new())
+            .register(LatencyModel::new(10.0, 1.0, 100.0))
+            .register(LatencyModel::new(10.0, 1.0, 100.0).worst_case());
+        run(&mut metrics, &mut heap);
+        let outputs = metrics.finish(&heap);
+
+        // Sampled after the second and fourth ops, and at the end.
+        // The free leaves free blocks of 20 and 40
+        let frag = 1.0 - 40.0 / 60.0;
+        assert_eq!(
+            outputs["frag_series"],
+            Metric::Series(vec![0.0, frag, frag])
+        );
+        let sizes: BTreeMap<usize, usize> = vec![(16, 1), (32, 2)].into_iter().collect();
+        assert_eq!(outputs["size_histogram"], Metric::Histogram(sizes));
+        // Three splits and no merges over four ops
+        assert_eq!(outputs["latency_model"], Metric::Value(43.0 / 4.0));
+        assert_eq!(outputs["worst_latency"], Metric::Value(11.0));
+    }
+
+    struct Frees(usize);
+
+    impl MetricCollector for Frees {
+        fn name(&self) -> &str {
+            "frees"
+        }
+
+        fn free(&mut self, _: &dyn Allocator, _: usize, freed: bool) {
+            if freed {
+                self.0 += 1;
+            }
+        }
+
+        fn finish(&mut self, _: &dyn Allocator) -> Metric {
+            Metric::Value(self.0 as f32)
+        }
+    }
+
+    #[test]
+    fn failure_forecast() {
+        // Every malloc takes the same 100 bytes off the largest
+        // block, so the trend is exact and the malloc on op 11 fails
+        let mut heap = FreeList::new(0, 1000, true);
+        let mut metrics = Metrics::new().register(FailureForecast::new(4));
+        for _ in 0..11 {
+            let result = heap.try_malloc(100);
+            metrics.malloc(&heap, 100, result);
+        }
+        assert_eq!(
+            metrics.finish(&heap)["failure_forecast"],
+            Metric::Value(1.0)
+        );
+
+        // A heap that never runs out should not be predicted to
+        let mut heap = FreeList::new(0, 1000, true);
+        let mut metrics = Metrics::new().register(FailureForecast::new(4));
+        for _ in 0..10 {
+            let result = heap.try_malloc(100);
+            metrics.malloc(&heap, 100, result);
+            let freed = heap.free(result.unwrap()).is_ok();
+            metrics.free(&heap, 0, freed);
+        }
+        assert_eq!(
+            metrics.finish(&heap)["failure_forecast"],
+            Metric::Value(1.0)
+        );
+    }
+
+    #[test]
+    fn deadlines() {
+        // Finding a block costs 15 if the first free block fits, and
+        // 20 if the search has to look at the second too
+        let model = LatencyModel::new(10.0, 0.0, 0.0).search(5.0);
+        let mut metrics = Metrics::new()
+            .register(Deadlines::new(model.clone(), 18.0))
+            .register(LatencyModel::new(10.0, 0.0, 0.0).search(5.0).worst_case());
+        let mut heap = FreeList::new(0, 100, true).policy(Policy::First);
+        run(&mut metrics, &mut heap);
+        for size in [30, 1000] {
+            let result = heap.try_malloc(size);
+            metrics.malloc(&heap, size, result);
+        }
+        let outputs = metrics.finish(&heap);
+        // Only the malloc of 30 was late. The one that failed doesn't
+        // count as late, but as a malloc
+        assert_eq!(outputs["deadline_misses"], Metric::Value(1.0 / 5.0));
+        assert_eq!(outputs["worst_latency"], Metric::Value(20.0));
+
+        let mut deadlines = Deadlines::new(model, 18.0).by_size(|size| size as f32);
+        let mut heap = FreeList::new(0, 100, true);
+        deadlines.start(&heap);
+        let result = heap.try_malloc(10);
+        deadlines.malloc(&heap, 10, result);
+        assert_eq!(deadlines.finish(&heap), Metric::Value(1.0));
+    }
+
+    #[test]
+    fn custom() {
+        let mut heap = FreeList::new(0, 100, true);
+        let mut metrics = Metrics::new().register(Frees(0));
+        run(&mut metrics, &mut heap);
+        assert_eq!(metrics.finish(&heap)["frees"], Metric::Value(1.0));
+    }
+}
@@ -0,0 +1,394 @@
+use crate::bins::size_classes;
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone)]
+enum Page {
+    Free,
+    // Blocks of one size class. Mallocs pop `free`, and frees push
+    // on `local` until `free` runs out and takes it over
+    Small {
+        class: usize,
+        free: Vec<usize>,
+        local: Vec<usize>,
+        used: usize,
+        capacity: usize,
+    },
+    // Part of the span of pages a large allocation took
+    Large,
+}
+
+/// How full one page in use is
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PageStats {
+    pub addr: usize,
+    /// Block size, or the whole span for a large allocation
+    pub block: usize,
+    pub used: usize,
+    pub capacity: usize,
+}
+
+impl PageStats {
+    pub fn occupancy(&self) -> f32 {
+        self.used as f32 / self.capacity.max(1) as f32
+    }
+}
+
+/// A model of mimalloc's layout. The heap is cut into segments, and
+/// segments into pages. A page holds blocks of a single size class,
+/// taken from its own free list, with frees going on a separate
+/// local list that replaces the free list once it runs dry. A
+/// class's pages are tried oldest first, and a new page comes from
+/// the lowest free page in any segment. Pages go back to their
+/// segment once all their blocks are free. Classes go up to an
+/// eighth of a page, and bigger requests take a span of whole pages
+/// in one segment. Setting up a page counts as a split and giving
+/// one back as a merge
+#[derive(Debug, Clone)]
+pub struct Mimalloc {
+    base: usize,
+    page: usize,
+    per_segment: usize,
+    classes: Vec<usize>,
+    pages: Vec<Page>,
+    // Pages of each class, oldest first
+    queues: Vec<Vec<usize>>,
+    // Requested and rounded size of each live allocation
+    live: BTreeMap<usize, (usize, usize)>,
+    /// Times a page's local list became its free list
+    pub collects: usize,
+    churn: Churn,
+}
+
+impl Mimalloc {
+    /// Manage [base, base + size) in segments of `segment` bytes and
+    /// pages of `page` bytes. Whatever doesn't make up a whole
+    /// segment is left out
+    pub fn new(base: usize, size: usize, segment: usize, page: usize) -> Self {
+        if page < 16 || !segment.is_multiple_of(page) || segment > size {
+            panic!("Need at least one segment of whole pages of 16 bytes or more");
+        }
+        let classes = size_classes(page / 8);
+        let pages = size / segment * (segment / page);
+
+        Self {
+            base,
+            page,
+            per_segment: segment / page,
+            queues: vec![Vec::new(); classes.len()],
+            classes,
+            pages: vec![Page::Free; pages],
+            live: BTreeMap::new(),
+            collects: 0,
+            churn: Churn::default(),
+        }
+    }
+
+    pub fn classes(&self) -> &[usize] {
+        &self.classes
+    }
+
+    /// Every page in use, in address order. A large allocation shows
+    /// up as its first page
+    pub fn page_stats(&self) -> Vec<PageStats> {
+        let mut stats = Vec::new();
+        for (i, page) in self.pages.iter().enumerate() {
+            let addr = self.base + i * self.page;
+            match page {
+                Page::Free => {}
+                Page::Small {
+                    class,
+                    used,
+                    capacity,
+                    ..
+                } => stats.push(PageStats {
+                    addr,
+                    block: self.classes[*class],
+                    used: *used,
+                    capacity: *capacity,
+                }),
+                Page::Large => {
+                    if let Some(&(_, span)) = self.live.get(&addr) {
+                        stats.push(PageStats {
+                            addr,
+                            block: span,
+                            used: 1,
+                            capacity: 1,
+                        });
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    /// Pages in use by how full they are, in steps of 10%
+    pub fn occupancy(&self) -> BTreeMap<usize, usize> {
+        let mut counts = BTreeMap::new();
+        for stats in self.page_stats() {
+            *counts
+                .entry(stats.used * 10 / stats.capacity * 10)
+                .or_default() += 1;
+        }
+        counts
+    }
+
+    /// Segments with at least one page in use
+    pub fn segments_used(&self) -> usize {
+        self.pages
+            .chunks(self.per_segment)
+            .filter(|segment| segment.iter().any(|page| !matches!(page, Page::Free)))
+            .count()
+    }
+
+    // First page of the lowest run of `span` free pages that stays
+    // in one segment
+    fn find_free(&self, span: usize) -> Option<usize> {
+        let mut run = 0;
+        for (i, page) in self.pages.iter().enumerate() {
+            if i % self.per_segment == 0 {
+                run = 0;
+            }
+            run = if matches!(page, Page::Free) {
+                run + 1
+            } else {
+                0
+            };
+            if run == span {
+                return Some(i + 1 - span);
+            }
+        }
+        None
+    }
+
+    fn malloc_small(&mut self, class: usize) -> Option<usize> {
+        let found = self.queues[class]
+            .iter()
+            .copied()
+            .find(|&i| match &self.pages[i] {
+                Page::Small { free, local, .. } => !free.is_empty() || !local.is_empty(),
+                _ => false,
+            });
+        let i = match found {
+            Some(i) => i,
+            None => {
+                let i = self.find_free(1)?;
+                let size = self.classes[class];
+                let addr = self.base + i * self.page;
+                let capacity = self.page / size;
+                self.pages[i] = Page::Small {
+                    class,
+                    free: (0..capacity).rev().map(|j| addr + j * size).collect(),
+                    local: Vec::new(),
+                    used: 0,
+                    capacity,
+                };
+                self.queues[class].push(i);
+                self.churn.splits += 1;
+                i
+            }
+        };
+
+        let Page::Small {
+            free, local, used, ..
+        } = &mut self.pages[i]
+        else {
+            unreachable!()
+        };
+        if free.is_empty() {
+            std::mem::swap(free, local);
+            self.collects += 1;
+        }
+        *used += 1;
+        free.pop()
+    }
+}
+
+impl Allocator for Mimalloc {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.churn.mallocs += 1;
+        if size == 0 {
+            return Err(AllocFailure::NoFit);
+        }
+
+        let (ptr, rounded) = match self.classes.iter().position(|&class| class >= size) {
+            Some(class) => (self.malloc_small(class), self.classes[class]),
+            None => {
+                let span = size.div_ceil(self.page);
+                if span > self.per_segment {
+                    return Err(AllocFailure::Oversized);
+                }
+                let ptr = self.find_free(span).map(|i| {
+                    self.pages[i..i + span].fill(Page::Large);
+                    self.base + i * self.page
+                });
+                (ptr, span * self.page)
+            }
+        };
+
+        match ptr {
+            Some(ptr) => {
+                self.live.insert(ptr, (size, rounded));
+                Ok(ptr)
+            }
+            None if self.free_space() < size => Err(AllocFailure::Exhausted),
+            None => Err(AllocFailure::NoFit),
+        }
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let (_, rounded) = self
+            .live
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        let i = (ptr - self.base) / self.page;
+        match &mut self.pages[i] {
+            Page::Small {
+                class, local, used, ..
+            } => {
+                local.push(ptr);
+                *used -= 1;
+                if *used == 0 {
+                    let class = *class;
+                    self.queues[class].retain(|&page| page != i);
+                    self.pages[i] = Page::Free;
+                    self.churn.merges += 1;
+                }
+            }
+            Page::Large => self.pages[i..i + rounded / self.page].fill(Page::Free),
+            Page::Free => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// The longest run of free pages in one segment, or the largest
+    /// class with a free block if that is bigger
+    fn largest_alloc(&self) -> usize {
+        let small = self
+            .pages
+            .iter()
+            .filter_map(|page| match page {
+                Page::Small {
+                    class,
+                    used,
+                    capacity,
+                    ..
+                } if used < capacity => Some(self.classes[*class]),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let span = (1..=self.per_segment)
+            .rev()
+            .find(|&span| self.find_free(span).is_some())
+            .unwrap_or(0);
+        small.max(span * self.page)
+    }
+
+    /// Free pages, and the free blocks of pages in use. What is left
+    /// at the end of a page after its last block doesn't count
+    fn free_space(&self) -> usize {
+        self.pages
+            .iter()
+            .map(|page| match page {
+                Page::Free => self.page,
+                Page::Small {
+                    class,
+                    used,
+                    capacity,
+                    ..
+                } => (capacity - used) * self.classes[*class],
+                Page::Large => 0,
+            })
+            .sum()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.live
+            .values()
+            .map(|(requested, rounded)| rounded - requested)
+            .sum()
+    }
+
+    fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().map(|&(requested, _)| requested).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for stats in self.page_stats() {
+            writeln!(
+                out,
+                "page {}: {} byte blocks, {} of {} used",
+                stats.addr, stats.block, stats.used, stats.capacity
+            )
+            .unwrap();
+        }
+        writeln!(out, "{} segments in use", self.segments_used()).unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        let mut heap = Mimalloc::new(0, 4096, 1024, 256);
+        assert_eq!(heap.classes(), &[16, 32]);
+        let a = heap.malloc(20).unwrap();
+        let b = heap.malloc(32).unwrap();
+        let c = heap.malloc(10).unwrap();
+        assert_eq!((a, b, c), (0, 32, 256));
+        assert_eq!(heap.internal_frag(), 12 + 6);
+        assert_eq!(heap.free_space(), 4096 - 512 + (256 - 64) + (256 - 16));
+
+        // Frees go on the local list, used once the free list is dry
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        for _ in 0..6 {
+            heap.malloc(32).unwrap();
+        }
+        assert_eq!(heap.collects, 0);
+        assert_eq!(heap.malloc(32), Some(a));
+        assert_eq!(heap.collects, 1);
+        assert_eq!(heap.page_stats()[0].occupancy(), 1.0);
+        // The page is full, so the next block needs a new one
+        assert_eq!(heap.malloc(32), Some(512));
+        assert_eq!(
+            heap.occupancy().into_iter().collect::<Vec<_>>(),
+            vec![(0, 1), (10, 1), (100, 1)]
+        );
+        assert_eq!(heap.churn().splits, 3);
+    }
+
+    #[test]
+    fn large() {
+        let mut heap = Mimalloc::new(0, 4096, 1024, 256);
+        heap.malloc(16).unwrap();
+        // Four pages only fit in a segment of their own
+        assert_eq!(heap.malloc(800), Some(1024));
+        assert_eq!(heap.malloc(600), Some(256));
+        assert_eq!(heap.internal_frag(), 1024 - 800 + 768 - 600);
+        assert_eq!(heap.segments_used(), 2);
+        assert_eq!(heap.try_malloc(1025), Err(AllocFailure::Oversized));
+        assert_eq!(heap.largest_alloc(), 1024);
+
+        heap.free(1024).unwrap();
+        assert_eq!(heap.segments_used(), 1);
+        assert_eq!(heap.largest_alloc(), 1024);
+        heap.free(0).unwrap();
+        assert_eq!(heap.churn().merges, 1);
+        assert_eq!(heap.live_bytes(), Some(600));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::trace::{Op, Trace};
+use std::collections::BTreeMap;
+
+/// Shrink a trace that makes `fails` return true to one that still
+/// does, using Zeller's delta debugging. The trace is cut into
+/// chunks, and any chunk that can be dropped with `fails` still true
+/// is dropped. Chunks get smaller until they are single ops, so in
+/// the result removing any one op makes the failure go away. Ids are
+/// then renumbered from 0 in the order they are malloced. None if
+/// the trace doesn't fail to begin with
+pub fn minimize(trace: &Trace, mut fails: impl FnMut(&Trace) -> bool) -> Option<Trace> {
+    if !fails(trace) {
+        return None;
+    }
+
+    let mut ops = trace.ops.clone();
+    let mut chunks = 2;
+    while ops.len() >= 2 {
+        let size = ops.len().div_ceil(chunks);
+        let smaller = (0..ops.len()).step_by(size).find_map(|start| {
+            let mut candidate = Trace {
+                ops: ops[..start].to_vec(),
+            };
+            candidate
+                .ops
+                .extend_from_slice(&ops[(start + size).min(ops.len())..]);
+            if fails(&candidate) {
+                Some(candidate.ops)
+            } else {
+                None
+            }
+        });
+
+        match smaller {
+            Some(smaller) => {
+                ops = smaller;
+                chunks = (chunks - 1).max(2);
+            }
+            None if chunks >= ops.len() => break,
+            None => chunks = (chunks * 2).min(ops.len()),
+        }
+    }
+
+    let minimized = Trace { ops };
+    let renumbered = renumber(&minimized);
+    if fails(&renumbered) {
+        Some(renumbered)
+    } else {
+        Some(minimized)
+    }
+}
+
+// Give allocations ids 0, 1, 2, ... in the order they are made. A
+// free of an id that was never malloced gets an id past all of them
+fn renumber(trace: &Trace) -> Trace {
+    let mut next = 0..;
+    let mut orphans = trace.ops.iter().filter(|op| op.bytes().is_some()).count()..;
+    let mut ids: BTreeMap<usize, usize> = BTreeMap::new();
+
+    let ops = trace
+        .ops
+        .iter()
+        .map(|&op| match op {
+            Op::Malloc { id, size } => {
+                let new = next.next().unwrap();
+                ids.insert(id, new);
+                Op::Malloc { id: new, size }
+            }
+            Op::Calloc { id, count, size } => {
+                let new = next.next().unwrap();
+                ids.insert(id, new);
+                Op::Calloc {
+                    id: new,
+                    count,
+                    size,
+                }
+            }
+            Op::Free { id } => Op::Free {
+                id: *ids.entry(id).or_insert_with(|| orphans.next().unwrap()),
+            },
+        })
+        .collect();
+
+    Trace { ops }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn shrinks_to_the_cause() {
+        // Fails when a 40 is freed while a 7 is live
+        let fails = |trace: &Trace| {
+            let events = trace.replay(&mut FreeList::new(0, 1024, true));
+            let mut live = BTreeMap::new();
+            events.iter().any(|event| match (event.op, event.block) {
+                (Op::Malloc { id, size }, Some(_)) => {
+                    live.insert(id, size);
+                    false
+                }
+                (Op::Free { id }, Some(_)) => {
+                    live.remove(&id) == Some(40) && live.values().any(|&size| size == 7)
+                }
+                _ => false,
+            })
+        };
+
+        let trace: Trace = "malloc 10 8\nmalloc 11 7\nmalloc 12 16\nfree 10\n\
+                            malloc 13 40\nfree 12\nmalloc 14 3\nfree 13\nfree 11"
+            .parse()
+            .unwrap();
+        let minimized = minimize(&trace, fails).unwrap();
+        assert_eq!(minimized.to_string(), "malloc 0 7\nmalloc 1 40\nfree 1\n");
+
+        assert!(minimize(&minimized, |_| false).is_none());
+    }
+
+    #[test]
+    fn one_minimal() {
+        // A random trace that runs a small heap out of space
+        let fails = |trace: &Trace| {
+            let mut list = FreeList::new(0, 2048, true);
+            trace
+                .replay(&mut list)
+                .iter()
+                .any(|event| matches!(event.op, Op::Malloc { .. }) && event.block.is_none())
+        };
+        let trace = crate::trace::random_memory(0.7, &mut StdRng::seed_from_u64(1));
+        let minimized = minimize(&trace, fails).unwrap();
+        assert!(minimized.len() < trace.len());
+
+        for i in 0..minimized.len() {
+            let mut ops = minimized.ops.clone();
+            ops.remove(i);
+            assert!(!fails(&Trace { ops }));
+        }
+    }
+}
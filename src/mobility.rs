@@ -0,0 +1,337 @@
+use crate::freelist::FreeList;
+use crate::{AllocError, AllocFailure, Allocator, Churn, Policy};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// How easily the pages of an allocation could be moved elsewhere,
+/// like the kernel's migrate types
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mobility {
+    /// Pinned where it is, like kernel data structures
+    Unmovable,
+    /// Can't be moved, but can be dropped, like slab caches
+    Reclaimable,
+    /// Can be moved, like the page cache and anonymous pages
+    Movable,
+}
+
+impl Mobility {
+    pub const ALL: [Mobility; 3] = [
+        Mobility::Unmovable,
+        Mobility::Reclaimable,
+        Mobility::Movable,
+    ];
+
+    // The classes to take pageblocks from when none of this one has
+    // room, in the order the kernel tries them
+    fn fallbacks(self) -> [Mobility; 2] {
+        match self {
+            Mobility::Unmovable => [Mobility::Reclaimable, Mobility::Movable],
+            Mobility::Reclaimable => [Mobility::Unmovable, Mobility::Movable],
+            Mobility::Movable => [Mobility::Reclaimable, Mobility::Unmovable],
+        }
+    }
+
+    fn letter(self) -> char {
+        match self {
+            Mobility::Unmovable => 'U',
+            Mobility::Reclaimable => 'R',
+            Mobility::Movable => 'M',
+        }
+    }
+}
+
+/// A heap cut into pageblocks of `block_size` bytes, with one first
+/// fit free list over all of it, that groups allocations by
+/// mobility like the kernel's page allocator. Mallocs are of the
+/// current class, and go in a pageblock claimed for that class if
+/// one has room, or else claim a pageblock nobody has used yet.
+/// Failing that they fall back on the emptiest pageblock of another
+/// class that fits, and claim it too if at least half of it was
+/// free. Without grouping, mallocs go wherever first fit puts them,
+/// so unmovable allocations end up spread over the whole heap
+#[derive(Debug, Clone)]
+pub struct Grouped {
+    list: FreeList,
+    base: usize,
+    size: usize,
+    block_size: usize,
+    grouping: bool,
+    mobility: Mobility,
+    // The class each pageblock was claimed for, if any yet
+    owners: Vec<Option<Mobility>>,
+    // Live bytes in each pageblock, and live allocations of each
+    // class touching it
+    used: Vec<usize>,
+    counts: Vec<[usize; 3]>,
+    // Class and size of each live allocation
+    live: BTreeMap<usize, (Mobility, usize)>,
+    mallocs: usize,
+    /// Mallocs that went in a pageblock of another class, and the
+    /// pageblocks those claimed for their own class
+    pub fallbacks: usize,
+    pub steals: usize,
+}
+
+impl Grouped {
+    /// Grouping over [base, base + size), with the last pageblock
+    /// getting whatever doesn't divide evenly
+    pub fn new(base: usize, size: usize, block_size: usize) -> Self {
+        if block_size == 0 || size < block_size {
+            panic!("Need at least one pageblock of at least one byte");
+        }
+
+        let blocks = size.div_ceil(block_size);
+        Self {
+            list: FreeList::new(base, size, true).policy(Policy::First),
+            base,
+            size,
+            block_size,
+            grouping: true,
+            mobility: Mobility::Movable,
+            owners: vec![None; blocks],
+            used: vec![0; blocks],
+            counts: vec![[0; 3]; blocks],
+            live: BTreeMap::new(),
+            mallocs: 0,
+            fallbacks: 0,
+            steals: 0,
+        }
+    }
+
+    /// Group by mobility, or place every malloc by first fit alone
+    pub fn grouping(mut self, grouping: bool) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    /// Make mallocs of this class from now on
+    pub fn switch_mobility(&mut self, mobility: Mobility) {
+        self.mobility = mobility;
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.owners.len()
+    }
+
+    /// Addresses [lo, hi) of a pageblock
+    pub fn window(&self, block: usize) -> (usize, usize) {
+        let lo = self.base + block * self.block_size;
+        (lo, (lo + self.block_size).min(self.base + self.size))
+    }
+
+    /// The class a pageblock was claimed for, if any
+    pub fn owner(&self, block: usize) -> Option<Mobility> {
+        self.owners[block]
+    }
+
+    /// Pageblocks with live allocations of more than one class
+    pub fn mixed_blocks(&self) -> usize {
+        self.counts
+            .iter()
+            .filter(|counts| counts.iter().filter(|&&n| n > 0).count() > 1)
+            .count()
+    }
+
+    /// Pageblocks with a live unmovable allocation, which compaction
+    /// can never empty
+    pub fn unmovable_blocks(&self) -> usize {
+        self.counts
+            .iter()
+            .filter(|counts| counts[Mobility::Unmovable as usize] > 0)
+            .count()
+    }
+
+    // Count an allocation into or out of every pageblock it touches
+    fn account(&mut self, ptr: usize, size: usize, mobility: Mobility, add: bool) {
+        let first = (ptr - self.base) / self.block_size;
+        let last = (ptr + size.max(1) - 1 - self.base) / self.block_size;
+        for block in first..=last {
+            let (lo, hi) = self.window(block);
+            let bytes = (ptr + size).min(hi) - ptr.max(lo);
+            if add {
+                self.used[block] += bytes;
+                self.counts[block][mobility as usize] += 1;
+            } else {
+                self.used[block] -= bytes;
+                self.counts[block][mobility as usize] -= 1;
+            }
+        }
+    }
+
+    // Malloc in a pageblock, grouping by mobility
+    fn malloc_grouped(&mut self, size: usize) -> Option<usize> {
+        let mobility = self.mobility;
+        let blocks = 0..self.blocks();
+        let own = blocks
+            .clone()
+            .filter(|&block| self.owners[block] == Some(mobility));
+        let unclaimed = blocks.clone().filter(|&block| self.owners[block].is_none());
+        for block in own.chain(unclaimed).collect::<Vec<_>>() {
+            let (lo, hi) = self.window(block);
+            if let Some(ptr) = self.list.malloc_in_range(size, lo, hi) {
+                self.owners[block] = Some(mobility);
+                return Some(ptr);
+            }
+        }
+
+        for fallback in mobility.fallbacks().iter() {
+            let mut blocks: Vec<usize> = blocks
+                .clone()
+                .filter(|&block| self.owners[block] == Some(*fallback))
+                .collect();
+            blocks.sort_by_key(|&block| self.used[block]);
+            for block in blocks {
+                let (lo, hi) = self.window(block);
+                if let Some(ptr) = self.list.malloc_in_range(size, lo, hi) {
+                    self.fallbacks += 1;
+                    if 2 * self.used[block] <= hi - lo {
+                        self.owners[block] = Some(mobility);
+                        self.steals += 1;
+                    }
+                    return Some(ptr);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Allocator for Grouped {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.mallocs += 1;
+        // Nothing can span two pageblocks while grouping
+        if self.grouping && size > self.block_size {
+            return Err(AllocFailure::Oversized);
+        }
+
+        let ptr = if self.grouping {
+            self.malloc_grouped(size)
+        } else {
+            self.list.malloc(size)
+        };
+        match ptr {
+            Some(ptr) => {
+                self.live.insert(ptr, (self.mobility, size));
+                self.account(ptr, size, self.mobility, true);
+                Ok(ptr)
+            }
+            None if size > self.size => Err(AllocFailure::Oversized),
+            None if self.list.free_space() < size => Err(AllocFailure::Exhausted),
+            None => Err(AllocFailure::NoFit),
+        }
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let &(mobility, size) = self.live.get(&ptr).ok_or(AllocError::InvalidPointer(ptr))?;
+        self.list.free(ptr)?;
+        self.live.remove(&ptr);
+        self.account(ptr, size, mobility, false);
+        Ok(())
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.list.alloc_size(ptr)
+    }
+
+    fn largest_alloc(&self) -> usize {
+        if self.grouping {
+            self.list.largest_alloc().min(self.block_size)
+        } else {
+            self.list.largest_alloc()
+        }
+    }
+
+    fn free_space(&self) -> usize {
+        self.list.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.list.internal_frag()
+    }
+
+    /// Trying more than one pageblock makes more than one search, but
+    /// only counts as one malloc
+    fn churn(&self) -> Churn {
+        let mut churn = self.list.churn();
+        churn.mallocs = self.mallocs;
+        churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.used.iter().sum())
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.list.coalesce()
+    }
+
+    /// A letter for the class each pageblock was claimed for, or `.`
+    /// if none, lower case if it holds more than one class
+    fn render(&self) -> String {
+        let blocks: String = (0..self.blocks())
+            .map(|block| match self.owners[block] {
+                Some(owner) if self.counts[block].iter().filter(|&&n| n > 0).count() > 1 => {
+                    owner.letter().to_ascii_lowercase()
+                }
+                Some(owner) => owner.letter(),
+                None => '.',
+            })
+            .collect();
+        let mut out = String::new();
+        writeln!(
+            out,
+            "Pageblocks: {}, {} mixed, {} unmovable",
+            blocks,
+            self.mixed_blocks(),
+            self.unmovable_blocks()
+        )
+        .unwrap();
+        out.push_str(&self.list.render());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grouping() {
+        let mut heap = Grouped::new(0, 400, 100);
+        let movable: Vec<usize> = (0..3).map(|_| heap.malloc(40).unwrap()).collect();
+        assert_eq!(movable, vec![0, 40, 100]);
+        heap.switch_mobility(Mobility::Unmovable);
+        assert_eq!(heap.malloc(60), Some(200));
+        assert_eq!(heap.malloc(80), Some(300));
+        assert_eq!(heap.owner(3), Some(Mobility::Unmovable));
+        assert_eq!((heap.mixed_blocks(), heap.unmovable_blocks()), (0, 2));
+
+        // No unmovable pageblock has room left, so it falls back on
+        // the emptiest movable one, and claims it
+        assert_eq!(heap.malloc(50), Some(140));
+        assert_eq!((heap.fallbacks, heap.steals), (1, 1));
+        assert_eq!(heap.owner(1), Some(Mobility::Unmovable));
+        assert_eq!((heap.mixed_blocks(), heap.unmovable_blocks()), (1, 3));
+        assert_eq!(heap.try_malloc(101), Err(AllocFailure::Oversized));
+        assert!(heap.render().starts_with("Pageblocks: MuUU, 1 mixed"));
+
+        heap.free(movable[2]).unwrap();
+        assert!(heap.free(movable[2]).is_err());
+        assert_eq!(heap.mixed_blocks(), 0);
+        assert_eq!(heap.live_bytes(), Some(270));
+
+        // First fit alone puts an unmovable allocation in the middle
+        // of the movable ones
+        let mut heap = Grouped::new(0, 400, 100).grouping(false);
+        let ptrs: Vec<usize> = (0..4).map(|_| heap.malloc(40).unwrap()).collect();
+        heap.free(ptrs[2]).unwrap();
+        heap.switch_mobility(Mobility::Unmovable);
+        assert_eq!(heap.malloc(40), Some(80));
+        assert_eq!((heap.mixed_blocks(), heap.unmovable_blocks()), (2, 2));
+    }
+}
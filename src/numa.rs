@@ -0,0 +1,205 @@
+use crate::freelist::FreeList;
+use crate::{AllocError, AllocFailure, Allocator, Churn, Policy};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// A heap split evenly between `nodes` NUMA nodes, with one free
+/// list over all of it. Mallocs ask for memory on the current node,
+/// and get it from that node's part of the heap if anything there
+/// fits. Otherwise they spill over to the next node up, wrapping
+/// around, and count as remote
+#[derive(Debug, Clone)]
+pub struct Numa {
+    list: FreeList,
+    base: usize,
+    size: usize,
+    node_size: usize,
+    nodes: usize,
+    node: usize,
+    // Node and size of each live allocation
+    live: BTreeMap<usize, (usize, usize)>,
+    used: Vec<usize>,
+    mallocs: usize,
+    /// Mallocs served by the node they asked for, or by another
+    pub local: usize,
+    pub remote: usize,
+}
+
+impl Numa {
+    /// First fit over [base, base + size). Each node gets an equal
+    /// share, and the last one whatever doesn't divide evenly
+    pub fn new(base: usize, size: usize, nodes: usize) -> Self {
+        if nodes == 0 || size / nodes == 0 {
+            panic!("Need at least one byte on each of at least one node");
+        }
+
+        Self {
+            list: FreeList::new(base, size, true).policy(Policy::First),
+            base,
+            size,
+            node_size: size / nodes,
+            nodes,
+            node: 0,
+            live: BTreeMap::new(),
+            used: vec![0; nodes],
+            mallocs: 0,
+            local: 0,
+            remote: 0,
+        }
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// Ask for memory on this node from now on
+    pub fn switch_node(&mut self, node: usize) {
+        if node >= self.nodes {
+            panic!("No node {}", node);
+        }
+        self.node = node;
+    }
+
+    /// The node an address belongs to
+    pub fn node_of(&self, addr: usize) -> usize {
+        ((addr - self.base) / self.node_size).min(self.nodes - 1)
+    }
+
+    /// Bytes live on a node
+    pub fn used(&self, node: usize) -> usize {
+        self.used[node]
+    }
+
+    /// Gap between the fullest and the emptiest node, as a fraction
+    /// of a node
+    pub fn imbalance(&self) -> f32 {
+        let max = self.used.iter().max().unwrap();
+        let min = self.used.iter().min().unwrap();
+        (max - min) as f32 / self.node_size as f32
+    }
+
+    /// Addresses [lo, hi) on a node
+    pub fn window(&self, node: usize) -> (usize, usize) {
+        let lo = self.base + node * self.node_size;
+        if node == self.nodes - 1 {
+            (lo, self.base + self.size)
+        } else {
+            (lo, lo + self.node_size)
+        }
+    }
+}
+
+impl Allocator for Numa {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.mallocs += 1;
+        // Nothing can span two nodes
+        if size > self.node_size {
+            return Err(AllocFailure::Oversized);
+        }
+
+        for i in 0..self.nodes {
+            let node = (self.node + i) % self.nodes;
+            let (lo, hi) = self.window(node);
+            if let Some(ptr) = self.list.malloc_in_range(size, lo, hi) {
+                if i == 0 {
+                    self.local += 1;
+                } else {
+                    self.remote += 1;
+                }
+                self.live.insert(ptr, (node, size));
+                self.used[node] += size;
+                return Ok(ptr);
+            }
+        }
+
+        if self.list.free_space() < size {
+            Err(AllocFailure::Exhausted)
+        } else {
+            Err(AllocFailure::NoFit)
+        }
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let &(node, size) = self.live.get(&ptr).ok_or(AllocError::InvalidPointer(ptr))?;
+        self.list.free(ptr)?;
+        self.live.remove(&ptr);
+        self.used[node] -= size;
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.list.largest_alloc().min(self.node_size)
+    }
+
+    fn free_space(&self) -> usize {
+        self.list.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.list.internal_frag()
+    }
+
+    /// Spilling over to other nodes makes more than one search, but
+    /// only counts as one malloc
+    fn churn(&self) -> Churn {
+        let mut churn = self.list.churn();
+        churn.mallocs = self.mallocs;
+        churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.used.iter().sum())
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.list.coalesce()
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for node in 0..self.nodes {
+            let (lo, hi) = self.window(node);
+            writeln!(
+                out,
+                "Node {}: [{}, {}), {} bytes live",
+                node, lo, hi, self.used[node]
+            )
+            .unwrap();
+        }
+        out.push_str(&self.list.render());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spill_over() {
+        let mut heap = Numa::new(1000, 300, 3);
+        heap.switch_node(1);
+        let ptrs: Vec<usize> = (0..3).map(|_| heap.malloc(40).unwrap()).collect();
+        assert_eq!(ptrs, vec![1100, 1140, 1200]);
+        assert_eq!((heap.local, heap.remote), (2, 1));
+        assert_eq!(heap.node_of(1200), 2);
+        assert_eq!((heap.used(1), heap.used(2)), (80, 40));
+        assert_eq!(heap.imbalance(), 0.8);
+
+        // The last node wraps around to the first
+        heap.switch_node(2);
+        assert_eq!(heap.malloc(60), Some(1240));
+        assert_eq!(heap.malloc(60), Some(1000));
+        assert_eq!(heap.try_malloc(101), Err(AllocFailure::Oversized));
+        assert_eq!(heap.churn().mallocs, 6);
+
+        heap.free(ptrs[0]).unwrap();
+        assert!(heap.free(ptrs[0]).is_err());
+        assert_eq!(heap.used(1), 40);
+        assert_eq!(heap.live_bytes(), Some(200));
+    }
+}
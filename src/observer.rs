@@ -0,0 +1,205 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn, FreeRangeError};
+
+/// Hears about every op of an allocator wrapped in `Observed`, right
+/// after it happens, for logging, plotting or exporting without
+/// touching the allocator. Every callback does nothing by default
+pub trait AllocObserver {
+    fn on_malloc(&mut self, _size: usize, _result: Result<usize, AllocFailure>) {}
+
+    fn on_free(&mut self, _ptr: usize, _result: Result<(), AllocError>) {}
+
+    /// How many free blocks the op split, going by the allocator's
+    /// churn, so only for allocators that count their splits
+    fn on_split(&mut self, _splits: usize) {}
+
+    /// How many merges of free blocks the op made, whether on a
+    /// free, on a malloc that needed the space, or on a `coalesce`
+    fn on_coalesce(&mut self, _merges: usize) {}
+}
+
+/// Wraps any allocator and tells each of its observers about every
+/// malloc and free, and the splits and merges they caused
+pub struct Observed<T> {
+    allocator: T,
+    observers: Vec<Box<dyn AllocObserver>>,
+}
+
+impl<T: Allocator> Observed<T> {
+    pub fn new(allocator: T) -> Self {
+        Self {
+            allocator,
+            observers: Vec::new(),
+        }
+    }
+
+    /// Add an observer, which hears about ops after the ones added
+    /// before it
+    pub fn observer(mut self, observer: impl AllocObserver + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    pub fn into_inner(self) -> T {
+        self.allocator
+    }
+
+    // Tell the observers what the last op split and merged
+    fn churned(&mut self, before: Churn) {
+        let churn = self.allocator.churn().since(before);
+        for observer in self.observers.iter_mut() {
+            if churn.splits > 0 {
+                observer.on_split(churn.splits);
+            }
+            if churn.merges > 0 {
+                observer.on_coalesce(churn.merges);
+            }
+        }
+    }
+
+    fn malloced(&mut self, size: usize, result: Result<usize, AllocFailure>, before: Churn) {
+        for observer in self.observers.iter_mut() {
+            observer.on_malloc(size, result);
+        }
+        self.churned(before);
+    }
+}
+
+impl<T: Allocator> Allocator for Observed<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let before = self.allocator.churn();
+        let result = self.allocator.try_malloc(size);
+        self.malloced(size, result, before);
+        result
+    }
+
+    /// Observers only see that an aligned malloc failed, not why
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        let before = self.allocator.churn();
+        let ptr = self.allocator.malloc_aligned(size, align);
+        self.malloced(size, ptr.ok_or(AllocFailure::NoFit), before);
+        ptr
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let before = self.allocator.churn();
+        let result = self.allocator.free(ptr);
+        for observer in self.observers.iter_mut() {
+            observer.on_free(ptr, result);
+        }
+        self.churned(before);
+        result
+    }
+
+    fn free_range(&mut self, addr: usize, len: usize) -> Result<(), FreeRangeError> {
+        let before = self.allocator.churn();
+        let result = self.allocator.free_range(addr, len);
+        self.churned(before);
+        result
+    }
+
+    fn shrink(&mut self, ptr: usize, new_size: usize) -> Result<usize, FreeRangeError> {
+        let before = self.allocator.churn();
+        let result = self.allocator.shrink(ptr, new_size);
+        self.churned(before);
+        result
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.allocator.alloc_size(ptr)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.allocator.reset()
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        self.allocator.churn()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        self.allocator.live_bytes()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        let before = self.allocator.churn();
+        let merges = self.allocator.coalesce();
+        self.churned(before);
+        merges
+    }
+
+    fn render(&self) -> String {
+        self.allocator.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Writes every callback down as a line, like a logger would
+    #[derive(Clone, Default)]
+    struct Log(Rc<RefCell<Vec<String>>>);
+
+    impl AllocObserver for Log {
+        fn on_malloc(&mut self, size: usize, result: Result<usize, AllocFailure>) {
+            self.0
+                .borrow_mut()
+                .push(format!("malloc {} {:?}", size, result));
+        }
+
+        fn on_free(&mut self, ptr: usize, result: Result<(), AllocError>) {
+            self.0
+                .borrow_mut()
+                .push(format!("free {} {:?}", ptr, result));
+        }
+
+        fn on_split(&mut self, splits: usize) {
+            self.0.borrow_mut().push(format!("split {}", splits));
+        }
+
+        fn on_coalesce(&mut self, merges: usize) {
+            self.0.borrow_mut().push(format!("coalesce {}", merges));
+        }
+    }
+
+    #[test]
+    fn callbacks() {
+        let log = Log::default();
+        let mut heap = Observed::new(BuddyAllocator::new(4, 6)).observer(log.clone());
+        let a = heap.malloc(16).unwrap();
+        assert_eq!(heap.malloc(128), None);
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+
+        assert_eq!(
+            *log.0.borrow(),
+            vec![
+                "malloc 16 Ok(0)",
+                "split 2",
+                "malloc 128 Err(Oversized)",
+                "free 0 Ok(())",
+                "coalesce 2",
+                "free 0 Err(InvalidPointer(0))",
+            ]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use crate::trace::{Event, Op, Trace};
+use std::collections::BTreeMap;
+
+/// When an allocation in a trace is live, as op indices. `end` is
+/// the index of its free, or the length of the trace if it is
+/// never freed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lifetime {
+    pub id: usize,
+    pub size: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Lifetime {
+    fn overlaps(&self, other: &Lifetime) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+/// The lifetime of every allocation in the trace, in the order
+/// they were made
+pub fn lifetimes(trace: &Trace) -> Vec<Lifetime> {
+    let mut live: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut lifetimes = Vec::new();
+
+    for (i, &op) in trace.ops.iter().enumerate() {
+        match op {
+            Op::Malloc { id, .. } | Op::Calloc { id, .. } => {
+                let size = op.bytes().unwrap();
+                live.insert(id, lifetimes.len());
+                lifetimes.push(Lifetime {
+                    id,
+                    size,
+                    start: i,
+                    end: trace.len(),
+                });
+            }
+            Op::Free { id } => {
+                if let Some(index) = live.remove(&id) {
+                    lifetimes[index].end = i;
+                }
+            }
+        }
+    }
+
+    lifetimes
+}
+
+/// Most bytes live at once. No placement, online or offline, can
+/// fit the trace in less
+pub fn peak_live(trace: &Trace) -> usize {
+    let mut sizes: BTreeMap<usize, usize> = BTreeMap::new();
+    let (mut live, mut peak) = (0, 0);
+
+    for &op in trace.ops.iter() {
+        match op {
+            Op::Malloc { id, .. } | Op::Calloc { id, .. } => {
+                let size = op.bytes().unwrap();
+                sizes.insert(id, size);
+                live += size;
+                peak = peak.max(live);
+            }
+            Op::Free { id } => live -= sizes.remove(&id).unwrap_or(0),
+        }
+    }
+
+    peak
+}
+
+/// Addresses for every allocation of a trace, picked knowing the
+/// whole trace up front
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Placement {
+    pub addrs: BTreeMap<usize, usize>,
+    /// One past the highest byte any allocation uses
+    pub heap: usize,
+}
+
+/// Place every allocation offline. Finding the smallest heap is NP
+/// hard, so this places the largest allocations first, each at the
+/// lowest address that doesn't overlap anything placed before it
+/// while both are live. The result is an upper bound on the optimal
+/// heap size, and in practice close to `peak_live`
+pub fn place(lifetimes: &[Lifetime]) -> Placement {
+    let mut order: Vec<&Lifetime> = lifetimes.iter().collect();
+    order.sort_by(|a, b| b.size.cmp(&a.size).then(a.start.cmp(&b.start)));
+
+    let mut placed: Vec<(&Lifetime, usize)> = Vec::new();
+    let mut placement = Placement::default();
+
+    for lifetime in order {
+        let mut taken: Vec<(usize, usize)> = placed
+            .iter()
+            .filter(|(other, _)| lifetime.overlaps(other))
+            .map(|&(other, addr)| (addr, addr + other.size))
+            .collect();
+        taken.sort_unstable();
+
+        let mut addr = 0;
+        for (start, end) in taken {
+            if addr + lifetime.size <= start {
+                break;
+            }
+            addr = addr.max(end);
+        }
+
+        placement.addrs.insert(lifetime.id, addr);
+        placement.heap = placement.heap.max(addr + lifetime.size);
+        placed.push((lifetime, addr));
+    }
+
+    placement
+}
+
+/// One past the highest byte an online allocator handed out while
+/// replaying a trace
+pub fn footprint(events: &[Event]) -> usize {
+    events
+        .iter()
+        .filter_map(|event| match (event.op, event.block) {
+            (Op::Malloc { .. } | Op::Calloc { .. }, Some((ptr, size))) => Some(ptr + size),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lower_bound() {
+        let trace: Trace = "malloc 0 10\nmalloc 1 20\nfree 0\nmalloc 2 15\nfree 1"
+            .parse()
+            .unwrap();
+        assert_eq!(peak_live(&trace), 35);
+
+        let lifetimes = lifetimes(&trace);
+        assert_eq!(lifetimes[0].end, 2);
+        assert_eq!(lifetimes[2].end, 5);
+    }
+
+    #[test]
+    fn placement() {
+        // 0 and 2 are never live at the same time, so 2 reuses the
+        // space of 0 even though it is placed first
+        let trace: Trace = "malloc 0 10\nmalloc 1 20\nfree 0\nmalloc 2 15\nmalloc 3 5"
+            .parse()
+            .unwrap();
+        let placement = place(&lifetimes(&trace));
+        assert_eq!(placement.addrs[&1], 0);
+        assert_eq!(placement.addrs[&2], 20);
+        assert_eq!(placement.addrs[&0], 20);
+        assert_eq!(placement.addrs[&3], 35);
+        assert_eq!(placement.heap, 40);
+        assert_eq!(placement.heap, peak_live(&trace));
+    }
+}
@@ -0,0 +1,223 @@
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+/// Which resident page a fault evicts once every frame is full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Replacement {
+    /// The page brought in longest ago
+    Fifo,
+    /// The page used longest ago
+    Lru,
+    /// Belady's optimal policy: the page whose next use is furthest
+    /// away, or that is never used again. It needs the whole
+    /// reference string up front, so it is a bound for the others
+    /// rather than something a kernel could run
+    Opt,
+}
+
+impl Replacement {
+    pub fn name(self) -> &'static str {
+        match self {
+            Replacement::Fifo => "FIFO",
+            Replacement::Lru => "LRU",
+            Replacement::Opt => "OPT",
+        }
+    }
+}
+
+/// Page faults of the reference string on `frames` frames, starting
+/// with every frame empty
+pub fn faults(refs: &[usize], frames: usize, policy: Replacement) -> usize {
+    if frames == 0 {
+        return refs.len();
+    }
+    // When each reference's page is used next, or refs.len() for never
+    let mut next_use = vec![refs.len(); refs.len()];
+    let mut seen = BTreeMap::new();
+    for (t, &page) in refs.iter().enumerate().rev() {
+        if let Some(next) = seen.insert(page, t) {
+            next_use[t] = next;
+        }
+    }
+
+    // Resident pages ordered by what the policy evicts first, which
+    // is the lowest key, and each page's key
+    let mut order: BTreeSet<(isize, usize)> = BTreeSet::new();
+    let mut keys: BTreeMap<usize, isize> = BTreeMap::new();
+    let mut arrivals = VecDeque::new();
+    let mut faults = 0;
+    for (t, &page) in refs.iter().enumerate() {
+        let key = match policy {
+            Replacement::Fifo => 0,
+            Replacement::Lru => t as isize,
+            Replacement::Opt => -(next_use[t] as isize),
+        };
+        if let Some(old) = keys.get(&page).copied() {
+            if policy != Replacement::Fifo {
+                order.remove(&(old, page));
+                order.insert((key, page));
+                keys.insert(page, key);
+            }
+            continue;
+        }
+
+        faults += 1;
+        if keys.len() == frames {
+            let victim = match policy {
+                Replacement::Fifo => arrivals.pop_front().unwrap(),
+                _ => order.pop_first().unwrap().1,
+            };
+            keys.remove(&victim);
+        }
+        match policy {
+            Replacement::Fifo => arrivals.push_back(page),
+            _ => {
+                order.insert((key, page));
+            }
+        }
+        keys.insert(page, key);
+    }
+    faults
+}
+
+/// Faults for each number of frames in `frames`
+pub fn fault_curve(
+    refs: &[usize],
+    frames: impl Iterator<Item = usize>,
+    policy: Replacement,
+) -> Vec<(usize, usize)> {
+    frames
+        .map(|frames| (frames, faults(refs, frames, policy)))
+        .collect()
+}
+
+/// Searches random reference strings of `length` references to
+/// `pages` pages for Belady's anomaly: FIFO faulting more with one
+/// more frame. Tries `tries` strings, and returns the first that
+/// shows it with the number of frames where it happens. With no
+/// pages there are no strings to try
+pub fn find_anomaly<R: Rng>(
+    pages: usize,
+    length: usize,
+    tries: usize,
+    rng: &mut R,
+) -> Option<(Vec<usize>, usize)> {
+    if pages == 0 {
+        return None;
+    }
+    (0..tries).find_map(|_| {
+        let refs: Vec<usize> = (0..length).map(|_| rng.gen_range(0..pages)).collect();
+        let curve = fault_curve(&refs, 1..=pages, Replacement::Fifo);
+        curve
+            .windows(2)
+            .find(|pair| pair[1].1 > pair[0].1)
+            .map(|pair| (refs.clone(), pair[0].0))
+    })
+}
+
+/// Fault counts of each policy across the frame counts of the
+/// curves, one row per frame count, with a `<` after every count
+/// that is higher than the one above it
+pub fn render_faults(curves: &[(Replacement, Vec<(usize, usize)>)]) -> String {
+    let mut out = String::new();
+    write!(out, "{:>7}", "frames").unwrap();
+    for (policy, _) in curves.iter() {
+        write!(out, " {:>7}", policy.name()).unwrap();
+    }
+    out.push('\n');
+    let rows = curves
+        .iter()
+        .map(|(_, curve)| curve.len())
+        .max()
+        .unwrap_or(0);
+    for row in 0..rows {
+        let frames = curves
+            .iter()
+            .find_map(|(_, curve)| curve.get(row))
+            .map_or(0, |&(frames, _)| frames);
+        write!(out, "{:>7}", frames).unwrap();
+        for (_, curve) in curves.iter() {
+            let anomaly = row > 0 && curve.get(row).map(|p| p.1) > curve.get(row - 1).map(|p| p.1);
+            match curve.get(row) {
+                Some(&(_, faults)) => {
+                    write!(out, " {:>6}{}", faults, if anomaly { "<" } else { " " })
+                }
+                None => write!(out, " {:>7}", ""),
+            }
+            .unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // The string Belady, Nelson and Shedler gave for the anomaly
+    const BELADY: [usize; 12] = [1, 2, 3, 4, 1, 2, 5, 1, 2, 3, 4, 5];
+
+    #[test]
+    fn faults() {
+        assert_eq!(super::faults(&BELADY, 3, Replacement::Fifo), 9);
+        assert_eq!(super::faults(&BELADY, 4, Replacement::Fifo), 10);
+        assert_eq!(super::faults(&BELADY, 3, Replacement::Lru), 10);
+        assert_eq!(super::faults(&BELADY, 4, Replacement::Lru), 8);
+        assert_eq!(super::faults(&BELADY, 3, Replacement::Opt), 7);
+        assert_eq!(super::faults(&BELADY, 4, Replacement::Opt), 6);
+        // Every page fits, so only the first use of each faults
+        assert_eq!(super::faults(&BELADY, 5, Replacement::Fifo), 5);
+        assert_eq!(super::faults(&BELADY, 0, Replacement::Opt), 12);
+    }
+
+    #[test]
+    fn opt_is_a_bound() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..50 {
+            let refs: Vec<usize> = (0..40).map(|_| rng.gen_range(0..8)).collect();
+            let opt = fault_curve(&refs, 1..=8, Replacement::Opt);
+            let lru = fault_curve(&refs, 1..=8, Replacement::Lru);
+            let fifo = fault_curve(&refs, 1..=8, Replacement::Fifo);
+            for i in 0..8 {
+                assert!(opt[i].1 <= lru[i].1 && opt[i].1 <= fifo[i].1);
+                // OPT and LRU are stack algorithms, so more frames
+                // never hurt them
+                if i > 0 {
+                    assert!(opt[i].1 <= opt[i - 1].1);
+                    assert!(lru[i].1 <= lru[i - 1].1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn anomaly() {
+        // About one string in a thousand of this shape shows it
+        let mut rng = StdRng::seed_from_u64(0);
+        let (refs, frames) = find_anomaly(5, 30, 10000, &mut rng).unwrap();
+        assert!(
+            super::faults(&refs, frames + 1, Replacement::Fifo)
+                > super::faults(&refs, frames, Replacement::Fifo)
+        );
+        assert_eq!(find_anomaly(0, 30, 10, &mut rng), None);
+
+        let curves = [
+            (
+                Replacement::Fifo,
+                fault_curve(&BELADY, 3..=4, Replacement::Fifo),
+            ),
+            (
+                Replacement::Opt,
+                fault_curve(&BELADY, 3..=4, Replacement::Opt),
+            ),
+        ];
+        assert_eq!(
+            render_faults(&curves),
+            " frames    FIFO     OPT\n      3      9       7 \n      4     10<      6 \n"
+        );
+    }
+}
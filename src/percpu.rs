@@ -0,0 +1,217 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+
+/// A front end cache for each simulated CPU, like the per-CPU
+/// layers of kernel allocators. Freed blocks are kept on the
+/// freeing CPU's list for their size, and a malloc of the same size
+/// on that CPU reuses one without asking the backing allocator.
+/// When a CPU caches more than `threshold` blocks of one size, the
+/// oldest are flushed back until half the threshold is left.
+/// Cached blocks stay allocated as far as the backing allocator
+/// knows, so they do not count as free space
+#[derive(Debug, Clone)]
+pub struct PerCpu<T> {
+    allocator: T,
+    threshold: usize,
+    cpu: usize,
+    caches: Vec<BTreeMap<usize, Vec<usize>>>,
+    live: BTreeMap<usize, usize>,
+    pub hits: usize,
+    pub misses: usize,
+    pub flushes: usize,
+}
+
+impl<T: Allocator> PerCpu<T> {
+    pub fn new(allocator: T, cpus: usize) -> Self {
+        if cpus == 0 {
+            panic!("Need at least one CPU");
+        }
+
+        Self {
+            allocator,
+            threshold: 8,
+            cpu: 0,
+            caches: vec![BTreeMap::new(); cpus],
+            live: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+            flushes: 0,
+        }
+    }
+
+    /// How many blocks of one size a CPU holds before flushing
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn cpus(&self) -> usize {
+        self.caches.len()
+    }
+
+    /// Run the following mallocs and frees on this CPU
+    pub fn switch_cpu(&mut self, cpu: usize) {
+        if cpu >= self.cpus() {
+            panic!("No CPU {}", cpu);
+        }
+        self.cpu = cpu;
+    }
+
+    /// Fraction of mallocs served from a CPU cache
+    pub fn hit_rate(&self) -> f32 {
+        self.hits as f32 / (self.hits + self.misses).max(1) as f32
+    }
+
+    /// Bytes sitting in CPU caches
+    pub fn cached_bytes(&self) -> usize {
+        self.caches
+            .iter()
+            .flat_map(|cache| cache.iter())
+            .map(|(size, ptrs)| size * ptrs.len())
+            .sum()
+    }
+
+    /// Give every cached block back to the backing allocator
+    pub fn flush(&mut self) {
+        for cache in self.caches.iter_mut() {
+            for (_, ptrs) in std::mem::take(cache) {
+                for ptr in ptrs {
+                    // These came from the backing allocator and were
+                    // never freed to it, so this only fails if the
+                    // backing allocator injects failures
+                    let _ = self.allocator.free(ptr);
+                }
+            }
+        }
+    }
+
+    pub fn into_inner(mut self) -> T {
+        self.flush();
+        self.allocator
+    }
+}
+
+impl<T: Allocator> Allocator for PerCpu<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let cached = self.caches[self.cpu]
+            .get_mut(&size)
+            .and_then(|ptrs| ptrs.pop());
+        let ptr = match cached {
+            Some(ptr) => {
+                self.hits += 1;
+                ptr
+            }
+            None => {
+                self.misses += 1;
+                self.allocator.try_malloc(size)?
+            }
+        };
+
+        self.live.insert(ptr, size);
+        Ok(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let size = self
+            .live
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        let ptrs = self.caches[self.cpu].entry(size).or_default();
+        ptrs.push(ptr);
+
+        if ptrs.len() > self.threshold {
+            self.flushes += 1;
+            let keep = self.threshold / 2;
+            let flushed: Vec<usize> = ptrs.drain(..ptrs.len() - keep).collect();
+            for ptr in flushed {
+                let _ = self.allocator.free(ptr);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+    }
+
+    /// Mallocs served from a cache count too, since avoiding the
+    /// backing allocator's splits is the point of caching
+    fn churn(&self) -> Churn {
+        let mut churn = self.allocator.churn();
+        churn.mallocs += self.hits;
+        churn
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    /// Cached blocks are allocated as far as the backing allocator
+    /// knows, but not live
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().sum())
+    }
+
+    fn render(&self) -> String {
+        self.allocator.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+
+    #[test]
+    fn reuse_on_same_cpu() {
+        let mut heap = PerCpu::new(FreeList::new(0, 100, true), 2);
+        let ptr = heap.malloc(10).unwrap();
+        assert!(heap.free(ptr).is_ok());
+        assert!(heap.free(ptr).is_err());
+        assert_eq!(heap.free_space(), 90);
+        assert_eq!(heap.cached_bytes(), 10);
+
+        // Other sizes and other CPUs miss
+        assert_eq!(heap.malloc(20), Some(10));
+        heap.switch_cpu(1);
+        assert_eq!(heap.malloc(10), Some(30));
+        heap.switch_cpu(0);
+        assert_eq!(heap.malloc(10), Some(ptr));
+        assert_eq!((heap.hits, heap.misses), (1, 3));
+        assert_eq!(heap.hit_rate(), 0.25);
+    }
+
+    #[test]
+    fn flush_past_threshold() {
+        let mut heap = PerCpu::new(FreeList::new(0, 100, true), 1).threshold(4);
+        let ptrs: Vec<usize> = (0..5).map(|_| heap.malloc(10).unwrap()).collect();
+        for &ptr in ptrs[..4].iter() {
+            heap.free(ptr).unwrap();
+        }
+        assert_eq!(heap.flushes, 0);
+        assert_eq!(heap.free_space(), 50);
+
+        // Over the threshold, so all but the newest two go back
+        heap.free(ptrs[4]).unwrap();
+        assert_eq!(heap.flushes, 1);
+        assert_eq!(heap.free_space(), 80);
+        assert_eq!(heap.malloc(10), Some(ptrs[4]));
+
+        heap.free(ptrs[4]).unwrap();
+        let list = heap.into_inner();
+        assert_eq!(list.free_space(), 100);
+    }
+}
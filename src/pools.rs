@@ -0,0 +1,202 @@
+use crate::wcet::WorstCase;
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Equal blocks carved out of the heap up front
+#[derive(Debug, Clone)]
+struct Pool {
+    block: usize,
+    addr: usize,
+    count: usize,
+    // Most recently freed last
+    free: Vec<usize>,
+}
+
+/// Fixed block pools, like the memory partitions of an RTOS. The
+/// heap is carved up front into pools of equal blocks, which are
+/// never split or merged, so every malloc and free takes the same
+/// time. A malloc takes the most recently freed block of the
+/// smallest pool that fits it, or of the next pool up if that one
+/// is empty. What rounding up to the block costs counts as internal
+/// fragmentation
+#[derive(Debug, Clone)]
+pub struct FixedPools {
+    pools: Vec<Pool>,
+    // Requested size and pool of each live block
+    live: BTreeMap<usize, (usize, usize)>,
+    mallocs: usize,
+}
+
+impl FixedPools {
+    /// Lay out a pool of `count` blocks of `block` bytes for each
+    /// pair in `pools`, one after the other from `base`. Panics if
+    /// they take up more than `size` bytes
+    pub fn new(base: usize, size: usize, pools: &[(usize, usize)]) -> Self {
+        let mut sizes = pools.to_vec();
+        sizes.sort_unstable();
+        let mut addr = base;
+        let pools: Vec<Pool> = sizes
+            .into_iter()
+            .filter(|&(block, count)| block > 0 && count > 0)
+            .map(|(block, count)| {
+                let pool = Pool {
+                    block,
+                    addr,
+                    count,
+                    free: (0..count).rev().map(|i| addr + i * block).collect(),
+                };
+                addr += block * count;
+                pool
+            })
+            .collect();
+        if addr - base > size {
+            panic!("Pools take {} bytes of a {} byte heap", addr - base, size);
+        }
+
+        Self {
+            pools,
+            live: BTreeMap::new(),
+            mallocs: 0,
+        }
+    }
+
+    /// Block size and free blocks of each pool, smallest first
+    pub fn pools(&self) -> Vec<(usize, usize)> {
+        self.pools
+            .iter()
+            .map(|pool| (pool.block, pool.free.len()))
+            .collect()
+    }
+}
+
+impl Allocator for FixedPools {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.mallocs += 1;
+        if size == 0 {
+            return Err(AllocFailure::NoFit);
+        }
+        let first = self
+            .pools
+            .iter()
+            .position(|pool| pool.block >= size)
+            .ok_or(AllocFailure::Oversized)?;
+
+        let Some(i) = (first..self.pools.len()).find(|&i| !self.pools[i].free.is_empty()) else {
+            return Err(if self.free_space() < size {
+                AllocFailure::Exhausted
+            } else {
+                AllocFailure::NoFit
+            });
+        };
+        let ptr = self.pools[i].free.pop().unwrap();
+        self.live.insert(ptr, (size, i));
+        Ok(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let (_, i) = self
+            .live
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        self.pools[i].free.push(ptr);
+        Ok(())
+    }
+
+    /// The biggest block of any pool with one free
+    fn largest_alloc(&self) -> usize {
+        self.pools
+            .iter()
+            .filter(|pool| !pool.free.is_empty())
+            .map(|pool| pool.block)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Free blocks of every pool. Whatever the pools leave over at
+    /// the end of the heap can never be handed out, so it doesn't
+    /// count
+    fn free_space(&self) -> usize {
+        self.pools
+            .iter()
+            .map(|pool| pool.free.len() * pool.block)
+            .sum()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.live
+            .values()
+            .map(|&(requested, i)| self.pools[i].block - requested)
+            .sum()
+    }
+
+    /// Mallocs only, since blocks are never split or merged
+    fn churn(&self) -> Churn {
+        Churn {
+            mallocs: self.mallocs,
+            ..Churn::default()
+        }
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().map(|&(requested, _)| requested).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for pool in self.pools.iter() {
+            writeln!(
+                out,
+                "pool {}: {} byte blocks, {} of {} free",
+                pool.addr,
+                pool.block,
+                pool.free.len(),
+                pool.count
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// Blocks are never split or merged
+impl WorstCase for FixedPools {
+    fn worst_op(&self) -> Option<Churn> {
+        Some(Churn::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pools() {
+        let mut heap = FixedPools::new(0, 256, &[(64, 2), (16, 4)]);
+        assert_eq!(heap.pools(), vec![(16, 4), (64, 2)]);
+        assert_eq!(heap.free_space(), 192);
+        assert_eq!(heap.try_malloc(65), Err(AllocFailure::Oversized));
+
+        let a = heap.malloc(10).unwrap();
+        let b = heap.malloc(16).unwrap();
+        assert_eq!((a, b), (0, 16));
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        // The last block freed is the first handed out again
+        assert_eq!(heap.malloc(12), Some(a));
+
+        // An empty pool falls through to the next one up
+        heap.malloc(16).unwrap();
+        heap.malloc(16).unwrap();
+        assert_eq!(heap.malloc(8), Some(64));
+        assert_eq!(heap.internal_frag(), 4 + 56);
+        assert_eq!(heap.largest_alloc(), 64);
+        heap.malloc(40).unwrap();
+        assert_eq!(heap.try_malloc(8), Err(AllocFailure::Exhausted));
+        assert_eq!(heap.churn().splits, 0);
+    }
+}
@@ -1,5 +1,55 @@
-pub use crate::buddy::BuddyAllocator;
+pub use crate::aging::{Aging, AgingPolicy, Freed, Route};
+pub use crate::aligned::{AlignedFreeList, AlignmentLoss};
+pub use crate::arrivals::{Arrivals, Load};
+pub use crate::bins::BinAllocator;
+pub use crate::buddy::{BuddyAllocator, BuddyBuilder, Order, SizeError};
+pub use crate::checkpoint::{Checkpointed, StateId};
+pub use crate::contiguous::{Blocks, Contiguous, PageOrder};
+pub use crate::controller::{Controller, Occupancy};
+pub use crate::experiment::{Experiment, Outcome, Trials, Workload};
+pub use crate::faults::{Faulty, Recovery};
 pub use crate::freelist::FreeList;
-pub use crate::workloads::Results;
-pub use crate::{demos, workloads};
-pub use crate::{Allocator, Policy};
+pub use crate::head_to_head::HeadToHead;
+pub use crate::hoard::HoardSim;
+pub use crate::hybrid::HybridAllocator;
+pub use crate::ids::{IdPolicy, IdSpace};
+pub use crate::linked::LinkedFreeList;
+pub use crate::maps::AddressMap;
+pub use crate::metadata::Tagged;
+pub use crate::metrics::{
+    Deadlines, FailureForecast, FragSeries, LatencyModel, Metric, MetricCollector, Metrics,
+    SizeHistogram,
+};
+pub use crate::mimalloc::{Mimalloc, PageStats};
+pub use crate::mobility::{Grouped, Mobility};
+pub use crate::numa::Numa;
+pub use crate::observer::{AllocObserver, Observed};
+pub use crate::paging::Replacement;
+pub use crate::percpu::PerCpu;
+pub use crate::pools::FixedPools;
+pub use crate::quickfit::QuickFit;
+pub use crate::region::RegionAllocator;
+pub use crate::report::{Drilldown, LiveBlock, Remedy};
+pub use crate::reservation::{ReservationId, Reserving};
+pub use crate::rng::Generator;
+pub use crate::rounding::{Rounded, Rounding};
+pub use crate::segregated::SegregatedList;
+pub use crate::segtree::FreeIndex;
+pub use crate::snapshot::{HeapSnapshot, Layout};
+pub use crate::spans::{LevelFrag, SpanHeap};
+pub use crate::stack::StackAllocator;
+pub use crate::stats::{Aggregate, Summary};
+pub use crate::synth::{Synth, TraceStats};
+pub use crate::tlsf::Tlsf;
+pub use crate::trace::Trace;
+pub use crate::usage::{Stats, Tracked, Usage};
+pub use crate::vmem::{Arena, Fit};
+pub use crate::wcet::WorstCase;
+pub use crate::workloads::{Compaction, Failures, Phase, Pressure, Results, SizeClass, Warm};
+pub use crate::{
+    bounds, demos, experiment, locality, offline, paging, presets, synth, trace, workloads,
+};
+pub use crate::{
+    AllocError, AllocFailure, Allocator, Churn, FreeRangeError, Policy, Recycling, Regional,
+    Retention,
+};
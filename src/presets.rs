@@ -0,0 +1,118 @@
+use crate::buddy::BuddyAllocator;
+use crate::freelist::FreeList;
+use crate::pools::FixedPools;
+use crate::rounding::{Rounded, Rounding};
+use crate::segregated::SegregatedList;
+use crate::stack::StackAllocator;
+use crate::tlsf::Tlsf;
+use crate::{Allocator, Policy};
+
+/// Builds a fresh allocator for a preset's heap
+pub type Make = fn() -> Box<dyn Allocator>;
+
+/// The heap of the general purpose benches and analyses
+pub const HEAP: usize = 32768;
+
+/// Device memory: 256M with no paging to fall back on, so every
+/// allocation needs one contiguous range
+pub const DEVICE_HEAP: usize = 256 << 20;
+/// Allocation granularity of device memory, and the alignment of
+/// every allocation
+pub const DEVICE_PAGE: usize = 64 << 10;
+
+/// An embedded system or RTOS heap: 4K, small enough that how much
+/// is lost to fragmentation matters as much as how long an op takes
+pub const EMBEDDED_HEAP: usize = 4096;
+
+/// Allocators an embedded system might pick from, by name. None of
+/// them search further than a fixed bound or merge free blocks,
+/// apart from TLSF, which does both in constant time
+pub fn embedded() -> Vec<(&'static str, Make)> {
+    vec![
+        ("fixed pools", || {
+            Box::new(FixedPools::new(
+                0,
+                EMBEDDED_HEAP,
+                &[(16, 32), (32, 16), (64, 16), (128, 16)],
+            ))
+        }),
+        ("freelist first fit, no coalescing", || {
+            Box::new(
+                FreeList::new(0, EMBEDDED_HEAP, false)
+                    .align(8)
+                    .policy(Policy::First),
+            )
+        }),
+        ("freelist bounded first fit, no coalescing", || {
+            Box::new(
+                FreeList::new(0, EMBEDDED_HEAP, false)
+                    .align(8)
+                    .policy(Policy::Bounded {
+                        budget: 4,
+                        fallback: true,
+                    }),
+            )
+        }),
+        ("buddy", || Box::new(BuddyAllocator::new(4, 12))),
+        ("tlsf", || Box::new(Tlsf::new(0, EMBEDDED_HEAP))),
+    ]
+}
+
+/// Allocators a device memory driver might pick from, by name. All
+/// of them hand out whole device pages
+pub fn device() -> Vec<(&'static str, Make)> {
+    vec![
+        ("buddy", || Box::new(BuddyAllocator::new(16, 28))),
+        ("freelist first fit", || {
+            Box::new(
+                FreeList::new(0, DEVICE_HEAP, true)
+                    .align(DEVICE_PAGE)
+                    .policy(Policy::First),
+            )
+        }),
+        ("freelist best fit", || {
+            Box::new(FreeList::new(0, DEVICE_HEAP, true).align(DEVICE_PAGE))
+        }),
+        ("tlsf", || {
+            Box::new(Rounded::new(
+                Tlsf::new(0, DEVICE_HEAP),
+                Rounding::Align(DEVICE_PAGE),
+            ))
+        }),
+    ]
+}
+
+/// Allocators the general purpose benches and analyses compare, by
+/// the name the command line takes for them
+pub fn general() -> Vec<(&'static str, Make)> {
+    vec![
+        ("freelist", || {
+            Box::new(FreeList::new(0, HEAP, true).align(32).policy(Policy::First))
+        }),
+        ("buddy", || {
+            Box::new(
+                BuddyAllocator::builder()
+                    .heap_bytes(HEAP)
+                    .min_block_bytes(32)
+                    .build()
+                    .unwrap(),
+            )
+        }),
+        ("segregated", || Box::new(SegregatedList::new(0, HEAP))),
+        ("tlsf", || Box::new(Tlsf::new(0, HEAP))),
+        ("stack", || Box::new(StackAllocator::new(0, HEAP))),
+    ]
+}
+
+/// The names in `general`, for command line choices
+pub fn names() -> Vec<&'static str> {
+    general().into_iter().map(|(name, _)| name).collect()
+}
+
+/// A fresh allocator from `general` by name
+pub fn make(name: &str) -> Option<Box<dyn Allocator>> {
+    general()
+        .into_iter()
+        .find(|&(general, _)| general == name)
+        .map(|(_, make)| make())
+}
@@ -0,0 +1,203 @@
+use crate::freelist::FreeList;
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Weinstock and Wulf's quick fit. Requests for one of a few common
+/// sizes are served from a list of freed blocks of exactly that
+/// size, and everything else goes to a general free list. Blocks on
+/// a quick list stay allocated as far as the free list knows, and
+/// only go back to it when a malloc would otherwise fail
+#[derive(Debug, Clone)]
+pub struct QuickFit {
+    list: FreeList,
+    quick: BTreeMap<usize, Vec<usize>>,
+    live: BTreeMap<usize, usize>,
+    /// Mallocs of a quick size served from, or missing, its list
+    pub hits: usize,
+    pub misses: usize,
+    /// Times every quick list was given back to the free list
+    pub flushes: usize,
+    /// Searches of the free list, and the free blocks they scanned
+    pub searches: usize,
+    pub nodes_scanned: usize,
+}
+
+impl QuickFit {
+    /// Keep a quick list for each of `sizes` in front of `list`
+    pub fn new(list: FreeList, sizes: &[usize]) -> Self {
+        Self {
+            list,
+            quick: sizes.iter().map(|&size| (size, Vec::new())).collect(),
+            live: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+            flushes: 0,
+            searches: 0,
+            nodes_scanned: 0,
+        }
+    }
+
+    /// Fraction of mallocs of a quick size served from its list
+    pub fn hit_rate(&self) -> f32 {
+        self.hits as f32 / (self.hits + self.misses).max(1) as f32
+    }
+
+    /// Bytes sitting on quick lists
+    pub fn quick_bytes(&self) -> usize {
+        self.quick
+            .iter()
+            .map(|(size, ptrs)| size * ptrs.len())
+            .sum()
+    }
+
+    /// Give every block on a quick list back to the free list
+    pub fn flush(&mut self) {
+        for ptrs in self.quick.values_mut() {
+            for ptr in ptrs.drain(..) {
+                // Never freed to the list, so this can't fail
+                let _ = self.list.free(ptr);
+            }
+        }
+    }
+
+    pub fn into_inner(mut self) -> FreeList {
+        self.flush();
+        self.list
+    }
+
+    // Malloc from the free list, counting the search
+    fn search(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let result = self.list.try_malloc(size);
+        self.searches += 1;
+        self.nodes_scanned += self.list.last_scan().1;
+        result
+    }
+}
+
+impl Allocator for QuickFit {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let ptr = match self.quick.get_mut(&size).map(|ptrs| ptrs.pop()) {
+            Some(Some(ptr)) => {
+                self.hits += 1;
+                ptr
+            }
+            quick => {
+                if quick.is_some() {
+                    self.misses += 1;
+                }
+                match self.search(size) {
+                    Ok(ptr) => ptr,
+                    // Cached blocks might make room once merged back
+                    Err(_) if self.quick_bytes() > 0 => {
+                        self.flushes += 1;
+                        self.flush();
+                        self.search(size)?
+                    }
+                    Err(failure) => return Err(failure),
+                }
+            }
+        };
+
+        self.live.insert(ptr, size);
+        Ok(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let size = *self.live.get(&ptr).ok_or(AllocError::InvalidPointer(ptr))?;
+        match self.quick.get_mut(&size) {
+            Some(ptrs) => ptrs.push(ptr),
+            None => self.list.free(ptr)?,
+        }
+        self.live.remove(&ptr);
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.list.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.list.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.list.internal_frag()
+    }
+
+    /// Mallocs served from a quick list count too, since they save
+    /// the free list a split
+    fn churn(&self) -> Churn {
+        let mut churn = self.list.churn();
+        churn.mallocs += self.hits;
+        churn
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.list.coalesce()
+    }
+
+    /// Blocks on quick lists are allocated as far as the free list
+    /// knows, but not live
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (size, ptrs) in self.quick.iter() {
+            writeln!(out, "quick {}: {:?}", size, ptrs).unwrap();
+        }
+        out.push_str(&self.list.render());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Policy;
+
+    #[test]
+    fn quick_lists() {
+        let mut heap = QuickFit::new(FreeList::new(0, 100, true).policy(Policy::First), &[10]);
+        let a = heap.malloc(10).unwrap();
+        let b = heap.malloc(20).unwrap();
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        assert_eq!(heap.quick_bytes(), 10);
+        assert_eq!(heap.free_space(), 70);
+
+        // Other sizes go to the free list and don't count
+        heap.free(b).unwrap();
+        assert_eq!(heap.free_space(), 90);
+        assert_eq!(heap.malloc(15), Some(10));
+        assert_eq!(heap.malloc(10), Some(a));
+        assert_eq!((heap.hits, heap.misses), (1, 1));
+        assert_eq!(heap.searches, 3);
+        assert_eq!(heap.live_bytes(), Some(25));
+    }
+
+    #[test]
+    fn flush_when_full() {
+        let mut heap = QuickFit::new(FreeList::new(0, 40, true), &[10, 20]);
+        let ptrs: Vec<usize> = (0..4).map(|_| heap.malloc(10).unwrap()).collect();
+        for &ptr in ptrs.iter() {
+            heap.free(ptr).unwrap();
+        }
+        assert_eq!(heap.free_space(), 0);
+
+        // Nothing fits until the quick lists go back and merge
+        assert_eq!(heap.malloc(20), Some(0));
+        assert_eq!(heap.flushes, 1);
+        assert_eq!(heap.misses, 5);
+        assert_eq!(heap.quick_bytes(), 0);
+        assert_eq!(heap.free_space(), 20);
+        assert!(heap.malloc(30).is_none());
+        assert_eq!(heap.flushes, 1);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{AllocError, Allocator, Churn, Regional};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Default)]
+struct Region {
+    // Chunks taken from the backing allocator, and their lengths
+    chunks: Vec<(usize, usize)>,
+    // Where the next malloc goes in the chunk being filled, and
+    // where that chunk ends
+    top: usize,
+    end: usize,
+    // Bytes freed one at a time, still held until the region goes
+    dead: usize,
+}
+
+/// Region based memory management, like arenas or APR pools, on top
+/// of another allocator. Mallocs go into the region entered last,
+/// packed one after the other into chunks of `chunk` bytes taken
+/// from the backing allocator, or a chunk of their own if they are
+/// bigger. Freeing a single allocation gives nothing back. All the
+/// memory goes back at once when its region is freed
+#[derive(Debug, Clone)]
+pub struct RegionAllocator<T> {
+    allocator: T,
+    chunk: usize,
+    regions: BTreeMap<String, Region>,
+    current: String,
+    // Requested size and region of each live allocation
+    live: BTreeMap<usize, (usize, String)>,
+    mallocs: usize,
+    /// Most bytes held at once by allocations that were freed but
+    /// whose region wasn't yet
+    pub peak_dead: usize,
+    pub regions_freed: usize,
+}
+
+impl<T: Allocator> RegionAllocator<T> {
+    /// Mallocs go into a region named "" until another is entered
+    pub fn new(allocator: T, chunk: usize) -> Self {
+        if chunk == 0 {
+            panic!("Chunks can't be empty");
+        }
+
+        Self {
+            allocator,
+            chunk,
+            regions: BTreeMap::new(),
+            current: String::new(),
+            live: BTreeMap::new(),
+            mallocs: 0,
+            peak_dead: 0,
+            regions_freed: 0,
+        }
+    }
+
+    /// Names of the regions that haven't been freed
+    pub fn regions(&self) -> impl Iterator<Item = &str> {
+        self.regions.keys().map(String::as_str)
+    }
+
+    /// Bytes taken from the backing allocator by every region
+    pub fn held(&self) -> usize {
+        self.regions
+            .values()
+            .flat_map(|region| region.chunks.iter())
+            .map(|&(_, len)| len)
+            .sum()
+    }
+
+    /// Bytes of allocations that were freed on their own, and are
+    /// held until their region is
+    pub fn dead(&self) -> usize {
+        self.regions.values().map(|region| region.dead).sum()
+    }
+}
+
+impl<T: Allocator> Allocator for RegionAllocator<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.mallocs += 1;
+        if size == 0 {
+            return None;
+        }
+        let region = self.regions.entry(self.current.clone()).or_default();
+
+        let ptr = if size > self.chunk {
+            let ptr = self.allocator.malloc(size)?;
+            region.chunks.push((ptr, size));
+            ptr
+        } else if region.end - region.top >= size {
+            region.top += size;
+            region.top - size
+        } else {
+            let ptr = self.allocator.malloc(self.chunk)?;
+            region.chunks.push((ptr, self.chunk));
+            region.top = ptr + size;
+            region.end = ptr + self.chunk;
+            ptr
+        };
+        self.live.insert(ptr, (size, self.current.clone()));
+        Some(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let (size, name) = self
+            .live
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        self.regions.get_mut(&name).unwrap().dead += size;
+        self.peak_dead = self.peak_dead.max(self.dead());
+        Ok(())
+    }
+
+    /// The largest free block of the backing allocator, or what is
+    /// left of the current region's chunk if that is bigger
+    fn largest_alloc(&self) -> usize {
+        let room = self
+            .regions
+            .get(&self.current)
+            .map_or(0, |region| region.end - region.top);
+        self.allocator.largest_alloc().max(room)
+    }
+
+    /// Free space of the backing allocator, and what is left of the
+    /// chunk each region is filling
+    fn free_space(&self) -> usize {
+        let room: usize = self
+            .regions
+            .values()
+            .map(|region| region.end - region.top)
+            .sum();
+        self.allocator.free_space() + room
+    }
+
+    /// Whatever the backing allocator loses, plus the bytes of the
+    /// regions' chunks that are neither live nor left to fill: dead
+    /// allocations, and the ends of chunks too short for the malloc
+    /// that moved on to a new one
+    fn internal_frag(&self) -> usize {
+        let live: usize = self.live.values().map(|&(size, _)| size).sum();
+        let room: usize = self
+            .regions
+            .values()
+            .map(|region| region.end - region.top)
+            .sum();
+        self.allocator.internal_frag() + self.held() - live - room
+    }
+
+    /// Splits and merges of the backing allocator, as chunks come
+    /// and go
+    fn churn(&self) -> Churn {
+        let mut churn = self.allocator.churn();
+        churn.mallocs = self.mallocs;
+        churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().map(|&(size, _)| size).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, region) in self.regions.iter() {
+            writeln!(
+                out,
+                "region {:?}: {} chunks, {} bytes dead, {} left in the last chunk",
+                name,
+                region.chunks.len(),
+                region.dead,
+                region.end - region.top
+            )
+            .unwrap();
+        }
+        writeln!(out, "Backing allocator:").unwrap();
+        out.push_str(&self.allocator.render());
+        out
+    }
+}
+
+impl<T: Allocator> Regional for RegionAllocator<T> {
+    fn enter_region(&mut self, name: &str) {
+        self.regions.entry(name.to_string()).or_default();
+        self.current = name.to_string();
+    }
+
+    fn free_region(&mut self, name: &str) -> Result<(), AllocError> {
+        let region = self.regions.remove(name).ok_or(AllocError::NotFound)?;
+        for (ptr, _) in region.chunks {
+            self.allocator.free(ptr)?;
+        }
+        self.live.retain(|_, (_, region)| region != name);
+        self.regions_freed += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::Policy;
+
+    #[test]
+    fn regions() {
+        let list = FreeList::new(0, 1024, true).policy(Policy::First);
+        let mut heap = RegionAllocator::new(list, 256);
+        heap.enter_region("parse");
+        let a = heap.malloc(100).unwrap();
+        let b = heap.malloc(100).unwrap();
+        // Doesn't fit in what is left of the chunk
+        let c = heap.malloc(100).unwrap();
+        assert_eq!((a, b, c), (0, 100, 256));
+        heap.enter_region("render");
+        assert_eq!(heap.malloc(300), Some(512));
+        assert_eq!(heap.held(), 812);
+        assert_eq!(heap.free_space(), 1024 - 812 + 156);
+        assert_eq!(heap.internal_frag(), 56);
+
+        // Freeing on its own gives nothing back
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        assert_eq!(heap.dead(), 100);
+        assert_eq!(heap.internal_frag(), 156);
+
+        heap.free_region("parse").unwrap();
+        assert_eq!(heap.free_region("parse"), Err(AllocError::NotFound));
+        assert!(heap.free(b).is_err());
+        assert_eq!(heap.regions().collect::<Vec<_>>(), vec!["render"]);
+        assert_eq!(heap.held(), 300);
+        assert_eq!(heap.live_bytes(), Some(300));
+        assert_eq!(heap.peak_dead, 100);
+        assert_eq!(heap.malloc(200), Some(0));
+    }
+}
@@ -0,0 +1,441 @@
+use crate::aligned::AlignedFreeList;
+use crate::bins::BinAllocator;
+use crate::buddy::BuddyAllocator;
+use crate::freelist::FreeList;
+use crate::hoard::HoardSim;
+use crate::hybrid::HybridAllocator;
+use crate::linked::LinkedFreeList;
+use crate::mimalloc::Mimalloc;
+use crate::numa::Numa;
+use crate::pools::FixedPools;
+use crate::presets::Make;
+use crate::quickfit::QuickFit;
+use crate::region::RegionAllocator;
+use crate::spans::SpanHeap;
+use crate::tlsf::Tlsf;
+use crate::trace::{Op, Trace};
+use crate::vmem::Arena;
+use crate::{AllocError, Allocator, Policy};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Size of the heap every allocator is checked with
+const HEAP: usize = 32768;
+
+/// The first op of a replay where an allocator broke one of the
+/// invariants `check` looks at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub op: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "op {}: {}", self.op, self.message)
+    }
+}
+
+/// Replay a trace on an allocator managing [base, base + size),
+/// checking after every op that no allocation falls outside the
+/// heap or overlaps another live one, that freeing a live
+/// allocation succeeds, and that free space never counts bytes
+/// that are allocated
+pub fn check<T: Allocator>(
+    allocator: &mut T,
+    trace: &Trace,
+    base: usize,
+    size: usize,
+) -> Result<(), Violation> {
+    let mut checker = Checker::new(base, size);
+    for (i, &op) in trace.ops.iter().enumerate() {
+        checker.step(allocator, i, op)?;
+    }
+    Ok(())
+}
+
+/// What `check` knows about a heap partway through a replay, so a
+/// replay can be checked one op at a time and forked
+#[derive(Debug, Clone)]
+pub struct Checker {
+    base: usize,
+    size: usize,
+    // Address and length of each live id
+    live: BTreeMap<usize, (usize, usize)>,
+    // End of each live block by address
+    blocks: BTreeMap<usize, usize>,
+    used: usize,
+}
+
+impl Checker {
+    pub fn new(base: usize, size: usize) -> Self {
+        Self {
+            base,
+            size,
+            live: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            used: 0,
+        }
+    }
+
+    /// Ids that were malloced and not freed yet
+    pub fn live(&self) -> impl Iterator<Item = usize> + '_ {
+        self.live.keys().copied()
+    }
+
+    /// Apply op `i` of a replay to the allocator and check it
+    pub fn step<T: Allocator>(
+        &mut self,
+        allocator: &mut T,
+        i: usize,
+        op: Op,
+    ) -> Result<(), Violation> {
+        let violation = |message: String| Violation { op: i, message };
+        match op {
+            Op::Malloc { id, .. } | Op::Calloc { id, .. } => {
+                let len = op.bytes().unwrap();
+                let ptr = match op {
+                    Op::Calloc { count, size, .. } => allocator.calloc(count, size),
+                    _ => allocator.malloc(len),
+                };
+                if let Some(ptr) = ptr {
+                    if ptr < self.base || ptr + len > self.base + self.size {
+                        return Err(violation(format!(
+                            "[{}, {}) is outside the heap",
+                            ptr,
+                            ptr + len
+                        )));
+                    }
+                    if let Some((&other, &end)) = self.blocks.range(..ptr + len).next_back() {
+                        if end > ptr {
+                            return Err(violation(format!(
+                                "[{}, {}) overlaps [{}, {})",
+                                ptr,
+                                ptr + len,
+                                other,
+                                end
+                            )));
+                        }
+                    }
+                    self.live.insert(id, (ptr, len));
+                    self.blocks.insert(ptr, ptr + len);
+                    self.used += len;
+                }
+            }
+            Op::Free { id } => {
+                if let Some((ptr, len)) = self.live.remove(&id) {
+                    match allocator.free(ptr) {
+                        Ok(()) => {
+                            self.blocks.remove(&ptr);
+                            self.used -= len;
+                        }
+                        // A stack only frees its top, so the allocation
+                        // stays live
+                        Err(AllocError::OutOfOrder { .. }) => {
+                            self.live.insert(id, (ptr, len));
+                        }
+                        Err(_) => return Err(violation(format!("could not free {}", ptr))),
+                    }
+                }
+            }
+        }
+
+        if allocator.free_space() + self.used > self.size {
+            return Err(violation(format!(
+                "{} bytes free with {} of {} allocated",
+                allocator.free_space(),
+                self.used,
+                self.size
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Every allocator, by name
+fn allocators() -> Vec<(&'static str, Make)> {
+    vec![
+        ("freelist first fit", || {
+            Box::new(FreeList::new(0, HEAP, true).policy(Policy::First))
+        }),
+        ("freelist first fit, aligned", || {
+            Box::new(FreeList::new(0, HEAP, true).policy(Policy::First).align(32))
+        }),
+        ("freelist next fit", || {
+            Box::new(FreeList::new(0, HEAP, true).policy(Policy::Next))
+        }),
+        ("freelist best fit", || {
+            Box::new(FreeList::new(0, HEAP, true))
+        }),
+        ("freelist best fit, by size", || {
+            Box::new(FreeList::new(0, HEAP, true).by_size())
+        }),
+        ("freelist without coalescing", || {
+            Box::new(FreeList::new(0, HEAP, false))
+        }),
+        ("freelist with boundary tags", || {
+            Box::new(
+                FreeList::new(0, HEAP, true)
+                    .policy(Policy::First)
+                    .boundary_tags(16),
+            )
+        }),
+        ("buddy", || Box::new(BuddyAllocator::new(5, 15))),
+        ("size class bins", || {
+            Box::new(BinAllocator::new(0, HEAP, 1024))
+        }),
+        ("aligned freelist", || {
+            Box::new(AlignedFreeList::new(0, HEAP, &[16, 64, 4096]))
+        }),
+        ("vmem", || Box::new(Arena::new(0, HEAP, 1))),
+        ("hoard", || Box::new(HoardSim::new(0, HEAP, 4, 1024))),
+        ("hybrid", || {
+            Box::new(
+                HybridAllocator::new(
+                    BuddyAllocator::new(5, 14),
+                    FreeList::new(HEAP / 2, HEAP / 2, true).policy(Policy::First),
+                    64,
+                )
+                .unwrap(),
+            )
+        }),
+        ("mimalloc", || Box::new(Mimalloc::new(0, HEAP, 8192, 1024))),
+        ("spans", || {
+            Box::new(SpanHeap::new(BuddyAllocator::new(5, 15), 2048))
+        }),
+        ("numa", || Box::new(Numa::new(0, HEAP, 4))),
+        ("fixed pools", || {
+            Box::new(FixedPools::new(
+                0,
+                HEAP,
+                &[(16, 256), (64, 128), (256, 32), (1024, 8)],
+            ))
+        }),
+        ("tlsf", || Box::new(Tlsf::new(0, HEAP))),
+        ("linked freelist", || Box::new(LinkedFreeList::new(0, HEAP))),
+        ("regions", || {
+            Box::new(RegionAllocator::new(
+                FreeList::new(0, HEAP, true).policy(Policy::First),
+                1024,
+            ))
+        }),
+        ("quick fit", || {
+            Box::new(QuickFit::new(
+                FreeList::new(0, HEAP, true).policy(Policy::First),
+                &[16, 32, 64],
+            ))
+        }),
+    ]
+}
+
+/// Check a trace against every allocator, each managing 32K from
+/// address 0
+pub fn check_all(trace: &Trace) -> Vec<(&'static str, Result<(), Violation>)> {
+    allocators()
+        .into_iter()
+        .map(|(name, make)| (name, check(&mut make(), trace, 0, HEAP)))
+        .collect()
+}
+
+/// Check that the order of a run of consecutive frees doesn't
+/// matter to coalescing. Each run of two or more frees is reversed
+/// in turn, and the trace up to the end of the run replayed on a
+/// fresh allocator from `make`. Both orders have to leave the same
+/// free space and the same largest free block. Later mallocs are
+/// not compared, since an allocator may reuse the most recently
+/// freed block first without coalescing any differently
+pub fn commutes<T: Allocator>(make: impl Fn() -> T, trace: &Trace) -> Result<(), Violation> {
+    let after = |ops: &[Op]| {
+        let mut allocator = make();
+        Trace { ops: ops.to_vec() }.replay(&mut allocator);
+        (allocator.free_space(), allocator.largest_alloc())
+    };
+
+    let mut start = 0;
+    while start < trace.len() {
+        let len = trace.ops[start..]
+            .iter()
+            .take_while(|op| matches!(op, Op::Free { .. }))
+            .count();
+        if len < 2 {
+            start += 1;
+            continue;
+        }
+
+        let end = start + len;
+        let mut reordered = trace.ops[..end].to_vec();
+        reordered[start..].reverse();
+        let (free, largest) = after(&trace.ops[..end]);
+        let (other_free, other_largest) = after(&reordered);
+        let changed = if other_free != free {
+            Some(format!("the free space from {} to {}", free, other_free))
+        } else if other_largest != largest {
+            Some(format!(
+                "the largest free block from {} to {}",
+                largest, other_largest
+            ))
+        } else {
+            None
+        };
+        if let Some(changed) = changed {
+            return Err(Violation {
+                op: start,
+                message: format!("reversing the {} frees from here changes {}", len, changed),
+            });
+        }
+        start = end;
+    }
+
+    Ok(())
+}
+
+/// `commutes` for every allocator
+pub fn commutes_all(trace: &Trace) -> Vec<(&'static str, Result<(), Violation>)> {
+    allocators()
+        .into_iter()
+        .map(|(name, make)| (name, commutes(make, trace)))
+        .collect()
+}
+
+/// `check` and then `commutes` on the allocator called `name`, one
+/// of the names `check_all` reports. Panics on any other name
+pub fn check_named(name: &str, trace: &Trace) -> Result<(), Violation> {
+    let (_, make) = allocators()
+        .into_iter()
+        .find(|&(other, _)| other == name)
+        .expect("Unknown allocator");
+    check(&mut make(), trace, 0, HEAP)?;
+    commutes(make, trace)
+}
+
+/// The first allocator either check fails on, and how
+pub fn first_violation(trace: &Trace) -> Option<(&'static str, Violation)> {
+    check_all(trace)
+        .into_iter()
+        .chain(commutes_all(trace))
+        .find_map(|(name, result)| result.err().map(|violation| (name, violation)))
+}
+
+/// Every `.trace` file in a directory, by file name. Minimized
+/// traces from fuzzing or bug reports go in a directory like this,
+/// so each one is replayed against every allocator from then on
+pub fn load(dir: impl AsRef<Path>) -> Result<Vec<(String, Trace)>, String> {
+    let entries = fs::read_dir(dir.as_ref()).map_err(|e| e.to_string())?;
+    let mut traces = Vec::new();
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("trace") {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let trace = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())?
+            .parse()
+            .map_err(|e| format!("{}: {}", name, e))?;
+        traces.push((name, trace));
+    }
+
+    traces.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(traces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AllocError;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn catches_overlap() {
+        // Hands out the same address every time
+        struct Broken;
+        impl Allocator for Broken {
+            fn malloc(&mut self, _: usize) -> Option<usize> {
+                Some(0)
+            }
+            fn free(&mut self, _: usize) -> Result<(), AllocError> {
+                Ok(())
+            }
+            fn largest_alloc(&self) -> usize {
+                0
+            }
+            fn free_space(&self) -> usize {
+                0
+            }
+            fn internal_frag(&self) -> usize {
+                0
+            }
+            fn render(&self) -> String {
+                String::new()
+            }
+        }
+
+        let trace: Trace = "malloc 0 8\nmalloc 1 8".parse().unwrap();
+        let violation = check(&mut Broken, &trace, 0, 64).unwrap_err();
+        assert_eq!(violation.op, 1);
+        assert_eq!(violation.to_string(), "op 1: [0, 8) overlaps [0, 8)");
+    }
+
+    #[test]
+    fn random_traces() {
+        for (seed, ratio) in [0.3, 0.5, 0.7].iter().enumerate() {
+            let mut rng = StdRng::seed_from_u64(seed as u64);
+            let trace = crate::trace::random_memory(*ratio, &mut rng);
+            for (name, result) in check_all(&trace).into_iter().chain(commutes_all(&trace)) {
+                if let Err(violation) = result {
+                    panic!("{} on\n{}\n{}", name, trace, violation);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn catches_order_dependence() {
+        // Only merges a freed block with a free block right after
+        // it, so freeing from the top down coalesces and freeing
+        // from the bottom up doesn't
+        #[derive(Default)]
+        struct ForwardOnly {
+            free: BTreeMap<usize, usize>,
+            next: usize,
+        }
+        impl Allocator for ForwardOnly {
+            fn malloc(&mut self, size: usize) -> Option<usize> {
+                self.next += size;
+                Some(self.next - size)
+            }
+            fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+                let size = match self.free.remove(&(ptr + 8)) {
+                    Some(next) => 8 + next,
+                    None => 8,
+                };
+                self.free.insert(ptr, size);
+                Ok(())
+            }
+            fn largest_alloc(&self) -> usize {
+                self.free.values().copied().max().unwrap_or(0)
+            }
+            fn free_space(&self) -> usize {
+                self.free.values().sum()
+            }
+            fn internal_frag(&self) -> usize {
+                0
+            }
+            fn render(&self) -> String {
+                String::new()
+            }
+        }
+
+        let trace: Trace = "malloc 0 8\nmalloc 1 8\nfree 1\nfree 0".parse().unwrap();
+        let violation = commutes(ForwardOnly::default, &trace).unwrap_err();
+        assert_eq!(violation.op, 2);
+        assert_eq!(
+            violation.message,
+            "reversing the 2 frees from here changes the largest free block from 16 to 8"
+        );
+    }
+}
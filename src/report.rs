@@ -0,0 +1,310 @@
+use crate::maps::AddressMap;
+use crate::trace::{Event, Op, Trace};
+use crate::Allocator;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A live allocation, as the report sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveBlock {
+    pub addr: usize,
+    pub size: usize,
+    pub tag: String,
+    /// Op it was made at
+    pub born: usize,
+}
+
+/// What would most likely have kept the failing malloc from failing
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Remedy {
+    /// Enough was free, just not in one block
+    Compact,
+    /// Not even the free space and the padding together were enough,
+    /// so the heap needs at least this many more bytes
+    Grow(usize),
+    /// The padding and rounding inside allocations would have been
+    /// enough on top of the free space
+    Policy,
+}
+
+/// A drill-down into a heap a malloc failed on: the biggest gaps
+/// between live allocations, the biggest and oldest allocations,
+/// live bytes by tag, where the space that wasn't live went, and
+/// what to try about it
+#[derive(Debug, Clone)]
+pub struct Drilldown {
+    /// Ops replayed so far
+    pub now: usize,
+    /// Size of every malloc that failed, in order
+    pub failed: Vec<usize>,
+    /// Address and length of every gap between live allocations,
+    /// longest first. Padding shows up as part of a gap
+    pub holes: Vec<(usize, usize)>,
+    pub live: Vec<LiveBlock>,
+    pub free_space: usize,
+    pub largest_alloc: usize,
+    pub internal_frag: usize,
+    top: usize,
+}
+
+impl Drilldown {
+    /// Report on the heap the allocator manages in [base, base + size)
+    /// after `now` ops, with these allocations live
+    pub fn new(
+        allocator: &dyn Allocator,
+        base: usize,
+        size: usize,
+        live: Vec<LiveBlock>,
+        failed: Vec<usize>,
+        now: usize,
+    ) -> Self {
+        let map = AddressMap::new(
+            base,
+            size,
+            live.iter()
+                .map(|block| (block.addr, block.size, block.tag.clone())),
+        );
+        let mut holes: Vec<(usize, usize)> = map
+            .mappings
+            .iter()
+            .filter(|mapping| !mapping.used)
+            .map(|mapping| (mapping.start, mapping.end - mapping.start))
+            .collect();
+        holes.sort_by_key(|&(addr, len)| (usize::MAX - len, addr));
+
+        Self {
+            now,
+            failed,
+            holes,
+            live,
+            free_space: allocator.free_space(),
+            largest_alloc: allocator.largest_alloc(),
+            internal_frag: allocator.internal_frag(),
+            top: 5,
+        }
+    }
+
+    /// Report on what a replay left allocated. Allocations are
+    /// tagged with their size rounded up to a power of two
+    pub fn from_events(
+        allocator: &dyn Allocator,
+        events: &[Event],
+        base: usize,
+        size: usize,
+    ) -> Self {
+        let mut live: BTreeMap<usize, LiveBlock> = BTreeMap::new();
+        let mut failed = Vec::new();
+        for (i, event) in events.iter().enumerate() {
+            match (event.op, event.block) {
+                (Op::Malloc { id, .. } | Op::Calloc { id, .. }, Some((addr, size))) => {
+                    let tag = format!("{} bytes or less", size.next_power_of_two());
+                    live.insert(
+                        id,
+                        LiveBlock {
+                            addr,
+                            size,
+                            tag,
+                            born: i,
+                        },
+                    );
+                }
+                (Op::Malloc { .. } | Op::Calloc { .. }, None) => {
+                    failed.push(event.op.bytes().unwrap())
+                }
+                (Op::Free { id }, Some(_)) => {
+                    live.remove(&id);
+                }
+                _ => {}
+            }
+        }
+
+        let live = live.into_values().collect();
+        Self::new(allocator, base, size, live, failed, events.len())
+    }
+
+    /// Replay the trace and report on the heap as it was right after
+    /// the last malloc that failed, or None if none did. `make` builds
+    /// a fresh allocator for each of the two replays this takes
+    pub fn record<T: Allocator>(
+        make: impl Fn() -> T,
+        trace: &Trace,
+        base: usize,
+        size: usize,
+    ) -> Option<Self> {
+        let events = trace.replay(&mut make());
+        let last = events
+            .iter()
+            .rposition(|event| event.op.bytes().is_some() && event.block.is_none())?;
+
+        let mut heap = make();
+        let prefix = Trace {
+            ops: trace.ops[..=last].to_vec(),
+        };
+        let events = prefix.replay(&mut heap);
+        Some(Self::from_events(&heap, &events, base, size))
+    }
+
+    /// How many entries each list shows
+    pub fn top(mut self, top: usize) -> Self {
+        self.top = top;
+        self
+    }
+
+    /// Count and bytes of the live allocations with each tag, most
+    /// bytes first
+    pub fn by_tag(&self) -> Vec<(String, usize, usize)> {
+        let mut tags: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+        for block in self.live.iter() {
+            let (count, bytes) = tags.entry(&block.tag).or_default();
+            *count += 1;
+            *bytes += block.size;
+        }
+        let mut tags: Vec<(String, usize, usize)> = tags
+            .into_iter()
+            .map(|(tag, (count, bytes))| (tag.to_string(), count, bytes))
+            .collect();
+        tags.sort_by_key(|(_, _, bytes)| usize::MAX - bytes);
+        tags
+    }
+
+    /// What to try about the last malloc that failed, judged on the
+    /// heap as it is now. None if nothing failed
+    pub fn remedy(&self) -> Option<Remedy> {
+        let &size = self.failed.last()?;
+        Some(if self.free_space >= size {
+            Remedy::Compact
+        } else if self.free_space + self.internal_frag >= size {
+            Remedy::Policy
+        } else {
+            Remedy::Grow(size - self.free_space)
+        })
+    }
+}
+
+impl fmt::Display for Remedy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Remedy::Compact => write!(f, "compact the heap"),
+            Remedy::Grow(bytes) => write!(f, "grow the heap by at least {} bytes", bytes),
+            Remedy::Policy => write!(f, "pick a policy that pads and rounds less"),
+        }
+    }
+}
+
+/// Plain text for people, one section after another
+impl fmt::Display for Drilldown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(&last) = self.failed.last() else {
+            return writeln!(f, "No malloc failed in {} ops", self.now);
+        };
+        writeln!(
+            f,
+            "{} mallocs failed in {} ops, the last for {} bytes",
+            self.failed.len(),
+            self.now,
+            last
+        )?;
+        writeln!(
+            f,
+            "{} bytes free, the largest block {} bytes",
+            self.free_space, self.largest_alloc
+        )?;
+
+        writeln!(f, "\nLargest holes")?;
+        for &(addr, len) in self.holes.iter().take(self.top) {
+            writeln!(f, "  {:08x}-{:08x} {:>8}", addr, addr + len, len)?;
+        }
+
+        let mut live: Vec<&LiveBlock> = self.live.iter().collect();
+        live.sort_by_key(|block| (usize::MAX - block.size, block.born));
+        writeln!(f, "\nLargest live allocations")?;
+        for block in live.iter().take(self.top) {
+            writeln!(f, "  {}", Row(block, self.now))?;
+        }
+        live.sort_by_key(|block| block.born);
+        writeln!(f, "\nOldest live allocations")?;
+        for block in live.iter().take(self.top) {
+            writeln!(f, "  {}", Row(block, self.now))?;
+        }
+
+        writeln!(f, "\nLive bytes by tag")?;
+        for (tag, count, bytes) in self.by_tag().into_iter().take(self.top) {
+            writeln!(f, "  {:>8} in {:>4} allocations  {}", bytes, count, tag)?;
+        }
+
+        let live_bytes: usize = self.live.iter().map(|block| block.size).sum();
+        writeln!(f, "\nWhere the heap went")?;
+        writeln!(f, "  {:>8} live", live_bytes)?;
+        writeln!(f, "  {:>8} padding and rounding", self.internal_frag)?;
+        writeln!(f, "  {:>8} in the largest free block", self.largest_alloc)?;
+        writeln!(
+            f,
+            "  {:>8} free outside the largest block",
+            self.free_space - self.largest_alloc.min(self.free_space)
+        )?;
+
+        let remedy = self.remedy().unwrap();
+        let reason = match remedy {
+            Remedy::Compact => format!("{} bytes were free, but not in one block", self.free_space),
+            Remedy::Policy => format!(
+                "{} bytes free and {} of padding would have fit it",
+                self.free_space, self.internal_frag
+            ),
+            Remedy::Grow(_) => format!(
+                "only {} bytes were free, and {} lost to padding",
+                self.free_space, self.internal_frag
+            ),
+        };
+        writeln!(f, "\nSuggestion: {}, since {}", remedy, reason)
+    }
+}
+
+// One live allocation with its age at the time of the report
+struct Row<'a>(&'a LiveBlock, usize);
+
+impl fmt::Display for Row<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Row(block, now) = self;
+        write!(
+            f,
+            "{:08x}-{:08x} {:>8} {:>6} ops old  {}",
+            block.addr,
+            block.addr + block.size,
+            block.size,
+            now - block.born,
+            block.tag
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::Policy;
+
+    #[test]
+    fn drilldown() {
+        let trace: Trace =
+            "malloc 0 40\nmalloc 1 20\nmalloc 2 30\nfree 0\nfree 2\nmalloc 3 50\nmalloc 4 10"
+                .parse()
+                .unwrap();
+        let list = FreeList::new(0, 100, true).policy(Policy::First);
+        let report = Drilldown::record(|| list.clone(), &trace, 0, 100).unwrap();
+
+        // The report stops at the failed malloc, before the last one
+        assert_eq!(report.now, 6);
+        assert_eq!(report.failed, vec![50]);
+        assert_eq!(report.holes, vec![(0, 40), (60, 40)]);
+        assert_eq!(report.live[0].tag, "32 bytes or less");
+        // 80 bytes are free, in two blocks of 40
+        assert_eq!(report.remedy(), Some(Remedy::Compact));
+        assert!(report.to_string().contains("Suggestion: compact the heap"));
+
+        let trace: Trace = "malloc 0 60\nmalloc 1 60".parse().unwrap();
+        let report = Drilldown::record(|| list.clone(), &trace, 0, 100).unwrap();
+        assert_eq!(report.remedy(), Some(Remedy::Grow(20)));
+        let trace: Trace = "malloc 0 60".parse().unwrap();
+        assert!(Drilldown::record(|| list.clone(), &trace, 0, 100).is_none());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::freelist::FreeList;
+use crate::{AllocError, AllocFailure, Allocator, Churn, Policy};
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReservationId(usize);
+
+#[derive(Debug, Clone)]
+struct Reservation {
+    base: usize,
+    size: usize,
+    list: FreeList,
+}
+
+impl Reservation {
+    fn contains(&self, ptr: usize) -> bool {
+        self.base <= ptr && ptr < self.base + self.size
+    }
+
+    fn used(&self) -> usize {
+        self.size - self.list.free_space()
+    }
+}
+
+/// Wraps an allocator so capacity can be set aside for critical
+/// allocations. A reservation is taken from the inner allocator
+/// up front, so regular mallocs can never use it, and is then
+/// managed by its own coalescing freelist
+#[derive(Debug, Clone)]
+pub struct Reserving<T> {
+    allocator: T,
+    reservations: Vec<Option<Reservation>>,
+}
+
+impl<T: Allocator> Reserving<T> {
+    pub fn new(allocator: T) -> Self {
+        Self {
+            allocator,
+            reservations: Vec::new(),
+        }
+    }
+
+    /// Set aside `size` bytes. Returns None if the inner allocator
+    /// cannot provide them
+    pub fn reserve(&mut self, size: usize) -> Option<ReservationId> {
+        if size == 0 {
+            return None;
+        }
+        let base = self.allocator.malloc(size)?;
+        self.reservations.push(Some(Reservation {
+            base,
+            size,
+            list: FreeList::new(base, size, true).policy(Policy::First),
+        }));
+        Some(ReservationId(self.reservations.len() - 1))
+    }
+
+    /// Allocate from a reservation. Pointers returned here are
+    /// freed with the regular `free`
+    pub fn alloc_from_reservation(&mut self, id: ReservationId, size: usize) -> Option<usize> {
+        self.reservations.get_mut(id.0)?.as_mut()?.list.malloc(size)
+    }
+
+    /// Give the reservation back to the inner allocator, dropping
+    /// anything still allocated from it
+    pub fn release(&mut self, id: ReservationId) -> Result<(), AllocError> {
+        let reservation = self
+            .reservations
+            .get_mut(id.0)
+            .and_then(Option::take)
+            .ok_or(AllocError::NotFound)?;
+        self.allocator.free(reservation.base)
+    }
+
+    /// Fraction of the reservation currently allocated
+    pub fn utilization(&self, id: ReservationId) -> Option<f32> {
+        let reservation = self.reservations.get(id.0)?.as_ref()?;
+        Some(reservation.used() as f32 / reservation.size as f32)
+    }
+
+    /// Fraction of all reserved bytes currently allocated
+    pub fn total_utilization(&self) -> f32 {
+        let (used, size) = self
+            .reservations
+            .iter()
+            .flatten()
+            .fold((0, 0), |(used, size), r| (used + r.used(), size + r.size));
+        if size == 0 {
+            0.0
+        } else {
+            used as f32 / size as f32
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.allocator
+    }
+}
+
+impl<T: Allocator> Allocator for Reserving<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.allocator.malloc(size)
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.allocator.try_malloc(size)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        match self
+            .reservations
+            .iter_mut()
+            .flatten()
+            .find(|r| r.contains(ptr))
+        {
+            Some(reservation) => reservation.list.free(ptr),
+            None => self.allocator.free(ptr),
+        }
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    // Reserved bytes are not free, even if nothing was allocated
+    // from them yet
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+            + self
+                .reservations
+                .iter()
+                .flatten()
+                .map(|r| r.list.internal_frag())
+                .sum::<usize>()
+    }
+
+    fn churn(&self) -> Churn {
+        self.allocator.churn()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&self.allocator.render());
+        for (i, reservation) in self.reservations.iter().enumerate() {
+            if let Some(reservation) = reservation {
+                writeln!(out, "Reservation {}", i).unwrap();
+                out.push_str(&reservation.list.render());
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_space_is_unavailable() {
+        let mut heap = Reserving::new(FreeList::new(0, 100, true));
+        let id = heap.reserve(40).unwrap();
+        assert_eq!(heap.free_space(), 60);
+        assert!(heap.malloc(61).is_none());
+        assert!(heap.reserve(61).is_none());
+
+        let ptr = heap.alloc_from_reservation(id, 30).unwrap();
+        assert_eq!(ptr, 0);
+        assert!(heap.alloc_from_reservation(id, 20).is_none());
+        assert_eq!(heap.utilization(id), Some(0.75));
+        assert_eq!(heap.free_space(), 60);
+    }
+
+    #[test]
+    fn free_and_release() {
+        let mut heap = Reserving::new(FreeList::new(0, 100, true));
+        let id = heap.reserve(40).unwrap();
+        let reserved = heap.alloc_from_reservation(id, 10).unwrap();
+        let regular = heap.malloc(10).unwrap();
+
+        assert!(heap.free(reserved).is_ok());
+        assert_eq!(heap.utilization(id), Some(0.0));
+        assert!(heap.free(regular).is_ok());
+        assert!(heap.free(regular).is_err());
+
+        heap.alloc_from_reservation(id, 10).unwrap();
+        assert!(heap.release(id).is_ok());
+        assert_eq!(heap.release(id), Err(AllocError::NotFound));
+        assert!(heap.alloc_from_reservation(id, 10).is_none());
+        assert_eq!(heap.free_space(), 100);
+        assert_eq!(heap.total_utilization(), 0.0);
+    }
+}
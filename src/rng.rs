@@ -0,0 +1,129 @@
+use rand::rngs::SmallRng;
+use rand::{Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::fmt;
+use std::str::FromStr;
+
+/// Which random number generator seeded runs draw their ops from
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Generator {
+    /// ChaCha with 8 rounds, from rand_chacha 0.3. Its output for a
+    /// seed is fixed by the algorithm, so seeded runs give the same
+    /// numbers on every platform
+    #[default]
+    ChaCha,
+    /// rand's `SmallRng`. Faster, but rand is free to change what it
+    /// is between versions and platforms, so it is only good for
+    /// reproducing runs on the same build
+    Small,
+}
+
+impl Generator {
+    pub fn seeded(&self, seed: u64) -> SeededRng {
+        match self {
+            Generator::ChaCha => SeededRng::ChaCha(Box::new(ChaCha8Rng::seed_from_u64(seed))),
+            Generator::Small => SeededRng::Small(SmallRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+/// Parses `chacha` or `small`
+impl FromStr for Generator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chacha" => Ok(Generator::ChaCha),
+            "small" => Ok(Generator::Small),
+            _ => Err(format!("Unknown generator \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Generator::ChaCha => write!(f, "chacha"),
+            Generator::Small => write!(f, "small"),
+        }
+    }
+}
+
+/// A generator picked at run time
+#[derive(Debug, Clone)]
+pub enum SeededRng {
+    ChaCha(Box<ChaCha8Rng>),
+    Small(SmallRng),
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            SeededRng::ChaCha(rng) => rng.next_u32(),
+            SeededRng::Small(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            SeededRng::ChaCha(rng) => rng.next_u64(),
+            SeededRng::Small(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            SeededRng::ChaCha(rng) => rng.fill_bytes(dest),
+            SeededRng::Small(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        match self {
+            SeededRng::ChaCha(rng) => rng.try_fill_bytes(dest),
+            SeededRng::Small(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pinned() {
+        // ChaCha8 output is part of its specification. If this
+        // changes, every published seeded result changes with it
+        let mut rng = Generator::ChaCha.seeded(42);
+        assert_eq!(rng.next_u64(), 12578764544318200737);
+
+        let mut a = Generator::Small.seeded(7);
+        let mut b = Generator::Small.seeded(7);
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+
+    #[test]
+    fn golden_ops() {
+        // The ops a seeded random bench draws depend on rand's
+        // sampling as well as ChaCha, which is why both crates are
+        // pinned exactly. An upgrade that changes these changes
+        // every saved manifest's results
+        let trace = crate::trace::random_memory(0.5, &mut Generator::ChaCha.seeded(42));
+        let first: Vec<String> = trace.ops[..16].iter().map(|op| op.to_string()).collect();
+        assert_eq!(
+            first.join("\n"),
+            "malloc 0 98\nmalloc 1 124\nmalloc 2 73\nmalloc 3 46\n\
+             malloc 4 55\nmalloc 5 81\nmalloc 6 119\nmalloc 7 89\n\
+             malloc 8 102\nmalloc 9 49\nfree 5\nmalloc 10 34\n\
+             malloc 11 101\nmalloc 12 95\nmalloc 13 68\nmalloc 14 106"
+        );
+    }
+
+    #[test]
+    fn parse() {
+        for generator in [Generator::ChaCha, Generator::Small].iter() {
+            assert_eq!(generator.to_string().parse(), Ok(*generator));
+        }
+        assert!("std".parse::<Generator>().is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// How request sizes are rounded before they reach the allocator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rounding {
+    None,
+    /// Round up to a multiple of the alignment
+    Align(usize),
+    /// Round up to the next power of two
+    PowerOfTwo,
+    /// Round up to the smallest class in the sorted table that fits.
+    /// Sizes above the largest class are left alone
+    SizeClass(Vec<usize>),
+}
+
+impl Rounding {
+    pub fn round(&self, size: usize) -> usize {
+        match self {
+            Rounding::None => size,
+            Rounding::Align(align) if *align > 1 => match size % align {
+                0 => size,
+                left => size + align - left,
+            },
+            Rounding::Align(_) => size,
+            Rounding::PowerOfTwo => size.next_power_of_two(),
+            Rounding::SizeClass(classes) => classes
+                .iter()
+                .copied()
+                .find(|&class| class >= size)
+                .unwrap_or(size),
+        }
+    }
+}
+
+/// Parses `none`, `pow2`, `align:<n>` or `classes:<a>,<b>,...`
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let num = |word: &str| {
+            word.parse::<usize>()
+                .map_err(|_| format!("Could not parse \"{}\" in rounding", word))
+        };
+
+        match s.split_once(':') {
+            None if s == "none" => Ok(Rounding::None),
+            None if s == "pow2" => Ok(Rounding::PowerOfTwo),
+            Some(("align", align)) => Ok(Rounding::Align(num(align)?)),
+            Some(("classes", classes)) => {
+                let mut classes = classes
+                    .split(',')
+                    .map(num)
+                    .collect::<Result<Vec<usize>, String>>()?;
+                classes.sort_unstable();
+                Ok(Rounding::SizeClass(classes))
+            }
+            _ => Err(format!("Unknown rounding \"{}\"", s)),
+        }
+    }
+}
+
+/// The same format `from_str` parses
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rounding::None => write!(f, "none"),
+            Rounding::Align(align) => write!(f, "align:{}", align),
+            Rounding::PowerOfTwo => write!(f, "pow2"),
+            Rounding::SizeClass(classes) => {
+                let classes: Vec<String> = classes.iter().map(usize::to_string).collect();
+                write!(f, "classes:{}", classes.join(","))
+            }
+        }
+    }
+}
+
+/// Wraps any allocator and rounds every request with the given
+/// strategy. The bytes added by rounding are counted as internal
+/// fragmentation on top of whatever the inner allocator wastes
+#[derive(Debug, Clone)]
+pub struct Rounded<T> {
+    allocator: T,
+    rounding: Rounding,
+    waste: BTreeMap<usize, usize>,
+}
+
+impl<T: Allocator> Rounded<T> {
+    pub fn new(allocator: T, rounding: Rounding) -> Self {
+        Self {
+            allocator,
+            rounding,
+            waste: BTreeMap::new(),
+        }
+    }
+
+    /// Bytes added by rounding over all live allocations
+    pub fn rounding_waste(&self) -> usize {
+        self.waste.values().sum()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.allocator
+    }
+}
+
+impl<T: Allocator> Allocator for Rounded<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let rounded = self.rounding.round(size);
+        let ptr = self.allocator.try_malloc(rounded)?;
+        self.waste.insert(ptr, rounded - size);
+        Ok(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        self.allocator.free(ptr)?;
+        self.waste.remove(&ptr);
+        Ok(())
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.allocator.alloc_size(ptr)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.allocator.reset()?;
+        self.waste.clear();
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag() + self.rounding_waste()
+    }
+
+    fn churn(&self) -> Churn {
+        self.allocator.churn()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.allocator.live_bytes()? - self.rounding_waste())
+    }
+
+    fn render(&self) -> String {
+        self.allocator.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+
+    #[test]
+    fn round() {
+        assert_eq!(Rounding::None.round(7), 7);
+        assert_eq!(Rounding::Align(8).round(7), 8);
+        assert_eq!(Rounding::Align(8).round(16), 16);
+        assert_eq!(Rounding::PowerOfTwo.round(33), 64);
+
+        let classes = Rounding::SizeClass(vec![16, 48, 128]);
+        assert_eq!(classes.round(17), 48);
+        assert_eq!(classes.round(48), 48);
+        assert_eq!(classes.round(200), 200);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("none".parse(), Ok(Rounding::None));
+        assert_eq!("pow2".parse(), Ok(Rounding::PowerOfTwo));
+        assert_eq!("align:16".parse(), Ok(Rounding::Align(16)));
+        assert_eq!(
+            "classes:64,16".parse(),
+            Ok(Rounding::SizeClass(vec![16, 64]))
+        );
+        assert!("align:x".parse::<Rounding>().is_err());
+        assert!("round".parse::<Rounding>().is_err());
+
+        for text in ["none", "pow2", "align:16", "classes:16,64"].iter() {
+            assert_eq!(text.parse::<Rounding>().unwrap().to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn waste_is_internal_frag() {
+        let list = FreeList::new(0, 100, true).align(4);
+        let mut rounded = Rounded::new(list, Rounding::PowerOfTwo);
+
+        let ptr = rounded.malloc(5).unwrap();
+        assert_eq!(rounded.free_space(), 92);
+        assert_eq!(rounded.rounding_waste(), 3);
+        assert_eq!(rounded.internal_frag(), 3);
+        assert_eq!(rounded.live_bytes(), Some(5));
+
+        rounded.free(ptr).unwrap();
+        assert_eq!(rounded.internal_frag(), 0);
+        assert!(rounded.free(ptr).is_err());
+    }
+
+    #[test]
+    fn failure_reasons_use_rounded_size() {
+        let list = FreeList::new(0, 100, true);
+        let mut rounded = Rounded::new(list, Rounding::PowerOfTwo);
+        assert_eq!(rounded.try_malloc(65), Err(AllocFailure::Oversized));
+
+        rounded.try_malloc(64).unwrap();
+        assert_eq!(rounded.try_malloc(33), Err(AllocFailure::Exhausted));
+        assert_eq!(rounded.rounding_waste(), 0);
+    }
+}
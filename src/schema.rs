@@ -0,0 +1,192 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Version of the results files the crate writes. Each one carries
+/// it in a `schema_version` field, and the loaders read every
+/// version up to this one, so old archives stay readable as the
+/// metrics change. Version 1 is the csv printed before the field
+/// existed, and version 2 manifests were saved before the generator
+/// could be picked
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// A csv table of results, as `analyze tradeoff` and `analyze
+/// alignment` print them. The `schema_version` column is written in
+/// front of the others, and is not one of `columns`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    /// Version the table was read from. It is always written as the
+    /// current version
+    pub schema_version: u32,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(columns: &[&str]) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a row with one cell per column
+    pub fn push(&mut self, row: Vec<String>) {
+        assert_eq!(row.len(), self.columns.len(), "Row doesn't fit the table");
+        self.rows.push(row);
+    }
+
+    /// The cell of a row in the named column
+    pub fn get(&self, row: usize, column: &str) -> Option<&str> {
+        let column = self.columns.iter().position(|name| name == column)?;
+        self.rows.get(row).map(|row| row[column].as_str())
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "schema_version,{}", self.columns.join(","))?;
+        for row in self.rows.iter() {
+            writeln!(f, "{},{}", SCHEMA_VERSION, row.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a table of any version. Tables without a `schema_version`
+/// column are version 1
+impl FromStr for Table {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+        let header = lines.next().ok_or("Empty table")?;
+        let mut columns: Vec<String> = header.split(',').map(str::to_string).collect();
+        let versioned = columns[0] == "schema_version";
+        if versioned {
+            columns.remove(0);
+        }
+
+        let mut table = Self {
+            schema_version: if versioned { SCHEMA_VERSION } else { 1 },
+            columns,
+            rows: Vec::new(),
+        };
+        for (i, line) in lines.enumerate() {
+            let mut row: Vec<String> = line.split(',').map(str::to_string).collect();
+            if versioned {
+                let version = row[0]
+                    .parse::<u32>()
+                    .map_err(|_| format!("Could not parse schema version \"{}\"", row[0]))?;
+                // Version 1 had no column, so it can't be in one
+                if !(2..=SCHEMA_VERSION).contains(&version) {
+                    return Err(format!("Unknown schema version {}", version));
+                }
+                if i > 0 && version != table.schema_version {
+                    return Err(format!(
+                        "Row {} has schema version {}, not {}",
+                        i + 1,
+                        version,
+                        table.schema_version
+                    ));
+                }
+                table.schema_version = version;
+                row.remove(0);
+            }
+            if row.len() != table.columns.len() {
+                return Err(format!(
+                    "Row {} has {} cells for {} columns",
+                    i + 1,
+                    row.len(),
+                    table.columns.len()
+                ));
+            }
+            table.rows.push(row);
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What `analyze tradeoff` and `analyze alignment` printed before
+    // the tables were versioned
+    const TRADEOFF_V1: &str = "\
+min_size,max_size,allocator,malloc_fails,internal_frag,external_frag
+1,16,freelist,0,98.2,0.0124
+1,16,buddy,0,301.4,0.37
+";
+    const ALIGNMENT_V1: &str = "\
+size,align,free_space,fits,misaligned,too_small,external_frag
+64,1,20012.8,19420.8,0,592,0.41
+64,4096,20012.8,0,19420.8,592,0.41
+";
+    const ALIGNMENT_V2: &str = "\
+schema_version,size,align,free_space,fits,misaligned,too_small,external_frag
+2,64,1,20012.8,19420.8,0,592,0.41
+2,64,4096,20012.8,0,19420.8,592,0.41
+";
+
+    #[test]
+    fn tradeoff_versions() {
+        let v1: Table = TRADEOFF_V1.parse().unwrap();
+        assert_eq!(v1.schema_version, 1);
+        assert_eq!(v1.columns.len(), 6);
+        assert_eq!(v1.get(1, "allocator"), Some("buddy"));
+        assert_eq!(v1.get(0, "internal_frag"), Some("98.2"));
+        assert_eq!(v1.get(2, "allocator"), None);
+        assert_eq!(v1.get(0, "schema_version"), None);
+
+        // Saving it again writes the current version
+        let saved = v1.to_string();
+        assert!(saved.starts_with("schema_version,min_size,max_size,"));
+        assert!(saved.contains("\n3,1,16,buddy,0,301.4,0.37\n"));
+        assert_eq!(saved.parse::<Table>().unwrap().rows, v1.rows);
+
+        let mut v2 = Table::new(&[
+            "min_size",
+            "max_size",
+            "allocator",
+            "malloc_fails",
+            "internal_frag",
+            "external_frag",
+        ]);
+        for row in v1.rows.iter() {
+            v2.push(row.clone());
+        }
+        let loaded: Table = v2.to_string().parse().unwrap();
+        assert_eq!(loaded.schema_version, SCHEMA_VERSION);
+        assert_eq!(loaded, v2);
+        assert_eq!((loaded.columns, loaded.rows), (v1.columns, v1.rows));
+    }
+
+    #[test]
+    fn alignment_versions() {
+        let v1: Table = ALIGNMENT_V1.parse().unwrap();
+        assert_eq!(v1.schema_version, 1);
+        assert_eq!(v1.get(1, "align"), Some("4096"));
+        assert_eq!(v1.get(1, "misaligned"), Some("19420.8"));
+
+        let v2: Table = ALIGNMENT_V2.parse().unwrap();
+        assert_eq!(v2.schema_version, 2);
+        assert!(v2.to_string().contains("\n3,64,4096,"));
+        assert_eq!((&v2.columns, &v2.rows), (&v1.columns, &v1.rows));
+    }
+
+    #[test]
+    fn rejects() {
+        let parse = |s: &str| s.parse::<Table>();
+        assert!(parse("").is_err());
+        assert!(parse("schema_version,size\n4,64\n").is_err());
+        assert!(parse("schema_version,size\n2,64\n3,64\n").is_err());
+        assert!(parse("schema_version,size\n0,64\n").is_err());
+        // Version 1 had no column, so it can't be in one
+        assert!(parse("schema_version,size\n1,64\n").is_err());
+        assert!(parse("schema_version,size\nv2,64\n").is_err());
+        assert!(parse("schema_version,size,align\n2,64\n").is_err());
+        assert!(parse("size,align\n64,8,1\n").is_err());
+        assert_eq!(parse("schema_version,size\n").unwrap().rows.len(), 0);
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// Blocks are multiples of ALIGN bytes, so no block is smaller
+const ALIGN: usize = 8;
+
+#[derive(Debug, Copy, Clone)]
+struct Block {
+    size: usize,
+    // Bytes asked for, or None if the block is free
+    requested: Option<usize>,
+}
+
+/// Segregated free lists: one first fit list of free blocks per size
+/// class, in address order. A malloc searches the list of its own
+/// class, where blocks may still be too small. If none fits, it
+/// splits the first block of the next class up that has one, since
+/// every block there is big enough. Frees coalesce with both
+/// neighbours, and the merged block goes in the list for its new
+/// size. Free blocks looked at are counted in `Churn::scanned`, so
+/// the class boundaries can be weighed by how much search they save
+/// against how much fragmentation they cause
+#[derive(Debug, Clone)]
+pub struct SegregatedList {
+    base_addr: usize,
+    size: usize,
+    // Smallest size of every class but the first, sorted. Class i
+    // holds blocks from bounds[i - 1] up to bounds[i]
+    bounds: Vec<usize>,
+    // Free block addresses of each class
+    lists: Vec<BTreeSet<usize>>,
+    // Every block by address, free or not. Stands in for the
+    // boundary tags that find a block's neighbours in a real heap
+    blocks: BTreeMap<usize, Block>,
+    churn: Churn,
+    last_scan: (usize, usize),
+}
+
+impl SegregatedList {
+    /// Manage [base_addr, base_addr + size), with a class for every
+    /// power of two from 16 to 4096. The size is rounded down to a
+    /// multiple of 8 bytes
+    pub fn new(base_addr: usize, size: usize) -> Self {
+        let size = size - size % ALIGN;
+        if size == 0 {
+            panic!("Don't make a heap with size 0");
+        }
+        let mut list = Self {
+            base_addr,
+            size,
+            bounds: Vec::new(),
+            lists: Vec::new(),
+            blocks: BTreeMap::new(),
+            churn: Churn::default(),
+            last_scan: (0, 0),
+        };
+        list.set_bounds((4..=12).map(|shift| 1 << shift).collect());
+        list
+    }
+
+    /// Start a class at each of these sizes, in any order. Sizes
+    /// below the smallest are a class of their own, and with no
+    /// boundaries at all every block is on one first fit list
+    pub fn classes(mut self, bounds: Vec<usize>) -> Self {
+        self.set_bounds(bounds);
+        self
+    }
+
+    /// The smallest size of each class
+    pub fn bounds(&self) -> Vec<usize> {
+        let mut bounds = vec![0];
+        bounds.extend(self.bounds.iter().copied());
+        bounds
+    }
+
+    /// Which class the last malloc started searching, and how many
+    /// free blocks it looked at
+    pub fn last_scan(&self) -> (usize, usize) {
+        self.last_scan
+    }
+
+    // Sort the free blocks into lists for the new classes
+    fn set_bounds(&mut self, mut bounds: Vec<usize>) {
+        bounds.sort_unstable();
+        bounds.dedup();
+        bounds.retain(|&bound| bound > 0);
+        self.lists = vec![BTreeSet::new(); bounds.len() + 1];
+        self.bounds = bounds;
+        let free: Vec<(usize, usize)> = self.free_blocks().map(|(&a, b)| (a, b.size)).collect();
+        for (addr, size) in free {
+            let class = self.class(size);
+            self.lists[class].insert(addr);
+        }
+        if self.blocks.is_empty() {
+            self.insert(self.base_addr, self.size);
+        }
+    }
+
+    fn class(&self, size: usize) -> usize {
+        self.bounds.partition_point(|&bound| bound <= size)
+    }
+
+    // Add a free block to the list for its size
+    fn insert(&mut self, addr: usize, size: usize) {
+        let class = self.class(size);
+        self.lists[class].insert(addr);
+        self.blocks.insert(
+            addr,
+            Block {
+                size,
+                requested: None,
+            },
+        );
+    }
+
+    fn remove(&mut self, addr: usize, size: usize) {
+        let class = self.class(size);
+        self.lists[class].remove(&addr);
+        self.blocks.remove(&addr);
+    }
+
+    // First fit on the list of the request's class, then the first
+    // block of any class above it
+    fn find(&mut self, size: usize) -> Option<usize> {
+        let class = self.class(size);
+        let mut scanned = 0;
+        let mut found = None;
+        for addr in self.lists[class].iter() {
+            scanned += 1;
+            if self.blocks[addr].size >= size {
+                found = Some(*addr);
+                break;
+            }
+        }
+        if found.is_none() {
+            found = self.lists[class + 1..]
+                .iter()
+                .find_map(|list| list.iter().next().copied());
+            scanned += found.is_some() as usize;
+        }
+        self.last_scan = (class, scanned);
+        self.churn.scanned += scanned;
+        found
+    }
+
+    fn free_blocks(&self) -> impl Iterator<Item = (&usize, &Block)> {
+        self.blocks.iter().filter(|(_, b)| b.requested.is_none())
+    }
+}
+
+impl Allocator for SegregatedList {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
+        if size == 0 || size > self.size {
+            self.last_scan = (0, 0);
+            return None;
+        }
+        let adjusted = size.div_ceil(ALIGN) * ALIGN;
+        let addr = self.find(adjusted)?;
+        let block = self.blocks[&addr];
+        self.remove(addr, block.size);
+
+        // Give back whatever is left over, if it can be a block
+        let mut used = block.size;
+        if block.size - adjusted >= ALIGN {
+            self.insert(addr + adjusted, block.size - adjusted);
+            self.churn.splits += 1;
+            used = adjusted;
+        }
+        self.blocks.insert(
+            addr,
+            Block {
+                size: used,
+                requested: Some(size),
+            },
+        );
+        Some(addr)
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        if size > self.size {
+            return Err(AllocFailure::Oversized);
+        }
+        self.malloc(size).ok_or(if self.free_space() < size {
+            AllocFailure::Exhausted
+        } else {
+            AllocFailure::NoFit
+        })
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let mut size = match self.blocks.get(&ptr) {
+            Some(Block {
+                size,
+                requested: Some(_),
+            }) => *size,
+            _ => return Err(AllocError::InvalidPointer(ptr)),
+        };
+        self.blocks.remove(&ptr);
+        let mut addr = ptr;
+
+        // Merge with the free blocks right after and right before
+        if let Some(&next) = self.blocks.get(&(addr + size)) {
+            if next.requested.is_none() {
+                self.remove(addr + size, next.size);
+                size += next.size;
+                self.churn.merges += 1;
+            }
+        }
+        if let Some((&prev_addr, &prev)) = self.blocks.range(..addr).next_back() {
+            if prev.requested.is_none() && prev_addr + prev.size == addr {
+                self.remove(prev_addr, prev.size);
+                addr = prev_addr;
+                size += prev.size;
+                self.churn.merges += 1;
+            }
+        }
+
+        self.insert(addr, size);
+        Ok(())
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        match self.blocks.get(&ptr) {
+            Some(Block {
+                size,
+                requested: Some(_),
+            }) => Some(*size),
+            _ => None,
+        }
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.lists.iter_mut().for_each(BTreeSet::clear);
+        self.blocks.clear();
+        self.churn = Churn::default();
+        self.last_scan = (0, 0);
+        self.insert(self.base_addr, self.size);
+        Ok(())
+    }
+
+    // A malloc looks at every block of its class before going up, so
+    // it finds the largest free block whatever its class
+    fn largest_alloc(&self) -> usize {
+        self.free_blocks().map(|(_, b)| b.size).max().unwrap_or(0)
+    }
+
+    fn free_space(&self) -> usize {
+        self.free_blocks().map(|(_, b)| b.size).sum()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.blocks
+            .values()
+            .filter_map(|b| b.requested.map(|requested| b.size - requested))
+            .sum()
+    }
+
+    fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.blocks.values().filter_map(|b| b.requested).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "Heap [{}, {}), classes from {:?}",
+            self.base_addr,
+            self.base_addr + self.size,
+            self.bounds()
+        )
+        .unwrap();
+        for (start, list) in self.bounds().into_iter().zip(self.lists.iter()) {
+            if !list.is_empty() {
+                let blocks: Vec<String> = list
+                    .iter()
+                    .map(|addr| format!("{}+{}", addr, self.blocks[addr].size))
+                    .collect();
+                writeln!(out, "Class from {}: {}", start, blocks.join(" ")).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes() {
+        let list = SegregatedList::new(0, 1024).classes(vec![64, 16, 64, 0]);
+        assert_eq!(list.bounds(), vec![0, 16, 64]);
+        assert_eq!(list.class(8), 0);
+        assert_eq!(list.class(16), 1);
+        assert_eq!(list.class(63), 1);
+        assert_eq!(list.class(1024), 2);
+        assert_eq!(list.lists[2].len(), 1);
+
+        let single = SegregatedList::new(0, 1024).classes(Vec::new());
+        assert_eq!(single.bounds(), vec![0]);
+        assert_eq!(single.lists.len(), 1);
+    }
+
+    #[test]
+    fn malloc_free() {
+        let mut list = SegregatedList::new(0, 1024).classes(vec![32, 128]);
+        assert_eq!(list.malloc(10), Some(0));
+        assert_eq!(list.malloc(100), Some(16));
+        assert_eq!(list.malloc(8), Some(120));
+        assert_eq!(list.internal_frag(), 10);
+        assert_eq!(list.live_bytes(), Some(118));
+        assert_eq!(list.free_space(), 1024 - 128);
+
+        assert!(list.free(16).is_ok());
+        assert!(list.free(16).is_err());
+        assert!(list.free(17).is_err());
+        // The freed 104 bytes are in the middle class, which a
+        // request of 40 searches first
+        assert_eq!(list.malloc(40), Some(16));
+        assert_eq!(list.last_scan(), (1, 1));
+        assert_eq!(list.lists[1].iter().copied().collect::<Vec<_>>(), vec![56]);
+
+        assert!(list.free(0).is_ok());
+        assert!(list.free(120).is_ok());
+        assert!(list.free(16).is_ok());
+        assert_eq!(list.free_space(), 1024);
+        assert_eq!(list.largest_alloc(), 1024);
+        assert_eq!(list.blocks.len(), 1);
+
+        let churn = list.churn();
+        assert_eq!((churn.mallocs, churn.splits), (4, 4));
+        assert_eq!(churn.merges, 4);
+
+        list.malloc(8).unwrap();
+        assert!(list.reset().is_ok());
+        assert_eq!(list.churn(), Churn::default());
+        assert_eq!(list.free_space(), 1024);
+    }
+
+    #[test]
+    fn splits_larger_classes() {
+        // Free blocks of 24, 48 and 96 bytes, each kept apart by a
+        // live block, in classes from 0, 32 and 64
+        let mut list = SegregatedList::new(0, 256).classes(vec![32, 64]);
+        let ptrs: Vec<usize> = [24, 8, 48, 8, 96, 8]
+            .iter()
+            .map(|&size| list.malloc(size).unwrap())
+            .collect();
+        list.malloc(256 - 192).unwrap();
+        for &ptr in ptrs.iter().step_by(2) {
+            list.free(ptr).unwrap();
+        }
+        assert_eq!(list.free_space(), 168);
+
+        // 16 bytes fit in the 24 byte block of its own class
+        assert_eq!(list.malloc(16), Some(0));
+        assert_eq!(list.last_scan(), (0, 1));
+        // Nothing of 24 bytes is left in the first class, so the 48
+        // byte block is split
+        assert_eq!(list.malloc(24), Some(32));
+        assert_eq!(list.last_scan(), (0, 2));
+        assert_eq!(list.alloc_size(32), Some(24));
+        // The rest of it went down to the first class
+        assert_eq!(list.malloc(24), Some(56));
+        assert_eq!(list.last_scan(), (0, 2));
+        assert_eq!(list.try_malloc(100), Err(AllocFailure::NoFit));
+        assert_eq!(list.try_malloc(512), Err(AllocFailure::Oversized));
+        assert_eq!(list.largest_alloc(), 96);
+    }
+}
@@ -0,0 +1,250 @@
+use std::ops::Range;
+
+// What a node knows about its granules: how many bytes of them are
+// free, and the runs of wholly free granules at its start, at its
+// end, and anywhere in it, in granules
+#[derive(Debug, Copy, Clone, Default)]
+struct Node {
+    start: usize,
+    len: usize,
+    free: usize,
+    prefix: usize,
+    suffix: usize,
+    best: usize,
+    best_at: usize,
+}
+
+impl Node {
+    fn leaf(i: usize, free: usize, granule: usize) -> Self {
+        let whole = (free == granule) as usize;
+        Self {
+            start: i,
+            len: 1,
+            free,
+            prefix: whole,
+            suffix: whole,
+            best: whole,
+            best_at: i,
+        }
+    }
+
+    fn join(left: Node, right: Node) -> Self {
+        let prefix = if left.prefix == left.len {
+            left.len + right.prefix
+        } else {
+            left.prefix
+        };
+        let suffix = if right.suffix == right.len {
+            right.len + left.suffix
+        } else {
+            right.suffix
+        };
+
+        // Ties go to the lowest address
+        let across = left.suffix + right.prefix;
+        let (best, best_at) = if left.best >= across && left.best >= right.best {
+            (left.best, left.best_at)
+        } else if across >= right.best {
+            (across, left.start + left.len - left.suffix)
+        } else {
+            (right.best, right.best_at)
+        };
+
+        Self {
+            start: left.start,
+            len: left.len + right.len,
+            free: left.free + right.free,
+            prefix,
+            suffix,
+            best,
+            best_at,
+        }
+    }
+}
+
+/// A segment tree over a heap cut into fixed size granules, kept
+/// alongside an allocator to answer range queries in O(log n): how
+/// many bytes are free in part of the heap, and where its largest
+/// run of free space is. Free space is tracked to the byte, but
+/// only wholly free granules count towards a run, and query ranges
+/// are rounded out to whole granules
+#[derive(Debug, Clone)]
+pub struct FreeIndex {
+    base: usize,
+    granule: usize,
+    granules: usize,
+    // Leaves from `width` on, padded to a power of two with empty
+    // nodes, and node `i` over nodes `2i` and `2i + 1`
+    nodes: Vec<Node>,
+    width: usize,
+}
+
+impl FreeIndex {
+    /// Index [base, base + size) with everything free. The size has
+    /// to be a multiple of the granule
+    pub fn new(base: usize, size: usize, granule: usize) -> Self {
+        if granule == 0 || !size.is_multiple_of(granule) {
+            panic!("Heap size has to be a multiple of the granule");
+        }
+        let granules = size / granule;
+        let width = granules.next_power_of_two();
+        let mut index = Self {
+            base,
+            granule,
+            granules,
+            nodes: vec![Node::default(); 2 * width],
+            width,
+        };
+        for i in 0..width {
+            index.nodes[width + i] = if i < granules {
+                Node::leaf(i, granule, granule)
+            } else {
+                Node {
+                    start: i,
+                    ..Node::default()
+                }
+            };
+        }
+        for i in (1..width).rev() {
+            index.nodes[i] = Node::join(index.nodes[2 * i], index.nodes[2 * i + 1]);
+        }
+        index
+    }
+
+    /// Index [base, base + size) with only the given ranges, as
+    /// address and length, free
+    pub fn from_free(
+        base: usize,
+        size: usize,
+        granule: usize,
+        free: impl IntoIterator<Item = (usize, usize)>,
+    ) -> Self {
+        let mut index = Self::new(base, size, granule);
+        index.set_used(base, size);
+        for (addr, len) in free {
+            index.set_free(addr, len);
+        }
+        index
+    }
+
+    /// Mark [addr, addr + len) free. It has to be in use
+    pub fn set_free(&mut self, addr: usize, len: usize) {
+        self.update(addr, len, |free, bytes| free + bytes);
+    }
+
+    /// Mark [addr, addr + len) in use. It has to be free
+    pub fn set_used(&mut self, addr: usize, len: usize) {
+        self.update(addr, len, |free, bytes| free - bytes);
+    }
+
+    /// Free bytes in the granules [range.start, range.end) touches
+    pub fn free_bytes(&self, range: Range<usize>) -> usize {
+        self.query(range).map_or(0, |node| node.free)
+    }
+
+    /// Address and length of the longest run of wholly free granules
+    /// among those [range.start, range.end) touches, the lowest if
+    /// there is a tie. None if no granule in it is wholly free
+    pub fn largest_free(&self, range: Range<usize>) -> Option<(usize, usize)> {
+        self.query(range).filter(|node| node.best > 0).map(|node| {
+            (
+                self.base + node.best_at * self.granule,
+                node.best * self.granule,
+            )
+        })
+    }
+
+    // Change the free bytes of every granule the range overlaps,
+    // then every node above them
+    fn update(&mut self, addr: usize, len: usize, change: impl Fn(usize, usize) -> usize) {
+        let (start, end) = (addr - self.base, addr - self.base + len);
+        if end > self.granules * self.granule {
+            panic!("[{}, {}) is outside the index", addr, addr + len);
+        }
+        for i in start / self.granule..end.div_ceil(self.granule) {
+            let overlap = end.min((i + 1) * self.granule) - start.max(i * self.granule);
+            let free = change(self.nodes[self.width + i].free, overlap);
+            self.nodes[self.width + i] = Node::leaf(i, free, self.granule);
+
+            let mut node = (self.width + i) / 2;
+            while node > 0 {
+                self.nodes[node] = Node::join(self.nodes[2 * node], self.nodes[2 * node + 1]);
+                node /= 2;
+            }
+        }
+    }
+
+    fn query(&self, range: Range<usize>) -> Option<Node> {
+        let start = range.start.max(self.base) - self.base;
+        let end = (range.end.max(self.base) - self.base).min(self.granules * self.granule);
+        if start >= end {
+            return None;
+        }
+        self.visit(
+            1,
+            0,
+            self.width,
+            start / self.granule,
+            end.div_ceil(self.granule),
+        )
+    }
+
+    // Join what node `node`, over granules [lo, hi), knows about the
+    // granules [start, end)
+    fn visit(&self, node: usize, lo: usize, hi: usize, start: usize, end: usize) -> Option<Node> {
+        if end <= lo || hi <= start {
+            return None;
+        }
+        if start <= lo && hi <= end {
+            return Some(self.nodes[node]);
+        }
+        let mid = (lo + hi) / 2;
+        match (
+            self.visit(2 * node, lo, mid, start, end),
+            self.visit(2 * node + 1, mid, hi, start, end),
+        ) {
+            (Some(left), Some(right)) => Some(Node::join(left, right)),
+            (left, right) => left.or(right),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::{Allocator, Policy};
+
+    #[test]
+    fn queries() {
+        let mut index = FreeIndex::new(1000, 64, 8);
+        index.set_used(1008, 8);
+        index.set_used(1040, 4);
+        assert_eq!(index.free_bytes(0..2000), 52);
+        assert_eq!(index.largest_free(1000..1064), Some((1016, 24)));
+        assert_eq!(index.largest_free(1000..1016), Some((1000, 8)));
+        // Rounded out to [1032, 1048), where half a granule is used
+        assert_eq!(index.free_bytes(1036..1044), 12);
+        assert_eq!(index.largest_free(1040..1048), None);
+
+        index.set_free(1008, 8);
+        assert_eq!(index.largest_free(1000..1064), Some((1000, 40)));
+        index.set_free(1040, 4);
+        assert_eq!(index.largest_free(1000..1064), Some((1000, 64)));
+        assert_eq!(index.free_bytes(1064..2000), 0);
+    }
+
+    #[test]
+    fn from_freelist() {
+        let mut list = FreeList::new(0, 100, true).policy(Policy::First);
+        let ptrs: Vec<usize> = (0..5).map(|_| list.malloc(20).unwrap()).collect();
+        list.free(ptrs[1]).unwrap();
+        list.free(ptrs[3]).unwrap();
+
+        let index = list.free_index(4);
+        assert_eq!(index.free_bytes(0..100), list.free_space());
+        assert_eq!(index.free_bytes(0..50), 20);
+        assert_eq!(index.largest_free(50..100), Some((60, 20)));
+        assert_eq!(index.largest_free(0..100), Some((20, 20)));
+    }
+}
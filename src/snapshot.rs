@@ -0,0 +1,177 @@
+use crate::buddy::BuddyAllocator;
+use crate::freelist::FreeList;
+use crate::trace::Trace;
+use crate::Allocator;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// A free or allocated block of a heap
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Block {
+    pub addr: usize,
+    pub size: usize,
+    pub used: bool,
+}
+
+/// An allocator that can list every block of its heap, free or not
+pub trait Layout {
+    fn blocks(&self) -> Vec<Block>;
+}
+
+impl Layout for FreeList {
+    fn blocks(&self) -> Vec<Block> {
+        both(self.free_blocks(), self.allocated_blocks())
+    }
+}
+
+impl Layout for BuddyAllocator {
+    fn blocks(&self) -> Vec<Block> {
+        both(self.free_blocks(), self.allocated_blocks())
+    }
+}
+
+// Free and allocated blocks, given as address and size, together
+fn both(
+    free: impl Iterator<Item = (usize, usize)>,
+    allocated: impl Iterator<Item = (usize, usize)>,
+) -> Vec<Block> {
+    let block = |used| move |(addr, size)| Block { addr, size, used };
+    free.map(block(false))
+        .chain(allocated.map(block(true)))
+        .collect()
+}
+
+/// How a block differs between two snapshots
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Only the later snapshot has it
+    Added(Block),
+    /// Only the earlier snapshot has it
+    Removed(Block),
+}
+
+impl Change {
+    pub fn block(&self) -> Block {
+        match *self {
+            Change::Added(block) | Change::Removed(block) => block,
+        }
+    }
+}
+
+/// Every block of a heap at one point in time, in address order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeapSnapshot {
+    /// Ops run before it was taken
+    pub op: usize,
+    pub blocks: Vec<Block>,
+}
+
+impl HeapSnapshot {
+    pub fn take(allocator: &impl Layout, op: usize) -> Self {
+        let mut blocks = allocator.blocks();
+        blocks.sort_unstable();
+        Self { op, blocks }
+    }
+
+    /// The blocks that are in only one of the two snapshots, in
+    /// address order. A block that went from free to allocated shows
+    /// up as removed and added, and so do the two halves of a split
+    /// and the block they came from
+    pub fn diff(&self, later: &HeapSnapshot) -> Vec<Change> {
+        let before: BTreeSet<Block> = self.blocks.iter().copied().collect();
+        let after: BTreeSet<Block> = later.blocks.iter().copied().collect();
+        let mut changes: Vec<Change> = before
+            .difference(&after)
+            .map(|&block| Change::Removed(block))
+            .chain(after.difference(&before).map(|&block| Change::Added(block)))
+            .collect();
+        changes.sort_by_key(|change| (change.block().addr, matches!(change, Change::Added(_))));
+        changes
+    }
+}
+
+/// Replay the trace and snapshot the heap before the first op and
+/// after every `every` ops, and after the last one
+pub fn every<T: Allocator + Layout>(
+    allocator: &mut T,
+    trace: &Trace,
+    every: usize,
+) -> Vec<HeapSnapshot> {
+    let every = every.max(1);
+    let mut snapshots = vec![HeapSnapshot::take(allocator, 0)];
+    trace.replay_inspect(allocator, |op, heap| {
+        if op % every == 0 || op == trace.len() {
+            snapshots.push(HeapSnapshot::take(heap, op));
+        }
+    });
+    snapshots
+}
+
+/// Like a line of a diff, `+` or `-`, then the block as `analyze
+/// maps` shows it
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let block = self.block();
+        let sign = if matches!(self, Change::Added(_)) {
+            '+'
+        } else {
+            '-'
+        };
+        write!(
+            f,
+            "{} {:08x}-{:08x} {} {:>8}",
+            sign,
+            block.addr,
+            block.addr + block.size,
+            if block.used { "rw-p" } else { "---p" },
+            block.size
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Policy;
+
+    #[test]
+    fn diff() {
+        let trace: Trace = "malloc 0 20\nmalloc 1 30\nfree 0\nmalloc 2 10"
+            .parse()
+            .unwrap();
+        let mut list = FreeList::new(0, 100, true).policy(Policy::First);
+        let snapshots = every(&mut list, &trace, 3);
+
+        let ops: Vec<usize> = snapshots.iter().map(|snapshot| snapshot.op).collect();
+        assert_eq!(ops, vec![0, 3, 4]);
+        let used = |addr, size| Block {
+            addr,
+            size,
+            used: true,
+        };
+        let free = |addr, size| Block {
+            addr,
+            size,
+            used: false,
+        };
+        assert_eq!(
+            snapshots[1].blocks,
+            vec![free(0, 20), used(20, 30), free(50, 50)]
+        );
+
+        // The malloc splits the first hole
+        assert_eq!(
+            snapshots[1].diff(&snapshots[2]),
+            vec![
+                Change::Removed(free(0, 20)),
+                Change::Added(used(0, 10)),
+                Change::Added(free(10, 10)),
+            ]
+        );
+        assert!(snapshots[2].diff(&snapshots[2]).is_empty());
+        assert_eq!(
+            Change::Added(used(0, 10)).to_string(),
+            "+ 00000000-0000000a rw-p       10"
+        );
+    }
+}
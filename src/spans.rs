@@ -0,0 +1,291 @@
+use crate::buddy::BuddyAllocator;
+use crate::freelist::FreeList;
+use crate::{AllocError, Allocator, Churn, Policy, Recycling, Retention};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Fragmentation in one layer of an allocator
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct LevelFrag {
+    /// Padding and rounding inside what the layer handed out
+    pub internal: usize,
+    /// Bytes the layer has free
+    pub free: usize,
+    /// Of those, the bytes outside the layer's largest free block
+    pub external: usize,
+}
+
+impl LevelFrag {
+    fn of(allocator: &impl Allocator) -> Self {
+        Self {
+            internal: allocator.internal_frag(),
+            free: allocator.free_space(),
+            external: allocator.free_space() - allocator.largest_alloc(),
+        }
+    }
+}
+
+/// Two levels, like the page heap and the small object heap of
+/// tcmalloc. A buddy allocator hands out spans of `span` bytes, and
+/// a first fit free list manages the allocations inside each span.
+/// A malloc tries every span in address order before taking a new
+/// one, and a span goes back to the buddy allocator once it is
+/// empty, unless the retention policy keeps it. Requests bigger than
+/// a quarter of a span skip the spans and go straight to the buddy
+/// allocator
+#[derive(Debug, Clone)]
+pub struct SpanHeap {
+    buddy: BuddyAllocator,
+    span: usize,
+    spans: BTreeMap<usize, FreeList>,
+    // Requested size of each live allocation
+    live: BTreeMap<usize, usize>,
+    mallocs: usize,
+    // Splits and merges of spans already given back
+    retired: Churn,
+    retention: Retention,
+    // Empty spans kept for later mallocs
+    empty: BTreeSet<usize>,
+    pub recycling: Recycling,
+}
+
+impl SpanHeap {
+    /// `span` has to be a power of two the buddy allocator can hand out
+    pub fn new(buddy: BuddyAllocator, span: usize) -> Self {
+        if !span.is_power_of_two() || span > buddy.size() {
+            panic!("Spans have to be a power of two no bigger than the heap");
+        }
+
+        Self {
+            buddy,
+            span,
+            spans: BTreeMap::new(),
+            live: BTreeMap::new(),
+            mallocs: 0,
+            retired: Churn::default(),
+            retention: Retention::Release,
+            empty: BTreeSet::new(),
+            recycling: Recycling::default(),
+        }
+    }
+
+    /// What to do with spans once they are empty. Releases them by
+    /// default
+    pub fn retain(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Bytes held in empty spans
+    pub fn retained(&self) -> usize {
+        self.empty.len() * self.span
+    }
+
+    /// Spans taken from the buddy allocator, empty ones included
+    pub fn spans(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Fragmentation of the buddy allocator, where a span counts as
+    /// one allocation, and of the free lists in the spans together
+    pub fn levels(&self) -> [(&'static str, LevelFrag); 2] {
+        let spans =
+            self.spans
+                .values()
+                .map(LevelFrag::of)
+                .fold(LevelFrag::default(), |sum, list| LevelFrag {
+                    internal: sum.internal + list.internal,
+                    free: sum.free + list.free,
+                    external: sum.external + list.external,
+                });
+        [("buddy", LevelFrag::of(&self.buddy)), ("spans", spans)]
+    }
+
+    // The span a pointer is in, if it isn't a large allocation
+    fn span_of(&self, ptr: usize) -> Option<usize> {
+        self.spans
+            .range(..=ptr)
+            .next_back()
+            .map(|(&addr, _)| addr)
+            .filter(|&addr| ptr < addr + self.span)
+    }
+}
+
+impl Allocator for SpanHeap {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.mallocs += 1;
+        if size == 0 {
+            return None;
+        }
+
+        let ptr = if size > self.span / 4 {
+            self.buddy.malloc(size)?
+        } else {
+            let found = self
+                .spans
+                .iter_mut()
+                .find_map(|(&addr, list)| list.malloc(size).map(|ptr| (addr, ptr)));
+            match found {
+                Some((addr, ptr)) => {
+                    if self.empty.remove(&addr) {
+                        self.recycling.reused += 1;
+                    }
+                    ptr
+                }
+                None => {
+                    let addr = self.buddy.malloc(self.span)?;
+                    let mut list = FreeList::new(addr, self.span, true).policy(Policy::First);
+                    let ptr = list.malloc(size)?;
+                    self.spans.insert(addr, list);
+                    self.recycling.taken += 1;
+                    ptr
+                }
+            }
+        };
+        self.live.insert(ptr, size);
+        Some(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        self.live
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+        let Some(addr) = self.span_of(ptr) else {
+            return self.buddy.free(ptr);
+        };
+
+        let list = self.spans.get_mut(&addr).unwrap();
+        list.free(ptr)?;
+        if list.free_space() == self.span {
+            if self.recycling.empty(self.retention, self.empty.len()) {
+                self.empty.insert(addr);
+                self.recycling.peak_retained = self.recycling.peak_retained.max(self.retained());
+                return Ok(());
+            }
+            let churn = self.spans.remove(&addr).unwrap().churn();
+            self.retired.splits += churn.splits;
+            self.retired.merges += churn.merges;
+            return self.buddy.free(addr);
+        }
+        Ok(())
+    }
+
+    /// The largest block the buddy allocator or any span could hand
+    /// out
+    fn largest_alloc(&self) -> usize {
+        let span = self
+            .spans
+            .values()
+            .map(|list| list.largest_alloc())
+            .max()
+            .unwrap_or(0);
+        self.buddy.largest_alloc().max(span)
+    }
+
+    fn free_space(&self) -> usize {
+        let [(_, buddy), (_, spans)] = self.levels();
+        buddy.free + spans.free
+    }
+
+    /// What the buddy allocator rounds large allocations up by, and
+    /// the padding inside the spans
+    fn internal_frag(&self) -> usize {
+        let [(_, buddy), (_, spans)] = self.levels();
+        buddy.internal + spans.internal
+    }
+
+    /// Splits and merges of both levels
+    fn churn(&self) -> Churn {
+        let mut churn = self.buddy.churn();
+        churn.splits += self.retired.splits;
+        churn.merges += self.retired.merges;
+        for list in self.spans.values() {
+            let span = list.churn();
+            churn.splits += span.splits;
+            churn.merges += span.merges;
+        }
+        churn.mallocs = self.mallocs;
+        churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.live.values().sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (&addr, list) in self.spans.iter() {
+            writeln!(
+                out,
+                "span {}: {} bytes free, largest {}",
+                addr,
+                list.free_space(),
+                list.largest_alloc()
+            )
+            .unwrap();
+        }
+        writeln!(out, "Buddy allocator:").unwrap();
+        out.push_str(&self.buddy.render());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans() {
+        let mut heap = SpanHeap::new(BuddyAllocator::new(5, 12), 1024);
+        let a = heap.malloc(100).unwrap();
+        let b = heap.malloc(200).unwrap();
+        assert_eq!((a, b), (0, 100));
+        // Too big for a span, so the buddy allocator rounds it up
+        let c = heap.malloc(300).unwrap();
+        assert_eq!(c, 1024);
+        assert_eq!(heap.spans(), 1);
+
+        let [(_, buddy), (_, spans)] = heap.levels();
+        assert_eq!(buddy.internal, 212);
+        assert_eq!(buddy.free, 4096 - 1024 - 512);
+        assert_eq!(spans.free, 1024 - 300);
+        assert_eq!(heap.internal_frag(), 212);
+
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        heap.free(b).unwrap();
+        // The span was empty, so it went back
+        assert_eq!(heap.recycling.returned, 1);
+        assert_eq!(heap.spans(), 0);
+        heap.free(c).unwrap();
+        assert_eq!(heap.free_space(), 4096);
+    }
+
+    #[test]
+    fn retention() {
+        let mut heap = SpanHeap::new(BuddyAllocator::new(5, 12), 512).retain(Retention::Keep(1));
+        let ptrs: Vec<usize> = (0..6).map(|_| heap.malloc(100).unwrap()).collect();
+        assert_eq!(heap.spans(), 2);
+        for &ptr in ptrs.iter() {
+            heap.free(ptr).unwrap();
+        }
+        // The first span to empty is kept, and the second goes back
+        assert_eq!(heap.spans(), 1);
+        assert_eq!(heap.retained(), 512);
+        assert_eq!(heap.free_space(), 4096);
+
+        assert_eq!(heap.malloc(100), Some(0));
+        assert_eq!(heap.retained(), 0);
+        assert_eq!(
+            heap.recycling,
+            Recycling {
+                taken: 2,
+                emptied: 2,
+                returned: 1,
+                retained: 1,
+                reused: 1,
+                peak_retained: 512,
+            }
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use crate::wcet::WorstCase;
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::fmt::Write;
+
+/// A stack allocator, like the ones games use for per-frame or
+/// per-level memory. Mallocs bump the top of the stack, and only the
+/// allocation on top can be freed. Freeing anything else fails with
+/// an error saying which allocation is on top, and leaves the stack
+/// as it was, so how often frees fail shows how far a workload
+/// strays from last in, first out
+#[derive(Debug, Clone)]
+pub struct StackAllocator {
+    base: usize,
+    size: usize,
+    // Address and requested size of each live allocation, bottom
+    // of the stack first
+    stack: Vec<(usize, usize)>,
+    mallocs: usize,
+    /// Frees of a live allocation that wasn't on top
+    pub violations: usize,
+}
+
+impl StackAllocator {
+    pub fn new(base: usize, size: usize) -> Self {
+        Self {
+            base,
+            size,
+            stack: Vec::new(),
+            mallocs: 0,
+            violations: 0,
+        }
+    }
+
+    fn top(&self) -> usize {
+        self.stack
+            .last()
+            .map_or(self.base, |&(ptr, size)| ptr + size)
+    }
+}
+
+impl Allocator for StackAllocator {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        self.mallocs += 1;
+        if size == 0 {
+            return Err(AllocFailure::NoFit);
+        }
+        if size > self.size {
+            return Err(AllocFailure::Oversized);
+        }
+        let ptr = self.top();
+        if ptr + size > self.base + self.size {
+            return Err(AllocFailure::Exhausted);
+        }
+        self.stack.push((ptr, size));
+        Ok(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        match self.stack.last() {
+            Some(&(top, _)) if top == ptr => {
+                self.stack.pop();
+                Ok(())
+            }
+            Some(&(top, _)) if self.stack.iter().any(|&(live, _)| live == ptr) => {
+                self.violations += 1;
+                let depth = self.stack.iter().rev().position(|&(live, _)| live == ptr);
+                Err(AllocError::OutOfOrder {
+                    ptr,
+                    depth: depth.unwrap(),
+                    top,
+                })
+            }
+            _ => Err(AllocError::InvalidPointer(ptr)),
+        }
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.stack.clear();
+        self.mallocs = 0;
+        self.violations = 0;
+        Ok(())
+    }
+
+    /// Everything above the top of the stack
+    fn largest_alloc(&self) -> usize {
+        self.base + self.size - self.top()
+    }
+
+    fn free_space(&self) -> usize {
+        self.largest_alloc()
+    }
+
+    /// Nothing is padded
+    fn internal_frag(&self) -> usize {
+        0
+    }
+
+    fn churn(&self) -> Churn {
+        Churn {
+            mallocs: self.mallocs,
+            ..Churn::default()
+        }
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.stack.iter().map(|&(_, size)| size).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for &(ptr, size) in self.stack.iter().rev() {
+            writeln!(out, "[{}, {})", ptr, ptr + size).unwrap();
+        }
+        writeln!(out, "{} bytes free above the top", self.free_space()).unwrap();
+        out
+    }
+}
+
+/// Bumping and popping the top of the stack is the same work every
+/// time
+impl WorstCase for StackAllocator {
+    fn worst_op(&self) -> Option<Churn> {
+        Some(Churn::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifo() {
+        let mut heap = StackAllocator::new(0, 100);
+        let a = heap.malloc(30).unwrap();
+        let b = heap.malloc(30).unwrap();
+        let c = heap.malloc(30).unwrap();
+        assert_eq!((a, b, c), (0, 30, 60));
+        assert_eq!(heap.try_malloc(20), Err(AllocFailure::Exhausted));
+
+        assert_eq!(
+            heap.free(a).unwrap_err().to_string(),
+            "Pointer 0 is 2 below the top of the stack, only 60 can be freed"
+        );
+        assert_eq!(heap.free(5), Err(AllocError::InvalidPointer(5)));
+        assert_eq!(heap.violations, 1);
+        heap.free(c).unwrap();
+        heap.free(b).unwrap();
+        assert_eq!(heap.free_space(), 70);
+        assert_eq!(heap.malloc(50), Some(30));
+        assert_eq!(heap.live_bytes(), Some(80));
+    }
+}
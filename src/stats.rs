@@ -0,0 +1,324 @@
+use crate::metrics::Metric;
+use crate::workloads::{Pressure, Results, SizeClass};
+use crate::AllocFailure;
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+
+/// The values of one metric over several runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    sorted: Vec<f32>,
+}
+
+impl Summary {
+    /// A NaN, say from a ratio over a run with nothing to divide
+    /// by, sorts above every number instead of panicking
+    pub fn new(mut values: Vec<f32>) -> Self {
+        values.sort_by(f32::total_cmp);
+        Self { sorted: values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sorted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sorted.is_empty()
+    }
+
+    /// 0 if there are no values
+    pub fn mean(&self) -> f32 {
+        self.sorted.iter().sum::<f32>() / self.len().max(1) as f32
+    }
+
+    /// Sample standard deviation, 0 with fewer than two values
+    pub fn stddev(&self) -> f32 {
+        if self.len() < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let squares: f32 = self.sorted.iter().map(|x| (x - mean) * (x - mean)).sum();
+        (squares / (self.len() - 1) as f32).sqrt()
+    }
+
+    pub fn min(&self) -> Option<f32> {
+        self.sorted.first().copied()
+    }
+
+    pub fn max(&self) -> Option<f32> {
+        self.sorted.last().copied()
+    }
+
+    /// Nearest rank percentile, with `p` between 0 and 100
+    pub fn percentile(&self, p: f32) -> Option<f32> {
+        if self.is_empty() {
+            return None;
+        }
+        let rank = (p / 100.0 * self.len() as f32).ceil() as usize;
+        Some(self.sorted[rank.clamp(1, self.len()) - 1])
+    }
+}
+
+/// The results of several runs of the same workload, so every
+/// report summarizes them the same way
+#[derive(Debug, Clone, Default)]
+pub struct Aggregate {
+    pub runs: Vec<Results>,
+}
+
+impl Aggregate {
+    pub fn new(runs: Vec<Results>) -> Self {
+        Self { runs }
+    }
+
+    pub fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// One value per run, in the order the runs were made
+    pub fn series(&self, metric: impl Fn(&Results) -> f32) -> Vec<f32> {
+        self.runs.iter().map(metric).collect()
+    }
+
+    pub fn summary(&self, metric: impl Fn(&Results) -> f32) -> Summary {
+        Summary::new(self.series(metric))
+    }
+
+    pub fn mean(&self, metric: impl Fn(&Results) -> f32) -> f32 {
+        self.summary(metric).mean()
+    }
+
+    /// Malloc failures with this reason in each run
+    pub fn failures(&self, reason: AllocFailure) -> Summary {
+        self.summary(|r| match reason {
+            AllocFailure::Oversized => r.failures.oversized as f32,
+            AllocFailure::NoFit => r.failures.no_fit as f32,
+            AllocFailure::Exhausted => r.failures.exhausted as f32,
+        })
+    }
+
+    /// A collector's value in every run that reported one
+    pub fn metric(&self, name: &str) -> Summary {
+        Summary::new(
+            self.runs
+                .iter()
+                .filter_map(|r| match r.metric(name) {
+                    Some(Metric::Value(value)) => Some(*value),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// A collector's series averaged point by point, over the runs
+    /// long enough to reach each point
+    pub fn mean_series(&self, name: &str) -> Vec<f32> {
+        mean_series(self.runs.iter().filter_map(|r| match r.metric(name) {
+            Some(Metric::Series(series)) => Some(series),
+            _ => None,
+        }))
+    }
+
+    /// A collector's histogram with the counts of every run added up
+    pub fn histogram(&self, name: &str) -> BTreeMap<usize, usize> {
+        let mut total = BTreeMap::new();
+        for r in self.runs.iter() {
+            if let Some(Metric::Histogram(counts)) = r.metric(name) {
+                for (&bucket, &count) in counts.iter() {
+                    *total.entry(bucket).or_default() += count;
+                }
+            }
+        }
+        total
+    }
+
+    /// Mallocs and failures of each size class, added up over the runs
+    pub fn size_classes(&self) -> BTreeMap<usize, SizeClass> {
+        let mut total: BTreeMap<usize, SizeClass> = BTreeMap::new();
+        for r in self.runs.iter() {
+            for (&size, class) in r.size_classes.iter() {
+                let sum = total.entry(size).or_default();
+                sum.mallocs += class.mallocs;
+                sum.failures.oversized += class.failures.oversized;
+                sum.failures.no_fit += class.failures.no_fit;
+                sum.failures.exhausted += class.failures.exhausted;
+            }
+        }
+        total
+    }
+
+    /// Mallocs and failures at each level of occupancy, added up over
+    /// the runs
+    pub fn pressure(&self) -> BTreeMap<usize, Pressure> {
+        sum_pressure(self.runs.iter().map(|r| &r.pressure))
+    }
+}
+
+impl Extend<Results> for Aggregate {
+    fn extend<I: IntoIterator<Item = Results>>(&mut self, iter: I) {
+        self.runs.extend(iter);
+    }
+}
+
+impl FromIterator<Results> for Aggregate {
+    fn from_iter<I: IntoIterator<Item = Results>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+// Average series point by point, over the ones long enough to
+// reach each point
+fn mean_series<'a>(all: impl Iterator<Item = &'a Vec<f32>>) -> Vec<f32> {
+    let mut sums: Vec<(f32, usize)> = Vec::new();
+    for series in all {
+        if sums.len() < series.len() {
+            sums.resize(series.len(), (0.0, 0));
+        }
+        for (sum, value) in sums.iter_mut().zip(series) {
+            sum.0 += value;
+            sum.1 += 1;
+        }
+    }
+    sums.into_iter().map(|(sum, n)| sum / n as f32).collect()
+}
+
+// Add up maps of pressure by key
+fn sum_pressure<'a, K: Ord + Copy + 'a>(
+    maps: impl Iterator<Item = &'a BTreeMap<K, Pressure>>,
+) -> BTreeMap<K, Pressure> {
+    let mut total: BTreeMap<K, Pressure> = BTreeMap::new();
+    for map in maps {
+        for (&key, pressure) in map.iter() {
+            let sum = total.entry(key).or_default();
+            sum.mallocs += pressure.mallocs;
+            sum.bytes += pressure.bytes;
+            sum.malloc_fails += pressure.malloc_fails;
+            sum.failed_bytes += pressure.failed_bytes;
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workloads::Failures;
+
+    #[test]
+    fn summary() {
+        let summary = Summary::new(vec![4.0, 1.0, 3.0, 2.0]);
+        assert_eq!(summary.mean(), 2.5);
+        assert!((summary.stddev() - 1.2909944).abs() < 1e-6);
+        assert_eq!(summary.min(), Some(1.0));
+        assert_eq!(summary.max(), Some(4.0));
+        assert_eq!(summary.percentile(50.0), Some(2.0));
+        assert_eq!(summary.percentile(90.0), Some(4.0));
+        assert_eq!(summary.percentile(0.0), Some(1.0));
+
+        let nan = Summary::new(vec![2.0, f32::NAN, 1.0]);
+        assert_eq!(nan.min(), Some(1.0));
+        assert!(nan.max().unwrap().is_nan());
+        assert_eq!(nan.percentile(50.0), Some(2.0));
+
+        let empty = Summary::default();
+        assert_eq!(empty.mean(), 0.0);
+        assert_eq!(empty.stddev(), 0.0);
+        assert_eq!(empty.percentile(50.0), None);
+    }
+
+    #[test]
+    fn aggregate() {
+        let runs: Aggregate = (0..3)
+            .map(|i| Results {
+                malloc_fails: i,
+                failures: Failures {
+                    no_fit: i,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        assert_eq!(runs.series(|r| r.malloc_fails as f32), vec![0.0, 1.0, 2.0]);
+        assert_eq!(runs.mean(|r| r.malloc_fails as f32), 1.0);
+        assert_eq!(runs.failures(AllocFailure::NoFit).max(), Some(2.0));
+        assert_eq!(runs.failures(AllocFailure::Exhausted).mean(), 0.0);
+    }
+
+    #[test]
+    fn size_classes() {
+        let runs: Aggregate = (0..2)
+            .map(|_| {
+                let mut results = Results::default();
+                results.malloc(24, Ok(0));
+                results.malloc(32, Ok(32));
+                results.malloc(100, Err(AllocFailure::NoFit));
+                results
+            })
+            .collect();
+
+        let classes = runs.size_classes();
+        assert_eq!(classes.keys().copied().collect::<Vec<_>>(), vec![32, 128]);
+        assert_eq!(classes[&32].mallocs, 4);
+        assert_eq!(classes[&32].failures.total(), 0);
+        assert_eq!(classes[&128].mallocs, 2);
+        assert_eq!(classes[&128].failures.no_fit, 2);
+        assert_eq!(runs.mean(|r| r.malloc_fails as f32), 1.0);
+    }
+
+    #[test]
+    fn pressure() {
+        let runs: Aggregate = (1..=2)
+            .map(|i| {
+                let mut results = Results::default();
+                results.pressure.insert(
+                    80,
+                    Pressure {
+                        mallocs: 2,
+                        bytes: 100 * i,
+                        malloc_fails: 1,
+                        failed_bytes: 25 * i,
+                    },
+                );
+                results
+            })
+            .collect();
+
+        let pressure = runs.pressure();
+        assert_eq!(pressure[&80].mallocs, 4);
+        assert_eq!(pressure[&80].failure_rate(), 0.25);
+    }
+
+    #[test]
+    fn metrics() {
+        let run = |value, series: Vec<f32>, bucket| {
+            let mut results = Results::default();
+            results
+                .metrics
+                .insert("value".to_string(), Metric::Value(value));
+            results
+                .metrics
+                .insert("series".to_string(), Metric::Series(series));
+            let counts = vec![(bucket, 1)].into_iter().collect();
+            results
+                .metrics
+                .insert("sizes".to_string(), Metric::Histogram(counts));
+            results
+        };
+        let runs = Aggregate::new(vec![
+            run(1.0, vec![0.0, 0.5], 16),
+            run(3.0, vec![1.0], 16),
+            run(5.0, vec![0.5, 1.0, 1.0], 32),
+        ]);
+
+        assert_eq!(runs.metric("value").mean(), 3.0);
+        assert!(runs.metric("missing").is_empty());
+        assert_eq!(runs.mean_series("series"), vec![0.5, 0.75, 1.0]);
+        let sizes: BTreeMap<usize, usize> = vec![(16, 2), (32, 1)].into_iter().collect();
+        assert_eq!(runs.histogram("sizes"), sizes);
+    }
+}
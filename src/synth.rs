@@ -0,0 +1,287 @@
+use crate::controller::Occupancy;
+use crate::offline;
+use crate::trace::{Op, Trace};
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How the sizes or the lifetimes of a synthetic trace are drawn.
+/// Lifetimes are in ops
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distribution {
+    /// Every value from `min` to `max` as likely
+    Uniform { min: usize, max: usize },
+    /// Every power of two from `min` to `max` as likely, so small
+    /// values are common and large ones rare
+    LogUniform { min: usize, max: usize },
+    /// Exponential with the given mean, at least 1
+    Exponential { mean: f64 },
+    /// Each value with its weight
+    Weighted(Vec<(usize, f64)>),
+}
+
+impl Distribution {
+    /// The value a fraction `u` of the way through the distribution.
+    /// Feeding in the same `u` for two distributions is what ties
+    /// sizes to lifetimes
+    pub fn quantile(&self, u: f64) -> usize {
+        let u = u.clamp(0.0, 1.0 - f64::EPSILON);
+        match self {
+            Distribution::Uniform { min, max } => {
+                (min + (u * (max - min + 1) as f64) as usize).min(*max)
+            }
+            Distribution::LogUniform { min, max } => {
+                let min = (*min).max(1);
+                let value = min as f64 * (*max as f64 / min as f64).powf(u);
+                (value.round() as usize).clamp(min, *max)
+            }
+            Distribution::Exponential { mean } => {
+                (-mean * (1.0 - u).ln()).round().max(1.0) as usize
+            }
+            Distribution::Weighted(weights) => {
+                let total: f64 = weights.iter().map(|&(_, weight)| weight).sum();
+                let mut left = u * total;
+                for &(value, weight) in weights.iter() {
+                    if left < weight {
+                        return value;
+                    }
+                    left -= weight;
+                }
+                weights.last().map_or(0, |&(value, _)| value)
+            }
+        }
+    }
+}
+
+/// Generates traces with the statistics asked for, so a hypothesis
+/// like "long lived large objects cause the fragmentation" can be
+/// tested by changing one knob and holding the others still.
+///
+/// Every op frees the allocation whose lifetime is up, if there is
+/// one. Otherwise it mallocs, unless live bytes are at the target
+/// occupancy, in which case the allocation closest to the end of its
+/// lifetime is freed early. Cutting lifetimes short keeps their order,
+/// so the correlation survives, but the lifetimes come out shorter
+/// than drawn when the target is low
+#[derive(Debug, Clone, PartialEq)]
+pub struct Synth {
+    sizes: Distribution,
+    lifetimes: Distribution,
+    correlation: f64,
+    occupancy: Option<(usize, Occupancy)>,
+    ops: usize,
+}
+
+impl Synth {
+    /// 10000 ops, with sizes and lifetimes drawn independently and
+    /// no occupancy target
+    pub fn new(sizes: Distribution, lifetimes: Distribution) -> Self {
+        Self {
+            sizes,
+            lifetimes,
+            correlation: 0.0,
+            occupancy: None,
+            ops: 10000,
+        }
+    }
+
+    /// Rank correlation between the size and the lifetime of each
+    /// allocation, from -1, where the largest die first, to 1, where
+    /// they live longest
+    pub fn correlation(mut self, correlation: f64) -> Self {
+        if !(-1.0..=1.0).contains(&correlation) {
+            panic!("Correlation has to be between -1 and 1");
+        }
+        self.correlation = correlation;
+        self
+    }
+
+    /// Keep live bytes at or below the target fraction of a `heap`
+    /// byte heap at each op
+    pub fn occupancy(mut self, heap: usize, occupancy: Occupancy) -> Self {
+        self.occupancy = Some((heap, occupancy));
+        self
+    }
+
+    pub fn ops(mut self, ops: usize) -> Self {
+        self.ops = ops;
+        self
+    }
+
+    pub fn generate<R: Rng>(&self, rng: &mut R) -> Trace {
+        let mut trace = Trace::new();
+        // When each live allocation dies, and its id
+        let mut deaths: BTreeSet<(usize, usize)> = BTreeSet::new();
+        let mut sizes: BTreeMap<usize, usize> = BTreeMap::new();
+        let (mut live, mut next_id) = (0, 0);
+
+        for now in 0..self.ops {
+            let full = self
+                .occupancy
+                .is_some_and(|(heap, target)| live as f64 >= target.at(now) * heap as f64);
+            let due = deaths.first().is_some_and(|&(death, _)| death <= now);
+
+            if due || (full && !deaths.is_empty()) {
+                let (_, id) = deaths.pop_first().unwrap();
+                live -= sizes.remove(&id).unwrap();
+                trace.push(Op::Free { id });
+                continue;
+            }
+
+            let (u, v) = correlated(rng, self.correlation);
+            let size = self.sizes.quantile(u);
+            deaths.insert((now + self.lifetimes.quantile(v), next_id));
+            sizes.insert(next_id, size);
+            live += size;
+            trace.push(Op::Malloc { id: next_id, size });
+            next_id += 1;
+        }
+
+        trace
+    }
+}
+
+/// Two quantiles, for a size and a lifetime, with about the given
+/// rank correlation. With a chance of the size of the correlation
+/// the second is the first, or its mirror image if the correlation is
+/// negative, and otherwise it is drawn on its own
+pub fn correlated<R: Rng>(rng: &mut R, correlation: f64) -> (f64, f64) {
+    if !(-1.0..=1.0).contains(&correlation) {
+        panic!("Correlation has to be between -1 and 1");
+    }
+    let u: f64 = rng.gen();
+    let v = if rng.gen_bool(correlation.abs()) {
+        if correlation > 0.0 {
+            u
+        } else {
+            1.0 - u
+        }
+    } else {
+        rng.gen()
+    };
+    (u, v)
+}
+
+/// What a trace turned out to have, to check a generated one against
+/// what was asked for. Allocations never freed live to the end of the
+/// trace
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct TraceStats {
+    pub mallocs: usize,
+    pub mean_size: f32,
+    pub mean_lifetime: f32,
+    /// Live bytes after each op, averaged over the trace
+    pub mean_live: f32,
+    pub peak_live: usize,
+    /// Rank correlation between size and lifetime
+    pub correlation: f32,
+}
+
+pub fn measure(trace: &Trace) -> TraceStats {
+    let lifetimes = offline::lifetimes(trace);
+    let mallocs = lifetimes.len();
+    let sizes: Vec<usize> = lifetimes.iter().map(|lifetime| lifetime.size).collect();
+    let spans: Vec<usize> = lifetimes
+        .iter()
+        .map(|lifetime| lifetime.end - lifetime.start)
+        .collect();
+
+    // Each allocation is live after the ops from its malloc up to
+    // just before its free
+    let live: usize = lifetimes
+        .iter()
+        .map(|lifetime| lifetime.size * (lifetime.end - lifetime.start))
+        .sum();
+
+    TraceStats {
+        mallocs,
+        mean_size: sizes.iter().sum::<usize>() as f32 / mallocs.max(1) as f32,
+        mean_lifetime: spans.iter().sum::<usize>() as f32 / mallocs.max(1) as f32,
+        mean_live: live as f32 / trace.len().max(1) as f32,
+        peak_live: offline::peak_live(trace),
+        correlation: pearson(&ranks(&sizes), &ranks(&spans)) as f32,
+    }
+}
+
+// Rank of each value, with ties sharing the mean of their ranks
+fn ranks(values: &[usize]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by_key(|&i| values[i]);
+    let mut ranks = vec![0.0; values.len()];
+
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        for &k in order[i..=j].iter() {
+            ranks[k] = (i + j) as f64 / 2.0;
+        }
+        i = j + 1;
+    }
+
+    ranks
+}
+
+// 0 if either side doesn't vary
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len().max(1) as f64;
+    let (mean_x, mean_y) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y.iter()) {
+        cov += (a - mean_x) * (b - mean_y);
+        var_x += (a - mean_x).powi(2);
+        var_y += (b - mean_y).powi(2);
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return 0.0;
+    }
+    cov / (var_x * var_y).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn targets() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let synth = Synth::new(
+            Distribution::Uniform { min: 16, max: 1024 },
+            Distribution::Exponential { mean: 200.0 },
+        )
+        .ops(20000);
+
+        for correlation in [-0.8, 0.0, 0.8] {
+            let trace = synth.clone().correlation(correlation).generate(&mut rng);
+            let stats = measure(&trace);
+            assert!((stats.mean_size - 520.0).abs() < 30.0);
+            assert!((stats.correlation - correlation as f32).abs() < 0.1);
+        }
+
+        // With no target, lifetimes come out as drawn
+        let stats = measure(&synth.generate(&mut rng));
+        assert!((stats.mean_lifetime - 200.0).abs() < 20.0);
+
+        let synth = synth
+            .correlation(0.8)
+            .occupancy(65536, Occupancy::Constant(0.5));
+        let trace = synth.generate(&mut rng);
+        let stats = measure(&trace);
+        assert!(stats.peak_live <= 32768 + 1024);
+        assert!(stats.mean_live > 30000.0);
+        // Frees cut short blur it a little
+        assert!(stats.correlation > 0.4);
+
+        assert_eq!(
+            Distribution::Weighted(vec![(16, 3.0), (64, 1.0)]).quantile(0.7),
+            16
+        );
+        assert_eq!(
+            Distribution::LogUniform { min: 16, max: 4096 }.quantile(0.5),
+            256
+        );
+    }
+}
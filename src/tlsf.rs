@@ -0,0 +1,417 @@
+use crate::wcet::WorstCase;
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Each first level range of sizes [2^f, 2^(f+1)) is split into
+// 2^SL_LOG2 second level lists of equal width
+const SL_LOG2: usize = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+// Blocks are multiples of ALIGN bytes, so no block is smaller
+const ALIGN_LOG2: usize = 3;
+const ALIGN: usize = 1 << ALIGN_LOG2;
+// Sizes below SMALL all go in first level 0, in lists ALIGN wide
+const FL_SHIFT: usize = SL_LOG2 + ALIGN_LOG2;
+const SMALL: usize = 1 << FL_SHIFT;
+
+#[derive(Debug, Copy, Clone)]
+struct Block {
+    size: usize,
+    // Bytes asked for, or None if the block is free
+    requested: Option<usize>,
+}
+
+/// A two-level segregated fit allocator, after Masmano et al. Free
+/// blocks are kept in lists by size class, and a bitmap of which
+/// lists are non-empty finds a fitting list with a couple of bit
+/// scans, so malloc and free take constant time however fragmented
+/// the heap is. Requests are rounded up to the next class before
+/// searching, so every block found fits without scanning a list,
+/// at the cost of sometimes failing a request a free block could
+/// have held
+#[derive(Debug, Clone)]
+pub struct Tlsf {
+    base_addr: usize,
+    size: usize,
+    // Bit f is set if any list of first level f has a block
+    fl_bitmap: u64,
+    // Bit s of entry f is set if list (f, s) has a block
+    sl_bitmap: Vec<u32>,
+    // Free block addresses of list (f, s) at f * SL_COUNT + s. New
+    // blocks go on the end and mallocs take from the end
+    lists: Vec<Vec<usize>>,
+    // Every block by address, free or not. Stands in for the
+    // boundary tags that find a block's neighbours in a real heap
+    blocks: BTreeMap<usize, Block>,
+    churn: Churn,
+}
+
+// First and second level index of the list a block of `size` goes in
+fn mapping(size: usize) -> (usize, usize) {
+    if size < SMALL {
+        (0, size / (SMALL / SL_COUNT))
+    } else {
+        let fl = size.ilog2() as usize;
+        let sl = (size >> (fl - SL_LOG2)) ^ SL_COUNT;
+        (fl - FL_SHIFT + 1, sl)
+    }
+}
+
+// The list to start searching from for a request of `size`. Rounds
+// up to the next class, so any block in it or above is big enough
+fn search_mapping(size: usize) -> (usize, usize) {
+    if size < SMALL {
+        mapping(size)
+    } else {
+        mapping(size + (1 << (size.ilog2() as usize - SL_LOG2)) - 1)
+    }
+}
+
+// Smallest size that goes in list (fl, sl)
+fn class_start(fl: usize, sl: usize) -> usize {
+    if fl == 0 {
+        sl * (SMALL / SL_COUNT)
+    } else {
+        let base = 1 << (fl + FL_SHIFT - 1);
+        base + sl * (base >> SL_LOG2)
+    }
+}
+
+impl Tlsf {
+    /// Manage [base_addr, base_addr + size). The size is rounded
+    /// down to a multiple of 8 bytes
+    pub fn new(base_addr: usize, size: usize) -> Self {
+        let size = size - size % ALIGN;
+        if size == 0 {
+            panic!("Don't make a heap with size 0");
+        }
+        let fl_count = mapping(size).0 + 1;
+        let mut tlsf = Self {
+            base_addr,
+            size,
+            fl_bitmap: 0,
+            sl_bitmap: vec![0; fl_count],
+            lists: vec![Vec::new(); fl_count * SL_COUNT],
+            blocks: BTreeMap::new(),
+            churn: Churn::default(),
+        };
+        tlsf.insert(base_addr, size);
+        tlsf
+    }
+
+    // Add a free block to the list for its size
+    fn insert(&mut self, addr: usize, size: usize) {
+        let (fl, sl) = mapping(size);
+        self.lists[fl * SL_COUNT + sl].push(addr);
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmap[fl] |= 1 << sl;
+        self.blocks.insert(
+            addr,
+            Block {
+                size,
+                requested: None,
+            },
+        );
+    }
+
+    // Take a free block out of its list, clearing the bitmaps if the
+    // list empties
+    fn remove(&mut self, addr: usize, size: usize) {
+        let (fl, sl) = mapping(size);
+        let list = &mut self.lists[fl * SL_COUNT + sl];
+        let i = list.iter().position(|&a| a == addr).unwrap();
+        list.swap_remove(i);
+        if list.is_empty() {
+            self.sl_bitmap[fl] &= !(1 << sl);
+            if self.sl_bitmap[fl] == 0 {
+                self.fl_bitmap &= !(1 << fl);
+            }
+        }
+    }
+
+    // The first non-empty list at or above (fl, sl)
+    fn find_list(&self, fl: usize, sl: usize) -> Option<(usize, usize)> {
+        if fl >= self.sl_bitmap.len() {
+            return None;
+        }
+        let sl_map = self.sl_bitmap[fl] & (!0 << sl);
+        if sl_map != 0 {
+            return Some((fl, sl_map.trailing_zeros() as usize));
+        }
+        let fl_map = self.fl_bitmap & (!0 << (fl + 1));
+        if fl_map == 0 {
+            return None;
+        }
+        let fl = fl_map.trailing_zeros() as usize;
+        Some((fl, self.sl_bitmap[fl].trailing_zeros() as usize))
+    }
+
+    // The highest non-empty list
+    fn top_list(&self) -> Option<(usize, usize)> {
+        if self.fl_bitmap == 0 {
+            return None;
+        }
+        let fl = self.fl_bitmap.ilog2() as usize;
+        Some((fl, self.sl_bitmap[fl].ilog2() as usize))
+    }
+
+    fn free_blocks(&self) -> impl Iterator<Item = (&usize, &Block)> {
+        self.blocks.iter().filter(|(_, b)| b.requested.is_none())
+    }
+}
+
+impl Allocator for Tlsf {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
+        if size == 0 || size > self.size {
+            return None;
+        }
+        let adjusted = size.div_ceil(ALIGN) * ALIGN;
+
+        let (fl, sl) = search_mapping(adjusted);
+        let (fl, sl) = self.find_list(fl, sl)?;
+        let addr = *self.lists[fl * SL_COUNT + sl].last().unwrap();
+        let block = self.blocks[&addr];
+        self.remove(addr, block.size);
+
+        // Give back whatever is left over, if it can be a block
+        let mut used = block.size;
+        if block.size - adjusted >= ALIGN {
+            self.insert(addr + adjusted, block.size - adjusted);
+            self.churn.splits += 1;
+            used = adjusted;
+        }
+        self.blocks.insert(
+            addr,
+            Block {
+                size: used,
+                requested: Some(size),
+            },
+        );
+        Some(addr)
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        if size > self.size {
+            return Err(AllocFailure::Oversized);
+        }
+        self.malloc(size).ok_or(if self.free_space() < size {
+            AllocFailure::Exhausted
+        } else {
+            AllocFailure::NoFit
+        })
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let mut size = match self.blocks.get(&ptr) {
+            Some(Block {
+                size,
+                requested: Some(_),
+            }) => *size,
+            _ => return Err(AllocError::InvalidPointer(ptr)),
+        };
+        self.blocks.remove(&ptr);
+        let mut addr = ptr;
+
+        // Merge with the free blocks right after and right before
+        if let Some(&next) = self.blocks.get(&(addr + size)) {
+            if next.requested.is_none() {
+                self.remove(addr + size, next.size);
+                self.blocks.remove(&(addr + size));
+                size += next.size;
+                self.churn.merges += 1;
+            }
+        }
+        if let Some((&prev_addr, &prev)) = self.blocks.range(..addr).next_back() {
+            if prev.requested.is_none() && prev_addr + prev.size == addr {
+                self.remove(prev_addr, prev.size);
+                self.blocks.remove(&prev_addr);
+                addr = prev_addr;
+                size += prev.size;
+                self.churn.merges += 1;
+            }
+        }
+
+        self.insert(addr, size);
+        Ok(())
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        match self.blocks.get(&ptr) {
+            Some(Block {
+                size,
+                requested: Some(_),
+            }) => Some(*size),
+            _ => None,
+        }
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.fl_bitmap = 0;
+        self.sl_bitmap.iter_mut().for_each(|bitmap| *bitmap = 0);
+        self.lists.iter_mut().for_each(Vec::clear);
+        self.blocks.clear();
+        self.churn = Churn::default();
+        self.insert(self.base_addr, self.size);
+        Ok(())
+    }
+
+    // Anything that rounds up to the highest non-empty list is
+    // found, which can be less than the largest free block
+    fn largest_alloc(&self) -> usize {
+        self.top_list().map_or(0, |(fl, sl)| class_start(fl, sl))
+    }
+
+    fn free_space(&self) -> usize {
+        self.free_blocks().map(|(_, b)| b.size).sum()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.blocks
+            .values()
+            .filter_map(|b| b.requested.map(|requested| b.size - requested))
+            .sum()
+    }
+
+    fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        Some(self.blocks.values().filter_map(|b| b.requested).sum())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "Heap [{}, {}), first level bitmap {:b}",
+            self.base_addr,
+            self.base_addr + self.size,
+            self.fl_bitmap
+        )
+        .unwrap();
+        for (i, list) in self.lists.iter().enumerate() {
+            if !list.is_empty() {
+                let (fl, sl) = (i / SL_COUNT, i % SL_COUNT);
+                let blocks: Vec<String> = list
+                    .iter()
+                    .map(|addr| format!("{}+{}", addr, self.blocks[addr].size))
+                    .collect();
+                writeln!(
+                    out,
+                    "List ({}, {}) from {}: {}",
+                    fl,
+                    sl,
+                    class_start(fl, sl),
+                    blocks.join(" ")
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// A malloc splits at most one block, and a free merges with at most
+/// its two neighbours, found through the bitmaps without a search
+impl WorstCase for Tlsf {
+    fn worst_op(&self) -> Option<Churn> {
+        Some(Churn {
+            splits: 1,
+            merges: 2,
+            ..Churn::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapping() {
+        assert_eq!(super::mapping(8), (0, 1));
+        assert_eq!(super::mapping(120), (0, 15));
+        assert_eq!(super::mapping(128), (1, 0));
+        assert_eq!(super::mapping(136), (1, 1));
+        assert_eq!(super::mapping(1000), (3, 15));
+        assert_eq!(super::mapping(1024), (4, 0));
+
+        // Rounding up skips to the next list unless the size is
+        // already the start of one
+        assert_eq!(search_mapping(128), (1, 0));
+        assert_eq!(search_mapping(129), (1, 1));
+        assert_eq!(search_mapping(1000), (4, 0));
+
+        for size in (ALIGN..4096).step_by(ALIGN) {
+            let (fl, sl) = super::mapping(size);
+            assert!(class_start(fl, sl) <= size);
+            let (fl, sl) = search_mapping(size);
+            assert!(class_start(fl, sl) >= size);
+        }
+    }
+
+    #[test]
+    fn malloc_free() {
+        let mut tlsf = Tlsf::new(0, 1024);
+        assert_eq!(tlsf.malloc(10), Some(0));
+        assert_eq!(tlsf.malloc(100), Some(16));
+        assert_eq!(tlsf.malloc(8), Some(120));
+        assert_eq!(tlsf.internal_frag(), 10);
+        assert_eq!(tlsf.live_bytes(), Some(118));
+        assert_eq!(tlsf.free_space(), 1024 - 128);
+
+        assert!(tlsf.free(16).is_ok());
+        assert!(tlsf.free(16).is_err());
+        assert!(tlsf.free(17).is_err());
+        // Reuses the freed block, splitting it
+        assert_eq!(tlsf.malloc(40), Some(16));
+
+        assert!(tlsf.free(0).is_ok());
+        assert!(tlsf.free(120).is_ok());
+        assert!(tlsf.free(16).is_ok());
+        assert_eq!(tlsf.free_space(), 1024);
+        assert_eq!(tlsf.largest_alloc(), 1024);
+        assert_eq!(tlsf.blocks.len(), 1);
+
+        let churn = tlsf.churn();
+        assert_eq!((churn.mallocs, churn.splits), (4, 4));
+        assert_eq!(churn.merges, 4);
+    }
+
+    #[test]
+    fn good_fit() {
+        // A 1000 byte block is in the list for 992 to 1023, so a
+        // request for 1000 searches from the next list up and misses
+        let mut tlsf = Tlsf::new(0, 2048);
+        let ptr = tlsf.malloc(1000).unwrap();
+        tlsf.malloc(1024).unwrap();
+        tlsf.free(ptr).unwrap();
+        assert_eq!(tlsf.free_space(), 1024);
+        assert_eq!(tlsf.largest_alloc(), 992);
+        assert!(tlsf.malloc(993).is_none());
+        assert_eq!(tlsf.try_malloc(1000), Err(AllocFailure::NoFit));
+        assert_eq!(tlsf.malloc(992), Some(ptr));
+        assert_eq!(tlsf.try_malloc(4096), Err(AllocFailure::Oversized));
+    }
+
+    #[test]
+    fn fills_up() {
+        let mut tlsf = Tlsf::new(64, 256);
+        let ptrs: Vec<usize> = (0..32).map(|_| tlsf.malloc(8).unwrap()).collect();
+        assert!(tlsf.malloc(1).is_none());
+        assert_eq!(tlsf.largest_alloc(), 0);
+        assert_eq!(tlsf.external_frag(), 0.0);
+
+        for &ptr in ptrs.iter().step_by(2) {
+            tlsf.free(ptr).unwrap();
+        }
+        assert_eq!(tlsf.free_space(), 128);
+        assert_eq!(tlsf.largest_alloc(), 8);
+        for &ptr in ptrs.iter().skip(1).step_by(2) {
+            tlsf.free(ptr).unwrap();
+        }
+        assert_eq!(tlsf.largest_alloc(), 256);
+    }
+}
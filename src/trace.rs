@@ -0,0 +1,430 @@
+use crate::{Allocator, Regional};
+use rand::distributions::Bernoulli;
+use rand::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A single operation in a trace. Allocations are named by an id
+/// so that frees can refer to them no matter which address the
+/// allocator picked when the trace is replayed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    Malloc {
+        id: usize,
+        size: usize,
+    },
+    /// `count` elements of `size` bytes, zero filled
+    Calloc {
+        id: usize,
+        count: usize,
+        size: usize,
+    },
+    Free {
+        id: usize,
+    },
+}
+
+impl Op {
+    /// Bytes a malloc or calloc asks for, or None for a free. The
+    /// product of a calloc saturates, so one that overflows asks
+    /// for more than any heap has, and one of 0 asks for 1 byte like
+    /// `Allocator::calloc` does
+    pub fn bytes(&self) -> Option<usize> {
+        match *self {
+            Op::Malloc { size, .. } => Some(size),
+            Op::Calloc { count, size, .. } => Some(count.saturating_mul(size).max(1)),
+            Op::Free { .. } => None,
+        }
+    }
+}
+
+/// What happened when an op was replayed. `block` is the address
+/// and size of the memory touched, or None if the malloc failed
+/// or the free was for an id that isn't live
+#[derive(Debug, Copy, Clone)]
+pub struct Event {
+    pub op: Op,
+    pub block: Option<(usize, usize)>,
+}
+
+/// A sequence of ops that can be replayed against any allocator
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub ops: Vec<Op>,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, op: Op) {
+        self.ops.push(op);
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Run every op against the allocator, returning one event per op
+    pub fn replay<T: Allocator>(&self, allocator: &mut T) -> Vec<Event> {
+        self.replay_inspect(allocator, |_, _| {})
+    }
+
+    /// Like `replay`, but `inspect` gets to look at the allocator
+    /// after every op, along with how many ops have run
+    pub fn replay_inspect<T: Allocator>(
+        &self,
+        allocator: &mut T,
+        mut inspect: impl FnMut(usize, &T),
+    ) -> Vec<Event> {
+        let mut live: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+        let mut events = Vec::with_capacity(self.ops.len());
+
+        for &op in self.ops.iter() {
+            let block = match op {
+                Op::Malloc { id, size } => allocator.malloc(size).map(|ptr| {
+                    live.insert(id, (ptr, size));
+                    (ptr, size)
+                }),
+                Op::Calloc { id, count, size } => allocator.calloc(count, size).map(|ptr| {
+                    let bytes = op.bytes().unwrap();
+                    live.insert(id, (ptr, bytes));
+                    (ptr, bytes)
+                }),
+                Op::Free { id } => match live.remove(&id) {
+                    Some((ptr, size)) if allocator.free(ptr).is_ok() => Some((ptr, size)),
+                    _ => None,
+                },
+            };
+            events.push(Event { op, block });
+            inspect(events.len(), allocator);
+        }
+
+        events
+    }
+
+    /// Like `replay`, but the mallocs go into regions of `mallocs`
+    /// each, in the order they are made. A region is freed as a
+    /// whole once every allocation in it has been freed and the
+    /// next region has started. The frees of single allocations
+    /// still go to the allocator, so their events are the same
+    pub fn replay_regions<T: Regional>(&self, allocator: &mut T, mallocs: usize) -> Vec<Event> {
+        let mallocs = mallocs.max(1);
+        // Address, size and region of each live id
+        let mut live: BTreeMap<usize, (usize, usize, usize)> = BTreeMap::new();
+        // Live allocations in each region that hasn't been freed
+        let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut made = 0;
+        let mut events = Vec::with_capacity(self.ops.len());
+
+        for &op in self.ops.iter() {
+            let block = match op {
+                Op::Malloc { id, .. } | Op::Calloc { id, .. } => {
+                    let region = made / mallocs;
+                    made += 1;
+                    if !counts.contains_key(&region) {
+                        if let Some((&last, &0)) = counts.iter().next_back() {
+                            counts.remove(&last);
+                            allocator.free_region(&last.to_string()).ok();
+                        }
+                        counts.insert(region, 0);
+                        allocator.enter_region(&region.to_string());
+                    }
+
+                    let size = op.bytes().unwrap();
+                    let ptr = match op {
+                        Op::Calloc { count, size, .. } => allocator.calloc(count, size),
+                        _ => allocator.malloc(size),
+                    };
+                    ptr.map(|ptr| {
+                        live.insert(id, (ptr, size, region));
+                        *counts.get_mut(&region).unwrap() += 1;
+                        (ptr, size)
+                    })
+                }
+                Op::Free { id } => match live.remove(&id) {
+                    Some((ptr, size, region)) if allocator.free(ptr).is_ok() => {
+                        let count = counts.get_mut(&region).unwrap();
+                        *count -= 1;
+                        if *count == 0 && region < made.saturating_sub(1) / mallocs {
+                            counts.remove(&region);
+                            allocator.free_region(&region.to_string()).ok();
+                        }
+                        Some((ptr, size))
+                    }
+                    _ => None,
+                },
+            };
+            events.push(Event { op, block });
+        }
+
+        events
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Malloc { id, size } => write!(f, "malloc {} {}", id, size),
+            Op::Calloc { id, count, size } => write!(f, "calloc {} {} {}", id, count, size),
+            Op::Free { id } => write!(f, "free {}", id),
+        }
+    }
+}
+
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let num = |word: &str| {
+            word.parse::<usize>()
+                .map_err(|_| format!("Could not parse number in \"{}\"", s))
+        };
+
+        match words.as_slice() {
+            ["malloc", id, size] => Ok(Op::Malloc {
+                id: num(id)?,
+                size: num(size)?,
+            }),
+            ["calloc", id, count, size] => Ok(Op::Calloc {
+                id: num(id)?,
+                count: num(count)?,
+                size: num(size)?,
+            }),
+            ["free", id] => Ok(Op::Free { id: num(id)? }),
+            _ => Err(format!("Unknown op \"{}\"", s)),
+        }
+    }
+}
+
+/// One op per line. Blank lines and lines starting with `#` are
+/// skipped when parsing
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for op in self.ops.iter() {
+            writeln!(f, "{}", op)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Trace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ops = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::parse)
+            .collect::<Result<Vec<Op>, String>>()?;
+        Ok(Trace { ops })
+    }
+}
+
+/// Same shape as `workloads::stack`, but recorded instead of run
+pub fn stack<R: Rng>(ratio: f64, rng: &mut R) -> Trace {
+    let size = 32;
+    let mut trace = Trace::new();
+    let mut ids = Vec::new();
+
+    for id in 0..10 {
+        trace.push(Op::Malloc { id, size });
+        ids.push(id);
+    }
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(990)
+        .collect();
+    let mut next_id = 10;
+
+    for sample in samples.into_iter() {
+        if sample {
+            trace.push(Op::Malloc { id: next_id, size });
+            ids.push(next_id);
+            next_id += 1;
+        } else if let Some(id) = ids.pop() {
+            trace.push(Op::Free { id });
+        }
+    }
+
+    trace
+}
+
+/// Same shape as `workloads::random_memory`, but recorded instead of run
+pub fn random_memory<R: Rng>(ratio: f64, rng: &mut R) -> Trace {
+    let mut trace = Trace::new();
+    let mut ids = Vec::new();
+
+    for id in 0..10 {
+        trace.push(Op::Malloc {
+            id,
+            size: rng.gen_range(32..=128),
+        });
+        ids.push(id);
+    }
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(990)
+        .collect();
+    let mut next_id = 10;
+
+    for sample in samples.into_iter() {
+        if sample {
+            trace.push(Op::Malloc {
+                id: next_id,
+                size: rng.gen_range(32..=128),
+            });
+            ids.push(next_id);
+            next_id += 1;
+        } else {
+            if ids.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ids.len());
+            trace.push(Op::Free { id: ids.remove(i) });
+        }
+    }
+
+    trace
+}
+
+/// Bytes the callocs of a replay asked for, and how many of them
+/// had been handed out before and so would have to be zeroed.
+/// Memory the allocator never handed out is taken to be zero
+/// already, like fresh pages from the OS, so the second number
+/// depends on where the allocator put things
+pub fn zeroing(events: &[Event]) -> (usize, usize) {
+    // Ranges handed out so far, merged, by start
+    let mut touched: BTreeMap<usize, usize> = BTreeMap::new();
+    let (mut asked, mut dirty) = (0, 0);
+
+    for event in events.iter() {
+        let Some((ptr, size)) = event.block else {
+            continue;
+        };
+        match event.op {
+            Op::Calloc { .. } => {
+                asked += size;
+                dirty += touched
+                    .range(..ptr + size)
+                    .map(|(&start, &end)| end.min(ptr + size).saturating_sub(start.max(ptr)))
+                    .sum::<usize>();
+            }
+            Op::Malloc { .. } => {}
+            Op::Free { .. } => continue,
+        }
+
+        let (mut start, mut end) = (ptr, ptr + size);
+        let overlapping: Vec<usize> = touched
+            .range(..=end)
+            .rev()
+            .take_while(|(_, &other)| other >= start)
+            .map(|(&other, _)| other)
+            .collect();
+        for other in overlapping {
+            let other_end = touched.remove(&other).unwrap();
+            start = start.min(other);
+            end = end.max(other_end);
+        }
+        touched.insert(start, end);
+    }
+
+    (asked, dirty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freelist::FreeList;
+    use crate::region::RegionAllocator;
+
+    #[test]
+    fn parse_roundtrip() {
+        let text = "# comment\nmalloc 0 16\n\nmalloc 1 8\nfree 0\n";
+        let trace: Trace = text.parse().unwrap();
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace.ops[2], Op::Free { id: 0 });
+        assert_eq!(trace.to_string().parse::<Trace>().unwrap(), trace);
+
+        let trace: Trace = "calloc 0 4 8\nfree 0".parse().unwrap();
+        assert_eq!(trace.ops[0].bytes(), Some(32));
+        assert_eq!(trace.to_string(), "calloc 0 4 8\nfree 0\n");
+
+        assert!("malloc 0".parse::<Trace>().is_err());
+        assert!("realloc 0 1".parse::<Trace>().is_err());
+    }
+
+    #[test]
+    fn replay() {
+        let trace: Trace = "malloc 0 16\nmalloc 1 200\nfree 1\nfree 0\nfree 0"
+            .parse()
+            .unwrap();
+        let mut list = FreeList::new(0, 100, true);
+        let events = trace.replay(&mut list);
+
+        assert_eq!(events[0].block, Some((0, 16)));
+        // Too big, so the free of the same id is skipped too
+        assert!(events[1].block.is_none());
+        assert!(events[2].block.is_none());
+        assert_eq!(events[3].block, Some((0, 16)));
+        // Double free
+        assert!(events[4].block.is_none());
+        assert_eq!(list.free_space(), 100);
+    }
+
+    #[test]
+    fn replay_regions() {
+        let trace: Trace = "malloc 0 100\nmalloc 1 100\nmalloc 2 100\nfree 0\nfree 1\nfree 2"
+            .parse()
+            .unwrap();
+        let list = FreeList::new(0, 1024, true).policy(crate::Policy::First);
+        let mut heap = RegionAllocator::new(list, 256);
+        let events = trace.replay_regions(&mut heap, 2);
+
+        assert_eq!(events[2].block, Some((256, 100)));
+        assert_eq!(events[5].block, Some((256, 100)));
+        // The first region went once both its allocations had, but
+        // the second is still taking mallocs
+        assert_eq!(heap.regions_freed, 1);
+        assert_eq!(heap.held(), 256);
+    }
+
+    #[test]
+    fn zeroing() {
+        let trace: Trace = "calloc 0 4 8\nmalloc 1 16\nfree 0\nfree 1\n\
+                            calloc 2 2 32\ncalloc 3 2 9223372036854775808"
+            .parse()
+            .unwrap();
+        let mut list = FreeList::new(0, 100, true);
+        let events = trace.replay(&mut list);
+        // Only the first 48 bytes of the second calloc were used before
+        assert_eq!(events[4].block, Some((0, 64)));
+        // The product overflows
+        assert!(events[5].block.is_none());
+        assert_eq!(super::zeroing(&events), (32 + 64, 48));
+
+        // Nothing asked for still gets a block of its own
+        let trace: Trace = "calloc 0 0 8\nmalloc 1 10\nfree 1\nfree 0\nmalloc 2 0"
+            .parse()
+            .unwrap();
+        let mut list = FreeList::new(0, 100, true);
+        let events = trace.replay(&mut list);
+        assert_eq!(events[0].block, Some((0, 1)));
+        assert_eq!(events[1].block, Some((1, 10)));
+        assert!(events[2].block.is_some() && events[3].block.is_some());
+        assert_eq!(list.free_space(), 100);
+        assert!(events[4].block.is_none());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{AllocError, AllocFailure, Allocator, Churn};
+use std::collections::BTreeMap;
+
+/// Running totals of the ops an allocator got through, and the most
+/// it held at once. Live bytes are the bytes asked for, without
+/// rounding or padding
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Mallocs and frees that went through
+    pub mallocs: usize,
+    pub frees: usize,
+    pub splits: usize,
+    pub merges: usize,
+    pub peak_live_bytes: usize,
+    pub peak_allocations: usize,
+}
+
+/// An allocator that keeps its own `Usage`
+pub trait Stats {
+    fn usage(&self) -> Usage;
+}
+
+// The live allocations behind a `Usage`, for anything that sees
+// every malloc and free go by. Frees of pointers it never saw still
+// count, so a run can start on a heap that is already in use
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Tally {
+    live: BTreeMap<usize, usize>,
+    live_bytes: usize,
+    usage: Usage,
+}
+
+impl Tally {
+    pub(crate) fn malloc(&mut self, ptr: usize, size: usize) {
+        self.live.insert(ptr, size);
+        self.live_bytes += size;
+        self.usage.mallocs += 1;
+        self.usage.peak_live_bytes = self.usage.peak_live_bytes.max(self.live_bytes);
+        self.usage.peak_allocations = self.usage.peak_allocations.max(self.live.len());
+    }
+
+    pub(crate) fn free(&mut self, ptr: usize) {
+        if let Some(size) = self.live.remove(&ptr) {
+            self.live_bytes -= size;
+        }
+        self.usage.frees += 1;
+    }
+
+    // Follow allocations that were moved, given as old and new
+    // address
+    pub(crate) fn moved(&mut self, moves: &BTreeMap<usize, usize>) {
+        for (from, to) in moves.iter() {
+            if let Some(size) = self.live.remove(from) {
+                self.live.insert(*to, size);
+            }
+        }
+    }
+
+    // With the splits and merges the allocator counted in the
+    // meantime
+    pub(crate) fn usage(&self, churn: Churn) -> Usage {
+        Usage {
+            splits: churn.splits,
+            merges: churn.merges,
+            ..self.usage
+        }
+    }
+}
+
+/// Keeps a `Usage` for any allocator, without touching it
+#[derive(Debug, Clone)]
+pub struct Tracked<T> {
+    allocator: T,
+    tally: Tally,
+}
+
+impl<T: Allocator> Tracked<T> {
+    pub fn new(allocator: T) -> Self {
+        Self {
+            allocator,
+            tally: Tally::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.allocator
+    }
+}
+
+impl<T: Allocator> Stats for Tracked<T> {
+    fn usage(&self) -> Usage {
+        self.tally.usage(self.allocator.churn())
+    }
+}
+
+impl<T: Allocator> Allocator for Tracked<T> {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.try_malloc(size).ok()
+    }
+
+    fn try_malloc(&mut self, size: usize) -> Result<usize, AllocFailure> {
+        let ptr = self.allocator.try_malloc(size)?;
+        self.tally.malloc(ptr, size);
+        Ok(ptr)
+    }
+
+    fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<usize> {
+        let ptr = self.allocator.malloc_aligned(size, align)?;
+        self.tally.malloc(ptr, size);
+        Some(ptr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        self.allocator.free(ptr)?;
+        self.tally.free(ptr);
+        Ok(())
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.allocator.alloc_size(ptr)
+    }
+
+    fn reset(&mut self) -> Result<(), AllocError> {
+        self.allocator.reset()?;
+        self.tally = Tally::default();
+        Ok(())
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.allocator.largest_alloc()
+    }
+
+    fn free_space(&self) -> usize {
+        self.allocator.free_space()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.allocator.internal_frag()
+    }
+
+    fn churn(&self) -> Churn {
+        self.allocator.churn()
+    }
+
+    fn live_bytes(&self) -> Option<usize> {
+        self.allocator.live_bytes()
+    }
+
+    fn coalesce(&mut self) -> usize {
+        self.allocator.coalesce()
+    }
+
+    fn render(&self) -> String {
+        self.allocator.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+
+    #[test]
+    fn usage() {
+        let mut heap = Tracked::new(BuddyAllocator::new(4, 7));
+        let a = heap.malloc(20).unwrap();
+        let b = heap.malloc(16).unwrap();
+        heap.free(a).unwrap();
+        assert!(heap.free(a).is_err());
+        assert_eq!(heap.malloc(200), None);
+        heap.malloc(50).unwrap();
+        heap.free(b).unwrap();
+
+        assert_eq!(
+            heap.usage(),
+            Usage {
+                mallocs: 3,
+                frees: 2,
+                splits: heap.churn().splits,
+                merges: heap.churn().merges,
+                peak_live_bytes: 66,
+                peak_allocations: 2,
+            }
+        );
+        assert!(heap.usage().splits > 0);
+
+        heap.reset().unwrap();
+        assert_eq!(heap.usage(), Usage::default());
+    }
+}
@@ -0,0 +1,371 @@
+use crate::{AllocError, Allocator, Churn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// How many freed segments a quantum cache keeps for each size
+const QCACHE_DEPTH: usize = 16;
+
+/// How an arena picks a free segment
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fit {
+    /// Take any segment from the first list whose segments are all
+    /// big enough. Constant time, but can split a large segment
+    /// when a smaller one would have fit
+    Instant,
+    /// Take the smallest segment that fits
+    Best,
+}
+
+/// A resource arena in the style of Bonwick's vmem. It hands out
+/// ranges of integers from one or more spans, so it can manage
+/// anything numbered, like PIDs or port ranges, and not just heap
+/// addresses. Sizes are rounded up to a multiple of the quantum.
+/// Free segments sit on power of two lists, where list i holds the
+/// segments with sizes in [2^i, 2^(i + 1)). Small sizes can be put
+/// behind quantum caches, which keep freed segments for reuse
+/// without splitting or coalescing
+#[derive(Debug, Clone)]
+pub struct Arena {
+    quantum: usize,
+    fit: Fit,
+    spans: BTreeMap<usize, usize>,
+    segments: BTreeMap<usize, usize>,
+    lists: Vec<BTreeSet<usize>>,
+    sizemap: BTreeMap<usize, (usize, usize)>,
+    qcache_max: usize,
+    qcaches: BTreeMap<usize, Vec<usize>>,
+    pub qcache_hits: usize,
+    churn: Churn,
+}
+
+impl Arena {
+    pub fn new(base: usize, size: usize, quantum: usize) -> Self {
+        if quantum == 0 {
+            panic!("Quantum must be at least 1");
+        }
+
+        let mut arena = Self {
+            quantum,
+            fit: Fit::Instant,
+            spans: BTreeMap::new(),
+            segments: BTreeMap::new(),
+            lists: vec![BTreeSet::new(); usize::BITS as usize],
+            sizemap: BTreeMap::new(),
+            qcache_max: 0,
+            qcaches: BTreeMap::new(),
+            qcache_hits: 0,
+            churn: Churn::default(),
+        };
+        arena.add(base, size).unwrap();
+        arena
+    }
+
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    /// Cache freed segments of every multiple of the quantum up to
+    /// `max` bytes
+    pub fn quantum_cache(mut self, max: usize) -> Self {
+        self.qcache_max = max;
+        self
+    }
+
+    /// Add another span to allocate from. Fails if it overlaps a
+    /// span the arena already has
+    pub fn add(&mut self, base: usize, size: usize) -> Result<(), AllocError> {
+        if size == 0 {
+            return Err(AllocError::BadSpan { base, size });
+        }
+        let end = base + size;
+        let overlaps = self
+            .spans
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, &span_end)| base < span_end);
+        if overlaps {
+            return Err(AllocError::BadSpan { base, size });
+        }
+
+        self.spans.insert(base, end);
+        self.insert(base, size);
+        Ok(())
+    }
+
+    /// Allocate a segment at exactly `addr`, if it is free
+    pub fn alloc_at(&mut self, addr: usize, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
+        let (size, diff) = self.round(size)?;
+        if !addr.is_multiple_of(self.quantum) {
+            return None;
+        }
+        let (&start, &len) = self.segments.range(..=addr).next_back()?;
+        if addr + size > start + len {
+            return None;
+        }
+
+        self.carve(start, addr, size);
+        self.sizemap.insert(addr, (size, diff));
+        Some(addr)
+    }
+
+    fn round(&self, size: usize) -> Option<(usize, usize)> {
+        if size == 0 {
+            return None;
+        }
+        match size % self.quantum {
+            0 => Some((size, 0)),
+            left => Some((size + self.quantum - left, self.quantum - left)),
+        }
+    }
+
+    fn list(size: usize) -> usize {
+        (usize::BITS - 1 - size.leading_zeros()) as usize
+    }
+
+    fn insert(&mut self, addr: usize, size: usize) {
+        self.segments.insert(addr, size);
+        self.lists[Self::list(size)].insert(addr);
+    }
+
+    fn remove(&mut self, addr: usize) -> usize {
+        let size = self.segments.remove(&addr).unwrap();
+        self.lists[Self::list(size)].remove(&addr);
+        size
+    }
+
+    // Take [addr, addr + size) out of the free segment at `start`
+    fn carve(&mut self, start: usize, addr: usize, size: usize) {
+        let len = self.remove(start);
+        if size < len {
+            self.churn.splits += 1;
+        }
+        if start < addr {
+            self.insert(start, addr - start);
+        }
+        if addr + size < start + len {
+            self.insert(addr + size, start + len - addr - size);
+        }
+    }
+
+    fn search(&self, size: usize) -> Option<usize> {
+        match self.fit {
+            Fit::Instant => {
+                // Every segment on a list above the one for `size`
+                // is big enough, unless `size` is a power of two.
+                // Only if none are left is the list for `size` searched
+                let first = if size.is_power_of_two() {
+                    Self::list(size)
+                } else {
+                    Self::list(size) + 1
+                };
+                self.lists[first.min(self.lists.len())..]
+                    .iter()
+                    .find_map(|list| list.iter().next().copied())
+                    .or_else(|| {
+                        self.lists[Self::list(size)]
+                            .iter()
+                            .find(|&addr| self.segments[addr] >= size)
+                            .copied()
+                    })
+            }
+            Fit::Best => self.lists[Self::list(size)..].iter().find_map(|list| {
+                list.iter()
+                    .map(|&addr| (self.segments[&addr], addr))
+                    .filter(|&(len, _)| len >= size)
+                    .min()
+                    .map(|(_, addr)| addr)
+            }),
+        }
+    }
+
+    // Put a segment back, merging with free neighbors in the same span
+    fn release(&mut self, mut addr: usize, mut size: usize) {
+        if !self.spans.contains_key(&addr) {
+            if let Some((&prev, &prev_size)) = self.segments.range(..addr).next_back() {
+                if prev + prev_size == addr {
+                    self.churn.merges += 1;
+                    self.remove(prev);
+                    addr = prev;
+                    size += prev_size;
+                }
+            }
+        }
+        let end = addr + size;
+        let span_end = self.spans.range(..=addr).next_back().map(|(_, &end)| end);
+        if span_end != Some(end) {
+            if let Some(&next_size) = self.segments.get(&end) {
+                self.churn.merges += 1;
+                self.remove(end);
+                size += next_size;
+            }
+        }
+        self.insert(addr, size);
+    }
+}
+
+impl Allocator for Arena {
+    fn malloc(&mut self, size: usize) -> Option<usize> {
+        self.churn.mallocs += 1;
+        let (size, diff) = self.round(size)?;
+
+        let cached = if size <= self.qcache_max {
+            self.qcaches.get_mut(&size).and_then(|addrs| addrs.pop())
+        } else {
+            None
+        };
+        let addr = match cached {
+            Some(addr) => {
+                self.qcache_hits += 1;
+                addr
+            }
+            None => {
+                let start = self.search(size)?;
+                self.carve(start, start, size);
+                start
+            }
+        };
+
+        self.sizemap.insert(addr, (size, diff));
+        Some(addr)
+    }
+
+    fn free(&mut self, ptr: usize) -> Result<(), AllocError> {
+        let (size, _) = self
+            .sizemap
+            .remove(&ptr)
+            .ok_or(AllocError::InvalidPointer(ptr))?;
+
+        if size <= self.qcache_max {
+            let addrs = self.qcaches.entry(size).or_default();
+            if addrs.len() < QCACHE_DEPTH {
+                addrs.push(ptr);
+                return Ok(());
+            }
+        }
+
+        self.release(ptr, size);
+        Ok(())
+    }
+
+    fn alloc_size(&self, ptr: usize) -> Option<usize> {
+        self.sizemap.get(&ptr).map(|&(size, _)| size)
+    }
+
+    fn largest_alloc(&self) -> usize {
+        self.segments.values().copied().max().unwrap_or(0)
+    }
+
+    /// Segments in quantum caches are not counted, since only
+    /// requests of their exact size can use them
+    fn free_space(&self) -> usize {
+        self.segments.values().sum()
+    }
+
+    fn internal_frag(&self) -> usize {
+        self.sizemap.values().map(|&(_, diff)| diff).sum()
+    }
+
+    fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (i, list) in self.lists.iter().enumerate() {
+            if list.is_empty() {
+                continue;
+            }
+            write!(out, "Segments of {} to {}:", 1usize << i, (2usize << i) - 1).unwrap();
+            for addr in list.iter() {
+                write!(out, " [{}, {})", addr, addr + self.segments[addr]).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+        for (size, addrs) in self.qcaches.iter() {
+            if !addrs.is_empty() {
+                writeln!(out, "Quantum cache for {}: {:?}", size, addrs).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans() {
+        let mut arena = Arena::new(100, 10, 1);
+        assert_eq!(
+            arena.add(105, 10),
+            Err(AllocError::BadSpan {
+                base: 105,
+                size: 10
+            })
+        );
+        assert!(arena.add(110, 10).is_ok());
+        assert_eq!(arena.free_space(), 20);
+
+        // Segments from different spans are never merged
+        assert_eq!(arena.largest_alloc(), 10);
+        assert!(arena.malloc(11).is_none());
+        let ptr = arena.malloc(10).unwrap();
+        assert!(arena.free(ptr).is_ok());
+        assert!(arena.free(ptr).is_err());
+        assert_eq!(arena.segments.len(), 2);
+    }
+
+    #[test]
+    fn quantum() {
+        let mut arena = Arena::new(0, 64, 8);
+        assert_eq!(arena.malloc(5), Some(0));
+        assert_eq!(arena.internal_frag(), 3);
+        assert_eq!(arena.alloc_at(20, 8), None);
+        assert_eq!(arena.alloc_at(24, 8), Some(24));
+        assert_eq!(arena.free_space(), 48);
+
+        arena.free(0).unwrap();
+        arena.free(24).unwrap();
+        assert_eq!(arena.internal_frag(), 0);
+        assert_eq!(arena.segments.len(), 1);
+    }
+
+    #[test]
+    fn fits() {
+        // Free segments of 5 at 0, and of 16 at 8
+        let setup = |fit| {
+            let mut arena = Arena::new(0, 32, 1).fit(fit);
+            arena.alloc_at(5, 3).unwrap();
+            arena.alloc_at(24, 8).unwrap();
+            arena
+        };
+        assert_eq!(setup(Fit::Instant).malloc(3), Some(0));
+
+        // 5 is not a power of two, so instant fit skips the list
+        // holding sizes 4 to 7 and splits the 16 instead
+        assert_eq!(setup(Fit::Instant).malloc(5), Some(8));
+        assert_eq!(setup(Fit::Best).malloc(5), Some(0));
+
+        // Nothing left on higher lists, so it searches that one
+        let mut arena = setup(Fit::Instant);
+        assert_eq!(arena.malloc(16), Some(8));
+        assert_eq!(arena.malloc(5), Some(0));
+    }
+
+    #[test]
+    fn quantum_cache() {
+        let mut arena = Arena::new(0, 64, 1).quantum_cache(8);
+        let small = arena.malloc(8).unwrap();
+        let big = arena.malloc(16).unwrap();
+        arena.free(small).unwrap();
+        arena.free(big).unwrap();
+
+        // The small segment stays cached instead of coalescing
+        assert_eq!(arena.free_space(), 56);
+        assert_eq!(arena.malloc(8), Some(small));
+        assert_eq!(arena.qcache_hits, 1);
+    }
+}
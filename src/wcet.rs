@@ -0,0 +1,69 @@
+use crate::Churn;
+
+/// An allocator that can say how much work a single op could ever
+/// take, as the most splits, merges and free blocks scanned any one
+/// malloc or free makes, whatever the heap looks like. Priced with
+/// `LatencyModel::cost`, that is a worst case execution time to hold
+/// the worst op of a run up against. None if the work grows with the
+/// heap, like a search through every free block
+pub trait WorstCase {
+    fn worst_op(&self) -> Option<Churn>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buddy::BuddyAllocator;
+    use crate::freelist::FreeList;
+    use crate::metrics::{LatencyModel, Metric, Metrics};
+    use crate::stack::StackAllocator;
+    use crate::tlsf::Tlsf;
+    use crate::workloads::{self, Warm};
+    use crate::{Allocator, Policy};
+
+    // The costliest op of a run of random sizes
+    fn observed(allocator: impl Allocator, model: &LatencyModel) -> f32 {
+        let results = workloads::random_from(
+            Warm::cold(allocator),
+            0.6,
+            8..=256,
+            Metrics::new().register(model.clone().worst_case()),
+            &mut rand::thread_rng(),
+        );
+        match results.metric("worst_latency") {
+            Some(Metric::Value(worst)) => *worst,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let model = LatencyModel::new(10.0, 1.0, 2.0).search(3.0);
+        let bound = |allocator: &dyn WorstCase| allocator.worst_op().map(|op| model.cost(op));
+        assert_eq!(bound(&Tlsf::new(0, 4096)), Some(15.0));
+        assert_eq!(bound(&BuddyAllocator::new(4, 12)), Some(34.0));
+        assert_eq!(
+            bound(&BuddyAllocator::new(4, 12).coalesce_on_free(false)),
+            Some(18.0)
+        );
+        let lazy = BuddyAllocator::new(4, 12)
+            .coalesce_on_free(false)
+            .lazy(true);
+        assert_eq!(bound(&lazy), None);
+        let list = FreeList::new(0, 4096, true);
+        assert_eq!(bound(&list), None);
+        let bounded = list.policy(Policy::Bounded {
+            budget: 4,
+            fallback: true,
+        });
+        assert_eq!(bound(&bounded), Some(27.0));
+        assert_eq!(bound(&StackAllocator::new(0, 4096)), Some(10.0));
+
+        // No op of a run comes near it
+        let tlsf = Tlsf::new(0, 4096);
+        assert!(observed(tlsf.clone(), &model) <= bound(&tlsf).unwrap());
+        let buddy = BuddyAllocator::new(4, 12);
+        assert!(observed(buddy.clone(), &model) <= bound(&buddy).unwrap());
+        assert!(observed(bounded.clone(), &model) <= bound(&bounded).unwrap());
+    }
+}
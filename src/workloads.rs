@@ -1,102 +1,1989 @@
-use crate::Allocator;
-use rand::distributions::Bernoulli;
+use crate::aging::{Aging, AgingPolicy};
+use crate::aligned::AlignedFreeList;
+use crate::arrivals::{Arrivals, Load};
+use crate::bins::BinAllocator;
+use crate::contiguous::{Contiguous, PageOrder};
+use crate::controller::{Controller, Occupancy};
+use crate::freelist::FreeList;
+use crate::head_to_head::HeadToHead;
+use crate::hoard::HoardSim;
+use crate::ids::IdSpace;
+use crate::metrics::{Metric, Metrics};
+use crate::mimalloc::Mimalloc;
+use crate::mobility::{Grouped, Mobility};
+use crate::numa::Numa;
+use crate::percpu::PerCpu;
+use crate::quickfit::QuickFit;
+use crate::reservation::{ReservationId, Reserving};
+use crate::segregated::SegregatedList;
+use crate::spans::SpanHeap;
+use crate::stats::Aggregate;
+use crate::synth;
+use crate::trace::{Op, Trace};
+use crate::usage::{Tally, Usage};
+use crate::{AllocFailure, Allocator, Churn, Recycling};
+use rand::distributions::{Bernoulli, WeightedIndex};
 use rand::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Range, RangeInclusive};
+use std::time::{Duration, Instant};
 
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct Results {
     pub malloc_fails: usize,
     pub free_fails: usize,
     pub internal_frag: usize,
     pub external_frag: f32,
+    pub churn: Churn,
+    /// Ops the run got through, and the most it had allocated at once
+    pub usage: Usage,
+    pub failures: Failures,
+    /// Mallocs and their failures by requested size, rounded up to a
+    /// power of two
+    pub size_classes: BTreeMap<usize, SizeClass>,
+    /// Mallocs and their failures by how full the heap was, in steps
+    /// of 10%. Only for allocators that report their live bytes
+    pub pressure: BTreeMap<usize, Pressure>,
+    /// The first `COLD_OPS` ops of the run, while the heap is still
+    /// mostly one big block, and the ops after them
+    pub cold: Phase,
+    pub steady: Phase,
+    /// What each registered collector reported, and whatever else
+    /// the workload measured, by name. The workloads document what
+    /// they add
+    pub metrics: BTreeMap<String, Metric>,
+    /// Real time the run took, bookkeeping and metrics included
+    pub elapsed: Duration,
 }
 
-pub fn stack<T: Allocator>(mut allocator: T, ratio: f64) -> Results {
+impl Results {
+    pub fn metric(&self, name: &str) -> Option<&Metric> {
+        self.metrics.get(name)
+    }
+
+    /// The metric's value, or 0 if the run didn't report it
+    pub fn value(&self, name: &str) -> f32 {
+        match self.metric(name) {
+            Some(Metric::Value(value)) => *value,
+            _ => 0.0,
+        }
+    }
+
+    /// The metric's series, or nothing if the run didn't report it
+    pub fn series(&self, name: &str) -> &[f32] {
+        match self.metric(name) {
+            Some(Metric::Series(series)) => series,
+            _ => &[],
+        }
+    }
+
+    /// The histogram metric's count in `bucket`, or 0 if the run
+    /// didn't report it
+    pub fn count(&self, name: &str, bucket: usize) -> usize {
+        match self.metric(name) {
+            Some(Metric::Histogram(counts)) => counts.get(&bucket).copied().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Mallocs and frees the run made
+    pub fn ops(&self) -> usize {
+        self.cold.ops + self.steady.ops
+    }
+
+    /// Ops per second of real time
+    pub fn throughput(&self) -> f32 {
+        self.ops() as f32 / self.elapsed.as_secs_f32().max(f32::EPSILON)
+    }
+
+    /// How much the fragmentation left after each quiet phase of
+    /// `cycles` grows per cycle over the second half of the cycles,
+    /// by least squares, so the heap filling up in the first ones
+    /// doesn't count. Near 0 it has converged, and above 0 it
+    /// ratchets up
+    pub fn drift(&self) -> f32 {
+        let quiet = self.series("quiet_frag");
+        let quiet = &quiet[quiet.len() / 2..];
+        let n = quiet.len() as f32;
+        if n < 2.0 {
+            return 0.0;
+        }
+        let mean_x = (n - 1.0) / 2.0;
+        let mean_y = quiet.iter().sum::<f32>() / n;
+        let (mut cov, mut var) = (0.0, 0.0);
+        for (i, frag) in quiet.iter().enumerate() {
+            cov += (i as f32 - mean_x) * (frag - mean_y);
+            var += (i as f32 - mean_x).powi(2);
+        }
+        cov / var
+    }
+
+    // Count a malloc of `size`, under its size class too
+    pub(crate) fn malloc(&mut self, size: usize, result: Result<usize, AllocFailure>) {
+        let class = self
+            .size_classes
+            .entry(size.next_power_of_two())
+            .or_default();
+        class.mallocs += 1;
+        if let Err(failure) = result {
+            self.malloc_fails += 1;
+            self.failures.add(failure);
+            class.failures.add(failure);
+        }
+    }
+}
+
+/// Malloc failures broken down by `AllocFailure` reason
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Failures {
+    pub oversized: usize,
+    pub no_fit: usize,
+    pub exhausted: usize,
+}
+
+impl Failures {
+    pub fn total(&self) -> usize {
+        self.oversized + self.no_fit + self.exhausted
+    }
+
+    fn add(&mut self, failure: AllocFailure) {
+        match failure {
+            AllocFailure::Oversized => self.oversized += 1,
+            AllocFailure::NoFit => self.no_fit += 1,
+            AllocFailure::Exhausted => self.exhausted += 1,
+        }
+    }
+}
+
+/// How many ops of a run count as its cold start
+pub const COLD_OPS: usize = 100;
+
+/// How many ops apart workloads sample anything they track over time
+pub const SAMPLE_EVERY: usize = 100;
+
+/// Bytes in a page, for workloads that only allocate whole pages
+pub const PAGE_SIZE: usize = 4096;
+
+/// What happened during part of a run
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Phase {
+    pub ops: usize,
+    pub malloc_fails: usize,
+    pub churn: Churn,
+    /// Mean over the ops, taken right after each
+    pub external_frag: f32,
+}
+
+/// The mallocs made while the heap was about as full, so allocators
+/// can be compared at the same pressure
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pressure {
+    pub mallocs: usize,
+    pub bytes: usize,
+    pub malloc_fails: usize,
+    pub failed_bytes: usize,
+}
+
+impl Pressure {
+    /// Failed bytes per byte requested
+    pub fn failure_rate(&self) -> f32 {
+        self.failed_bytes as f32 / self.bytes.max(1) as f32
+    }
+}
+
+/// The mallocs of one size class and how they failed
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeClass {
+    pub mallocs: usize,
+    pub failures: Failures,
+}
+
+// One run of a workload. Tallies failures and tells the collectors
+// about every op. `capacity` is the whole heap, for allocators that
+// report their live bytes
+struct Run {
+    results: Results,
+    metrics: Metrics,
+    capacity: Option<usize>,
+    churn: Churn,
+    tally: Tally,
+    start: Instant,
+}
+
+impl Run {
+    fn new<T: Allocator>(allocator: &T, mut metrics: Metrics) -> Self {
+        metrics.start(allocator);
+        let capacity = allocator
+            .live_bytes()
+            .map(|live| live + allocator.free_space() + allocator.internal_frag());
+        Self {
+            results: Results::default(),
+            metrics,
+            capacity,
+            churn: allocator.churn(),
+            tally: Tally::default(),
+            start: Instant::now(),
+        }
+    }
+
+    // Count an op towards the cold start or the steady state, closing
+    // the cold start once it has all its ops
+    fn tick<T: Allocator>(&mut self, allocator: &T) {
+        let results = &mut self.results;
+        let phase = if results.cold.ops < COLD_OPS {
+            &mut results.cold
+        } else {
+            &mut results.steady
+        };
+        phase.ops += 1;
+        phase.external_frag += allocator.external_frag();
+
+        if results.cold.ops == COLD_OPS && results.steady.ops == 0 {
+            results.cold.churn = allocator.churn().since(self.churn);
+            results.cold.malloc_fails = results.malloc_fails;
+        }
+    }
+
+    fn malloc<T: Allocator>(&mut self, allocator: &mut T, size: usize) -> Option<usize> {
+        let result = allocator.try_malloc(size);
+        self.record_malloc(allocator, size, result)
+    }
+
+    // For mallocs that don't go through `try_malloc`
+    fn record_malloc<T: Allocator>(
+        &mut self,
+        allocator: &T,
+        size: usize,
+        result: Result<usize, AllocFailure>,
+    ) -> Option<usize> {
+        self.metrics.malloc(allocator, size, result);
+        self.results.malloc(size, result);
+        if let Ok(ptr) = result {
+            self.tally.malloc(ptr, size);
+        }
+        self.tick(allocator);
+        if let (Some(capacity), Some(live)) = (self.capacity, allocator.live_bytes()) {
+            // How full the heap was when the request came in
+            let before = if result.is_ok() {
+                live.saturating_sub(size)
+            } else {
+                live
+            };
+            let step = (10 * before / capacity.max(1)).min(10) * 10;
+            let pressure = self.results.pressure.entry(step).or_default();
+            pressure.mallocs += 1;
+            pressure.bytes += size;
+            if result.is_err() {
+                pressure.malloc_fails += 1;
+                pressure.failed_bytes += size;
+            }
+        }
+        result.ok()
+    }
+
+    fn free<T: Allocator>(&mut self, allocator: &mut T, ptr: usize) {
+        let freed = allocator.free(ptr).is_ok();
+        if freed {
+            self.tally.free(ptr);
+        } else {
+            self.results.free_fails += 1;
+        }
+        self.tick(allocator);
+        self.metrics.free(allocator, ptr, freed);
+    }
+
+    // Report multi-block mallocs by order, the way `pages` documents
+    fn orders(&mut self, orders: &BTreeMap<usize, PageOrder>) {
+        for (&order, counts) in orders.iter() {
+            self.metrics.tally("order_requests", order, counts.requests);
+            self.metrics.tally("order_failures", order, counts.failures);
+            self.metrics
+                .tally("order_scattered", order, counts.scattered);
+        }
+    }
+
+    // Report a probe for a run of pages of `order`, the way
+    // `high_order` documents
+    fn probed(&mut self, order: usize, failed: bool) {
+        let name = format!("probe_failed_{}", order);
+        self.metrics.sample(&name, failed as usize as f32);
+    }
+
+    // Report how an allocator built on spans or runs recycled them,
+    // the way `spans` documents
+    fn recycling(&mut self, recycling: Recycling) {
+        self.metrics.count("recycling_taken", recycling.taken);
+        self.metrics.count("recycling_emptied", recycling.emptied);
+        self.metrics.count("recycling_returned", recycling.returned);
+        self.metrics.count("recycling_retained", recycling.retained);
+        self.metrics.count("recycling_reused", recycling.reused);
+        self.metrics
+            .count("recycling_peak_retained", recycling.peak_retained);
+    }
+
+    // Follow allocations the workload moved, given as old and new
+    // address
+    fn moved(&mut self, moves: &BTreeMap<usize, usize>) {
+        self.tally.moved(moves);
+    }
+
+    fn finish<T: Allocator>(self, allocator: &T) -> Results {
+        let mut results = self.results;
+        results.internal_frag = allocator.internal_frag();
+        results.external_frag = allocator.external_frag();
+        results.churn = allocator.churn();
+        results.metrics = self.metrics.finish(allocator);
+
+        let churn = allocator.churn().since(self.churn);
+        results.usage = self.tally.usage(churn);
+        if results.steady.ops == 0 {
+            results.cold.churn = churn;
+            results.cold.malloc_fails = results.malloc_fails;
+        }
+        results.steady.churn = churn.since(results.cold.churn);
+        results.steady.malloc_fails = results.malloc_fails - results.cold.malloc_fails;
+        for phase in [&mut results.cold, &mut results.steady] {
+            phase.external_frag /= phase.ops.max(1) as f32;
+        }
+        results.elapsed = self.start.elapsed();
+        results
+    }
+}
+
+/// A heap that has already been used, and the pointers still live
+/// in it, so a workload can start from an aged heap instead of an
+/// empty one
+#[derive(Debug, Clone)]
+pub struct Warm<T> {
+    pub allocator: T,
+    pub live: Vec<usize>,
+    /// Frees the warm-up made that the allocator refused. Those
+    /// pointers stay allocated, so they are still in `live`
+    pub free_fails: usize,
+}
+
+impl<T: Allocator> Warm<T> {
+    /// Start from an empty heap
+    pub fn cold(allocator: T) -> Self {
+        Self {
+            allocator,
+            live: Vec::new(),
+            free_fails: 0,
+        }
+    }
+
+    /// Replay a trace, keeping whatever it leaves allocated
+    pub fn from_trace(mut allocator: T, trace: &Trace) -> Self {
+        let mut live = Vec::new();
+        for event in trace.replay(&mut allocator) {
+            match (event.op, event.block) {
+                (Op::Malloc { .. } | Op::Calloc { .. }, Some((ptr, _))) => live.push(ptr),
+                (Op::Free { .. }, Some((ptr, _))) => live.retain(|&p| p != ptr),
+                _ => {}
+            }
+        }
+
+        Self {
+            allocator,
+            live,
+            free_fails: 0,
+        }
+    }
+
+    /// Fill the heap with blocks of `size` and free every other
+    /// one, leaving a checkerboard of holes. Blocks the allocator
+    /// won't free are left live
+    pub fn checkerboard(mut allocator: T, size: usize) -> Self {
+        let mut ptrs = Vec::new();
+        while let Some(ptr) = allocator.malloc(size) {
+            ptrs.push(ptr);
+        }
+
+        let mut live = Vec::new();
+        let mut free_fails = 0;
+        for (i, ptr) in ptrs.into_iter().enumerate() {
+            if i % 2 == 1 {
+                live.push(ptr);
+            } else if allocator.free(ptr).is_err() {
+                live.push(ptr);
+                free_fails += 1;
+            }
+        }
+
+        Self {
+            allocator,
+            live,
+            free_fails,
+        }
+    }
+
+    /// Age the heap with `ops` random mallocs and frees, sizes drawn
+    /// from `sizes`, so measurements start from a steady state heap.
+    /// Nothing about the churn itself is recorded, apart from the
+    /// frees the allocator refused, which are left live
+    pub fn aged<R: Rng>(
+        mut allocator: T,
+        ops: usize,
+        ratio: f64,
+        sizes: RangeInclusive<usize>,
+        rng: &mut R,
+    ) -> Self {
+        let mut live = Vec::new();
+        let mut free_fails = 0;
+
+        for _ in 0..ops {
+            if rng.gen_bool(ratio) {
+                if let Some(ptr) = allocator.malloc(rng.gen_range(sizes.clone())) {
+                    live.push(ptr);
+                }
+            } else if !live.is_empty() {
+                let i = rng.gen_range(0..live.len());
+                if allocator.free(live[i]).is_ok() {
+                    live.swap_remove(i);
+                } else {
+                    free_fails += 1;
+                }
+            }
+        }
+
+        Self {
+            allocator,
+            live,
+            free_fails,
+        }
+    }
+}
+
+pub fn stack<T: Allocator, R: Rng>(allocator: T, ratio: f64, rng: &mut R) -> Results {
+    stack_from(Warm::cold(allocator), ratio, Metrics::new(), rng)
+}
+
+/// Like `stack`, but the live pointers of the warm heap are on the
+/// stack to begin with
+pub fn stack_from<T: Allocator, R: Rng>(
+    warm: Warm<T>,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
     let size = 32;
-    let mut rng = rand::thread_rng();
-    let mut results = Results::default();
 
     let samples: Vec<bool> = Bernoulli::new(ratio)
         .unwrap()
-        .sample_iter(&mut rng)
+        .sample_iter(&mut *rng)
         .take(990)
         .collect();
-    let mut ptrs = Vec::new();
+    let Warm {
+        mut allocator,
+        live: mut ptrs,
+        ..
+    } = warm;
+    let warm_churn = allocator.churn();
+    let mut run = Run::new(&allocator, metrics);
 
     for _ in 0..10 {
-        match allocator.malloc(size) {
-            Some(ptr) => ptrs.push(ptr),
-            None => {
-                results.malloc_fails += 1;
-            }
+        if let Some(ptr) = run.malloc(&mut allocator, size) {
+            ptrs.push(ptr);
         }
     }
 
     for sample in samples.into_iter() {
         if sample {
-            match allocator.malloc(size) {
-                Some(ptr) => ptrs.push(ptr),
-                None => {
-                    results.malloc_fails += 1;
-                }
+            if let Some(ptr) = run.malloc(&mut allocator, size) {
+                ptrs.push(ptr);
             }
         } else {
             if ptrs.is_empty() {
                 continue;
             }
-            if allocator.free(ptrs.pop().unwrap()).is_err() {
-                results.free_fails += 1;
+            run.free(&mut allocator, ptrs.pop().unwrap());
+        }
+    }
+
+    let mut results = run.finish(&allocator);
+    results.churn = allocator.churn().since(warm_churn);
+
+    results
+}
+
+pub fn random_memory<T: Allocator, R: Rng>(allocator: T, ratio: f64, rng: &mut R) -> Results {
+    random_sized(allocator, ratio, 32..=128, rng)
+}
+
+/// Like `random_memory`, but with sizes drawn uniformly from `sizes`
+pub fn random_sized<T: Allocator, R: Rng>(
+    allocator: T,
+    ratio: f64,
+    sizes: RangeInclusive<usize>,
+    rng: &mut R,
+) -> Results {
+    random_from(Warm::cold(allocator), ratio, sizes, Metrics::new(), rng)
+}
+
+/// Like `random_sized`, but the live pointers of the warm heap can
+/// be freed too
+pub fn random_from<T: Allocator, R: Rng>(
+    warm: Warm<T>,
+    ratio: f64,
+    sizes: RangeInclusive<usize>,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(990)
+        .collect();
+    let Warm {
+        mut allocator,
+        live: mut ptrs,
+        ..
+    } = warm;
+    let warm_churn = allocator.churn();
+    let mut run = Run::new(&allocator, metrics);
+
+    for _ in 0..10 {
+        if let Some(ptr) = run.malloc(&mut allocator, rng.gen_range(sizes.clone())) {
+            ptrs.push(ptr);
+        }
+    }
+
+    for sample in samples.into_iter() {
+        if sample {
+            let size = rng.gen_range(sizes.clone());
+            if let Some(ptr) = run.malloc(&mut allocator, size) {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
             }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
         }
     }
 
-    results.internal_frag = allocator.internal_frag();
-    results.external_frag = allocator.external_frag();
+    let mut results = run.finish(&allocator);
+    results.churn = allocator.churn().since(warm_churn);
 
     results
 }
 
-pub fn random_memory<T: Allocator>(mut allocator: T, ratio: f64) -> Results {
-    let mut rng = rand::thread_rng();
-    let mut results = Results::default();
+/// Like `random_memory`, but half of the time a live allocation
+/// is shrunk to half its size instead of being freed. Shrinks are
+/// not shown to the collectors. Reports the bytes the shrinks gave
+/// up as `shrink_requested`, and the bytes the allocator got back
+/// as `shrink_recovered`
+pub fn shrinking<T: Allocator, R: Rng>(
+    mut allocator: T,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
 
     let samples: Vec<bool> = Bernoulli::new(ratio)
         .unwrap()
-        .sample_iter(&mut rng)
+        .sample_iter(&mut *rng)
         .take(990)
         .collect();
-    let mut ptrs = Vec::new();
+    let mut ptrs: Vec<(usize, usize)> = Vec::new();
 
     for _ in 0..10 {
-        match allocator.malloc(rng.gen_range(32..=128)) {
-            Some(ptr) => ptrs.push(ptr),
-            None => {
-                results.malloc_fails += 1;
+        let size = rng.gen_range(32..=128);
+        if let Some(ptr) = run.malloc(&mut allocator, size) {
+            ptrs.push((ptr, size));
+        }
+    }
+
+    for sample in samples.into_iter() {
+        if sample {
+            let size = rng.gen_range(32..=128);
+            if let Some(ptr) = run.malloc(&mut allocator, size) {
+                ptrs.push((ptr, size));
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            let (ptr, size) = ptrs[i];
+            if size > 1 && rng.gen_bool(0.5) {
+                let new_size = size / 2;
+                match allocator.shrink(ptr, new_size) {
+                    Ok(recovered) => {
+                        run.metrics.count("shrink_requested", size - new_size);
+                        run.metrics.count("shrink_recovered", recovered);
+                        ptrs[i].1 = new_size;
+                    }
+                    Err(_) => run.results.free_fails += 1,
+                }
+            } else {
+                run.free(&mut allocator, ptrs.remove(i).0);
             }
         }
     }
 
+    run.finish(&allocator)
+}
+
+/// Like `random_memory`, but a malloc that finds no block to fit has
+/// the allocator coalesce and tries once more if anything merged.
+/// Meant for allocators that leave coalescing until asked, to weigh
+/// the merges it costs against the mallocs it saves. Reports the
+/// times it coalesced as `coalesces`, and the mallocs that went
+/// through after as `rescued`
+pub fn coalescing<T: Allocator, R: Rng>(
+    mut allocator: T,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
     for sample in samples.into_iter() {
         if sample {
             let size = rng.gen_range(32..=128);
-            match allocator.malloc(size) {
-                Some(ptr) => ptrs.push(ptr),
-                None => {
-                    results.malloc_fails += 1;
+            let mut result = allocator.try_malloc(size);
+            if result == Err(AllocFailure::NoFit) {
+                run.metrics.count("coalesces", 1);
+                if allocator.coalesce() > 0 {
+                    result = allocator.try_malloc(size);
+                    if result.is_ok() {
+                        run.metrics.count("rescued", 1);
+                    }
                 }
             }
+            if let Some(ptr) = run.record_malloc(&allocator, size, result) {
+                ptrs.push(ptr);
+            }
         } else {
             if ptrs.is_empty() {
                 continue;
             }
             let i = rng.gen_range(0..ptrs.len());
-            if allocator.free(ptrs.remove(i)).is_err() {
-                results.free_fails += 1;
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+    }
+
+    run.finish(&allocator)
+}
+
+/// Device memory over `frames` frames of rendering. Each frame frees
+/// whatever has expired, then streams in a texture with a chance of
+/// 0.3 and makes one to three buffers. Textures are power of two
+/// sizes from 64K to 4M, and live for 50 to 500 frames. Buffers are
+/// any multiple of 4K from 16K to 2M, and live for 1 to 8 frames,
+/// except for one in a hundred, which stays pinned for good, like a
+/// buffer mapped for the host
+pub fn device<T: Allocator, R: Rng>(
+    mut allocator: T,
+    frames: usize,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    // Live allocations with the frame they expire at, if ever
+    let mut live: Vec<(usize, Option<usize>)> = Vec::new();
+
+    for frame in 0..frames {
+        let mut i = 0;
+        while i < live.len() {
+            if live[i].1 == Some(frame) {
+                let (ptr, _) = live.swap_remove(i);
+                run.free(&mut allocator, ptr);
+            } else {
+                i += 1;
+            }
+        }
+
+        if rng.gen_bool(0.3) {
+            let size = 1 << rng.gen_range(16..=22);
+            if let Some(ptr) = run.malloc(&mut allocator, size) {
+                live.push((ptr, Some(frame + rng.gen_range(50..=500))));
+            }
+        }
+        for _ in 0..rng.gen_range(1..=3) {
+            let size = rng.gen_range(4..=512) * 4096;
+            if let Some(ptr) = run.malloc(&mut allocator, size) {
+                let expires = if rng.gen_bool(0.01) {
+                    None
+                } else {
+                    Some(frame + rng.gen_range(1..=8))
+                };
+                live.push((ptr, expires));
             }
         }
     }
 
-    results.internal_frag = allocator.internal_frag();
-    results.external_frag = allocator.external_frag();
+    run.finish(&allocator)
+}
 
-    results
+/// Like `coalescing`, but a malloc that finds no block to fit makes
+/// the workload compact the heap and try once more. A `pinned`
+/// fraction of allocations can't be moved, and the live pointers
+/// follow the rest as they move. Reports the times it compacted as
+/// `compactions`, the mallocs that went through after as `rescued`,
+/// the bytes moved as `moved_bytes`, and the mean fraction of the
+/// free space the largest free block held right after as
+/// `consolidation`
+pub fn compacting<R: Rng>(
+    mut list: FreeList,
+    ratio: f64,
+    pinned: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&list, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+    let mut sizes = BTreeMap::new();
+    let mut unmovable = BTreeSet::new();
+    let (mut compactions, mut consolidation) = (0, 0.0);
+
+    for sample in samples.into_iter() {
+        if sample {
+            let size = rng.gen_range(32..=128);
+            let mut result = list.try_malloc(size);
+            if result == Err(AllocFailure::NoFit) {
+                let moves: BTreeMap<usize, usize> = list.compact(&unmovable).into_iter().collect();
+                run.moved(&moves);
+                let moved = |ptr: usize| moves.get(&ptr).copied().unwrap_or(ptr);
+                let moved_bytes = moves.keys().map(|ptr| sizes[ptr]).sum();
+                run.metrics.count("moved_bytes", moved_bytes);
+                ptrs.iter_mut().for_each(|ptr| *ptr = moved(*ptr));
+                sizes = sizes
+                    .into_iter()
+                    .map(|(ptr, size)| (moved(ptr), size))
+                    .collect();
+                compactions += 1;
+                consolidation += list.largest_alloc() as f32 / list.free_space().max(1) as f32;
+
+                result = list.try_malloc(size);
+                if result.is_ok() {
+                    run.metrics.count("rescued", 1);
+                }
+            }
+            if let Some(ptr) = run.record_malloc(&list, size, result) {
+                ptrs.push(ptr);
+                sizes.insert(ptr, size);
+                if rng.gen_bool(pinned) {
+                    unmovable.insert(ptr);
+                }
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let ptr = ptrs.remove(rng.gen_range(0..ptrs.len()));
+            sizes.remove(&ptr);
+            unmovable.remove(&ptr);
+            run.free(&mut list, ptr);
+        }
+    }
+
+    run.metrics.count("compactions", compactions);
+    run.metrics
+        .report("consolidation", consolidation / compactions.max(1) as f32);
+    run.finish(&list)
+}
+
+/// Like `random_memory`, but every malloc has to come from one of
+/// `windows`, picked at random, like memory for DMA or for one NUMA
+/// node. Failures are also broken down by window, as histograms
+/// by the window's index of `window_mallocs`, `window_bytes`,
+/// `window_malloc_fails` and `window_failed_bytes`
+pub fn regions<R: Rng>(
+    mut list: FreeList,
+    ratio: f64,
+    windows: &[Range<usize>],
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&list, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let size = rng.gen_range(32..=128);
+            let i = rng.gen_range(0..windows.len());
+            let window = &windows[i];
+            let result = match list.malloc_in_range(size, window.start, window.end) {
+                Some(ptr) => Ok(ptr),
+                None if list.free_space() < size => Err(AllocFailure::Exhausted),
+                None => Err(AllocFailure::NoFit),
+            };
+
+            run.metrics.tally("window_mallocs", i, 1);
+            run.metrics.tally("window_bytes", i, size);
+            let failed = result.is_err() as usize;
+            run.metrics.tally("window_malloc_fails", i, failed);
+            run.metrics.tally("window_failed_bytes", i, failed * size);
+            if let Some(ptr) = run.record_malloc(&list, size, result) {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut list, ptrs.remove(i));
+        }
+    }
+
+    run.finish(&list)
+}
+
+/// `random_memory` on a free list, counting the mallocs as
+/// `searches` and the free blocks they scanned as `nodes_scanned`
+pub fn searching<R: Rng>(mut list: FreeList, ratio: f64, metrics: Metrics, rng: &mut R) -> Results {
+    let mut run = Run::new(&list, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let ptr = run.malloc(&mut list, rng.gen_range(32..=128));
+            run.metrics.count("searches", 1);
+            run.metrics.count("nodes_scanned", list.last_scan().1);
+            if let Some(ptr) = ptr {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut list, ptrs.remove(i));
+        }
+    }
+
+    run.finish(&list)
+}
+
+/// Like `random_memory`, but every malloc asks for an alignment drawn
+/// from `aligns`, and the searches and the free blocks they scan
+/// are counted like in `searching`
+pub fn aligned<R: Rng>(
+    mut allocator: AlignedFreeList,
+    ratio: f64,
+    aligns: &[usize],
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    aligned_requests(&mut allocator, ratio, aligns, metrics, rng, |list| {
+        list.last_scan()
+    })
+}
+
+/// `aligned` on a plain free list, which pads the front of a block
+/// to align it
+pub fn aligned_padded<R: Rng>(
+    mut allocator: FreeList,
+    ratio: f64,
+    aligns: &[usize],
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    aligned_requests(&mut allocator, ratio, aligns, metrics, rng, |list| {
+        list.last_scan().1
+    })
+}
+
+fn aligned_requests<T: Allocator, R: Rng>(
+    allocator: &mut T,
+    ratio: f64,
+    aligns: &[usize],
+    metrics: Metrics,
+    rng: &mut R,
+    last_scan: impl Fn(&T) -> usize,
+) -> Results {
+    let mut run = Run::new(&*allocator, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let size = rng.gen_range(32..=128);
+            let align = *aligns.choose(rng).unwrap_or(&1);
+            let result = match allocator.malloc_aligned(size, align) {
+                Some(ptr) => Ok(ptr),
+                None if allocator.free_space() < size => Err(AllocFailure::Exhausted),
+                None => Err(AllocFailure::NoFit),
+            };
+            run.metrics.count("searches", 1);
+            run.metrics.count("nodes_scanned", last_scan(allocator));
+            if let Some(ptr) = run.record_malloc(&*allocator, size, result) {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(allocator, ptrs.remove(i));
+        }
+    }
+
+    run.finish(&*allocator)
+}
+
+/// Like `random_sized` on segregated free lists, counting the
+/// mallocs as `searches` and the free blocks they scanned as
+/// `nodes_scanned`
+pub fn segregated<R: Rng>(
+    mut list: SegregatedList,
+    ratio: f64,
+    sizes: RangeInclusive<usize>,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&list, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let size = rng.gen_range(sizes.clone());
+            let result = list.try_malloc(size);
+            run.metrics.count("searches", 1);
+            run.metrics.count("nodes_scanned", list.last_scan().1);
+            if let Some(ptr) = run.record_malloc(&list, size, result) {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut list, ptrs.remove(i));
+        }
+    }
+
+    run.finish(&list)
+}
+
+/// Like `random_memory`, but sizes come from a few fixed classes
+/// and every op runs on a random CPU, so blocks are often freed on
+/// a different CPU than the one that allocated them. Reports the
+/// caches' `cache_hits`, `cache_misses` and `cache_flushes`
+pub fn per_cpu<T: Allocator, R: Rng>(
+    mut allocator: PerCpu<T>,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let sizes = [16, 32, 64, 128];
+    let mut run = Run::new(&allocator, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        allocator.switch_cpu(rng.gen_range(0..allocator.cpus()));
+        if i < 10 || sample {
+            if let Some(ptr) = run.malloc(&mut allocator, *sizes.choose(rng).unwrap()) {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+    }
+
+    run.metrics.count("cache_hits", allocator.hits);
+    run.metrics.count("cache_misses", allocator.misses);
+    run.metrics.count("cache_flushes", allocator.flushes);
+    run.finish(&allocator)
+}
+
+/// Like `per_cpu`, but on one heap, with the policy deciding where
+/// every free goes. Reports the hot cache's `cache_hits` and
+/// `cache_misses`, and the frees sent to the cache, to the free
+/// structure and to be coalesced as `routed_cached`, `routed_freed`
+/// and `routed_coalesced`
+pub fn aging<T: Allocator, P: AgingPolicy, R: Rng>(
+    mut allocator: Aging<T, P>,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let sizes = [16, 32, 64, 128];
+    let mut run = Run::new(&allocator, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            if let Some(ptr) = run.malloc(&mut allocator, *sizes.choose(rng).unwrap()) {
+                ptrs.push(ptr);
+            }
+        } else if !ptrs.is_empty() {
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+    }
+
+    run.metrics.count("cache_hits", allocator.hits);
+    run.metrics.count("cache_misses", allocator.misses);
+    run.metrics.count("routed_cached", allocator.cached);
+    run.metrics.count("routed_freed", allocator.freed);
+    run.metrics.count("routed_coalesced", allocator.coalesced);
+    run.finish(&allocator)
+}
+
+/// Random churn from one allocation stream per NUMA node, with
+/// stream `n` making `n + 1` times as many ops as stream 0. Each
+/// stream asks for memory on its home node, except for a `remote`
+/// fraction of mallocs that ask for another node at random. Reports
+/// the mallocs placed on the stream's home node as `local_mallocs`
+/// and elsewhere as `remote_mallocs`, and the gap between the
+/// fullest and emptiest node every `SAMPLE_EVERY` ops, as a fraction
+/// of a node, as the series `node_imbalance`
+pub fn numa<R: Rng>(
+    mut allocator: Numa,
+    ratio: f64,
+    remote: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    let nodes = allocator.nodes();
+    let streams = WeightedIndex::new(1..=nodes).unwrap();
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        let home = streams.sample(rng);
+        if i < 10 || sample {
+            let node = if nodes > 1 && rng.gen_bool(remote) {
+                (home + rng.gen_range(1..nodes)) % nodes
+            } else {
+                home
+            };
+            allocator.switch_node(node);
+            if let Some(ptr) = run.malloc(&mut allocator, rng.gen_range(32..=128)) {
+                if allocator.node_of(ptr) == home {
+                    run.metrics.count("local_mallocs", 1);
+                } else {
+                    run.metrics.count("remote_mallocs", 1);
+                }
+                ptrs.push(ptr);
+            }
+        } else if !ptrs.is_empty() {
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+
+        if (i + 1).is_multiple_of(SAMPLE_EVERY) {
+            run.metrics.sample("node_imbalance", allocator.imbalance());
+        }
+    }
+
+    run.finish(&allocator)
+}
+
+/// Random churn of runs of `block_size` pages, like a kernel's page
+/// allocator sees. A malloc asks for `2^order` pages, with each order
+/// up to `max_order` half as likely as the one below it, so most
+/// take a single page. Reports the mallocs of each order, the ones
+/// that found no run, and of those the ones the allocator scattered
+/// instead, as histograms by order of `order_requests`,
+/// `order_failures` and `order_scattered`. Mallocs the allocator
+/// scattered count as served
+pub fn pages<T: Allocator, R: Rng>(
+    mut allocator: Contiguous<T>,
+    ratio: f64,
+    block_size: usize,
+    max_order: usize,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    let orders = WeightedIndex::new((0..=max_order).map(|order| 1 << (max_order - order))).unwrap();
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let n = 1 << orders.sample(rng);
+            let size = n * block_size;
+            let result = match allocator.malloc_blocks(n, block_size) {
+                Some(blocks) => Ok(blocks.first()),
+                None if allocator.free_space() < size => Err(AllocFailure::Exhausted),
+                None => Err(AllocFailure::NoFit),
+            };
+            if let Some(ptr) = run.record_malloc(&allocator, size, result) {
+                ptrs.push(ptr);
+            }
+        } else if !ptrs.is_empty() {
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+    }
+
+    run.orders(&allocator.orders);
+    run.finish(&allocator)
+}
+
+// The churn of pages `high_order` describes, which `kcompactd` and
+// `mobility` run too, out of a heap of `total` pages
+struct PageChurn {
+    target: usize,
+    low: usize,
+    high: usize,
+    orders: WeightedIndex<u32>,
+    // Each live allocation and how many pages it has
+    live: Vec<(usize, usize)>,
+    pages: usize,
+    filled: bool,
+}
+
+// What the churn does next: malloc some pages, or free a live
+// allocation, which it has already let go of
+enum PageOp {
+    Malloc(usize),
+    Free(usize),
+}
+
+impl PageChurn {
+    fn new(total: usize, occupancy: f64) -> Self {
+        let target = (occupancy * total as f64) as usize;
+        Self {
+            target,
+            low: target.saturating_sub(total / 10),
+            high: target + total / 10,
+            orders: WeightedIndex::new([4, 2, 1, 1]).unwrap(),
+            live: Vec::new(),
+            pages: 0,
+            filled: false,
+        }
+    }
+
+    fn next<R: Rng>(&mut self, rng: &mut R) -> PageOp {
+        self.filled |= self.pages >= self.target;
+        if !self.filled
+            || self.live.is_empty()
+            || self.pages <= self.low
+            || (self.pages < self.high && rng.gen_bool(0.5))
+        {
+            PageOp::Malloc(1 << self.orders.sample(rng))
+        } else {
+            let (ptr, n) = self.live.swap_remove(rng.gen_range(0..self.live.len()));
+            self.pages -= n;
+            PageOp::Free(ptr)
+        }
+    }
+
+    // Keep a malloc of `n` pages that went through
+    fn push(&mut self, ptr: usize, n: usize) {
+        self.live.push((ptr, n));
+        self.pages += n;
+    }
+}
+
+/// Pages of `block_size` bytes coming and going the way they do in
+/// a kernel. Half the allocations take a single page, and the rest
+/// two, four or eight, like slabs and stacks do. The heap fills up
+/// until `occupancy` of its pages are allocated, and from then on
+/// each op frees a random allocation or makes one with equal
+/// chance, as long as no more than a tenth of the heap more or less
+/// than that is allocated, so the free pages spread out over time.
+/// Every `SAMPLE_EVERY` ops it probes each order from 1 up to
+/// `max_order`, asking for a run of `2^order` pages and freeing it
+/// right away, like the kernel's high order allocation tests. The
+/// probes aren't counted as mallocs of the run. Each probe is
+/// reported as 1 if it failed and 0 if not, in a series for its
+/// order named like `probe_failed_3`, and the multi-block mallocs
+/// by order like in `pages`
+pub fn high_order<T: Allocator, R: Rng>(
+    mut allocator: Contiguous<T>,
+    ops: usize,
+    occupancy: f64,
+    block_size: usize,
+    max_order: usize,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    let mut churn = PageChurn::new(allocator.free_space() / block_size, occupancy);
+
+    for op in 0..ops {
+        match churn.next(rng) {
+            PageOp::Malloc(n) => {
+                if let Some(ptr) = run.malloc(&mut allocator, n * block_size) {
+                    churn.push(ptr, n);
+                }
+            }
+            PageOp::Free(ptr) => run.free(&mut allocator, ptr),
+        }
+
+        if (op + 1).is_multiple_of(SAMPLE_EVERY) {
+            for order in 1..=max_order {
+                let ptr = allocator.malloc_contiguous(1 << order, block_size);
+                if let Some(ptr) = ptr {
+                    allocator.free(ptr).unwrap();
+                }
+                run.probed(order, ptr.is_none());
+            }
+        }
+    }
+
+    run.orders(&allocator.orders);
+    run.finish(&allocator)
+}
+
+/// What `kcompactd` has to work around and how much work it may do:
+/// the chance an allocation is pinned where it is, and the most
+/// bytes moved each time it wakes up
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Compaction {
+    pub unmovable: f64,
+    pub budget: usize,
+}
+
+/// Like `high_order` on a free list, with a fraction
+/// `compaction.unmovable` of the allocations pinned where they are,
+/// and the rest movable like the kernel's page cache and anonymous
+/// pages. Right before every probe the free list is compacted, moving
+/// at most `compaction.budget` bytes, like kcompactd waking up to do
+/// a bounded amount of work. The live pointers follow the
+/// allocations that moved. Reports the probes like `high_order`, the
+/// times it compacted as `compactions` and the bytes moved as
+/// `moved_bytes`, and the probes of each order, and how many failed,
+/// as histograms of `order_requests` and `order_failures`
+pub fn kcompactd<R: Rng>(
+    mut list: FreeList,
+    ops: usize,
+    occupancy: f64,
+    compaction: Compaction,
+    max_order: usize,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let Compaction { unmovable, budget } = compaction;
+    let mut run = Run::new(&list, metrics);
+    let mut churn = PageChurn::new(list.free_space() / PAGE_SIZE, occupancy);
+    let mut pinned = BTreeSet::new();
+
+    for op in 0..ops {
+        match churn.next(rng) {
+            PageOp::Malloc(n) => {
+                if let Some(ptr) = run.malloc(&mut list, n * PAGE_SIZE) {
+                    churn.push(ptr, n);
+                    if rng.gen_bool(unmovable) {
+                        pinned.insert(ptr);
+                    }
+                }
+            }
+            PageOp::Free(ptr) => {
+                pinned.remove(&ptr);
+                run.free(&mut list, ptr);
+            }
+        }
+
+        if !(op + 1).is_multiple_of(SAMPLE_EVERY) {
+            continue;
+        }
+        if budget > 0 {
+            let moves: BTreeMap<usize, usize> =
+                list.compact_budget(&pinned, budget).into_iter().collect();
+            run.moved(&moves);
+            for (ptr, n) in churn.live.iter_mut() {
+                if let Some(&to) = moves.get(ptr) {
+                    *ptr = to;
+                    run.metrics.count("moved_bytes", *n * PAGE_SIZE);
+                }
+            }
+            run.metrics.count("compactions", 1);
+        }
+        for order in 1..=max_order {
+            let ptr = list.malloc_contiguous(1 << order, PAGE_SIZE);
+            if let Some(ptr) = ptr {
+                list.free(ptr).unwrap();
+            }
+            run.metrics.tally("order_requests", order, 1);
+            run.metrics
+                .tally("order_failures", order, ptr.is_none() as usize);
+            run.probed(order, ptr.is_none());
+        }
+    }
+
+    run.finish(&list)
+}
+
+/// The background churn of `high_order` in pages of `PAGE_SIZE`,
+/// with each allocation unmovable with chance `unmovable`,
+/// reclaimable with chance `reclaimable`, and movable otherwise.
+/// Every `SAMPLE_EVERY` ops it samples the fraction of pageblocks
+/// with allocations of more than one class into the series
+/// `mixed_blocks`, and with an unmovable one into `unmovable_blocks`.
+/// Reports the mallocs that fell back on a pageblock of another
+/// class as `fallbacks`
+pub fn mobility<R: Rng>(
+    mut allocator: Grouped,
+    ops: usize,
+    occupancy: f64,
+    unmovable: f64,
+    reclaimable: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    let mut churn = PageChurn::new(allocator.free_space() / PAGE_SIZE, occupancy);
+    let classes = WeightedIndex::new([unmovable, reclaimable, 1.0 - unmovable - reclaimable])
+        .expect("Chances of each class should add up to at most 1");
+
+    for op in 0..ops {
+        match churn.next(rng) {
+            PageOp::Malloc(n) => {
+                allocator.switch_mobility(Mobility::ALL[classes.sample(rng)]);
+                if let Some(ptr) = run.malloc(&mut allocator, n * PAGE_SIZE) {
+                    churn.push(ptr, n);
+                }
+            }
+            PageOp::Free(ptr) => run.free(&mut allocator, ptr),
+        }
+
+        if (op + 1).is_multiple_of(SAMPLE_EVERY) {
+            let blocks = allocator.blocks() as f32;
+            let mixed = allocator.mixed_blocks() as f32 / blocks;
+            run.metrics.sample("mixed_blocks", mixed);
+            let unmovable = allocator.unmovable_blocks() as f32 / blocks;
+            run.metrics.sample("unmovable_blocks", unmovable);
+        }
+    }
+
+    run.metrics.count("fallbacks", allocator.fallbacks);
+    run.finish(&allocator)
+}
+
+/// `random_memory` with every op on a random thread of a Hoard
+/// simulation. A block is as likely to be freed by another thread
+/// as by the one that allocated it. Reports the most memory held at
+/// once over the most live as `blowup`, how often superblocks moved
+/// between heaps as `superblock_moves`, and the mean fraction of
+/// superblocks in use with live blocks from more than one thread,
+/// sampled every `SAMPLE_EVERY` ops, as `false_sharing`
+pub fn hoard<R: Rng>(
+    mut allocator: HoardSim,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+    let (mut shared, mut sampled) = (0.0, 0);
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        allocator.switch_thread(rng.gen_range(0..allocator.threads()));
+        if i < 10 || sample {
+            if let Some(ptr) = run.malloc(&mut allocator, rng.gen_range(32..=128)) {
+                ptrs.push(ptr);
+            }
+        } else if !ptrs.is_empty() {
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+
+        if (i + 1).is_multiple_of(SAMPLE_EVERY) && allocator.in_use() > 0 {
+            shared += allocator.shared() as f32 / allocator.in_use() as f32;
+            sampled += 1;
+        }
+    }
+
+    run.metrics.report("blowup", allocator.blowup());
+    run.metrics.count("superblock_moves", allocator.moves);
+    run.metrics
+        .report("false_sharing", shared / sampled.max(1) as f32);
+    run.finish(&allocator)
+}
+
+/// `random_memory` on a mimalloc model, sampling how full its pages
+/// are as it goes. Reports the pages in use by how full they were,
+/// in steps of 10%, added up over samples every `SAMPLE_EVERY` ops,
+/// as the histogram `page_occupancy`
+pub fn mimalloc<R: Rng>(
+    mut allocator: Mimalloc,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            if let Some(ptr) = run.malloc(&mut allocator, rng.gen_range(32..=128)) {
+                ptrs.push(ptr);
+            }
+        } else if !ptrs.is_empty() {
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+
+        if (i + 1).is_multiple_of(SAMPLE_EVERY) {
+            for (step, count) in allocator.occupancy() {
+                run.metrics.tally("page_occupancy", step, count);
+            }
+        }
+    }
+
+    run.finish(&allocator)
+}
+
+/// Nine in ten mallocs ask for 16 to 128 bytes, and the rest for 512
+/// to 4096, like small objects next to buffers
+pub fn small_and_large<T: Allocator, R: Rng>(
+    mut allocator: T,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    mostly_small(&mut allocator, ratio, metrics, rng).finish(&allocator)
+}
+
+/// `small_and_large` on a span heap. Reports the fragmentation of
+/// each level at the end, the buddy allocator's first, as the series
+/// `level_internal`, `level_free` and `level_external` with the
+/// fields of `LevelFrag`, and how the spans were recycled as
+/// `recycling_taken`, `recycling_emptied`, `recycling_returned`,
+/// `recycling_retained`, `recycling_reused` and
+/// `recycling_peak_retained`, with the fields of `Recycling`
+pub fn spans<R: Rng>(
+    mut allocator: SpanHeap,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = mostly_small(&mut allocator, ratio, metrics, rng);
+    for (_, level) in allocator.levels() {
+        run.metrics.sample("level_internal", level.internal as f32);
+        run.metrics.sample("level_free", level.free as f32);
+        run.metrics.sample("level_external", level.external as f32);
+    }
+    run.recycling(allocator.recycling);
+    run.finish(&allocator)
+}
+
+/// `small_and_large` on size class bins, reporting how their runs
+/// were recycled like `spans`
+pub fn bins<R: Rng>(
+    mut allocator: BinAllocator,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = mostly_small(&mut allocator, ratio, metrics, rng);
+    run.recycling(allocator.recycling);
+    run.finish(&allocator)
+}
+
+// The ops of `small_and_large`, leaving the run open for the
+// workload to report on
+fn mostly_small<T: Allocator, R: Rng>(
+    allocator: &mut T,
+    ratio: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Run {
+    let mut run = Run::new(&*allocator, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let size = if rng.gen_bool(0.1) {
+                rng.gen_range(512..=4096)
+            } else {
+                rng.gen_range(16..=128)
+            };
+            if let Some(ptr) = run.malloc(allocator, size) {
+                ptrs.push(ptr);
+            }
+        } else if !ptrs.is_empty() {
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(allocator, ptrs.remove(i));
+        }
+    }
+
+    run
+}
+
+/// Like `random_memory`, but most mallocs ask for one of `common`
+/// and the rest for any size up to 256. Reports the quick lists'
+/// `cache_hits`, `cache_misses` and `cache_flushes`, and the free
+/// list `searches` behind them and the `nodes_scanned` in those
+pub fn quick_fit<R: Rng>(
+    mut allocator: QuickFit,
+    ratio: f64,
+    common: &[usize],
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let size = match common.choose(rng) {
+                Some(&size) if rng.gen_bool(0.8) => size,
+                _ => rng.gen_range(1..=256),
+            };
+            if let Some(ptr) = run.malloc(&mut allocator, size) {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+    }
+
+    run.metrics.count("cache_hits", allocator.hits);
+    run.metrics.count("cache_misses", allocator.misses);
+    run.metrics.count("cache_flushes", allocator.flushes);
+    run.metrics.count("searches", allocator.searches);
+    run.metrics.count("nodes_scanned", allocator.nodes_scanned);
+    run.finish(&allocator)
+}
+
+/// Random churn on an id space. Most requests are for a single id,
+/// and the rest for a short run of them. Reports the ops between an
+/// id being freed and handed out again, on average as
+/// `mean_reuse_latency` and at the shortest as `min_reuse_latency`,
+/// if any id was reused
+pub fn ids<R: Rng>(mut space: IdSpace, ratio: f64, metrics: Metrics, rng: &mut R) -> Results {
+    let mut run = Run::new(&space, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ids = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let count = if rng.gen_bool(0.9) {
+                1
+            } else {
+                rng.gen_range(2..=8)
+            };
+            if let Some(id) = run.malloc(&mut space, count) {
+                ids.push(id);
+            }
+        } else {
+            if ids.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ids.len());
+            run.free(&mut space, ids.remove(i));
+        }
+    }
+
+    run.metrics
+        .report("mean_reuse_latency", space.mean_reuse_latency());
+    if let Some(latency) = space.min_reuse_latency() {
+        run.metrics.report("min_reuse_latency", latency as f32);
+    }
+    run.finish(&space)
+}
+
+/// Like `random_memory`, but with `critical` of the mallocs coming
+/// from reservation `id` instead of the regular heap, or from the
+/// heap like the rest without a reservation. Reports the
+/// critical mallocs that failed as the `critical_fails` metric, and
+/// the fraction of reserved bytes allocated at the end of the run
+/// as `reservation_utilization`
+pub fn reserved<T: Allocator, R: Rng>(
+    mut heap: Reserving<T>,
+    id: Option<ReservationId>,
+    ratio: f64,
+    critical: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&heap, metrics);
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            let size = rng.gen_range(32..=128);
+            let ptr = if rng.gen_bool(critical) {
+                let result = match id {
+                    Some(id) => heap
+                        .alloc_from_reservation(id, size)
+                        .ok_or(AllocFailure::Exhausted),
+                    None => heap.try_malloc(size),
+                };
+                run.metrics
+                    .count("critical_fails", result.is_err() as usize);
+                run.record_malloc(&heap, size, result)
+            } else {
+                run.malloc(&mut heap, size)
+            };
+            if let Some(ptr) = ptr {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut heap, ptrs.remove(i));
+        }
+    }
+
+    run.metrics
+        .report("reservation_utilization", heap.total_utilization());
+    run.finish(&heap)
+}
+
+/// Like `random_memory`, but on two allocators sharing one address
+/// space. Returns the results of the low and the high allocator
+pub fn head_to_head<A: Allocator, B: Allocator, R: Rng>(
+    mut heap: HeadToHead<A, B>,
+    ratio: f64,
+    rng: &mut R,
+) -> [Results; 2] {
+    let mut run = Run::new(&heap, Metrics::new());
+
+    let samples: Vec<bool> = Bernoulli::new(ratio)
+        .unwrap()
+        .sample_iter(&mut *rng)
+        .take(1000)
+        .collect();
+    let mut ptrs = Vec::new();
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if i < 10 || sample {
+            if let Some(ptr) = run.malloc(&mut heap, rng.gen_range(32..=128)) {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut heap, ptrs.remove(i));
+        }
+    }
+
+    heap.results()
+}
+
+/// Mallocs arrive on a simulation clock, `arrivals` of them, and each
+/// allocation lives for an exponential time of mean `lifetime` before
+/// it is freed. At an arrival rate r about r * lifetime allocations
+/// are live, so the pressure on the heap follows the load level.
+/// `correlation` ties sizes to lifetimes, from -1 where the largest
+/// allocations die first to 1 where they live longest, with 0 drawing
+/// them independently. Sizes are 32 to 128 bytes, like
+/// `random_memory`. Results are also broken down by level, as series
+/// with one point per level of `load_rate`, `load_mallocs`,
+/// `load_malloc_fails`, `load_external_frag` and `load_live_bytes`,
+/// with the fields of `Load`
+pub fn timed<T: Allocator, R: Rng>(
+    mut allocator: T,
+    process: &Arrivals,
+    arrivals: usize,
+    lifetime: f64,
+    correlation: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    let mut clock = process.clock(rng);
+
+    let mut loads: Vec<Load> = process
+        .rates()
+        .into_iter()
+        .map(|rate| Load {
+            rate,
+            ..Load::default()
+        })
+        .collect();
+    // Live allocations as expiry time, address and size
+    let mut live: Vec<(f64, usize, usize)> = Vec::new();
+    let mut live_bytes = 0;
+
+    for _ in 0..arrivals {
+        let (now, level) = clock.next(rng);
+        live.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        while live.last().is_some_and(|&(expiry, _, _)| expiry <= now) {
+            let (_, ptr, size) = live.pop().unwrap();
+            run.free(&mut allocator, ptr);
+            live_bytes -= size;
+        }
+
+        let (u, v) = synth::correlated(rng, correlation);
+        let size = synth::Distribution::Uniform { min: 32, max: 128 }.quantile(u);
+        let load = &mut loads[level];
+        load.mallocs += 1;
+        match run.malloc(&mut allocator, size) {
+            Some(ptr) => {
+                let expiry = now - lifetime * (1.0 - v).ln();
+                live.push((expiry, ptr, size));
+                live_bytes += size;
+            }
+            None => load.malloc_fails += 1,
+        }
+        load.external_frag += allocator.external_frag();
+        load.live_bytes += live_bytes as f32;
+    }
+
+    for load in loads.iter_mut().filter(|load| load.mallocs > 0) {
+        load.external_frag /= load.mallocs as f32;
+        load.live_bytes /= load.mallocs as f32;
+    }
+    for load in loads {
+        run.metrics.sample("load_rate", load.rate as f32);
+        run.metrics.sample("load_mallocs", load.mallocs as f32);
+        run.metrics
+            .sample("load_malloc_fails", load.malloc_fails as f32);
+        run.metrics.sample("load_external_frag", load.external_frag);
+        run.metrics.sample("load_live_bytes", load.live_bytes);
+    }
+    run.finish(&allocator)
+}
+
+/// Random sizes, but instead of a fixed malloc ratio the controller
+/// picks one before every op, steering occupancy (the fraction of
+/// the heap not free) along `target`. Reports the mean gap between
+/// the target and the real occupancy as `tracking_error`
+pub fn controlled<T: Allocator, R: Rng>(
+    mut allocator: T,
+    target: Occupancy,
+    ops: usize,
+    mut controller: Controller,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    let capacity = allocator.free_space().max(1) as f64;
+    let mut ptrs = Vec::new();
+    let mut error = 0.0;
+
+    for op in 0..ops {
+        let occupancy = 1.0 - allocator.free_space() as f64 / capacity;
+        error += (target.at(op) - occupancy).abs();
+        let ratio = controller.ratio(target.at(op), occupancy);
+
+        if rng.gen_bool(ratio) {
+            if let Some(ptr) = run.malloc(&mut allocator, rng.gen_range(32..=128)) {
+                ptrs.push(ptr);
+            }
+        } else {
+            if ptrs.is_empty() {
+                continue;
+            }
+            let i = rng.gen_range(0..ptrs.len());
+            run.free(&mut allocator, ptrs.remove(i));
+        }
+    }
+
+    run.metrics
+        .report("tracking_error", (error / ops.max(1) as f64) as f32);
+    run.finish(&allocator)
+}
+
+/// Many cycles of heavy churn followed by a quiet phase, to see
+/// whether fragmentation keeps building up or settles down over a
+/// much longer run than the other workloads. During the churn, half
+/// the ops malloc 16 to 256 bytes and half free a random short lived
+/// allocation. A malloc is long lived instead with a chance of
+/// `keep`. The quiet phase frees everything short lived, and each
+/// long lived allocation with a chance of `release`, so long lived
+/// data is left scattered wherever the churn put it. Reports the heap
+/// at the end of each cycle, as series with one point per cycle: the
+/// external fragmentation right after the churn as `churn_frag` and
+/// after the quiet phase as `quiet_frag`, the `free_space`, the
+/// `largest_alloc`, and the `cycle_malloc_fails` during the cycle
+pub fn cycles<T: Allocator, R: Rng>(
+    mut allocator: T,
+    cycles: usize,
+    churn: usize,
+    keep: f64,
+    release: f64,
+    metrics: Metrics,
+    rng: &mut R,
+) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    let mut short: Vec<usize> = Vec::new();
+    let mut kept: Vec<usize> = Vec::new();
+
+    for _ in 0..cycles {
+        let fails = run.results.malloc_fails;
+        for _ in 0..churn {
+            if short.is_empty() || rng.gen_bool(0.5) {
+                let size = rng.gen_range(16..=256);
+                if let Some(ptr) = run.malloc(&mut allocator, size) {
+                    if rng.gen_bool(keep) {
+                        kept.push(ptr);
+                    } else {
+                        short.push(ptr);
+                    }
+                }
+            } else {
+                let i = rng.gen_range(0..short.len());
+                run.free(&mut allocator, short.swap_remove(i));
+            }
+        }
+        let churn_frag = allocator.external_frag();
+
+        for ptr in short.drain(..) {
+            run.free(&mut allocator, ptr);
+        }
+        kept.retain(|&ptr| {
+            let released = rng.gen_bool(release);
+            if released {
+                run.free(&mut allocator, ptr);
+            }
+            !released
+        });
+        run.metrics.sample("churn_frag", churn_frag);
+        run.metrics.sample("quiet_frag", allocator.external_frag());
+        run.metrics
+            .sample("free_space", allocator.free_space() as f32);
+        run.metrics
+            .sample("largest_alloc", allocator.largest_alloc() as f32);
+        let fails = run.results.malloc_fails - fails;
+        run.metrics.sample("cycle_malloc_fails", fails as f32);
+    }
+
+    run.finish(&allocator)
+}
+
+/// Replay a trace, recording it like any other workload. Frees of
+/// allocations whose malloc failed are skipped
+pub fn replayed<T: Allocator>(mut allocator: T, trace: &Trace, metrics: Metrics) -> Results {
+    let mut run = Run::new(&allocator, metrics);
+    let mut ptrs: BTreeMap<usize, usize> = BTreeMap::new();
+
+    for &op in trace.ops.iter() {
+        match op {
+            Op::Malloc { id, size } => {
+                if let Some(ptr) = run.malloc(&mut allocator, size) {
+                    ptrs.insert(id, ptr);
+                }
+            }
+            Op::Calloc { id, count, size } => {
+                let result = allocator.try_calloc(count, size);
+                let bytes = op.bytes().unwrap();
+                if let Some(ptr) = run.record_malloc(&allocator, bytes, result) {
+                    ptrs.insert(id, ptr);
+                }
+            }
+            Op::Free { id } => {
+                if let Some(ptr) = ptrs.remove(&id) {
+                    run.free(&mut allocator, ptr);
+                }
+            }
+        }
+    }
+
+    run.finish(&allocator)
+}
+
+/// Run a workload `runs` times on one allocator, resetting it before
+/// each run instead of cloning a fresh one, for allocators that are
+/// expensive to build. Panics if the allocator can't be reset
+pub fn trials<T: Allocator>(
+    allocator: &mut T,
+    runs: usize,
+    mut workload: impl FnMut(&mut T) -> Results,
+) -> Aggregate {
+    (0..runs)
+        .map(|_| {
+            allocator.reset().expect("Could not reset the allocator");
+            workload(allocator)
+        })
+        .collect()
 }
@@ -0,0 +1,61 @@
+use freespace_sim::prelude::*;
+use std::env;
+use std::fs;
+
+// Leaves holes of a few sizes behind, so every drawing has more than
+// one free block to show
+const TRACE: &str = "malloc 0 100
+malloc 1 50
+malloc 2 200
+malloc 3 30
+malloc 4 120
+free 1
+free 3
+malloc 5 20";
+
+// Each drawing has to match tests/golden/<name>.txt byte for byte.
+// After changing how an allocator draws on purpose, rerun with
+// UPDATE_GOLDEN=1 to rewrite the files, and check the diff
+#[test]
+fn renderings() {
+    let trace: Trace = TRACE.parse().unwrap();
+    let mut next_fit = FreeList::new(0, 1024, true).policy(Policy::Next);
+    trace.replay(&mut next_fit);
+    let mut drawings = vec![("freelist scan", next_fit.render_scan())];
+
+    let allocators: Vec<(&str, Box<dyn Allocator>)> = vec![
+        (
+            "freelist",
+            Box::new(FreeList::new(0, 1024, true).policy(Policy::First)),
+        ),
+        ("freelist wide", Box::new(FreeList::new(100000, 1024, true))),
+        ("buddy", Box::new(BuddyAllocator::new(4, 10))),
+        ("tlsf", Box::new(Tlsf::new(0, 1024))),
+        ("linked", Box::new(LinkedFreeList::new(0, 1024))),
+        ("stack", Box::new(StackAllocator::new(0, 1024))),
+        ("vmem", Box::new(Arena::new(0, 1024, 8))),
+    ];
+    for (name, mut allocator) in allocators {
+        trace.replay(&mut allocator);
+        drawings.push((name, allocator.render()));
+    }
+
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    for (name, drawing) in drawings {
+        let path = format!("{}/{}.txt", dir, name.replace(' ', "_"));
+        if update {
+            fs::write(&path, &drawing).unwrap();
+            continue;
+        }
+        let golden = fs::read_to_string(&path).unwrap_or_default();
+        if golden != drawing {
+            failures.push(format!(
+                "{} drew\n{}\nbut {} has\n{}",
+                name, drawing, path, golden
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
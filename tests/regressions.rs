@@ -0,0 +1,23 @@
+use freespace_sim::regressions;
+
+// Every trace in tests/regressions has to replay cleanly on every
+// allocator. Add a minimized trace there for each bug that's fixed
+#[test]
+fn corpus() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/regressions");
+    let traces = regressions::load(dir).unwrap();
+    assert!(!traces.is_empty());
+
+    let mut failures = Vec::new();
+    for (name, trace) in traces.iter() {
+        let results = regressions::check_all(trace)
+            .into_iter()
+            .chain(regressions::commutes_all(trace));
+        for (allocator, result) in results {
+            if let Err(violation) = result {
+                failures.push(format!("{} on {}: {}", name, allocator, violation));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}